@@ -1,17 +1,27 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
 use sqlx::{PgPool, Row};
 use std::collections::HashMap;
+use tokio::io::AsyncWrite;
 use anyhow::Result;
 use tracing::{error, info, warn};
 
 use crate::{TrafficEvent, ReportQuery, TrafficReport, RealtimeMetrics, ResponseTimeStats};
+use crate::export::{self, ExportFormat};
+use crate::reports;
+use crate::stats::{self, STATS_CACHE_TTL_SECS};
+use crate::webhooks::WebhookDispatcher;
 use config_manager::Config;
-use storage_layer::Database;
+use nostr_types::Filter;
+use storage_layer::{slow_query::DEFAULT_SLOW_QUERY_THRESHOLD_MS, Database, FilterQueryBuilder, QueryParam, SlowQuery, SlowQueryLogger};
 
 pub struct AnalyticsEngine {
     db: Database,
+    /// Runs every analytics query, logging the ones over the slow query threshold
+    queries: SlowQueryLogger,
     redis: redis::Client,
+    webhooks: WebhookDispatcher,
 }
 
 impl AnalyticsEngine {
@@ -21,8 +31,18 @@ impl AnalyticsEngine {
         
         // Create analytics tables if they don't exist
         Self::init_analytics_tables(&db.pool).await?;
+        reports::init_tables(&db.pool).await?;
+
+        let webhooks = WebhookDispatcher::new(db.pool.clone())?;
+        webhooks.init_tables().await?;
+
+        let threshold_ms = std::env::var("SLOW_QUERY_THRESHOLD_MS")
+            .ok()
+            .and_then(|threshold| threshold.parse().ok())
+            .unwrap_or(DEFAULT_SLOW_QUERY_THRESHOLD_MS);
+        let queries = SlowQueryLogger::new(db.pool.clone(), threshold_ms);
         
-        Ok(Self { db, redis })
+        Ok(Self { db, queries, redis, webhooks })
     }
 
     async fn init_analytics_tables(pool: &PgPool) -> Result<()> {
@@ -83,9 +103,14 @@ impl AnalyticsEngine {
         .bind(&event.event_type)
         .bind(event.timestamp)
         .bind(serde_json::to_value(&event.metadata)?)
-        .execute(&self.db.pool)
+        .execute(&self.queries)
         .await?;
 
+        // Notify subscribed webhooks; delivery problems never fail recording
+        if let Err(e) = self.webhooks.dispatch(&event).await {
+            warn!("Failed to dispatch webhooks for event {}: {}", event.event_id, e);
+        }
+
         // Update real-time counters in Redis
         let mut conn = self.redis.get_async_connection().await?;
         let key = format!("events:{}:{}", event.event_type, Utc::now().format("%Y%m%d%H"));
@@ -148,7 +173,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
         
         Ok(row.get::<i64, _>("count") as u64)
@@ -160,7 +185,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
         
         Ok(row.get::<i64, _>("count") as u64)
@@ -172,7 +197,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_all(&self.db.pool)
+        .fetch_all(&self.queries)
         .await?;
 
         let mut events_by_type = HashMap::new();
@@ -191,7 +216,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
         
         Ok(row.get::<Option<i32>, _>("max_connections").unwrap_or(0) as u64)
@@ -203,7 +228,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
         
         Ok(row.get::<Option<i64>, _>("total_bytes").unwrap_or(0) as u64)
@@ -221,7 +246,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
 
         let total: i64 = row.get("total");
@@ -248,7 +273,7 @@ impl AnalyticsEngine {
         )
         .bind(start)
         .bind(end)
-        .fetch_one(&self.db.pool)
+        .fetch_one(&self.queries)
         .await?;
 
         Ok(ResponseTimeStats {
@@ -259,6 +284,97 @@ impl AnalyticsEngine {
         })
     }
 
+    /// Number of stored events matching any of the given filters
+    pub async fn count_matching_events(&self, filters: &[Filter]) -> Result<u64> {
+        let (sql, params) = FilterQueryBuilder::new(filters).build_count_query();
+
+        let row = QueryParam::bind_all(params, sqlx::query(&sql))
+            .fetch_one(&self.queries)
+            .await?;
+
+        Ok(row.get::<i64, _>("count") as u64)
+    }
+
+    /// Authors with the most stored events, cached for a minute
+    pub async fn top_pubkeys_by_event_count(&self, limit: u64, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<(String, u64)>> {
+        let key = format!("stats:top-pubkeys:{}:{}:{}", limit, since.timestamp(), until.timestamp());
+        self.cached(&key, stats::top_pubkeys(&self.queries, limit, since, until)).await
+    }
+
+    /// Most common event kinds, cached for a minute
+    pub async fn top_kinds_by_count(&self, limit: u64, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<(u16, u64)>> {
+        let key = format!("stats:top-kinds:{}:{}:{}", limit, since.timestamp(), until.timestamp());
+        self.cached(&key, stats::top_kinds(&self.queries, limit, since, until)).await
+    }
+
+    /// Stored events per hour between `since` and `until`, cached for a minute
+    pub async fn events_per_hour(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, u64)>> {
+        let key = format!("stats:events-per-hour:{}:{}", since.timestamp(), until.timestamp());
+        self.cached(&key, stats::events_per_hour(&self.queries, since, until)).await
+    }
+
+    /// Roll traffic events from `since`'s hour on into hourly summaries
+    pub async fn summarize_hourly_traffic(&self, since: Option<DateTime<Utc>>) -> Result<u64> {
+        reports::summarize_hourly_traffic(&self.queries, since).await
+    }
+
+    /// Traffic event counts by hour and type for one UTC day
+    pub async fn hourly_traffic(&self, date: NaiveDate) -> Result<Vec<(DateTime<Utc>, String, u64)>> {
+        reports::hourly_summaries(&self.queries, date).await
+    }
+
+    /// Serve `key` from Redis, or run `query` and cache its result with
+    /// `SETEX`. Stats still work, uncached, while Redis is unavailable.
+    async fn cached<T, F>(&self, key: &str, query: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T>>,
+    {
+        let mut conn = match self.redis.get_async_connection().await {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                warn!("Stats cache unavailable: {}", e);
+                None
+            }
+        };
+
+        if let Some(conn) = conn.as_mut() {
+            match redis::cmd("GET").arg(key).query_async::<_, Option<String>>(conn).await {
+                Ok(Some(cached)) => match serde_json::from_str(&cached) {
+                    Ok(value) => return Ok(value),
+                    Err(e) => warn!("Ignoring malformed cached stats {}: {}", key, e),
+                },
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read cached stats {}: {}", key, e),
+            }
+        }
+
+        let value = query.await?;
+
+        if let Some(conn) = conn.as_mut() {
+            let result = redis::cmd("SETEX")
+                .arg(key)
+                .arg(STATS_CACHE_TTL_SECS)
+                .arg(serde_json::to_string(&value)?)
+                .query_async::<_, ()>(conn)
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to cache stats {}: {}", key, e);
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub fn webhooks(&self) -> &WebhookDispatcher {
+        &self.webhooks
+    }
+
+    /// The most recent queries that exceeded the slow query threshold, newest first
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.queries.recent()
+    }
+
     pub async fn get_realtime_metrics(&self) -> Result<RealtimeMetrics> {
         // Get latest metrics from connection_metrics table
         let row = sqlx::query(
@@ -268,7 +384,7 @@ impl AnalyticsEngine {
             LIMIT 1
             "#
         )
-        .fetch_optional(&self.db.pool)
+        .fetch_optional(&self.queries)
         .await?;
 
         if let Some(row) = row {
@@ -293,46 +409,12 @@ impl AnalyticsEngine {
         }
     }
 
-    pub async fn export_csv_report(&self, query: ReportQuery) -> Result<String> {
-        let start_date = query.start_date.unwrap_or_else(|| Utc::now() - chrono::Duration::days(7));
-        let end_date = query.end_date.unwrap_or_else(|| Utc::now());
-
-        let rows = sqlx::query(
-            r#"
-            SELECT 
-                event_id,
-                client_id,
-                event_type,
-                timestamp,
-                response_time_ms,
-                bytes_transferred,
-                error_code
-            FROM traffic_events 
-            WHERE timestamp BETWEEN $1 AND $2
-            ORDER BY timestamp DESC
-            "#
-        )
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_all(&self.db.pool)
-        .await?;
-
-        let mut csv = String::from("event_id,client_id,event_type,timestamp,response_time_ms,bytes_transferred,error_code\n");
-        
-        for row in rows {
-            csv.push_str(&format!(
-                "{},{},{},{},{},{},{}\n",
-                row.get::<String, _>("event_id"),
-                row.get::<Option<String>, _>("client_id").unwrap_or_else(|| "".to_string()),
-                row.get::<String, _>("event_type"),
-                row.get::<DateTime<Utc>, _>("timestamp"),
-                row.get::<Option<i32>, _>("response_time_ms").unwrap_or(0),
-                row.get::<Option<i64>, _>("bytes_transferred").unwrap_or(0),
-                row.get::<Option<String>, _>("error_code").unwrap_or_else(|| "".to_string()),
-            ));
-        }
-
-        Ok(csv)
+    /// Stream the traffic events matching `query` to `writer` in `format`
+    pub async fn export_report<W>(&self, query: &ReportQuery, format: ExportFormat, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        export::write_report(&self.queries, query, format, writer).await
     }
 
     pub async fn record_metrics(&self, metrics: RealtimeMetrics) -> Result<()> {
@@ -350,7 +432,7 @@ impl AnalyticsEngine {
         .bind(metrics.memory_usage as i64)
         .bind(metrics.cpu_usage as f32)
         .bind(metrics.disk_usage as i64)
-        .execute(&self.db.pool)
+        .execute(&self.queries)
         .await?;
 
         Ok(())
@@ -1,31 +1,55 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::Json,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Json},
     routing::{get, post},
     Router,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::net::TcpListener;
+use tokio_util::io::ReaderStream;
 use tracing::{error, info};
+use utoipa::{
+    openapi::{
+        self,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
+    IntoParams, Modify, OpenApi, ToSchema,
+};
+use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 
 mod analytics;
+mod auth;
+mod export;
 mod metrics;
 mod reports;
+mod stats;
+mod webhooks;
 
 use analytics::AnalyticsEngine;
+use export::{ExportFormat, EXPORT_BUFFER_SIZE};
+use metrics::{MetricsCollectorTask, DEFAULT_RELAY_ENGINE_URL};
+use reports::HourlySummaryTask;
+use webhooks::{WebhookConfig, WebhookDelivery};
 use config_manager::Config;
+use stats::MAX_STATS_LIMIT;
+use nostr_types::Filter;
+use storage_layer::SlowQuery;
 
 #[derive(Clone)]
 pub struct AppState {
     analytics: Arc<AnalyticsEngine>,
+    /// Bearer token for the admin routes; without one they are disabled
+    admin_token: Option<Arc<str>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct TrafficEvent {
     pub event_id: String,
     pub client_id: Option<String>,
@@ -34,15 +58,91 @@ pub struct TrafficEvent {
     pub metadata: HashMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ReportQuery {
     pub start_date: Option<DateTime<Utc>>,
     pub end_date: Option<DateTime<Utc>>,
     pub report_type: Option<String>,
-    pub granularity: Option<String>, // hour, day, week, month
+    /// hour, day, week or month
+    pub granularity: Option<String>,
+    /// Most rows to export
+    pub limit: Option<u64>,
+    /// Rows to skip before exporting, newest first
+    pub offset: Option<u64>,
+    /// csv (the default), json or ndjson
+    pub format: Option<String>,
+}
+
+/// Query parameters of the `/api/stats` endpoints
+#[derive(Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    pub limit: Option<u64>,
+    /// Start of the range, rounded down to the hour; defaults to a day before `until`
+    pub since: Option<DateTime<Utc>>,
+    /// End of the range, rounded up to the hour; defaults to now. At most 31 days after `since`.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl StatsQuery {
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(20).min(MAX_STATS_LIMIT)
+    }
+
+    fn range(&self) -> Result<(DateTime<Utc>, DateTime<Utc>), StatusCode> {
+        stats::stats_range(self.since, self.until, Utc::now()).map_err(|_| StatusCode::BAD_REQUEST)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PubkeyCount {
+    pub pubkey: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct KindCount {
+    pub kind: u16,
+    pub count: u64,
+}
+
+/// Body of `POST /api/stats/count`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CountQuery {
+    /// NIP-01 filters; events matching any of them are counted once
+    #[schema(value_type = Vec<Object>)]
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EventCount {
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HourlyCount {
+    pub hour: DateTime<Utc>,
+    pub count: u64,
+}
+
+/// Query parameters of `GET /reports/hourly`
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HourlyReportQuery {
+    /// UTC day to report, as YYYY-MM-DD
+    #[param(value_type = String, format = Date)]
+    pub date: NaiveDate,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HourlyTrafficSummary {
+    pub hour: DateTime<Utc>,
+    pub event_type: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct TrafficReport {
     pub period: String,
     pub total_events: u64,
@@ -54,7 +154,7 @@ pub struct TrafficReport {
     pub response_times: ResponseTimeStats,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ResponseTimeStats {
     pub average_ms: f64,
     pub p50_ms: f64,
@@ -62,7 +162,7 @@ pub struct ResponseTimeStats {
     pub p99_ms: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RealtimeMetrics {
     pub active_connections: u64,
     pub events_per_second: f64,
@@ -72,6 +172,16 @@ pub struct RealtimeMetrics {
     pub disk_usage: u64,
 }
 
+#[utoipa::path(
+    post,
+    path = "/events",
+    tag = "traffic",
+    request_body = TrafficEvent,
+    responses(
+        (status = 200, description = "Event recorded"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
 async fn record_traffic_event(
     State(state): State<AppState>,
     Json(event): Json<TrafficEvent>,
@@ -85,6 +195,16 @@ async fn record_traffic_event(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/reports/traffic",
+    tag = "reports",
+    params(ReportQuery),
+    responses(
+        (status = 200, description = "Traffic over the requested period", body = TrafficReport),
+        (status = 500, description = "Storage failure"),
+    )
+)]
 async fn get_traffic_report(
     State(state): State<AppState>,
     Query(query): Query<ReportQuery>,
@@ -98,6 +218,151 @@ async fn get_traffic_report(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/reports/hourly",
+    tag = "reports",
+    params(HourlyReportQuery),
+    responses(
+        (status = 200, description = "Traffic events per hour and type on the day, oldest first", body = Vec<HourlyTrafficSummary>),
+        (status = 400, description = "Missing or malformed date"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn get_hourly_report(
+    State(state): State<AppState>,
+    Query(query): Query<HourlyReportQuery>,
+) -> Result<Json<Vec<HourlyTrafficSummary>>, StatusCode> {
+    match state.analytics.hourly_traffic(query.date).await {
+        Ok(hours) => Ok(Json(
+            hours
+                .into_iter()
+                .map(|(hour, event_type, count)| HourlyTrafficSummary { hour, event_type, count })
+                .collect(),
+        )),
+        Err(e) => {
+            error!("Failed to get hourly traffic for {}: {}", query.date, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats/top-pubkeys",
+    tag = "stats",
+    security(("admin_token" = [])),
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "Pubkeys with the most events in the range, most first", body = Vec<PubkeyCount>),
+        (status = 400, description = "The range is empty or too long"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn get_top_pubkeys(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Vec<PubkeyCount>>, StatusCode> {
+    let (since, until) = query.range()?;
+    match state.analytics.top_pubkeys_by_event_count(query.limit(), since, until).await {
+        Ok(top) => Ok(Json(top.into_iter().map(|(pubkey, count)| PubkeyCount { pubkey, count }).collect())),
+        Err(e) => {
+            error!("Failed to get top pubkeys: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats/top-kinds",
+    tag = "stats",
+    security(("admin_token" = [])),
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "Event kinds by count in the range, most first", body = Vec<KindCount>),
+        (status = 400, description = "The range is empty or too long"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn get_top_kinds(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Vec<KindCount>>, StatusCode> {
+    let (since, until) = query.range()?;
+    match state.analytics.top_kinds_by_count(query.limit(), since, until).await {
+        Ok(top) => Ok(Json(top.into_iter().map(|(kind, count)| KindCount { kind, count }).collect())),
+        Err(e) => {
+            error!("Failed to get top kinds: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats/events-per-hour",
+    tag = "stats",
+    security(("admin_token" = [])),
+    params(StatsQuery),
+    responses(
+        (status = 200, description = "Events stored in each hour of the range, oldest first", body = Vec<HourlyCount>),
+        (status = 400, description = "The range is empty or too long"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn get_events_per_hour(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Vec<HourlyCount>>, StatusCode> {
+    let (since, until) = query.range()?;
+
+    match state.analytics.events_per_hour(since, until).await {
+        Ok(hours) => Ok(Json(hours.into_iter().map(|(hour, count)| HourlyCount { hour, count }).collect())),
+        Err(e) => {
+            error!("Failed to get events per hour: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/stats/count",
+    tag = "stats",
+    security(("admin_token" = [])),
+    request_body = CountQuery,
+    responses(
+        (status = 200, description = "Stored events matching any of the filters", body = EventCount),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn count_events(
+    State(state): State<AppState>,
+    Json(query): Json<CountQuery>,
+) -> Result<Json<EventCount>, StatusCode> {
+    match state.analytics.count_matching_events(&query.filters).await {
+        Ok(count) => Ok(Json(EventCount { count })),
+        Err(e) => {
+            error!("Failed to count matching events: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics/realtime",
+    tag = "reports",
+    responses(
+        (status = 200, description = "Current load", body = RealtimeMetrics),
+        (status = 500, description = "Storage failure"),
+    )
+)]
 async fn get_realtime_metrics(
     State(state): State<AppState>,
 ) -> Result<Json<RealtimeMetrics>, StatusCode> {
@@ -110,19 +375,265 @@ async fn get_realtime_metrics(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/reports/export",
+    tag = "reports",
+    params(ReportQuery),
+    responses(
+        (status = 200, description = "The traffic events over the requested period as CSV, a JSON array or NDJSON, streamed as an attachment", content_type = "text/csv", body = String),
+        (status = 400, description = "Unsupported format"),
+    )
+)]
 async fn export_report(
     State(state): State<AppState>,
     Query(query): Query<ReportQuery>,
-) -> Result<String, StatusCode> {
-    match state.analytics.export_csv_report(query).await {
-        Ok(csv_data) => Ok(csv_data),
-        Err(e) => {
+) -> Result<impl IntoResponse, StatusCode> {
+    let format = match query.format.as_deref() {
+        Some(format) => format.parse::<ExportFormat>().map_err(|e| {
+            error!("Rejected export query: {}", e);
+            StatusCode::BAD_REQUEST
+        })?,
+        None => ExportFormat::default(),
+    };
+
+    // Rows are written into one end of a pipe while the response body reads
+    // the other. The status is sent before the first row, so a storage failure
+    // part way through cuts the download short.
+    let (mut writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        if let Err(e) = state.analytics.export_report(&query, format, &mut writer).await {
             error!("Failed to export report: {}", e);
+        }
+    });
+
+    let filename = format!("report-{}.{}", Utc::now().format("%Y-%m-%d"), format.extension());
+    let headers = [
+        (header::CONTENT_TYPE, format.content_type().to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(reader))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    request_body = WebhookConfig,
+    responses(
+        (status = 201, description = "Webhook registered", body = WebhookConfig),
+        (status = 400, description = "URL is not a public http(s) address"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn create_webhook(
+    State(state): State<AppState>,
+    Json(webhook): Json<WebhookConfig>,
+) -> Result<(StatusCode, Json<WebhookConfig>), StatusCode> {
+    check_webhook_target(&webhook).await?;
+    match state.analytics.webhooks().create(&webhook).await {
+        Ok(_) => Ok((StatusCode::CREATED, Json(webhook))),
+        Err(e) => {
+            error!("Failed to create webhook: {}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
+// Refuse a webhook aimed at the service's own network
+async fn check_webhook_target(webhook: &WebhookConfig) -> Result<(), StatusCode> {
+    webhooks::check_target(&webhook.url).await.map_err(|reason| {
+        info!("Refused webhook {}: {}", webhook.id, reason);
+        StatusCode::BAD_REQUEST
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Registered webhooks", body = Vec<WebhookConfig>),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn list_webhooks(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<WebhookConfig>>, StatusCode> {
+    match state.analytics.webhooks().list().await {
+        Ok(webhooks) => Ok(Json(webhooks)),
+        Err(e) => {
+            error!("Failed to list webhooks: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    params(("id" = Uuid, Path, description = "Webhook ID")),
+    responses(
+        (status = 200, description = "The webhook", body = WebhookConfig),
+        (status = 404, description = "No such webhook"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn get_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WebhookConfig>, StatusCode> {
+    match state.analytics.webhooks().get(id).await {
+        Ok(Some(webhook)) => Ok(Json(webhook)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to get webhook {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    params(("id" = Uuid, Path, description = "Webhook ID")),
+    request_body = WebhookConfig,
+    responses(
+        (status = 200, description = "The updated webhook", body = WebhookConfig),
+        (status = 400, description = "URL is not a public http(s) address"),
+        (status = 404, description = "No such webhook"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn update_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(mut webhook): Json<WebhookConfig>,
+) -> Result<Json<WebhookConfig>, StatusCode> {
+    webhook.id = id;
+    check_webhook_target(&webhook).await?;
+    match state.analytics.webhooks().update(&webhook).await {
+        Ok(true) => Ok(Json(webhook)),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to update webhook {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    params(("id" = Uuid, Path, description = "Webhook ID")),
+    responses(
+        (status = 204, description = "Webhook removed"),
+        (status = 404, description = "No such webhook"),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn delete_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    match state.analytics.webhooks().delete(id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to delete webhook {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    security(("admin_token" = [])),
+    params(("id" = Uuid, Path, description = "Webhook ID")),
+    responses(
+        (status = 200, description = "Delivery attempts, newest first", body = Vec<WebhookDelivery>),
+        (status = 500, description = "Storage failure"),
+    )
+)]
+async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<WebhookDelivery>>, StatusCode> {
+    match state.analytics.webhooks().deliveries(id).await {
+        Ok(deliveries) => Ok(Json(deliveries)),
+        Err(e) => {
+            error!("Failed to list deliveries for webhook {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/slow-queries",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The most recent queries over the slow query threshold", body = Vec<SlowQuery>),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+async fn list_slow_queries(State(state): State<AppState>) -> Json<Vec<SlowQuery>> {
+    Json(state.analytics.slow_queries())
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Pleb.One analytics API", description = "Traffic reports, event statistics and webhooks"),
+    paths(
+        record_traffic_event,
+        get_traffic_report,
+        get_hourly_report,
+        get_realtime_metrics,
+        export_report,
+        create_webhook,
+        list_webhooks,
+        get_webhook,
+        update_webhook,
+        delete_webhook,
+        list_webhook_deliveries,
+        list_slow_queries,
+        get_top_pubkeys,
+        get_top_kinds,
+        get_events_per_hour,
+        count_events,
+    ),
+    modifiers(&AdminSecurityScheme),
+    tags(
+        (name = "webhooks", description = "Webhook registration; needs `Authorization: Bearer <ANALYTICS_ADMIN_TOKEN>`"),
+        (name = "stats", description = "Event statistics from the relay's database; needs `Authorization: Bearer <ANALYTICS_ADMIN_TOKEN>`"),
+    )
+)]
+pub struct ApiDoc;
+
+// The credential the admin endpoints' `security` refers to
+struct AdminSecurityScheme;
+
+impl Modify for AdminSecurityScheme {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::init();
@@ -130,13 +641,38 @@ async fn main() -> anyhow::Result<()> {
     let config = Config::load("analytics-service")?;
     let analytics = Arc::new(AnalyticsEngine::new(&config).await?);
 
-    let state = AppState { analytics };
+    // Background jobs feeding /metrics/realtime and /reports/hourly
+    let relay_engine_url = std::env::var("RELAY_ENGINE_URL").unwrap_or_else(|_| DEFAULT_RELAY_ENGINE_URL.to_string());
+    MetricsCollectorTask::new(analytics.clone(), relay_engine_url)?.spawn();
+    HourlySummaryTask::new(analytics.clone()).spawn();
+
+    let admin_token = std::env::var("ANALYTICS_ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    let state = AppState {
+        analytics,
+        admin_token: admin_token.map(Arc::from),
+    };
+
+    // Routes that change what the service does, send data to other hosts,
+    // scan the relay's events table or show the SQL it runs
+    let admin = Router::new()
+        .route("/admin/slow-queries", get(list_slow_queries))
+        .route("/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/webhooks/:id", get(get_webhook).put(update_webhook).delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(list_webhook_deliveries))
+        .route("/api/stats/top-pubkeys", get(get_top_pubkeys))
+        .route("/api/stats/top-kinds", get(get_top_kinds))
+        .route("/api/stats/events-per-hour", get(get_events_per_hour))
+        .route("/api/stats/count", post(count_events))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_admin));
 
     let app = Router::new()
         .route("/events", post(record_traffic_event))
         .route("/reports/traffic", get(get_traffic_report))
+        .route("/reports/hourly", get(get_hourly_report))
         .route("/metrics/realtime", get(get_realtime_metrics))
         .route("/reports/export", get(export_report))
+        .merge(admin)
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()))
         .with_state(state);
 
     let listener = TcpListener::bind(&config.server.bind_address).await?;
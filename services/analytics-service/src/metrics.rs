@@ -0,0 +1,194 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::warn;
+
+use crate::{analytics::AnalyticsEngine, RealtimeMetrics};
+
+/// How often the relay engine's metrics are sampled into `connection_metrics`
+pub const METRICS_COLLECT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Path of the relay engine's combined metrics endpoint
+pub const RELAY_METRICS_PATH: &str = "/api/metrics/all";
+
+/// Relay engine base URL used when `RELAY_ENGINE_URL` isn't set
+pub const DEFAULT_RELAY_ENGINE_URL: &str = "http://localhost:8080";
+
+// A slow relay shouldn't hold up the next sample
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The parts of the relay engine's `/api/metrics/all` document the collector
+/// reads; everything else in it is ignored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayMetrics {
+    pub relay_status: RelayStatus,
+    pub events: RelayEvents,
+    pub performance: RelayPerformance,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayStatus {
+    pub active_connections: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayEvents {
+    /// Events received since the relay started
+    pub events_received: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayPerformance {
+    pub active_subscriptions: u64,
+}
+
+/// Samples the relay engine's `/api/metrics/all` every
+/// `METRICS_COLLECT_INTERVAL` and records it as the realtime metrics served
+/// by `/metrics/realtime`.
+pub struct MetricsCollectorTask {
+    analytics: Arc<AnalyticsEngine>,
+    client: reqwest::Client,
+    relay_engine_url: String,
+    /// When the last sample was taken and the relay's events received total at that time
+    previous: Option<(Instant, u64)>,
+}
+
+impl MetricsCollectorTask {
+    pub fn new(analytics: Arc<AnalyticsEngine>, relay_engine_url: String) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        Ok(Self {
+            analytics,
+            client,
+            relay_engine_url,
+            previous: None,
+        })
+    }
+
+    /// Start collecting on its own task
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = interval(METRICS_COLLECT_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            // An unreachable relay leaves a gap in the samples; the next tick tries again
+            if let Err(e) = self.collect().await {
+                warn!("Failed to collect relay metrics from {}: {}", self.relay_engine_url, e);
+            }
+        }
+    }
+
+    /// Take one sample and record it
+    pub async fn collect(&mut self) -> Result<()> {
+        let metrics = fetch_relay_metrics(&self.client, &self.relay_engine_url).await?;
+        let now = Instant::now();
+        let received = metrics.events.events_received;
+
+        let events_per_second = self
+            .previous
+            .map_or(0.0, |(at, previous)| events_per_second(previous, received, now.duration_since(at)));
+        self.previous = Some((now, received));
+
+        self.analytics.record_metrics(to_realtime_metrics(&metrics, events_per_second)).await
+    }
+}
+
+/// The relay engine's current metrics
+pub async fn fetch_relay_metrics(client: &reqwest::Client, relay_engine_url: &str) -> Result<RelayMetrics> {
+    let url = format!("{}{}", relay_engine_url.trim_end_matches('/'), RELAY_METRICS_PATH);
+    Ok(client.get(url).send().await?.error_for_status()?.json().await?)
+}
+
+/// Events received per second between two samples of the relay's running
+/// total. A total that went down means the relay restarted in between, so
+/// there's no rate to report for that interval.
+pub fn events_per_second(previous: u64, current: u64, elapsed: Duration) -> f64 {
+    if current < previous || elapsed.is_zero() {
+        return 0.0;
+    }
+    (current - previous) as f64 / elapsed.as_secs_f64()
+}
+
+/// The relay engine's metrics as realtime metrics. The relay doesn't report
+/// host resource usage, so memory, CPU and disk are left at zero.
+pub fn to_realtime_metrics(metrics: &RelayMetrics, events_per_second: f64) -> RealtimeMetrics {
+    RealtimeMetrics {
+        active_connections: metrics.relay_status.active_connections,
+        events_per_second,
+        subscriptions_count: metrics.performance.active_subscriptions,
+        memory_usage: 0,
+        cpu_usage: 0.0,
+        disk_usage: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Json, Router};
+    use tokio::net::TcpListener;
+
+    fn relay_metrics(active_connections: u64, events_received: u64, active_subscriptions: u64) -> RelayMetrics {
+        RelayMetrics {
+            relay_status: RelayStatus { active_connections },
+            events: RelayEvents { events_received },
+            performance: RelayPerformance { active_subscriptions },
+        }
+    }
+
+    #[test]
+    fn test_events_per_second() {
+        assert_eq!(events_per_second(100, 150, Duration::from_secs(10)), 5.0);
+        assert_eq!(events_per_second(100, 100, Duration::from_secs(10)), 0.0);
+        // The relay restarted and its counters began again
+        assert_eq!(events_per_second(100, 20, Duration::from_secs(10)), 0.0);
+        assert_eq!(events_per_second(100, 150, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_to_realtime_metrics() {
+        let realtime = to_realtime_metrics(&relay_metrics(12, 500, 34), 2.5);
+
+        assert_eq!(realtime.active_connections, 12);
+        assert_eq!(realtime.events_per_second, 2.5);
+        assert_eq!(realtime.subscriptions_count, 34);
+        assert_eq!(realtime.memory_usage, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_relay_metrics() {
+        // A trimmed-down relay metrics document; fields the collector doesn't read are skipped
+        let document = serde_json::json!({
+            "relay_status": { "instance": null, "active_connections": 3, "total_connections": 9, "uptime_seconds": 60, "status": "healthy" },
+            "events": { "events_received": 42, "events_stored": 40, "events_rejected": 2, "avg_processing_time_ms": 1.5, "by_kind": {} },
+            "performance": { "queries_received": 0, "active_subscriptions": 7, "rate_limited_events": 0 },
+        });
+        let app = Router::new().route(RELAY_METRICS_PATH, get(move || async move { Json(document) }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+        let metrics = fetch_relay_metrics(&client, &format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(metrics.relay_status.active_connections, 3);
+        assert_eq!(metrics.events.events_received, 42);
+        assert_eq!(metrics.performance.active_subscriptions, 7);
+
+        // Anything but a metrics document is an error, not zeroed metrics
+        assert!(fetch_relay_metrics(&client, &format!("http://{}/elsewhere", addr)).await.is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{Executor, PgPool, Row};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::TrafficEvent;
+
+/// Header carrying the hex HMAC-SHA256 of the request body, keyed by the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Per-request timeout for webhook deliveries
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Retries after the first failed attempt, with exponential backoff
+pub const MAX_RETRIES: u32 = 3;
+
+/// How long the registered webhooks are served from memory before being
+/// re-read, so other instances' changes are picked up
+pub const WEBHOOK_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// An operator-registered callback for traffic events of the given types
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookConfig {
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    /// Never returned by the API once stored
+    #[serde(skip_serializing)]
+    #[schema(write_only)]
+    pub secret: String,
+}
+
+impl WebhookConfig {
+    pub fn matches(&self, event_type: &str) -> bool {
+        self.event_types.iter().any(|t| t == event_type || t == "*")
+    }
+}
+
+/// One attempt to deliver an event to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub webhook_id: Uuid,
+    pub event_id: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Hex-encoded HMAC-SHA256 of `body` keyed by `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Delay before retry `attempt` (1-based): 1s, 2s, 4s, ...
+pub fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << (attempt - 1))
+}
+
+/// Refuse webhook URLs that aren't http(s) or whose host resolves to a
+/// loopback, private, link-local or otherwise internal address, so webhooks
+/// can't be pointed at services only the analytics host can reach
+pub async fn check_target(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme: {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    // IPv6 literals come bracketed from `Url`
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("failed to resolve {}: {}", host, e))?;
+    for addr in addrs {
+        if !is_public(addr.ip()) {
+            return Err(format!("{} resolves to non-public address {}", host, addr.ip()));
+        }
+    }
+    Ok(())
+}
+
+// Whether `ip` is routable on the public internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // 0.0.0.0/8, carrier-grade NAT 100.64.0.0/10 and the reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Stores webhook configurations and delivers matching traffic events to them
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    pool: PgPool,
+    client: reqwest::Client,
+    // The registered webhooks as last read, and when, so dispatching every
+    // traffic event doesn't query the table
+    cache: Arc<RwLock<Option<(Instant, Vec<WebhookConfig>)>>>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: PgPool) -> Result<Self> {
+        // Redirects could lead a delivery past `check_target` to an internal address
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            pool,
+            client,
+            cache: Arc::new(RwLock::new(None)),
+        })
+    }
+
+    pub async fn init_tables(&self) -> Result<()> {
+        // Executing the raw string allows several statements in one call
+        self.pool
+            .execute(
+                r#"
+            CREATE TABLE IF NOT EXISTS webhooks (
+                id UUID PRIMARY KEY,
+                url TEXT NOT NULL,
+                event_types TEXT[] NOT NULL,
+                secret TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGSERIAL PRIMARY KEY,
+                webhook_id UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+                event_id VARCHAR NOT NULL,
+                attempt INTEGER NOT NULL,
+                status_code INTEGER,
+                error TEXT,
+                attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, attempted_at);
+            "#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create(&self, webhook: &WebhookConfig) -> Result<()> {
+        sqlx::query("INSERT INTO webhooks (id, url, event_types, secret) VALUES ($1, $2, $3, $4)")
+            .bind(webhook.id)
+            .bind(&webhook.url)
+            .bind(&webhook.event_types)
+            .bind(&webhook.secret)
+            .execute(&self.pool)
+            .await?;
+        self.invalidate().await;
+        Ok(())
+    }
+
+    /// The registered webhooks, read from the table at most once per `WEBHOOK_CACHE_TTL`
+    pub async fn list(&self) -> Result<Vec<WebhookConfig>> {
+        if let Some((read_at, webhooks)) = &*self.cache.read().await {
+            if read_at.elapsed() < WEBHOOK_CACHE_TTL {
+                return Ok(webhooks.clone());
+            }
+        }
+
+        let rows = sqlx::query("SELECT id, url, event_types, secret FROM webhooks ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+        let webhooks: Vec<WebhookConfig> = rows.iter().map(Self::from_row).collect();
+        *self.cache.write().await = Some((Instant::now(), webhooks.clone()));
+        Ok(webhooks)
+    }
+
+    async fn invalidate(&self) {
+        *self.cache.write().await = None;
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<WebhookConfig>> {
+        let row = sqlx::query("SELECT id, url, event_types, secret FROM webhooks WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.as_ref().map(Self::from_row))
+    }
+
+    /// Returns whether the webhook existed
+    pub async fn update(&self, webhook: &WebhookConfig) -> Result<bool> {
+        let result = sqlx::query("UPDATE webhooks SET url = $2, event_types = $3, secret = $4 WHERE id = $1")
+            .bind(webhook.id)
+            .bind(&webhook.url)
+            .bind(&webhook.event_types)
+            .bind(&webhook.secret)
+            .execute(&self.pool)
+            .await?;
+        self.invalidate().await;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns whether the webhook existed
+    pub async fn delete(&self, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        self.invalidate().await;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn deliveries(&self, webhook_id: Uuid) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query(
+            "SELECT webhook_id, event_id, attempt, status_code, error, attempted_at \
+             FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY attempted_at DESC LIMIT 100",
+        )
+        .bind(webhook_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| WebhookDelivery {
+                webhook_id: row.get("webhook_id"),
+                event_id: row.get("event_id"),
+                attempt: row.get("attempt"),
+                status_code: row.get("status_code"),
+                error: row.get("error"),
+                attempted_at: row.get("attempted_at"),
+            })
+            .collect())
+    }
+
+    /// Deliver `event` to every webhook subscribed to its type. Each delivery
+    /// runs in its own task so a slow endpoint never holds up recording.
+    pub async fn dispatch(&self, event: &TrafficEvent) -> Result<()> {
+        let webhooks: Vec<WebhookConfig> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|webhook| webhook.matches(&event.event_type))
+            .collect();
+        if webhooks.is_empty() {
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(event)?;
+        for webhook in webhooks {
+            let dispatcher = self.clone();
+            let body = body.clone();
+            let event_id = event.event_id.clone();
+            tokio::spawn(async move {
+                dispatcher.deliver(&webhook, &event_id, body).await;
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn deliver(&self, webhook: &WebhookConfig, event_id: &str, body: Vec<u8>) {
+        let signature = sign(&webhook.secret, &body);
+
+        for attempt in 1..=MAX_RETRIES + 1 {
+            if attempt > 1 {
+                tokio::time::sleep(backoff(attempt - 1)).await;
+            }
+
+            // Checked again on every attempt, since the host may resolve elsewhere by now
+            if let Err(e) = check_target(&webhook.url).await {
+                self.log_delivery(webhook.id, event_id, attempt, None, Some(&e)).await;
+                warn!("Not delivering event {} to webhook {}: {}", event_id, webhook.id, e);
+                return;
+            }
+
+            let result = self
+                .client
+                .post(&webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status_code, error) = match &result {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16() as i32), None),
+                Ok(response) => (Some(response.status().as_u16() as i32), Some(format!("HTTP {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            self.log_delivery(webhook.id, event_id, attempt, status_code, error.as_deref()).await;
+
+            match error {
+                None => {
+                    info!("Delivered event {} to webhook {}", event_id, webhook.id);
+                    return;
+                }
+                Some(e) => warn!("Webhook {} attempt {} for event {} failed: {}", webhook.id, attempt, event_id, e),
+            }
+        }
+
+        warn!("Giving up on delivering event {} to webhook {}", event_id, webhook.id);
+    }
+
+    async fn log_delivery(&self, webhook_id: Uuid, event_id: &str, attempt: u32, status_code: Option<i32>, error: Option<&str>) {
+        let result = sqlx::query(
+            "INSERT INTO webhook_deliveries (webhook_id, event_id, attempt, status_code, error) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(webhook_id)
+        .bind(event_id)
+        .bind(attempt as i32)
+        .bind(status_code)
+        .bind(error)
+        .execute(&self.pool)
+        .await;
+
+        if let Err(e) = result {
+            warn!("Failed to log delivery to webhook {}: {}", webhook_id, e);
+        }
+    }
+
+    fn from_row(row: &sqlx::postgres::PgRow) -> WebhookConfig {
+        WebhookConfig {
+            id: row.get("id"),
+            url: row.get("url"),
+            event_types: row.get("event_types"),
+            secret: row.get("secret"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_matches_known_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let delays: Vec<_> = (1..=MAX_RETRIES).map(backoff).collect();
+        assert_eq!(delays, vec![Duration::from_secs(1), Duration::from_secs(2), Duration::from_secs(4)]);
+    }
+
+    #[test]
+    fn test_webhook_matches_event_types() {
+        let webhook = WebhookConfig {
+            id: Uuid::new_v4(),
+            url: "https://example.com/hook".to_string(),
+            event_types: vec!["event_rejected".to_string()],
+            secret: "secret".to_string(),
+        };
+        assert!(webhook.matches("event_rejected"));
+        assert!(!webhook.matches("connection_opened"));
+
+        // The secret is write-only
+        let json = serde_json::to_value(&webhook).unwrap();
+        assert!(json.get("secret").is_none());
+    }
+
+    #[test]
+    fn test_internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0", "100.64.0.1",
+            "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} counted as public", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public(ip.parse().unwrap()), "{} counted as internal", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_target_refuses_internal_urls() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://[::1]/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "ftp://example.com/hook",
+            "not a url",
+        ] {
+            assert!(check_target(url).await.is_err(), "{} was accepted", url);
+        }
+        assert!(check_target("https://93.184.216.34/hook").await.is_ok());
+    }
+}
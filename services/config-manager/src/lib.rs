@@ -10,21 +10,26 @@ pub use environment::Environment;
 pub use loader::ConfigLoader;
 pub use error::{ConfigError, ConfigResult};
 
-/// Load configuration from multiple sources with environment override
+/// Load configuration from multiple sources with environment override, and
+/// refuse it if any setting is invalid
 pub fn load_config() -> ConfigResult<AppConfig> {
-    ConfigLoader::new()
+    let config = ConfigLoader::new()
         .add_file("config/default.toml")?
         .add_env_file()?
         .add_environment_variables()?
-        .load()
+        .load()?;
+    validation::validate(&config)?;
+    Ok(config)
 }
 
 /// Load configuration for specific environment
 pub fn load_config_for_env(env: Environment) -> ConfigResult<AppConfig> {
-    ConfigLoader::new()
+    let config = ConfigLoader::new()
         .add_file("config/default.toml")?
         .add_file(&format!("config/{}.toml", env.as_str()))?
         .add_env_file()?
         .add_environment_variables()?
-        .load()
+        .load()?;
+    validation::validate(&config)?;
+    Ok(config)
 }
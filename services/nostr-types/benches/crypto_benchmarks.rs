@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use pleb_one_nostr_types::{
+    batch_verify_signatures, crypto::sha256_hash, generate_keypair, sign_event, verify_signature,
+    Event, EventBuilder, PublicKey, Signature,
+};
+
+fn signed_events(count: usize) -> Vec<(Vec<u8>, Event)> {
+    (0..count)
+        .map(|i| {
+            let (private_key, public_key) = generate_keypair();
+            let unsigned = EventBuilder::new()
+                .pubkey(public_key)
+                .kind(1)
+                .content(format!("Benchmark note {}", i))
+                .created_at(1672531200 + i as i64)
+                .build_unsigned()
+                .unwrap();
+            let event = sign_event(&unsigned, &private_key.as_bytes()).unwrap();
+            (sha256_hash(event.to_canonical_json().as_bytes()), event)
+        })
+        .collect()
+}
+
+fn bench_signature_verification(c: &mut Criterion) {
+    let mut group = c.benchmark_group("signature_verification");
+
+    for count in [10, 100, 1000] {
+        let events = signed_events(count);
+        let items: Vec<(&[u8], &PublicKey, &Signature)> = events
+            .iter()
+            .map(|(hash, event)| (hash.as_slice(), &event.pubkey, &event.sig))
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("sequential", count), &items, |b, items| {
+            b.iter(|| {
+                for (hash, pubkey, sig) in items {
+                    black_box(verify_signature(hash, pubkey, sig).unwrap());
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("batch", count), &items, |b, items| {
+            b.iter(|| black_box(batch_verify_signatures(items).unwrap()))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_signature_verification);
+criterion_main!(benches);
@@ -1,7 +1,13 @@
 use crate::error::NostrError;
-use secp256k1::{Secp256k1, Message, Signature as Secp256k1Signature};
+#[cfg(feature = "crypto-signing")]
+use crate::event::{Event, UnsignedEvent};
+use secp256k1::{Secp256k1, Message, schnorr::Signature as Secp256k1Signature};
+#[cfg(feature = "crypto-signing")]
+use secp256k1::Keypair;
 use sha2::{Sha256, Digest};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "crypto-signing")]
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -10,6 +16,11 @@ pub struct PublicKey(String);
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Signature(String);
 
+/// Hex-encoded secp256k1 secret key
+#[cfg(feature = "crypto-signing")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKey(String);
+
 impl PublicKey {
     pub fn new(hex: String) -> Result<Self, NostrError> {
         if hex.len() != 64 {
@@ -74,6 +85,73 @@ impl FromStr for Signature {
     }
 }
 
+#[cfg(feature = "crypto-signing")]
+impl PrivateKey {
+    pub fn from_hex(hex: &str) -> Result<Self, NostrError> {
+        let bytes = hex::decode(hex).map_err(|_| {
+            NostrError::CryptoError("Invalid private key hex encoding".to_string())
+        })?;
+        secp256k1::SecretKey::from_slice(&bytes)
+            .map_err(|e| NostrError::CryptoError(format!("Invalid private key: {}", e)))?;
+
+        Ok(PrivateKey(hex.to_lowercase()))
+    }
+
+    pub fn as_hex(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(&self.0, &mut bytes).expect("validated in from_hex");
+        bytes
+    }
+}
+
+// Keep secret keys out of logs
+#[cfg(feature = "crypto-signing")]
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PrivateKey(..)")
+    }
+}
+
+/// Generate a random keypair
+#[cfg(feature = "crypto-signing")]
+pub fn generate_keypair() -> (PrivateKey, PublicKey) {
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let (x_only, _) = public_key.x_only_public_key();
+
+    (
+        PrivateKey(hex::encode(secret_key.secret_bytes())),
+        PublicKey(hex::encode(x_only.serialize())),
+    )
+}
+
+/// Sign an event with the private key matching its pubkey: the event ID is the
+/// SHA256 hash of its canonical JSON, signed with BIP-340 Schnorr
+#[cfg(feature = "crypto-signing")]
+pub fn sign_event(unsigned: &UnsignedEvent, private_key: &[u8; 32]) -> Result<Event, NostrError> {
+    let secp = Secp256k1::signing_only();
+    let keypair = Keypair::from_seckey_slice(&secp, private_key)
+        .map_err(|e| NostrError::CryptoError(format!("Invalid private key: {}", e)))?;
+
+    let (x_only, _) = keypair.x_only_public_key();
+    if hex::encode(x_only.serialize()) != unsigned.pubkey.as_hex().to_lowercase() {
+        return Err(NostrError::CryptoError(
+            "Private key does not match the event pubkey".to_string()
+        ));
+    }
+
+    let hash = sha256_hash(unsigned.to_canonical_json().as_bytes());
+    let message = Message::from_digest_slice(&hash)
+        .map_err(|e| NostrError::CryptoError(format!("Invalid message hash: {}", e)))?;
+    let signature = secp.sign_schnorr_with_rng(&message, &keypair, &mut rand::thread_rng());
+
+    Ok(unsigned.clone().sign(Signature(hex::encode(signature.as_ref()))))
+}
+
 /// Verify a Schnorr signature for a message
 pub fn verify_signature(
     message_hash: &[u8],
@@ -81,7 +159,41 @@ pub fn verify_signature(
     signature: &Signature,
 ) -> Result<bool, NostrError> {
     let secp = Secp256k1::verification_only();
-    
+    let (sig, message, pubkey) = parse_signed_message(message_hash, public_key, signature)?;
+
+    Ok(secp.verify_schnorr(&sig, &message, &pubkey).is_ok())
+}
+
+/// Verify a batch of Schnorr signatures, one result per `(message_hash,
+/// public_key, signature)` item in order. Fails if any item is malformed,
+/// before anything is verified.
+///
+/// secp256k1 0.28 exposes no batch verification API, so the signatures are
+/// checked one by one against a single shared verification context; this
+/// still saves the per-call context setup of `verify_signature`.
+pub fn batch_verify_signatures(
+    items: &[(&[u8], &PublicKey, &Signature)],
+) -> Result<Vec<bool>, NostrError> {
+    let parsed = items
+        .iter()
+        .map(|(message_hash, public_key, signature)| {
+            parse_signed_message(message_hash, public_key, signature)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let secp = Secp256k1::verification_only();
+    Ok(parsed
+        .iter()
+        .map(|(sig, message, pubkey)| secp.verify_schnorr(sig, message, pubkey).is_ok())
+        .collect())
+}
+
+// Decode the signature, message and public key of one verification
+fn parse_signed_message(
+    message_hash: &[u8],
+    public_key: &PublicKey,
+    signature: &Signature,
+) -> Result<(Secp256k1Signature, Message, secp256k1::XOnlyPublicKey), NostrError> {
     // Parse public key
     let pubkey_bytes = public_key.as_bytes()?;
     let pubkey = secp256k1::XOnlyPublicKey::from_slice(&pubkey_bytes)
@@ -93,11 +205,10 @@ pub fn verify_signature(
         .map_err(|e| NostrError::CryptoError(format!("Invalid signature: {}", e)))?;
     
     // Create message
-    let message = Message::from_slice(message_hash)
+    let message = Message::from_digest_slice(message_hash)
         .map_err(|e| NostrError::CryptoError(format!("Invalid message hash: {}", e)))?;
-    
-    // Verify signature
-    Ok(secp.verify_schnorr(&sig, &message, &pubkey).is_ok())
+
+    Ok((sig, message, pubkey))
 }
 
 /// Create SHA256 hash of the given data
@@ -136,4 +247,92 @@ mod tests {
         let invalid_sig = "1234567890abcdef";
         assert!(Signature::new(invalid_sig.to_string()).is_err());
     }
+
+    #[test]
+    fn test_private_key_validation() {
+        let (private_key, _) = generate_keypair();
+        let parsed = PrivateKey::from_hex(private_key.as_hex()).unwrap();
+        assert_eq!(parsed.as_bytes(), private_key.as_bytes());
+        assert_eq!(format!("{:?}", parsed), "PrivateKey(..)");
+
+        // Zero is not a valid secret key
+        assert!(PrivateKey::from_hex(&"00".repeat(32)).is_err());
+        assert!(PrivateKey::from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn test_sign_event_produces_valid_signature() {
+        let (private_key, public_key) = generate_keypair();
+        let unsigned = crate::EventBuilder::new()
+            .pubkey(public_key.clone())
+            .kind(1)
+            .content("Signed locally")
+            .created_at(1672531200)
+            .build_unsigned()
+            .unwrap();
+
+        let event = sign_event(&unsigned, &private_key.as_bytes()).unwrap();
+
+        assert_eq!(event.id, unsigned.id());
+        assert_eq!(event.pubkey, public_key);
+        assert!(event.verify_signature().unwrap());
+    }
+
+    #[test]
+    fn test_sign_event_rejects_mismatched_key() {
+        let (_, public_key) = generate_keypair();
+        let (other_key, _) = generate_keypair();
+        let unsigned = crate::EventBuilder::new()
+            .pubkey(public_key)
+            .kind(1)
+            .content("Wrong key")
+            .build_unsigned()
+            .unwrap();
+
+        assert!(sign_event(&unsigned, &other_key.as_bytes()).is_err());
+    }
+
+    fn signed_note(content: &str) -> Event {
+        let (private_key, public_key) = generate_keypair();
+        let unsigned = crate::EventBuilder::new()
+            .pubkey(public_key)
+            .kind(1)
+            .content(content)
+            .build_unsigned()
+            .unwrap();
+        sign_event(&unsigned, &private_key.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_batch_verify_signatures() {
+        let events: Vec<Event> = (0..3).map(|i| signed_note(&format!("note {}", i))).collect();
+        let hashes: Vec<Vec<u8>> = events
+            .iter()
+            .map(|event| sha256_hash(event.to_canonical_json().as_bytes()))
+            .collect();
+
+        // The second event's signature checked against the third's hash fails
+        let items: Vec<(&[u8], &PublicKey, &Signature)> = vec![
+            (&hashes[0], &events[0].pubkey, &events[0].sig),
+            (&hashes[2], &events[1].pubkey, &events[1].sig),
+            (&hashes[2], &events[2].pubkey, &events[2].sig),
+        ];
+
+        assert_eq!(batch_verify_signatures(&items).unwrap(), vec![true, false, true]);
+        assert!(batch_verify_signatures(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_batch_verify_signatures_rejects_malformed_items() {
+        let event = signed_note("note");
+        let hash = sha256_hash(event.to_canonical_json().as_bytes());
+        let short_hash = [0u8; 16];
+
+        let items: Vec<(&[u8], &PublicKey, &Signature)> = vec![
+            (&hash, &event.pubkey, &event.sig),
+            (&short_hash, &event.pubkey, &event.sig),
+        ];
+
+        assert!(batch_verify_signatures(&items).is_err());
+    }
 }
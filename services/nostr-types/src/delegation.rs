@@ -0,0 +1,240 @@
+use crate::crypto::{sha256_hash, verify_signature, PublicKey, Signature};
+use crate::error::NostrError;
+use crate::event::Event;
+use std::str::FromStr;
+
+/// Delegation conditions (NIP-26), e.g. `kind=1&created_at>1674834236&created_at<1677426236`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conditions {
+    /// Kinds the delegatee may publish; empty allows any kind
+    pub kinds: Vec<u64>,
+    /// Events must be created strictly before this timestamp
+    pub created_before: Option<i64>,
+    /// Events must be created strictly after this timestamp
+    pub created_after: Option<i64>,
+}
+
+impl Conditions {
+    /// Check whether an event with this kind and timestamp is covered by the conditions
+    pub fn allows(&self, kind: u64, created_at: i64) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return false;
+        }
+
+        if let Some(before) = self.created_before {
+            if created_at >= before {
+                return false;
+            }
+        }
+
+        if let Some(after) = self.created_after {
+            if created_at <= after {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl FromStr for Conditions {
+    type Err = NostrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = Conditions::default();
+
+        for condition in s.split('&').filter(|c| !c.is_empty()) {
+            let invalid = || NostrError::InvalidEvent(format!("Invalid delegation condition: {}", condition));
+
+            if let Some(kind) = condition.strip_prefix("kind=") {
+                conditions.kinds.push(kind.parse().map_err(|_| invalid())?);
+            } else if let Some(before) = condition.strip_prefix("created_at<") {
+                conditions.created_before = Some(before.parse().map_err(|_| invalid())?);
+            } else if let Some(after) = condition.strip_prefix("created_at>") {
+                conditions.created_after = Some(after.parse().map_err(|_| invalid())?);
+            } else {
+                return Err(invalid());
+            }
+        }
+
+        Ok(conditions)
+    }
+}
+
+/// A parsed `["delegation", <delegator pubkey>, <conditions>, <token>]` tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delegation {
+    pub delegator: PublicKey,
+    pub conditions: Conditions,
+    pub token: Signature,
+    raw_conditions: String,
+}
+
+impl Delegation {
+    /// Parse the delegation tag of an event. Returns `Ok(None)` when the event
+    /// isn't delegated and an error when the tag is malformed.
+    pub fn from_event(event: &Event) -> Result<Option<Self>, NostrError> {
+        let Some(tag) = event.tags.iter().find(|tag| tag.tag_name() == Some("delegation")) else {
+            return Ok(None);
+        };
+
+        let (Some(delegator), Some(raw_conditions), Some(token)) = (tag.get(1), tag.get(2), tag.get(3)) else {
+            return Err(NostrError::InvalidEvent("Malformed delegation tag".to_string()));
+        };
+
+        Ok(Some(Self {
+            delegator: PublicKey::new(delegator.to_string())?,
+            conditions: raw_conditions.parse()?,
+            token: Signature::new(token.to_string())?,
+            raw_conditions: raw_conditions.to_string(),
+        }))
+    }
+
+    /// Verify that the delegator signed this delegation for the event's author
+    /// and that the event falls within the delegated conditions
+    pub fn verify(&self, event: &Event) -> Result<(), NostrError> {
+        let message = delegation_message(event.pubkey.as_hex(), &self.raw_conditions);
+        if !verify_signature(&sha256_hash(message.as_bytes()), &self.delegator, &self.token)? {
+            return Err(NostrError::InvalidSignature);
+        }
+
+        if !self.conditions.allows(event.kind, event.created_at) {
+            return Err(NostrError::InvalidEvent("Event does not satisfy delegation conditions".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// The delegator of an event whose delegation tag is well formed and
+    /// verifies; `None` for events that aren't delegated or fail verification
+    pub fn verified_delegator(event: &Event) -> Option<PublicKey> {
+        let delegation = Self::from_event(event).ok()??;
+        delegation.verify(event).is_ok().then_some(delegation.delegator)
+    }
+}
+
+/// The string a delegator signs to produce a delegation token
+pub fn delegation_message(delegatee: &str, conditions: &str) -> String {
+    format!("nostr:delegation:{}:{}", delegatee, conditions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{EventBuilder, Tag};
+    use crate::filter::Filter;
+    use secp256k1::{Keypair, Message, Secp256k1, SecretKey};
+
+    // Deterministic test keys derived from a seed
+    fn keypair(seed: &str) -> (Keypair, PublicKey) {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&sha256_hash(seed.as_bytes())).unwrap();
+        let keypair = Keypair::from_secret_key(&secp, &secret_key);
+        let pubkey = pubkey_of(&keypair);
+        (keypair, pubkey)
+    }
+
+    fn pubkey_of(keypair: &Keypair) -> PublicKey {
+        PublicKey::new(hex::encode(keypair.x_only_public_key().0.serialize())).unwrap()
+    }
+
+    fn sign(keypair: &Keypair, data: &[u8]) -> Signature {
+        let secp = Secp256k1::new();
+        let message = Message::from_digest_slice(&sha256_hash(data)).unwrap();
+        let sig = secp.sign_schnorr_no_aux_rand(&message, keypair);
+        Signature::new(hex::encode(sig.as_ref())).unwrap()
+    }
+
+    fn delegated_event(delegator: &Keypair, conditions: &str, kind: u64, created_at: i64) -> Event {
+        let delegator_pubkey = pubkey_of(delegator);
+        let (delegatee, delegatee_pubkey) = keypair("delegatee");
+        let token = sign(delegator, delegation_message(delegatee_pubkey.as_hex(), conditions).as_bytes());
+
+        let unsigned = EventBuilder::new()
+            .pubkey(delegatee_pubkey)
+            .kind(kind)
+            .created_at(created_at)
+            .content("delegated")
+            .tag(Tag::new(vec![
+                "delegation".to_string(),
+                delegator_pubkey.as_hex().to_string(),
+                conditions.to_string(),
+                token.as_hex().to_string(),
+            ]))
+            .build_unsigned()
+            .unwrap();
+        let sig = sign(&delegatee, unsigned.to_canonical_json().as_bytes());
+        unsigned.sign(sig)
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        let conditions: Conditions = "kind=1&created_at<2000&created_at>1000".parse().unwrap();
+        assert_eq!(conditions.kinds, vec![1]);
+        assert_eq!(conditions.created_before, Some(2000));
+        assert_eq!(conditions.created_after, Some(1000));
+
+        assert!(conditions.allows(1, 1500));
+        assert!(!conditions.allows(0, 1500));
+        assert!(!conditions.allows(1, 2000));
+        assert!(!conditions.allows(1, 1000));
+
+        assert!("kind=abc".parse::<Conditions>().is_err());
+        assert!("pubkey=abc".parse::<Conditions>().is_err());
+    }
+
+    #[test]
+    fn test_valid_delegation() {
+        let (delegator, delegator_pubkey) = keypair("delegator");
+        let event = delegated_event(&delegator, "kind=1&created_at>1000", 1, 1500);
+
+        let delegation = Delegation::from_event(&event).unwrap().unwrap();
+        assert_eq!(delegation.delegator, delegator_pubkey);
+        assert!(delegation.verify(&event).is_ok());
+        assert_eq!(event.delegator(), Some(delegator_pubkey.as_hex()));
+        assert_eq!(Delegation::verified_delegator(&event), Some(delegator_pubkey.clone()));
+
+        // Author filters match the delegator as well as the signing key
+        assert!(Filter::new().author(delegator_pubkey.as_hex()).matches(&event));
+        assert!(Filter::new().author(event.pubkey.as_hex()).matches(&event));
+    }
+
+    #[test]
+    fn test_delegation_conditions_are_enforced() {
+        let (delegator, _) = keypair("delegator");
+        let event = delegated_event(&delegator, "kind=1", 7, 1500);
+
+        let delegation = Delegation::from_event(&event).unwrap().unwrap();
+        assert!(delegation.verify(&event).is_err());
+        assert!(!Filter::new().author(delegation.delegator.as_hex()).matches(&event));
+    }
+
+    #[test]
+    fn test_token_from_another_key_is_rejected() {
+        let (delegator, _) = keypair("delegator");
+        let mut event = delegated_event(&delegator, "kind=1", 1, 1500);
+
+        // Claim a different delegator while keeping the original token
+        let (_, impostor) = keypair("impostor");
+        let mut values = event.tags[0].values().to_vec();
+        values[1] = impostor.as_hex().to_string();
+        event.tags[0] = Tag::new(values);
+
+        let delegation = Delegation::from_event(&event).unwrap().unwrap();
+        assert!(matches!(delegation.verify(&event), Err(NostrError::InvalidSignature)));
+
+        // An unverified tag doesn't make the event match the claimed delegator
+        assert_eq!(Delegation::verified_delegator(&event), None);
+        assert!(!Filter::new().author(impostor.as_hex()).matches(&event));
+        assert!(Filter::new().author(event.pubkey.as_hex()).matches(&event));
+    }
+
+    #[test]
+    fn test_malformed_delegation_tag() {
+        let (delegator, _) = keypair("delegator");
+        let mut event = delegated_event(&delegator, "kind=1", 1, 1500);
+        event.tags[0] = Tag::new(vec!["delegation".to_string(), "abc".to_string()]);
+
+        assert!(Delegation::from_event(&event).is_err());
+    }
+}
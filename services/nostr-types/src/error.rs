@@ -38,12 +38,12 @@ pub enum ValidationError {
     #[error("Content too long: {length} chars (max: {max})")]
     ContentTooLong { length: usize, max: usize },
     
-    #[error("Invalid kind: {kind}")]
+    #[error("Invalid kind: {0}")]
     InvalidKind(u64),
     
-    #[error("Missing required field: {field}")]
+    #[error("Missing required field: {0}")]
     MissingField(String),
     
-    #[error("Invalid field format: {field}")]
+    #[error("Invalid field format: {0}")]
     InvalidFieldFormat(String),
 }
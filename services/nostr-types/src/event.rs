@@ -2,7 +2,6 @@ use crate::crypto::{PublicKey, Signature, sha256_hash};
 use crate::error::NostrError;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 /// Event ID type
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -137,6 +136,16 @@ impl Event {
             .collect()
     }
     
+    /// Pubkey named in the NIP-26 'delegation' tag, if any. This is the logical
+    /// author of the event; the tag is only trustworthy once verified with
+    /// `Delegation::verify`.
+    pub fn delegator(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.tag_name() == Some("delegation"))
+            .and_then(|tag| tag.get(1))
+    }
+    
     /// Get all referenced pubkeys from 'p' tags
     pub fn referenced_pubkeys(&self) -> Vec<&str> {
         self.tags
@@ -204,6 +213,36 @@ impl EventBuilder {
         self
     }
     
+    /// NIP-10: mark the event as a reply to `event_id`
+    pub fn reply_to(self, event_id: &str, relay_hint: Option<&str>) -> Self {
+        self.add_tag("e", vec![event_id.to_string(), relay_hint.unwrap_or_default().to_string(), "reply".to_string()])
+    }
+    
+    /// NIP-10: mention `event_id` without replying to it
+    pub fn mention_event(self, event_id: &str) -> Self {
+        self.add_tag("e", vec![event_id.to_string(), String::new(), "mention".to_string()])
+    }
+    
+    /// Reference a hex pubkey, e.g. to mention or notify its owner
+    pub fn tag_pubkey(self, pubkey: &str, relay_hint: Option<&str>) -> Self {
+        self.add_tag("p", vec![pubkey.to_string(), relay_hint.unwrap_or_default().to_string()])
+    }
+    
+    /// NIP-40: unix timestamp after which relays may drop the event
+    pub fn expiration(self, timestamp: i64) -> Self {
+        self.add_tag("expiration", vec![timestamp.to_string()])
+    }
+    
+    /// NIP-14: subject line of a text note
+    pub fn subject(self, text: &str) -> Self {
+        self.add_tag("subject", vec![text.to_string()])
+    }
+    
+    /// Identifier of a parameterized replaceable event
+    pub fn d_tag(self, value: &str) -> Self {
+        self.add_tag("d", vec![value.to_string()])
+    }
+    
     /// Build the event (requires external signing)
     pub fn build_unsigned(self) -> Result<UnsignedEvent, NostrError> {
         let pubkey = self.pubkey.ok_or_else(|| {
@@ -265,7 +304,7 @@ impl UnsignedEvent {
         EventId(hex::encode(hash))
     }
     
-    /// Sign the event (signature implementation would be external)
+    /// Attach a signature made elsewhere; see `crypto::sign_event` to sign with a private key
     pub fn sign(self, signature: Signature) -> Event {
         let id = self.id();
         Event {
@@ -317,4 +356,62 @@ mod tests {
         let id = unsigned.id();
         assert_eq!(id.as_hex().len(), 64);
     }
+    
+    #[test]
+    fn test_tag_constructors() {
+        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        let parent = "a".repeat(64);
+        let quoted = "b".repeat(64);
+        let author = "c".repeat(64);
+        let other = "d".repeat(64);
+        
+        let unsigned = EventBuilder::new()
+            .pubkey(pubkey)
+            .kind(1)
+            .content("Replying")
+            .reply_to(&parent, Some("wss://relay.example"))
+            .mention_event(&quoted)
+            .tag_pubkey(&author, Some("wss://relay.example"))
+            .tag_pubkey(&other, None)
+            .expiration(1672617600)
+            .subject("Re: plebs")
+            .build_unsigned()
+            .unwrap();
+        
+        let tag = |values: &[&str]| Tag::new(values.iter().map(|value| value.to_string()).collect());
+        assert_eq!(
+            unsigned.tags,
+            vec![
+                tag(&["e", &parent, "wss://relay.example", "reply"]),
+                tag(&["e", &quoted, "", "mention"]),
+                tag(&["p", &author, "wss://relay.example"]),
+                tag(&["p", &other, ""]),
+                tag(&["expiration", "1672617600"]),
+                tag(&["subject", "Re: plebs"]),
+            ]
+        );
+        
+        let event = unsigned.sign(Signature::new("0".repeat(128)).unwrap());
+        assert_eq!(event.referenced_events(), vec![parent.as_str(), quoted.as_str()]);
+        assert_eq!(event.referenced_pubkeys(), vec![author.as_str(), other.as_str()]);
+    }
+    
+    #[test]
+    fn test_d_tag_constructor() {
+        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        
+        let unsigned = EventBuilder::new()
+            .pubkey(pubkey)
+            .kind(30023)
+            .content("Long-form article")
+            .d_tag("my-article")
+            .build_unsigned()
+            .unwrap();
+        
+        assert_eq!(unsigned.tags, vec![Tag::new(vec!["d".to_string(), "my-article".to_string()])]);
+        
+        let event = unsigned.sign(Signature::new("0".repeat(128)).unwrap());
+        assert!(event.is_parameterized_replaceable());
+        assert_eq!(event.d_tag(), Some("my-article"));
+    }
 }
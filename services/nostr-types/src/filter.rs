@@ -1,3 +1,4 @@
+use crate::delegation::Delegation;
 use crate::event::Event;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -49,6 +50,10 @@ pub struct Filter {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<u64>,
     
+    /// NIP-50 search query, matched as a case-insensitive substring of content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    
     #[serde(flatten)]
     pub tags: HashMap<String, Vec<String>>,
 }
@@ -62,22 +67,27 @@ impl Filter {
             since: None,
             until: None,
             limit: None,
+            search: None,
             tags: HashMap::new(),
         }
     }
     
     /// Check if an event matches this filter
     pub fn matches(&self, event: &Event) -> bool {
-        // Check IDs
+        // Check IDs; values may be hex prefixes (NIP-01)
         if let Some(ref ids) = self.ids {
-            if !ids.contains(&event.id.as_hex().to_string()) {
+            if !ids.iter().any(|id| event.id.as_hex().starts_with(id.as_str())) {
                 return false;
             }
         }
         
-        // Check authors
+        // Check authors, also by prefix; delegated events (NIP-26) also match
+        // their delegator once the delegation token verifies
         if let Some(ref authors) = self.authors {
-            if !authors.contains(&event.pubkey.as_hex().to_string()) {
+            let matches_prefix = |pubkey: &str| authors.iter().any(|author| pubkey.starts_with(author.as_str()));
+            let matches_author = matches_prefix(event.pubkey.as_hex())
+                || Delegation::verified_delegator(event).is_some_and(|delegator| matches_prefix(delegator.as_hex()));
+            if !matches_author {
                 return false;
             }
         }
@@ -102,11 +112,23 @@ impl Filter {
             }
         }
         
-        // Check tag filters
-        for (tag_name, values) in &self.tags {
+        // NIP-50: case-insensitive substring match on content
+        if let Some(ref search) = self.search {
+            if !event.content.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+        
+        // Check tag filters. Keys are "#<letter>" and match the first value
+        // of tags with that name; anything else isn't a tag query (the SQL
+        // query builder skips those keys too).
+        for (key, values) in &self.tags {
+            let Some(tag_name) = key.strip_prefix('#').filter(|name| name.chars().count() == 1) else {
+                continue;
+            };
             let matches_tag = event.tags.iter().any(|tag| {
-                tag.tag_name() == Some(tag_name) && 
-                tag.values().iter().skip(1).any(|v| values.contains(v))
+                tag.tag_name() == Some(tag_name) &&
+                tag.get(1).is_some_and(|v| values.iter().any(|value| value == v))
             });
             
             if !matches_tag {
@@ -185,6 +207,12 @@ impl Filter {
         self
     }
     
+    /// Set a NIP-50 search query
+    pub fn search<S: Into<String>>(mut self, query: S) -> Self {
+        self.search = Some(query.into());
+        self
+    }
+    
     /// Add a tag filter
     pub fn tag<S1, S2>(mut self, tag_name: S1, value: S2) -> Self 
     where
@@ -193,7 +221,7 @@ impl Filter {
     {
         self.tags
             .entry(format!("#{}", tag_name.into()))
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(value.into());
         self
     }
@@ -340,7 +368,7 @@ impl RelayMessage {
 mod tests {
     use super::*;
     use crate::crypto::PublicKey;
-    use crate::event::EventBuilder;
+    use crate::event::{EventBuilder, Tag};
     
     #[test]
     fn test_filter_matching() {
@@ -377,6 +405,140 @@ mod tests {
         assert!(!filter.matches(&event));
     }
     
+    #[test]
+    fn test_search_filter() {
+        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        let other = PublicKey::new("abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890".to_string()).unwrap();
+        
+        let unsigned = EventBuilder::new()
+            .pubkey(pubkey.clone())
+            .kind(1)
+            .content("Running a Nostr relay in Rust")
+            .created_at(1672531200)
+            .build_unsigned()
+            .unwrap();
+        let sig = crate::crypto::Signature::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        let event = unsigned.sign(sig);
+        
+        // Matching ignores case
+        assert!(Filter::new().search("nostr relay").matches(&event));
+        assert!(Filter::new().search("RUST").matches(&event));
+        assert!(!Filter::new().search("bitcoin").matches(&event));
+        
+        // Search combines with the other conditions
+        assert!(Filter::new().search("relay").kind(1).author(pubkey.as_hex()).matches(&event));
+        assert!(!Filter::new().search("relay").kind(7).matches(&event));
+        assert!(!Filter::new().search("relay").author(other.as_hex()).matches(&event));
+        
+        // The search field round-trips through JSON
+        let json = serde_json::to_string(&Filter::new().search("relay").kind(1)).unwrap();
+        assert!(json.contains("\"search\":\"relay\""));
+        let parsed: Filter = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.search.as_deref(), Some("relay"));
+        assert!(parsed.tags.is_empty());
+    }
+    
+    #[test]
+    fn test_prefix_matching() {
+        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        let sig = crate::crypto::Signature::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        
+        for created_at in 1672531200..1672531220 {
+            let event = EventBuilder::new()
+                .pubkey(pubkey.clone())
+                .kind(1)
+                .content("Prefix test")
+                .created_at(created_at)
+                .build_unsigned()
+                .unwrap()
+                .sign(sig.clone());
+            let id = event.id.as_hex().to_string();
+            
+            // Every prefix of the ID and pubkey matches, up to the full value
+            for len in 1..=64 {
+                assert!(Filter::new().id(&id[..len]).matches(&event), "id prefix of length {}", len);
+                assert!(Filter::new().author(&pubkey.as_hex()[..len]).matches(&event), "author prefix of length {}", len);
+            }
+            
+            // Changing the last character of a prefix stops it matching
+            for len in 1..=64 {
+                let mut other = id[..len].to_string();
+                let last = other.pop().unwrap();
+                other.push(if last == '0' { '1' } else { '0' });
+                assert!(!Filter::new().id(other).matches(&event));
+            }
+            
+            // Any one matching prefix in the list is enough
+            assert!(Filter::new().ids(["ffff", &id[..8]]).matches(&event));
+        }
+    }
+    
+    fn tagged_event(tags: Vec<Tag>) -> Event {
+        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        let sig = crate::crypto::Signature::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        
+        tags.into_iter()
+            .fold(EventBuilder::new().pubkey(pubkey).kind(1).content("Tagged").created_at(1672531200), |builder, tag| builder.tag(tag))
+            .build_unsigned()
+            .unwrap()
+            .sign(sig)
+    }
+    
+    #[test]
+    fn test_tag_filter_matching() {
+        let referenced = "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890";
+        let event = tagged_event(vec![
+            Tag::new(vec!["e".to_string(), referenced.to_string(), "wss://relay.example.com".to_string()]),
+            Tag::new(vec!["t".to_string(), "nostr".to_string()]),
+        ]);
+        
+        // Filter::tag stores the key as "#e"; it has to match the "e" tag
+        assert!(Filter::new().tag("e", referenced).matches(&event));
+        assert!(Filter::new().tag("t", "nostr").tag("e", referenced).matches(&event));
+        assert!(!Filter::new().tag("e", "ffff").matches(&event));
+        assert!(!Filter::new().tag("p", referenced).matches(&event));
+        
+        // Only the first value of a tag is indexed
+        assert!(!Filter::new().tag("e", "wss://relay.example.com").matches(&event));
+        
+        // The same filter parsed from the wire format
+        let parsed: Filter = serde_json::from_str(&format!(r##"{{"#e":["{}"]}}"##, referenced)).unwrap();
+        assert!(parsed.matches(&event));
+    }
+    
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+        
+        proptest! {
+            #[test]
+            fn tag_filter_matches_its_own_tag(name in "[a-zA-Z]", value in "[a-z0-9]{1,16}") {
+                let event = tagged_event(vec![Tag::new(vec![name.clone(), value.clone()])]);
+                prop_assert!(Filter::new().tag(name.as_str(), value.as_str()).matches(&event));
+            }
+            
+            #[test]
+            fn tag_filter_requires_name_and_first_value(
+                name in "[a-zA-Z]",
+                other_name in "[a-zA-Z]",
+                value in "[a-z0-9]{1,16}",
+                other_value in "[a-z0-9]{1,16}",
+            ) {
+                let event = tagged_event(vec![Tag::new(vec![name.clone(), value.clone(), other_value.clone()])]);
+                let filter = Filter::new().tag(other_name.as_str(), other_value.as_str());
+                prop_assert_eq!(filter.matches(&event), other_name == name && other_value == value);
+            }
+            
+            #[test]
+            fn tag_filter_survives_json_round_trip(name in "[a-zA-Z]", value in "[a-z0-9]{1,16}") {
+                let event = tagged_event(vec![Tag::new(vec![name.clone(), value.clone()])]);
+                let filter = Filter::new().tag(name.as_str(), value.as_str());
+                let parsed: Filter = serde_json::from_str(&serde_json::to_string(&filter).unwrap()).unwrap();
+                prop_assert_eq!(parsed.matches(&event), filter.matches(&event));
+            }
+        }
+    }
+    
     #[test]
     fn test_message_serialization() {
         let subscription_id = SubscriptionId::new("test-sub");
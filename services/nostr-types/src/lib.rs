@@ -4,13 +4,17 @@ pub mod message;
 pub mod error;
 pub mod crypto;
 pub mod validation;
+pub mod delegation;
 
 // Re-export commonly used types
 pub use event::{Event, EventId, EventBuilder};
 pub use filter::Filter;
 pub use message::{ClientMessage, RelayMessage, SubscriptionId};
 pub use error::{NostrError, ValidationError};
-pub use crypto::{PublicKey, Signature, verify_signature};
+pub use crypto::{PublicKey, Signature, verify_signature, batch_verify_signatures};
+#[cfg(feature = "crypto-signing")]
+pub use crypto::{PrivateKey, generate_keypair, sign_event};
+pub use delegation::{Conditions, Delegation};
 
 /// Nostr protocol constants
 pub mod constants {
@@ -28,4 +32,7 @@ pub mod constants {
     
     /// Maximum number of active subscriptions per connection
     pub const MAX_SUBSCRIPTIONS_PER_CONNECTION: usize = 20;
+    
+    /// Shortest ID or author prefix accepted in filters (NIP-11 `min_prefix`)
+    pub const MIN_PREFIX_LENGTH: usize = 4;
 }
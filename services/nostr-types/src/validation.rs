@@ -15,7 +15,7 @@ impl Validate for Event {
     fn validate(&self) -> Result<(), Self::Error> {
         // Validate event size
         let json_size = serde_json::to_string(self)
-            .map_err(|e| NostrError::InvalidJson(e))?
+            .map_err(NostrError::InvalidJson)?
             .len();
         
         if json_size > MAX_EVENT_SIZE {
@@ -244,6 +244,7 @@ impl FilterValidator {
                     "Too many IDs in filter".to_string()
                 ));
             }
+            Self::validate_prefixes("ids", ids)?;
         }
         
         if let Some(ref authors) = filter.authors {
@@ -252,6 +253,7 @@ impl FilterValidator {
                     "Too many authors in filter".to_string()
                 ));
             }
+            Self::validate_prefixes("authors", authors)?;
         }
         
         if let Some(ref kinds) = filter.kinds {
@@ -264,18 +266,30 @@ impl FilterValidator {
         
         Ok(())
     }
+    
+    // Short prefixes match a large share of all events, so they're refused
+    fn validate_prefixes(field: &str, values: &[String]) -> Result<(), ValidationError> {
+        if values.iter().any(|value| value.len() < MIN_PREFIX_LENGTH) {
+            return Err(ValidationError::InvalidFieldFormat(format!(
+                "{} prefixes must be at least {} characters",
+                field, MIN_PREFIX_LENGTH
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::{PublicKey, Signature};
+    use crate::crypto::{generate_keypair, Signature};
     use crate::event::EventBuilder;
     use crate::filter::Filter;
     
     #[test]
     fn test_event_validation() {
-        let pubkey = PublicKey::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
+        // A real key, so only the mock signature fails verification
+        let (_, pubkey) = generate_keypair();
         let sig = Signature::new("1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef".to_string()).unwrap();
         
         let unsigned = EventBuilder::new()
@@ -297,13 +311,14 @@ mod tests {
             Ok(_) => panic!("Expected validation to fail with invalid signature"),
         }
         
-        // Test content too long
+        // Test content too long; the serialized event outgrows MAX_EVENT_SIZE
+        // first, since both limits are 64KB
         event.content = "x".repeat(MAX_CONTENT_LENGTH + 1);
         match event.validate() {
-            Err(NostrError::ValidationError(ValidationError::ContentTooLong { .. })) => {
+            Err(NostrError::ValidationError(ValidationError::EventTooLarge { .. })) => {
                 // Expected
             },
-            _ => panic!("Expected content too long error"),
+            _ => panic!("Expected event too large error"),
         }
     }
     
@@ -320,5 +335,14 @@ mod tests {
         // Too many filters
         let filters = vec![Filter::new(); 15];
         assert!(FilterValidator::validate_subscription_filters(&filters).is_err());
+        
+        // ID and author prefixes must be at least MIN_PREFIX_LENGTH characters
+        for len in 0..=64 {
+            let prefix = "a".repeat(len);
+            let ids = Filter::new().id(prefix.clone());
+            let authors = Filter::new().author(prefix);
+            assert_eq!(FilterValidator::validate_subscription_filters(&[ids]).is_ok(), len >= MIN_PREFIX_LENGTH);
+            assert_eq!(FilterValidator::validate_subscription_filters(&[authors]).is_ok(), len >= MIN_PREFIX_LENGTH);
+        }
     }
 }
@@ -0,0 +1,601 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{
+        header::{CONTENT_TYPE, TRANSFER_ENCODING},
+        HeaderMap, StatusCode,
+    },
+    middleware,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
+    Router,
+};
+use futures_util::StreamExt;
+use nostr::{Event, EventId, Filter, JsonUtil, Kind, PublicKey, RelayMessage, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, net::IpAddr, time::UNIX_EPOCH};
+use tracing::{debug, error, info};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{app_state::AppState, database::{RelayDatabase, SlowQuery}, hooks, relay_list, subscription, validation::{self, UrlViolation}};
+
+pub mod auth;
+
+/// Media type of event exports and imports: one JSON event per line
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Most per-line failures listed in an import response
+pub const MAX_IMPORT_ERRORS: usize = 100;
+
+/// NOTICE sent to a client an operator disconnects
+pub const DISCONNECTED_BY_OPERATOR: &str = "disconnected by operator";
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BlocklistResponse {
+    pub pubkeys: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BlockPubkeyRequest {
+    pub pubkey: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletePubkeyEventsResponse {
+    pub deleted: u64,
+    pub blocked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConnectionInfo {
+    pub connection_id: String,
+    #[schema(value_type = String)]
+    pub peer_addr: IpAddr,
+    pub user_agent: Option<String>,
+    pub origin: Option<String>,
+    /// Unix timestamp of the WebSocket upgrade
+    pub connected_at: u64,
+    /// Seconds since the WebSocket upgrade
+    pub session_duration_secs: u64,
+    /// Messages waiting to be written to the client
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub subscriptions: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConnectionsResponse {
+    pub connections: Vec<ConnectionInfo>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// Only events of this kind
+    pub kind: Option<u16>,
+    /// Only events by this hex pubkey
+    pub pubkey: Option<String>,
+    /// Oldest created_at, in unix seconds
+    pub since: Option<u64>,
+    /// Newest created_at, in unix seconds
+    pub until: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportResponse {
+    pub imported: u64,
+    pub duplicates: u64,
+    pub rejected: u64,
+    /// The first `MAX_IMPORT_ERRORS` rejected lines, as `line N: reason`
+    pub errors: Vec<String>,
+}
+
+/// Optional header naming the operator behind a request, for the moderation log
+pub const ADMIN_USER_HEADER: &str = "x-admin-user";
+
+// The operator to record in the moderation log for a request
+fn admin_user(headers: &HeaderMap) -> String {
+    headers
+        .get(ADMIN_USER_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("admin")
+        .to_string()
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<String, StatusCode> {
+    PublicKey::from_hex(pubkey)
+        .map(|pubkey| pubkey.to_hex())
+        .map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/blocklist",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    responses(
+        (status = 200, description = "Blocked hex pubkeys, sorted", body = BlocklistResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+pub async fn list_blocked_pubkeys(
+    State(state): State<AppState>,
+) -> Result<Json<BlocklistResponse>, StatusCode> {
+    let mut pubkeys: Vec<String> = state.pubkey_blocklist.read().await.iter().cloned().collect();
+    pubkeys.sort();
+    Ok(Json(BlocklistResponse { pubkeys }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/blocklist",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    request_body = BlockPubkeyRequest,
+    responses(
+        (status = 201, description = "Pubkey blocked"),
+        (status = 200, description = "Pubkey was already blocked"),
+        (status = 400, description = "Malformed pubkey"),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+pub async fn block_pubkey(
+    State(state): State<AppState>,
+    Json(request): Json<BlockPubkeyRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let pubkey = parse_pubkey(&request.pubkey)?;
+
+    // Persist first so the ban survives a restart
+    state.database.block_pubkey(&pubkey).await.map_err(|e| {
+        error!("Failed to store blocked pubkey {}: {}", pubkey, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    info!("Blocked pubkey {}", pubkey);
+    if state.pubkey_blocklist.write().await.insert(pubkey) {
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::OK)
+    }
+}
+
+// Pubkeys listed in RELAY_BLOCKED_PUBKEYS are blocked again on restart
+#[utoipa::path(
+    delete,
+    path = "/admin/blocklist/{pubkey}",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    params(("pubkey" = String, Path, description = "Hex pubkey")),
+    responses(
+        (status = 204, description = "Pubkey unblocked"),
+        (status = 400, description = "Malformed pubkey"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 404, description = "Pubkey wasn't blocked"),
+    )
+)]
+pub async fn unblock_pubkey(
+    State(state): State<AppState>,
+    Path(pubkey): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let pubkey = parse_pubkey(&pubkey)?;
+
+    let stored = state.database.unblock_pubkey(&pubkey).await.map_err(|e| {
+        error!("Failed to remove blocked pubkey {}: {}", pubkey, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let cached = state.pubkey_blocklist.write().await.remove(&pubkey);
+
+    if stored || cached {
+        info!("Unblocked pubkey {}", pubkey);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+// Erase everything a pubkey has published and refuse its future events
+#[utoipa::path(
+    delete,
+    path = "/admin/events/by-pubkey/{pubkey}",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    params(
+        ("pubkey" = String, Path, description = "Hex pubkey"),
+        ("x-admin-user" = Option<String>, Header, description = "Operator recorded in the moderation log"),
+    ),
+    responses(
+        (status = 200, description = "Events deleted and pubkey blocked", body = DeletePubkeyEventsResponse),
+        (status = 400, description = "Malformed pubkey"),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+pub async fn delete_pubkey_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(pubkey): Path<String>,
+) -> Result<Json<DeletePubkeyEventsResponse>, StatusCode> {
+    let admin = admin_user(&headers);
+    let pubkey = parse_pubkey(&pubkey)?;
+
+    let internal_error = |e: anyhow::Error| {
+        error!("Failed to delete events by {}: {}", pubkey, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    // Block first so nothing new slips in while the deletion runs
+    state.database.block_pubkey(&pubkey).await.map_err(internal_error)?;
+    state.pubkey_blocklist.write().await.insert(pubkey.clone());
+
+    let deleted = state.database.delete_all_events_by_pubkey(&pubkey).await.map_err(internal_error)?;
+    relay_list::evict_relay_list(&pubkey, &state).await;
+    state
+        .database
+        .log_moderation_action(&admin, "delete_events_by_pubkey", &pubkey)
+        .await
+        .map_err(internal_error)?;
+
+    info!("{} deleted {} events by {} and blocked the pubkey", admin, deleted, pubkey);
+    Ok(Json(DeletePubkeyEventsResponse { deleted, blocked: true }))
+}
+
+// Remove a single event, e.g. illegal content or doxxing, without a deletion
+// request from its author. Publishing it again is acknowledged but not stored.
+#[utoipa::path(
+    delete,
+    path = "/admin/events/{id}",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    params(
+        ("id" = String, Path, description = "Hex event ID"),
+        ("x-admin-user" = Option<String>, Header, description = "Operator recorded in the moderation log"),
+    ),
+    responses(
+        (status = 204, description = "Event deleted"),
+        (status = 400, description = "Malformed event ID"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 404, description = "Event isn't stored"),
+    )
+)]
+pub async fn force_delete_event(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let admin = admin_user(&headers);
+    let id = EventId::from_hex(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let internal_error = |e: anyhow::Error| {
+        error!("Failed to force delete event {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    // Marked before the row goes, so a copy published in between can't be
+    // stored again. An ID that wasn't stored stays refused all the same.
+    state.force_deleted_events.insert(&id).await.map_err(internal_error)?;
+    if !state.database.force_delete_event(&id.to_hex()).await.map_err(internal_error)? {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state
+        .database
+        .log_moderation_action(&admin, "force_delete_event", &id.to_hex())
+        .await
+        .map_err(internal_error)?;
+
+    info!("{} force deleted event {}", admin, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// The most recent database queries over the slow query threshold
+#[utoipa::path(
+    get,
+    path = "/admin/slow-queries",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    responses(
+        (status = 200, description = "The most recent queries over the slow query threshold, newest first", body = Vec<SlowQuery>),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+pub async fn list_slow_queries(State(state): State<AppState>) -> Json<Vec<SlowQuery>> {
+    Json(state.database.slow_queries())
+}
+
+// Every open connection with where it came from and its outbound queue depth,
+// deepest first, to spot clients about to be closed as slow subscribers
+#[utoipa::path(
+    get,
+    path = "/admin/connections",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    responses(
+        (status = 200, description = "Open connections, deepest outbound queue first", body = ConnectionsResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+    )
+)]
+pub async fn list_connections(
+    State(state): State<AppState>,
+) -> Result<Json<ConnectionsResponse>, StatusCode> {
+    let mut connections: Vec<ConnectionInfo> = state
+        .clients
+        .read()
+        .await
+        .iter()
+        .map(|(connection_id, client)| ConnectionInfo {
+            connection_id: connection_id.clone(),
+            peer_addr: client.metadata.peer_addr,
+            user_agent: client.metadata.user_agent.clone(),
+            origin: client.metadata.origin.clone(),
+            connected_at: client
+                .metadata
+                .connected_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            session_duration_secs: client.metadata.connected_at.elapsed().map_or(0, |duration| duration.as_secs()),
+            queue_depth: client.sender.queue_depth(),
+            queue_capacity: state.config.max_outbound_queue,
+            // Filters are keyed `<sub_id>:<index>`
+            subscriptions: state.subscriptions.get(connection_id).map_or(0, |subs| {
+                subs.iter()
+                    .filter_map(|entry| entry.key().rsplit_once(':').map(|(sub_id, _)| sub_id.to_string()))
+                    .collect::<HashSet<_>>()
+                    .len()
+            }),
+        })
+        .collect();
+    connections.sort_by(|a, b| b.queue_depth.cmp(&a.queue_depth).then_with(|| a.connection_id.cmp(&b.connection_id)));
+
+    Ok(Json(ConnectionsResponse { connections }))
+}
+
+// Drop a misbehaving client: it is sent a NOTICE, its subscriptions end and
+// its socket task closes the WebSocket
+#[utoipa::path(
+    delete,
+    path = "/admin/connections/{connection_id}",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    params(("connection_id" = String, Path, description = "Connection ID, as listed by `GET /admin/connections`")),
+    responses(
+        (status = 204, description = "Connection closed"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 404, description = "No such connection"),
+    )
+)]
+pub async fn disconnect_connection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(connection_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let admin = admin_user(&headers);
+
+    // Dropping the client's entry drops the relay's sender to its outbound
+    // queue, so nothing else is fanned out to it
+    let client = state
+        .clients
+        .write()
+        .await
+        .remove(&connection_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    client.sender.try_send(RelayMessage::Notice {
+        message: DISCONNECTED_BY_OPERATOR.to_string(),
+    });
+    client.close.cancel();
+    subscription::cleanup_client_subscriptions(&state, &connection_id);
+
+    if let Err(e) = state
+        .database
+        .log_moderation_action(&admin, "disconnect_connection", &connection_id)
+        .await
+    {
+        error!("Failed to log disconnecting connection {}: {}", connection_id, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    info!("{} disconnected connection {} from {}", admin, connection_id, client.metadata.peer_addr);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Stream stored events as JSONL, oldest first, for backups and migrations
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "One JSON event per line, oldest first", content_type = "application/x-ndjson"),
+        (status = 400, description = "Malformed pubkey"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 503, description = "Database unavailable"),
+    )
+)]
+pub async fn export_events(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, StatusCode> {
+    let mut filter = Filter::new();
+    if let Some(kind) = query.kind {
+        filter = filter.kind(Kind::from(kind));
+    }
+    if let Some(pubkey) = &query.pubkey {
+        filter = filter.author(PublicKey::from_hex(pubkey).map_err(|_| StatusCode::BAD_REQUEST)?);
+    }
+    if let Some(since) = query.since {
+        filter = filter.since(Timestamp::from(since));
+    }
+    if let Some(until) = query.until {
+        filter = filter.until(Timestamp::from(until));
+    }
+
+    let events = state.database.export_events(&filter).map_err(|e| {
+        error!("Failed to start event export: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    // A database error mid-export aborts the response, so a truncated file
+    // can't be mistaken for a complete one
+    let lines = events.map(|event| {
+        event.map(|json| format!("{}\n", json)).inspect_err(|e| error!("Event export failed: {}", e))
+    });
+
+    Ok((
+        [(CONTENT_TYPE, NDJSON), (TRANSFER_ENCODING, "chunked")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+// Store events from a JSONL body, applying the same checks as events published
+// over WebSocket. Lines are processed as they arrive, so imports of any size
+// are never buffered whole.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    tag = "admin",
+    security(("admin_token" = []), ("nip98" = [])),
+    request_body(content = String, description = "One JSON event per line", content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "Counts of imported, duplicate and rejected lines", body = ImportResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 413, description = "A line is longer than the relay's max_message_length"),
+    )
+)]
+pub async fn import_events(
+    State(state): State<AppState>,
+    body: Body,
+) -> Result<Json<ImportResponse>, StatusCode> {
+    let mut response = ImportResponse::default();
+    let mut body = body.into_data_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_number = 0;
+
+    loop {
+        let chunk = body.next().await.transpose().map_err(|e| {
+            debug!("Event import body failed: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        let finished = chunk.is_none();
+        if let Some(chunk) = chunk {
+            buffer.extend_from_slice(&chunk);
+        }
+
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            line_number += 1;
+            import_line(&state, &line, line_number, &mut response).await?;
+        }
+
+        // A line longer than any publishable message can't be an event
+        if buffer.len() > state.config.max_message_length {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        if finished {
+            if !buffer.is_empty() {
+                line_number += 1;
+                import_line(&state, &buffer, line_number, &mut response).await?;
+            }
+            break;
+        }
+    }
+
+    info!(
+        "Imported {} events ({} duplicates, {} rejected)",
+        response.imported, response.duplicates, response.rejected
+    );
+    Ok(Json(response))
+}
+
+async fn import_line(
+    state: &AppState,
+    line: &[u8],
+    line_number: usize,
+    response: &mut ImportResponse,
+) -> Result<(), StatusCode> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let event = match parse_import_event(state, line).await {
+        Ok(event) => event,
+        Err(reason) => {
+            response.rejected += 1;
+            if response.errors.len() < MAX_IMPORT_ERRORS {
+                response.errors.push(format!("line {}: {}", line_number, reason));
+            }
+            return Ok(());
+        }
+    };
+
+    let internal_error = |e: anyhow::Error| {
+        error!("Failed to import event {}: {}", event.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    if state.database.event_exists(&event.id).await.map_err(internal_error)? {
+        response.duplicates += 1;
+        return Ok(());
+    }
+
+    if event.is_replaceable() || event.is_parameterized_replaceable() {
+        state.database.replace_event(&event).await.map_err(internal_error)?;
+    } else {
+        state.database.save_event(&event).await.map_err(internal_error)?;
+    }
+    hooks::after_store(&event, state).await;
+    response.imported += 1;
+    Ok(())
+}
+
+async fn parse_import_event(state: &AppState, line: &str) -> Result<Event, String> {
+    let event = Event::from_json(line).map_err(|e| format!("invalid event: {}", e))?;
+    validation::verify_event_cached(&event, &state.sig_cache)
+        .await
+        .map_err(|e| format!("invalid: {}", e))?;
+
+    if state.pubkey_blocklist.read().await.contains(&event.pubkey.to_hex()) {
+        return Err("blocked: pubkey is banned".to_string());
+    }
+    if event.is_ephemeral() {
+        return Err("invalid: ephemeral events are not stored".to_string());
+    }
+    if state.force_deleted_events.contains(&event.id).await {
+        return Err("blocked: event was removed by the relay".to_string());
+    }
+    validation::validate_event(&event, &*state.mutable_config.read().await)?;
+    if validation::matching_content_filter(&event, &state.content_filters).is_some() {
+        state.metrics.record_content_filtered();
+        return Err("blocked: content policy".to_string());
+    }
+    if let Some(violation) = validation::url_violation(&event, &*state.mutable_config.read().await) {
+        match violation {
+            UrlViolation::TooMany(_) => state.metrics.record_url_limit_rejected(),
+            UrlViolation::BlockedDomain(_) => state.metrics.record_blocked_domain_rejected(),
+        }
+        return Err(violation.message().to_string());
+    }
+    if validation::excessive_spam_score(&event, state.config.spam_score_threshold).is_some() {
+        state.metrics.record_spam_rejected();
+        return Err("blocked: spam".to_string());
+    }
+
+    Ok(event)
+}
+
+// Router setup for admin endpoints, all behind the admin token
+pub fn create_admin_router(state: AppState) -> Router<AppState> {
+    let routes = Router::new()
+        .route("/blocklist", get(list_blocked_pubkeys).post(block_pubkey))
+        .route("/blocklist/:pubkey", delete(unblock_pubkey))
+        .route("/events/by-pubkey/:pubkey", delete(delete_pubkey_events))
+        .route("/events/:id", delete(force_delete_event))
+        .route("/connections", get(list_connections))
+        .route("/connections/:connection_id", delete(disconnect_connection))
+        .route("/export", get(export_events))
+        .route("/import", post(import_events))
+        .route("/slow-queries", get(list_slow_queries))
+        .route_layer(middleware::from_fn_with_state(state, auth::require_admin));
+    Router::new().nest("/admin", routes)
+}
@@ -0,0 +1,76 @@
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use subtle::ConstantTimeEq;
+
+use crate::app_state::AppState;
+use crate::auth::nip98::{Nip98Auth, MAX_SIGNED_BODY_BYTES};
+
+/// Let a request through to the admin API only if it carries
+/// `Authorization: Bearer <RELAY_ADMIN_TOKEN>`, or a NIP-98 `Authorization:
+/// Nostr <event>` signed by one of `RELAY_ADMIN_PUBKEYS` for a URL under
+/// `RELAY_PUBLIC_URL`. With neither
+/// configured the admin API is disabled and every request gets a 404.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let config = &state.config;
+    if config.admin_token.is_none() && config.admin_pubkeys.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let (parts, mut body) = request.into_parts();
+    match bearer_token(&parts.headers) {
+        // Constant-time, so response timing doesn't reveal how much of a guess matched
+        Some(provided) => {
+            let authorized = config
+                .admin_token
+                .as_deref()
+                .is_some_and(|expected| bool::from(provided.as_bytes().ct_eq(expected.as_bytes())));
+            if !authorized {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        }
+        // The body is buffered so it can be checked against the event's `payload` hash
+        None => {
+            let bytes = body::to_bytes(body, MAX_SIGNED_BODY_BYTES)
+                .await
+                .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+            Nip98Auth::authorize(&parts, &state, &bytes, |pubkey| config.admin_pubkeys.contains(&pubkey.to_hex())).await?;
+            body = Body::from(bytes);
+        }
+    }
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert_eq!(bearer_token(&headers), Some("secret"));
+    }
+}
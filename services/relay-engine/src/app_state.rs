@@ -1,19 +1,132 @@
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
-use nostr::Filter;
+use axum::http::{header::{ORIGIN, USER_AGENT}, HeaderMap, HeaderValue};
+use dashmap::DashMap;
+use std::{collections::{HashMap, HashSet}, net::IpAddr, sync::{atomic::{AtomicU64, AtomicUsize}, Arc, Mutex}, time::{Duration, Instant, SystemTime}};
+use tokio::{sync::{broadcast, RwLock}, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use nostr::{Event, Filter, PublicKey};
+use regex::Regex;
 
 use crate::{
+    audit::AuditLogger,
+    auth::nip98::NonceStore,
+    batch::EventBatcher,
     config::Config,
-    database::PostgresDatabase,
+    database::{ForceDeletedEvents, PostgresDatabase, RelayDatabase},
+    fanout::EventFanout,
     metrics::Metrics,
+    outbound::ClientSender,
     rate_limiter::RateLimiter,
+    relay_list::RelayUrl,
+    subscription::SubscriptionLimit,
+    subscription_index::SubscriptionIndex,
+    validation::SigCache,
 };
 
+/// Where a connection came from, taken from its WebSocket upgrade request
+#[derive(Debug, Clone)]
+pub struct ConnectionMetadata {
+    pub peer_addr: IpAddr,
+    pub user_agent: Option<String>,
+    pub origin: Option<String>,
+    pub connected_at: SystemTime,
+}
+
+impl ConnectionMetadata {
+    pub fn from_request(peer_addr: IpAddr, headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|value: &HeaderValue| value.to_str().ok()).map(String::from);
+        Self {
+            peer_addr,
+            user_agent: header(USER_AGENT),
+            origin: header(ORIGIN),
+            connected_at: SystemTime::now(),
+        }
+    }
+}
+
+/// An open WebSocket connection: its outbound queue and where it came from
+#[derive(Clone)]
+pub struct ConnectedClient {
+    pub sender: ClientSender,
+    pub metadata: ConnectionMetadata,
+    /// When the client last sent a message
+    pub last_activity: Arc<Mutex<Instant>>,
+    /// Cancel to have the connection's socket task close it
+    pub close: CancellationToken,
+    /// Bytes of text messages written to the client
+    pub bytes_sent: Arc<AtomicU64>,
+    /// Bytes of text messages read from the client
+    pub bytes_received: Arc<AtomicU64>,
+    /// The pubkey the client authenticated as over NIP-42, once it has
+    pub pubkey: Arc<Mutex<Option<PublicKey>>>,
+}
+
+impl ConnectedClient {
+    pub fn new(sender: ClientSender, metadata: ConnectionMetadata) -> Self {
+        Self {
+            sender,
+            metadata,
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            close: CancellationToken::new(),
+            bytes_sent: Arc::default(),
+            bytes_received: Arc::default(),
+            pubkey: Arc::default(),
+        }
+    }
+
+    pub fn authenticated_pubkey(&self) -> Option<PublicKey> {
+        *self.pubkey.lock().unwrap()
+    }
+
+    /// How long the client had been silent at `now`
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.last_activity.lock().unwrap())
+    }
+}
+
+/// Shared relay state. The database defaults to PostgreSQL; code generic over
+/// `D` also runs against `MockDatabase` in tests.
 #[derive(Clone)]
-pub struct AppState {
-    pub database: PostgresDatabase,
-    pub subscriptions: Arc<RwLock<HashMap<String, HashMap<String, Filter>>>>,
+pub struct AppState<D: RelayDatabase = PostgresDatabase> {
+    pub database: D,
+    pub subscriptions: Arc<DashMap<String, DashMap<String, Filter>>>,
+    /// Live-event limits of subscriptions opened with a `limit`, by client ID then subscription ID
+    pub subscription_limits: Arc<DashMap<String, DashMap<String, SubscriptionLimit>>>,
+    /// `subscriptions` inverted by kind and author, used to find the subscriptions an event goes to
+    pub subscription_index: Arc<std::sync::RwLock<SubscriptionIndex>>,
+    /// Open WebSocket connections by client ID
+    pub clients: Arc<RwLock<HashMap<String, ConnectedClient>>>,
     pub rate_limiter: RateLimiter,
     pub metrics: Metrics,
     pub config: Config,
+    pub sig_cache: Arc<SigCache>,
+    /// Cancelled when the relay begins shutting down
+    pub shutdown: CancellationToken,
+    /// WebSocket connection tasks, drained on shutdown
+    pub connections: Arc<Mutex<JoinSet<()>>>,
+    /// WebSocket connections currently open, held against `max_total_connections`
+    pub open_connections: Arc<AtomicUsize>,
+    /// Cross-instance event fanout, when Redis is configured
+    pub fanout: Option<EventFanout>,
+    /// Hex pubkeys whose events are refused
+    pub pubkey_blocklist: Arc<RwLock<HashSet<String>>>,
+    /// NIP-65 relay lists by hex pubkey, for outbox routing decisions
+    pub pubkey_relays: Arc<RwLock<HashMap<String, Vec<RelayUrl>>>>,
+    /// Compiled `Config::content_filters`, checked against text notes
+    pub content_filters: Arc<Vec<Regex>>,
+    /// Groups regular events into multi-row inserts; without it each event is saved on its own
+    pub event_batcher: Option<EventBatcher>,
+    /// Every event delivered to local subscribers, for the SSE feed
+    pub event_feed: broadcast::Sender<Event>,
+    /// Records every accepted event, when `Config::audit_log_enabled` is set
+    pub audit_log: Option<Arc<AuditLogger>>,
+    /// `config` as last re-read by the `ConfigWatcher`; only the hot-reloadable fields ever differ
+    pub mutable_config: Arc<RwLock<Config>>,
+    /// NIP-98 HTTP auth events already used, refused if presented again
+    pub http_auth_nonces: Arc<NonceStore>,
+    /// Open subscriptions of authenticated connections by hex pubkey, across all its connections
+    pub pubkey_subscription_counts: Arc<DashMap<String, AtomicUsize>>,
+    /// The pubkey each subscription is counted against, by client ID then subscription ID
+    pub subscription_pubkeys: Arc<DashMap<String, DashMap<String, String>>>,
+    /// Events removed through the admin API, acknowledged but not stored if published again
+    pub force_deleted_events: Arc<ForceDeletedEvents>,
 }
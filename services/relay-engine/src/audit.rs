@@ -0,0 +1,257 @@
+use std::{
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use nostr::{
+    hashes::{sha256::Hash as Sha256Hash, Hash},
+    Event,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, warn};
+use tracing_appender::rolling::RollingFileAppender;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::config::LogRotation;
+use crate::telemetry::rolling_file;
+
+/// Records waiting for the writer thread. When it falls this far behind,
+/// further records are reported as failed rather than stalling connections.
+pub const AUDIT_QUEUE_SIZE: usize = 10_000;
+
+/// `prev_hash` of the first record of a new log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub event_id: String,
+    pub pubkey: String,
+    pub kind: u16,
+    /// The event's own timestamp, in Unix seconds
+    pub created_at: u64,
+    /// When the relay accepted the event, in Unix seconds
+    pub received_at: u64,
+    pub client_ip: IpAddr,
+    /// Hex SHA-256 of the previous line, so removing or editing a line breaks
+    /// the chain from there on
+    pub prev_hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(event: &Event, client_ip: IpAddr) -> Self {
+        Self {
+            event_id: event.id.to_hex(),
+            pubkey: event.pubkey.to_hex(),
+            kind: event.kind.as_u16(),
+            created_at: event.created_at.as_u64(),
+            received_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs()),
+            client_ip,
+            prev_hash: String::new(),
+        }
+    }
+}
+
+/// Hex SHA-256 of an audit line, without its line break
+pub fn line_hash(line: &str) -> String {
+    Sha256Hash::hash(line.as_bytes()).to_string()
+}
+
+/// Check that each line's `prev_hash` is the hash of the line before it,
+/// starting from `prev_hash`. Returns the index of the first line that
+/// doesn't follow on.
+pub fn verify_chain<'a>(lines: impl IntoIterator<Item = &'a str>, mut prev_hash: String) -> Result<(), usize> {
+    for (index, line) in lines.into_iter().enumerate() {
+        let record: AuditRecord = serde_json::from_str(line).map_err(|_| index)?;
+        if record.prev_hash != prev_hash {
+            return Err(index);
+        }
+        prev_hash = line_hash(line);
+    }
+    Ok(())
+}
+
+/// Appends a JSON line for every event the relay accepts to a rotated file,
+/// e.g. `audit/events.log.2024-01-31` with daily rotation. Lines are written
+/// by a dedicated thread, so connections never wait on the disk, and are
+/// hash-chained across files and restarts.
+#[derive(Debug)]
+pub struct AuditLogger {
+    records: Option<mpsc::Sender<AuditRecord>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AuditLogger {
+    pub fn new(path: &Path, rotation: LogRotation) -> anyhow::Result<Self> {
+        let appender = rolling_file(path, rotation)?;
+        let prev_hash = last_line_hash(path)?.unwrap_or_else(|| GENESIS_HASH.to_string());
+        let (records, receiver) = mpsc::channel(AUDIT_QUEUE_SIZE);
+        let writer = std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_records(receiver, appender, prev_hash))?;
+
+        Ok(Self {
+            records: Some(records),
+            writer: Some(writer),
+        })
+    }
+
+    /// Queue the record of an accepted event
+    pub fn record(&self, event: &Event, client_ip: IpAddr) -> anyhow::Result<()> {
+        let Some(records) = &self.records else {
+            anyhow::bail!("audit log is closed");
+        };
+        match records.try_send(AuditRecord::new(event, client_ip)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => anyhow::bail!("audit log queue is full"),
+            Err(TrySendError::Closed(_)) => anyhow::bail!("audit log writer stopped"),
+        }
+    }
+}
+
+impl Drop for AuditLogger {
+    // Write out what is still queued before going away
+    fn drop(&mut self) {
+        self.records.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_records(mut receiver: mpsc::Receiver<AuditRecord>, appender: RollingFileAppender, mut prev_hash: String) {
+    while let Some(mut record) = receiver.blocking_recv() {
+        record.prev_hash = prev_hash.clone();
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit record for event {}: {}", record.event_id, e);
+                continue;
+            }
+        };
+        // Each line is written in a single call, so a line is never split
+        if let Err(e) = appender.make_writer().write_all(format!("{}\n", line).as_bytes()) {
+            error!("Failed to write audit log entry for event {}: {}", record.event_id, e);
+            continue;
+        }
+        prev_hash = line_hash(&line);
+    }
+}
+
+// Hash of the last line of the newest rotated file, so the chain carries on
+// across restarts
+fn last_line_hash(path: &Path) -> anyhow::Result<Option<String>> {
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(None);
+    };
+    let prefix = format!("{}.", file_name);
+
+    // Rotated names end in a timestamp, so the newest file sorts last
+    let newest = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix)))
+        .max();
+    let Some(newest) = newest else {
+        return Ok(None);
+    };
+
+    let contents = std::fs::read_to_string(&newest)?;
+    let mut lines = contents.lines();
+    let Some(first) = lines.next() else {
+        return Ok(None);
+    };
+    if let Err(index) = verify_chain(lines, line_hash(first)) {
+        warn!("Audit log {} is broken at line {}", newest.display(), index + 2);
+    }
+    Ok(contents.lines().last().map(line_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Kind};
+
+    #[test]
+    fn test_audit_log_writes_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit").join("events.log");
+        let logger = AuditLogger::new(&path, LogRotation::Hourly).unwrap();
+
+        let keys = Keys::generate();
+        let client_ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let events: Vec<Event> = [Kind::TextNote, Kind::Reaction, Kind::Metadata]
+            .into_iter()
+            .map(|kind| EventBuilder::new(kind, "audited", []).to_event(&keys).unwrap())
+            .collect();
+        for event in &events {
+            logger.record(event, client_ip).unwrap();
+        }
+        // Dropping the logger waits for the queue to be written
+        drop(logger);
+
+        // The rotated file is named after the configured one, with a time suffix
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("audit")).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].file_name().unwrap().to_str().unwrap().starts_with("events.log."));
+
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let records: Vec<AuditRecord> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        for (record, event) in records.iter().zip(&events) {
+            assert_eq!(record.event_id, event.id.to_hex());
+            assert_eq!(record.pubkey, keys.public_key().to_hex());
+            assert_eq!(record.kind, event.kind.as_u16());
+            assert_eq!(record.created_at, event.created_at.as_u64());
+            assert!(record.received_at >= record.created_at);
+            assert_eq!(record.client_ip, client_ip);
+        }
+
+        let line: serde_json::Value = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(line["client_ip"], "203.0.113.7");
+        assert_eq!(line["prev_hash"], GENESIS_HASH);
+        assert_eq!(verify_chain(contents.lines(), GENESIS_HASH.to_string()), Ok(()));
+
+        // A restarted logger carries on from the last line
+        let logger = AuditLogger::new(&path, LogRotation::Hourly).unwrap();
+        logger.record(&events[0], client_ip).unwrap();
+        drop(logger);
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("audit")).unwrap().map(|entry| entry.unwrap().path()).collect();
+        let contents = std::fs::read_to_string(files.iter().max().unwrap()).unwrap();
+        let last: AuditRecord = serde_json::from_str(contents.lines().last().unwrap()).unwrap();
+        assert_ne!(last.prev_hash, GENESIS_HASH);
+    }
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let keys = Keys::generate();
+        let client_ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut lines = Vec::new();
+        for content in ["one", "two", "three"] {
+            let event = EventBuilder::new(Kind::TextNote, content, []).to_event(&keys).unwrap();
+            let record = AuditRecord { prev_hash: prev_hash.clone(), ..AuditRecord::new(&event, client_ip) };
+            let line = serde_json::to_string(&record).unwrap();
+            prev_hash = line_hash(&line);
+            lines.push(line);
+        }
+        let chain = |lines: &[String]| verify_chain(lines.iter().map(String::as_str), GENESIS_HASH.to_string());
+
+        assert_eq!(chain(&lines), Ok(()));
+
+        // A removed line
+        let mut removed = lines.clone();
+        removed.remove(1);
+        assert_eq!(chain(&removed), Err(1));
+
+        // An edited line breaks the link to the next one
+        let mut edited = lines.clone();
+        edited[0] = edited[0].replace("203.0.113.7", "198.51.100.1");
+        assert_eq!(chain(&edited), Err(1));
+    }
+}
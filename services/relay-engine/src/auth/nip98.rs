@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri},
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, TRANSFER_ENCODING},
+        request::Parts,
+        Method, StatusCode, Uri,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use nostr::hashes::{sha256::Hash as Sha256Hash, Hash};
+use nostr::{Event, EventId, JsonUtil, Kind, PublicKey, Timestamp, Url};
+use redis::{aio::MultiplexedConnection, Client};
+use tracing::{debug, error};
+
+use crate::{app_state::AppState, database::RelayDatabase, validation::tag_value};
+
+/// How far an HTTP auth event's `created_at` may drift from the relay's clock, in seconds
+pub const HTTP_AUTH_WINDOW_SECS: u64 = 60;
+
+/// Redis key prefix of HTTP auth events that have been used
+pub const NONCE_KEY_PREFIX: &str = "nip98:nonce:";
+
+// An event is remembered for as long as its created_at could still pass the window check
+const NONCE_TTL_SECS: u64 = 2 * HTTP_AUTH_WINDOW_SECS;
+
+/// The `Authorization` scheme carrying a NIP-98 event
+pub const AUTHORIZATION_SCHEME: &str = "Nostr ";
+
+/// Largest body of a NIP-98 signed request; it is buffered to check its
+/// `payload` hash before the request is handled
+pub const MAX_SIGNED_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// A request signed under NIP-98 with an `Authorization: Nostr <base64 event>`
+/// header. Extracting it rejects the request with `401` unless the event is a
+/// fresh, unused kind-27235 event signed for this URL and method. Requests
+/// with a body are refused by the extractor, which can't hash it; routes
+/// taking one go through `Nip98Auth::authorize` with the buffered body.
+#[derive(Debug, Clone)]
+pub struct Nip98Auth {
+    pub pubkey: PublicKey,
+}
+
+impl Nip98Auth {
+    /// Check the request's NIP-98 event, and `body` against its `payload`
+    /// tag, then mark the event used. Events signed by a pubkey `allowed`
+    /// refuses are rejected before that, so they aren't used up.
+    pub async fn authorize<D: RelayDatabase>(
+        parts: &Parts,
+        state: &AppState<D>,
+        body: &[u8],
+        allowed: impl Fn(&PublicKey) -> bool,
+    ) -> Result<Self, StatusCode> {
+        let reject = |reason: String| {
+            debug!("Rejected NIP-98 authorization for {}: {}", parts.uri, reason);
+            StatusCode::UNAUTHORIZED
+        };
+
+        let header = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        let event = decode_authorization(header).map_err(reject)?;
+
+        // The Host header is up to the client, so `u` tags are checked against
+        // the URL the operator configured instead
+        let public_url = state
+            .config
+            .public_url
+            .as_deref()
+            .and_then(|url| Url::parse(url).ok())
+            .ok_or_else(|| reject("RELAY_PUBLIC_URL is not configured".to_string()))?;
+        // Nested routers see the URI without their prefix; the event signs the full one
+        let uri = parts.extensions.get::<OriginalUri>().map_or(&parts.uri, |OriginalUri(uri)| uri);
+        verify_auth_event(&event, &parts.method, &public_url, uri, Timestamp::now()).map_err(reject)?;
+        verify_payload(&event, body).map_err(reject)?;
+        if !allowed(&event.pubkey) {
+            return Err(reject(format!("{} is not allowed", event.pubkey)));
+        }
+
+        match state.http_auth_nonces.claim(&event.id).await {
+            Ok(true) => Ok(Self { pubkey: event.pubkey }),
+            Ok(false) => Err(reject("event was already used".to_string())),
+            Err(e) => {
+                error!("Failed to record NIP-98 event {}: {}", event.id, e);
+                Err(StatusCode::UNAUTHORIZED)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<D: RelayDatabase> FromRequestParts<AppState<D>> for Nip98Auth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState<D>) -> Result<Self, Self::Rejection> {
+        let has_body = parts.headers.contains_key(TRANSFER_ENCODING)
+            || parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|length| length != "0");
+        if has_body {
+            debug!("Rejected NIP-98 authorization for {}: body can't be checked", parts.uri);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        Self::authorize(parts, state, &[], |_| true).await
+    }
+}
+
+/// The event in an `Authorization: Nostr <base64 event>` header value
+pub fn decode_authorization(header: &str) -> Result<Event, String> {
+    let encoded = header
+        .strip_prefix(AUTHORIZATION_SCHEME)
+        .ok_or("not a Nostr authorization")?;
+    let json = STANDARD.decode(encoded.trim()).map_err(|_| "event is not base64")?;
+    Event::from_json(json).map_err(|e| format!("malformed event: {}", e))
+}
+
+/// Check an HTTP auth event against the request it authorizes.
+///
+/// The `u` tag must be the request's path and query under `public_url`, the
+/// relay's URL as clients see it.
+pub fn verify_auth_event(event: &Event, method: &Method, public_url: &Url, uri: &Uri, now: Timestamp) -> Result<(), String> {
+    if event.kind != Kind::HttpAuth {
+        return Err("not an HTTP auth event".to_string());
+    }
+    if event.verify().is_err() {
+        return Err("bad signature".to_string());
+    }
+    if event.created_at.as_u64().abs_diff(now.as_u64()) > HTTP_AUTH_WINDOW_SECS {
+        return Err("created_at is too far from the current time".to_string());
+    }
+
+    let url = tag_value(event, "u")
+        .and_then(|url| Url::parse(url).ok())
+        .ok_or("missing or malformed u tag")?;
+    let path = format!("{}{}", public_url.path().trim_end_matches('/'), uri.path());
+    if url.origin() != public_url.origin() || url.path() != path || url.query() != uri.query() {
+        return Err("u tag does not match the request URL".to_string());
+    }
+
+    if !tag_value(event, "method").is_some_and(|tagged| tagged.eq_ignore_ascii_case(method.as_str())) {
+        return Err("method tag does not match the request method".to_string());
+    }
+
+    Ok(())
+}
+
+/// Check a request body against the event's `payload` tag, the hex SHA-256 of
+/// the body. A request without a body needs no tag.
+pub fn verify_payload(event: &Event, body: &[u8]) -> Result<(), String> {
+    let payload = tag_value(event, "payload");
+    if body.is_empty() && payload.is_none() {
+        return Ok(());
+    }
+    if payload != Some(Sha256Hash::hash(body).to_string().as_str()) {
+        return Err("payload tag does not match the request body".to_string());
+    }
+    Ok(())
+}
+
+/// HTTP auth events that have already been used, so a captured
+/// `Authorization` header can't be replayed. Kept in Redis when it's
+/// configured, so every relay instance sees them, and in memory otherwise.
+#[derive(Debug, Default)]
+pub struct NonceStore {
+    redis: Option<MultiplexedConnection>,
+    /// Event IDs by the Unix time they can be forgotten
+    local: Mutex<HashMap<EventId, u64>>,
+}
+
+impl NonceStore {
+    pub async fn connect(redis_url: &str) -> anyhow::Result<Self> {
+        let client = Client::open(redis_url)?;
+        Ok(Self {
+            redis: Some(client.get_multiplexed_tokio_connection().await?),
+            local: Mutex::default(),
+        })
+    }
+
+    /// Record an event as used, returning `false` when it already was
+    pub async fn claim(&self, id: &EventId) -> anyhow::Result<bool> {
+        if let Some(redis) = &self.redis {
+            let mut redis = redis.clone();
+            let set: Option<String> = redis::cmd("SET")
+                .arg(format!("{}{}", NONCE_KEY_PREFIX, id.to_hex()))
+                .arg(1)
+                .arg("NX")
+                .arg("EX")
+                .arg(NONCE_TTL_SECS)
+                .query_async(&mut redis)
+                .await?;
+            return Ok(set.is_some());
+        }
+
+        let now = Timestamp::now().as_u64();
+        let mut local = self.local.lock().unwrap();
+        local.retain(|_, forget_at| *forget_at > now);
+        Ok(local.insert(*id, now + NONCE_TTL_SECS).is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Tag};
+
+    const URL: &str = "https://relay.example.com/admin/blocklist?limit=10";
+
+    fn auth_event(keys: &Keys, kind: Kind, url: &str, method: &str, created_at: Timestamp) -> Event {
+        EventBuilder::new(kind, "", [Tag::parse(&["u", url]).unwrap(), Tag::parse(&["method", method]).unwrap()])
+            .custom_created_at(created_at)
+            .to_event(keys)
+            .unwrap()
+    }
+
+    fn verify(event: &Event, method: Method, public_url: &str, uri: &str) -> Result<(), String> {
+        verify_auth_event(event, &method, &Url::parse(public_url).unwrap(), &uri.parse().unwrap(), Timestamp::now())
+    }
+
+    fn header(event: &Event) -> String {
+        format!("{}{}", AUTHORIZATION_SCHEME, STANDARD.encode(event.as_json()))
+    }
+
+    #[test]
+    fn test_decode_authorization() {
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        assert_eq!(decode_authorization(&header(&event)), Ok(event.clone()));
+
+        assert!(decode_authorization(&format!("Bearer {}", STANDARD.encode(event.as_json()))).is_err());
+        assert!(decode_authorization("Nostr not-base64!").is_err());
+        assert!(decode_authorization(&format!("Nostr {}", STANDARD.encode("{\"kind\":27235}"))).is_err());
+    }
+
+    #[test]
+    fn test_valid_event_matches_request() {
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        assert!(verify(&event, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=10").is_ok());
+        assert!(verify(&event, Method::GET, "https://relay.example.com/", "/admin/blocklist?limit=10").is_ok());
+
+        // Behind a proxy serving the relay under a path, the request URI leaves it out
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, "https://example.com/relay/admin/blocklist", "GET", Timestamp::now());
+        assert!(verify(&event, Method::GET, "https://example.com/relay", "/admin/blocklist").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_wrong_kind() {
+        let event = auth_event(&Keys::generate(), Kind::TextNote, URL, "GET", Timestamp::now());
+        assert_eq!(verify(&event, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=10"), Err("not an HTTP auth event".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_bad_signature() {
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        let mut json: serde_json::Value = serde_json::from_str(&event.as_json()).unwrap();
+        json["sig"] = serde_json::json!("00".repeat(64));
+        let forged = Event::from_json(json.to_string()).unwrap();
+        assert_eq!(verify(&forged, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=10"), Err("bad signature".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_stale_and_future_events() {
+        let keys = Keys::generate();
+        let now = Timestamp::now().as_u64();
+        for created_at in [now - HTTP_AUTH_WINDOW_SECS - 5, now + HTTP_AUTH_WINDOW_SECS + 5] {
+            let event = auth_event(&keys, Kind::HttpAuth, URL, "GET", Timestamp::from(created_at));
+            assert_eq!(
+                verify(&event, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=10"),
+                Err("created_at is too far from the current time".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_url_mismatch() {
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        let mismatch = Err("u tag does not match the request URL".to_string());
+        assert_eq!(verify(&event, Method::GET, "https://other.example.com", "/admin/blocklist?limit=10"), mismatch);
+        assert_eq!(verify(&event, Method::GET, "http://relay.example.com", "/admin/blocklist?limit=10"), mismatch);
+        assert_eq!(verify(&event, Method::GET, "https://relay.example.com:8443", "/admin/blocklist?limit=10"), mismatch);
+        assert_eq!(verify(&event, Method::GET, "https://relay.example.com", "/admin/connections?limit=10"), mismatch);
+        assert_eq!(verify(&event, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=99"), mismatch);
+        assert_eq!(verify(&event, Method::GET, "https://relay.example.com", "/admin/blocklist"), mismatch);
+
+        let untagged = EventBuilder::new(Kind::HttpAuth, "", [Tag::parse(&["method", "GET"]).unwrap()])
+            .to_event(&Keys::generate())
+            .unwrap();
+        assert_eq!(verify(&untagged, Method::GET, "https://relay.example.com", "/admin/blocklist?limit=10"), Err("missing or malformed u tag".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_method_mismatch() {
+        let event = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        assert_eq!(
+            verify(&event, Method::DELETE, "https://relay.example.com", "/admin/blocklist?limit=10"),
+            Err("method tag does not match the request method".to_string())
+        );
+    }
+
+    #[test]
+    fn test_payload_must_hash_the_body() {
+        let keys = Keys::generate();
+        let body = b"{\"pubkey\":\"abc\"}";
+        let signed = |tags: Vec<Tag>| EventBuilder::new(Kind::HttpAuth, "", tags).to_event(&keys).unwrap();
+        let hashed = signed(vec![Tag::parse(&["payload", &Sha256Hash::hash(body).to_string()]).unwrap()]);
+        let untagged = signed(vec![]);
+
+        assert!(verify_payload(&hashed, body).is_ok());
+        assert!(verify_payload(&untagged, &[]).is_ok());
+        assert!(verify_payload(&untagged, body).is_err());
+        assert!(verify_payload(&hashed, b"{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_nonces_are_claimed_once() {
+        let store = NonceStore::default();
+        let first = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+        let second = auth_event(&Keys::generate(), Kind::HttpAuth, URL, "GET", Timestamp::now());
+
+        assert!(store.claim(&first.id).await.unwrap());
+        assert!(!store.claim(&first.id).await.unwrap());
+        assert!(store.claim(&second.id).await.unwrap());
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use nostr::Event;
+use std::{collections::HashSet, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, error, warn};
+
+use crate::database::{self, circuit_breaker::CircuitOpen, PostgresDatabase, MAX_BATCH_SIZE};
+
+/// How long an event waits for others to share its `INSERT`
+pub const BATCH_WINDOW: Duration = Duration::from_millis(5);
+
+// Events waiting to be picked up by the accumulator before senders wait
+const QUEUE_SIZE: usize = MAX_BATCH_SIZE * 4;
+
+struct PendingEvent {
+    event: Event,
+    // Whether the event was newly stored, or why the batch failed
+    reply: oneshot::Sender<Result<bool, String>>,
+}
+
+/// Handle for queueing events on a running `BatchAccumulator`
+#[derive(Clone)]
+pub struct EventBatcher {
+    sender: mpsc::Sender<PendingEvent>,
+}
+
+impl EventBatcher {
+    /// Queue `event` for the next batch and wait for it to be written. Returns
+    /// false if the event was already stored. Replaceable events are refused;
+    /// store them with `PostgresDatabase::replace_event`.
+    pub async fn save(&self, event: Event) -> Result<bool> {
+        if event.is_replaceable() || event.is_parameterized_replaceable() {
+            return Err(anyhow!("replaceable event {} cannot be stored in a batch", event.id));
+        }
+
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(PendingEvent { event, reply })
+            .await
+            .map_err(|_| anyhow!("event batcher has stopped"))?;
+
+        response
+            .await
+            .map_err(|_| anyhow!("event batcher has stopped"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+/// Collects events from concurrent connections and stores them with
+/// `save_events_batch`, once `BATCH_WINDOW` has passed or `MAX_BATCH_SIZE`
+/// events are queued, so a burst of events costs a few round trips instead
+/// of one each
+pub struct BatchAccumulator {
+    database: PostgresDatabase,
+    receiver: mpsc::Receiver<PendingEvent>,
+    pending: Vec<PendingEvent>,
+}
+
+impl BatchAccumulator {
+    pub fn new(database: PostgresDatabase) -> (Self, EventBatcher) {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let accumulator = Self {
+            database,
+            receiver,
+            pending: Vec::with_capacity(MAX_BATCH_SIZE),
+        };
+        (accumulator, EventBatcher { sender })
+    }
+
+    /// Start the accumulator on its own task
+    pub fn spawn(database: PostgresDatabase) -> EventBatcher {
+        let (accumulator, batcher) = Self::new(database);
+        tokio::spawn(accumulator.run());
+        batcher
+    }
+
+    /// Write batches until every `EventBatcher` has been dropped, then flush
+    /// whatever is left
+    pub async fn run(mut self) {
+        let mut ticker = interval(BATCH_WINDOW);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                pending = self.receiver.recv() => match pending {
+                    Some(pending) => {
+                        self.pending.push(pending);
+                        if self.pending.len() >= MAX_BATCH_SIZE {
+                            self.flush().await;
+                            ticker.reset();
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush().await,
+            }
+        }
+
+        self.flush().await;
+        debug!("Batch accumulator stopped");
+    }
+
+    async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let batch = std::mem::take(&mut self.pending);
+        let (events, replies): (Vec<Event>, Vec<_>) = batch.into_iter().map(|p| (p.event, p.reply)).unzip();
+
+        match self.database.save_events_batch(&events).await {
+            Ok(inserted) => {
+                let inserted: HashSet<_> = inserted.into_iter().collect();
+                for (event, reply) in events.iter().zip(replies) {
+                    let _ = reply.send(Ok(inserted.contains(&event.id)));
+                }
+            }
+            // One bad event fails the whole statement, so the rest are stored
+            // one by one and only the bad one is refused. Pointless while the
+            // database itself is down.
+            Err(e) if events.len() > 1 && !database::is_unavailable(&e) && !e.is::<CircuitOpen>() => {
+                warn!("Failed to store batch of {} events, storing them one by one: {}", events.len(), e);
+                for (event, reply) in events.iter().zip(replies) {
+                    let result = self
+                        .database
+                        .save_events_batch(std::slice::from_ref(event))
+                        .await
+                        .map(|inserted| !inserted.is_empty())
+                        .map_err(|e| {
+                            error!("Failed to store event {}: {}", event.id, e);
+                            e.to_string()
+                        });
+                    let _ = reply.send(result);
+                }
+            }
+            Err(e) => {
+                error!("Failed to store batch of {} events: {}", events.len(), e);
+                let message = e.to_string();
+                for reply in replies {
+                    let _ = reply.send(Err(message.clone()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+
+use crate::config::Config;
+
+/// The address a connection should be attributed to. Connections from a
+/// trusted proxy are attributed to the client named in its `real_ip_header`;
+/// in a chain like `X-Forwarded-For: client, proxy1, proxy2` the nearest
+/// address not belonging to a trusted proxy is the client, since anything to
+/// its left could have been made up by that client. The walk stops at a hop
+/// that isn't an address, leaving the outermost trusted proxy before it. Falls
+/// back to `peer` when the header is missing or its nearest hop is malformed,
+/// or `peer` isn't trusted.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, config: &Config) -> IpAddr {
+    if !config.trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let Some(value) = headers
+        .get(config.real_ip_header.as_str())
+        .and_then(|value| value.to_str().ok())
+    else {
+        return peer;
+    };
+
+    // Walk from the nearest hop outwards; if every hop is a trusted proxy, the
+    // outermost one is as close to the client as we can get
+    let mut outermost = None;
+    for entry in value.rsplit(',') {
+        let Ok(addr) = entry.trim().parse::<IpAddr>() else {
+            break;
+        };
+        if !config.trusted_proxies.contains(&addr) {
+            return addr;
+        }
+        outermost = Some(addr);
+    }
+    outermost.unwrap_or(peer)
+}
+
+/// Header a reverse proxy names the request it forwarded with
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest forwarded request ID kept; anything longer isn't a proxy's
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The request ID a trusted proxy set on the request, so the relay's and the
+/// proxy's logs can be joined. IDs from anyone else, or that aren't short
+/// printable ASCII, are ignored so clients can't forge or garble log lines.
+pub fn forwarded_request_id(peer: IpAddr, headers: &HeaderMap, config: &Config) -> Option<String> {
+    if !config.trusted_proxies.contains(&peer) {
+        return None;
+    }
+
+    let request_id = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let well_formed = !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic());
+    well_formed.then(|| request_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn config(trusted_proxies: &[&str], real_ip_header: &str) -> Config {
+        let mut config = Config::from_env();
+        config.trusted_proxies = trusted_proxies.iter().map(|addr| addr.parse().unwrap()).collect();
+        config.real_ip_header = real_ip_header.to_string();
+        config
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_single_trusted_proxy() {
+        let config = config(&["127.0.0.1"], "X-Forwarded-For");
+        let resolved = resolve_client_ip(ip("127.0.0.1"), &headers("x-forwarded-for", "203.0.113.9"), &config);
+        assert_eq!(resolved, ip("203.0.113.9"));
+
+        // Without the header the proxy itself is all we know
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &HeaderMap::new(), &config), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_proxy_chain_stops_at_first_untrusted_hop() {
+        let config = config(&["127.0.0.1", "10.0.0.2"], "X-Forwarded-For");
+
+        // The client claims to be 198.51.100.1, but only 203.0.113.9 was added by a trusted proxy
+        let chain = headers("x-forwarded-for", "198.51.100.1, 203.0.113.9, 10.0.0.2");
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &chain, &config), ip("203.0.113.9"));
+
+        let all_trusted = headers("x-forwarded-for", "10.0.0.2");
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &all_trusted, &config), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_untrusted_peer_header_is_ignored() {
+        let config = config(&["10.0.0.2"], "X-Forwarded-For");
+        let spoofed = headers("x-forwarded-for", "203.0.113.9");
+        assert_eq!(resolve_client_ip(ip("198.51.100.7"), &spoofed, &config), ip("198.51.100.7"));
+
+        let config = self::config(&[], "X-Forwarded-For");
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &spoofed, &config), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_custom_header_and_malformed_values() {
+        let config = config(&["::1"], "CF-Connecting-IP");
+        let cloudflare = headers("cf-connecting-ip", "2001:db8::1");
+        assert_eq!(resolve_client_ip(ip("::1"), &cloudflare, &config), ip("2001:db8::1"));
+
+        // The default header is not consulted when another one is configured
+        let forwarded = headers("x-forwarded-for", "203.0.113.9");
+        assert_eq!(resolve_client_ip(ip("::1"), &forwarded, &config), ip("::1"));
+
+        let garbage = headers("cf-connecting-ip", "unknown");
+        assert_eq!(resolve_client_ip(ip("::1"), &garbage, &config), ip("::1"));
+    }
+
+    #[test]
+    fn test_malformed_hops_beyond_the_client_are_ignored() {
+        let config = config(&["127.0.0.1", "10.0.0.2"], "X-Forwarded-For");
+
+        // Whatever the client put in front of its own address can't hide it
+        let prefixed = headers("x-forwarded-for", "not-an-ip, 203.0.113.9, 10.0.0.2");
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &prefixed, &config), ip("203.0.113.9"));
+
+        // A malformed hop after the trusted proxies leaves the outermost of them
+        let broken = headers("x-forwarded-for", "203.0.113.9, unknown, 10.0.0.2");
+        assert_eq!(resolve_client_ip(ip("127.0.0.1"), &broken, &config), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_request_id_only_from_trusted_proxies() {
+        let config = config(&["127.0.0.1"], "X-Forwarded-For");
+        let request = headers("x-request-id", "req-42");
+
+        assert_eq!(forwarded_request_id(ip("127.0.0.1"), &request, &config).as_deref(), Some("req-42"));
+        assert_eq!(forwarded_request_id(ip("203.0.113.9"), &request, &config), None);
+        assert_eq!(forwarded_request_id(ip("127.0.0.1"), &HeaderMap::new(), &config), None);
+
+        let spaced = headers("x-request-id", "req 42");
+        assert_eq!(forwarded_request_id(ip("127.0.0.1"), &spaced, &config), None);
+        let mut long = HeaderMap::new();
+        long.insert("x-request-id", HeaderValue::from_str(&"a".repeat(129)).unwrap());
+        assert_eq!(forwarded_request_id(ip("127.0.0.1"), &long, &config), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::RwLock,
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+/// Re-reads `RELAY_CONFIG_FILE` every `metadata_refresh_interval_secs` and on
+/// SIGHUP, and applies the settings that are safe to change while the relay
+/// runs: the NIP-11 and NIP-05 operator fields and the kind, proof-of-work
+/// and URL policy. Everything else, such as `database_url` or `port`, keeps
+/// its startup value. The environment can't change after the relay starts,
+/// so without a config file there is nothing to reload.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    config: Arc<RwLock<Config>>,
+    refresh_interval: Duration,
+}
+
+impl ConfigWatcher {
+    pub fn new(config: Arc<RwLock<Config>>, refresh_interval: Duration) -> Self {
+        Self { config, refresh_interval }
+    }
+
+    /// Start the watcher on its own task
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    pub async fn run(self) {
+        let mut ticker = interval(self.refresh_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, and the startup config is already current
+        ticker.tick().await;
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("Failed to install SIGHUP handler, reloading on the interval only: {}", e);
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                Some(()) = async { hangup.as_mut()?.recv().await } => info!("SIGHUP received, reloading config"),
+            }
+            self.refresh().await;
+        }
+    }
+
+    /// Re-read the config file once, returning whether anything changed. A
+    /// file that can't be read leaves the current settings in place.
+    pub async fn refresh(&self) -> bool {
+        let Some(path) = self.config.read().await.config_file.clone() else {
+            return false;
+        };
+        let fresh = match Config::from_file(&path) {
+            Ok(fresh) => fresh,
+            Err(e) => {
+                warn!("Failed to reload config from {}: {}", path.display(), e);
+                return false;
+            }
+        };
+        let changed = apply_hot_reload(&mut *self.config.write().await, fresh);
+        if changed {
+            info!("Reloaded relay metadata and event policy from {}", path.display());
+        }
+        changed
+    }
+}
+
+/// Copy the hot-reloadable fields of `fresh` onto `config`, returning whether
+/// any of them differed
+pub fn apply_hot_reload(config: &mut Config, fresh: Config) -> bool {
+    let changed = config.relay_name != fresh.relay_name
+        || config.relay_description != fresh.relay_description
+        || config.relay_contact != fresh.relay_contact
+        || config.relay_pubkey != fresh.relay_pubkey
+        || config.kind_blocklist != fresh.kind_blocklist
+        || config.kind_allowlist != fresh.kind_allowlist
+        || config.min_pow_difficulty != fresh.min_pow_difficulty
+        || config.max_urls_per_event != fresh.max_urls_per_event
+        || config.blocked_url_domains != fresh.blocked_url_domains;
+
+    config.relay_name = fresh.relay_name;
+    config.relay_description = fresh.relay_description;
+    config.relay_contact = fresh.relay_contact;
+    config.relay_pubkey = fresh.relay_pubkey;
+    config.kind_blocklist = fresh.kind_blocklist;
+    config.kind_allowlist = fresh.kind_allowlist;
+    config.min_pow_difficulty = fresh.min_pow_difficulty;
+    config.max_urls_per_event = fresh.max_urls_per_event;
+    config.blocked_url_domains = fresh.blocked_url_domains;
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_hot_reload_keeps_startup_only_fields() {
+        let mut config = Config::from_env();
+        let startup = config.clone();
+
+        let mut fresh = config.clone();
+        assert!(!apply_hot_reload(&mut config, fresh.clone()));
+
+        fresh.relay_name = "Renamed Relay".to_string();
+        fresh.relay_contact = Some("ops@example.com".to_string());
+        fresh.kind_blocklist = vec![4];
+        fresh.min_pow_difficulty = 12;
+        fresh.blocked_url_domains = vec!["spam.example".to_string()];
+        fresh.database_url = "postgresql://elsewhere/other".to_string();
+        fresh.port = startup.port.wrapping_add(1);
+        assert!(apply_hot_reload(&mut config, fresh));
+
+        assert_eq!(config.relay_name, "Renamed Relay");
+        assert_eq!(config.relay_contact.as_deref(), Some("ops@example.com"));
+        assert_eq!(config.kind_blocklist, vec![4]);
+        assert_eq!(config.min_pow_difficulty, 12);
+        assert_eq!(config.blocked_url_domains, vec!["spam.example".to_string()]);
+        assert_eq!(config.database_url, startup.database_url);
+        assert_eq!(config.port, startup.port);
+    }
+    #[tokio::test]
+    async fn test_refresh_rereads_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("relay.env");
+        std::fs::write(&path, "RELAY_NAME=Before\n").unwrap();
+
+        let mut config = Config::from_file(&path).unwrap();
+        config.config_file = Some(path.clone());
+        let watcher = ConfigWatcher::new(Arc::new(RwLock::new(config)), Duration::from_secs(60));
+        assert!(!watcher.refresh().await);
+
+        std::fs::write(&path, "RELAY_NAME=After\nRELAY_MIN_POW_DIFFICULTY=20\n").unwrap();
+        assert!(watcher.refresh().await);
+        assert_eq!(watcher.config.read().await.relay_name, "After");
+        assert_eq!(watcher.config.read().await.min_pow_difficulty, 20);
+
+        // A file that disappears keeps the last good settings
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.refresh().await);
+        assert_eq!(watcher.config.read().await.relay_name, "After");
+    }
+}
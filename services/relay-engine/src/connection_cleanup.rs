@@ -0,0 +1,63 @@
+use dashmap::DashMap;
+use nostr::Filter;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::app_state::{AppState, ConnectedClient};
+
+/// Remove connections whose client has sent nothing for longer than
+/// `idle_timeout` as of `now`, and tell their socket tasks to close them.
+/// Connections with open subscriptions are waiting for events rather than
+/// idle, and are kept. Returns how many were removed.
+pub async fn cleanup_inactive_connections(
+    clients: &RwLock<HashMap<String, ConnectedClient>>,
+    subscriptions: &DashMap<String, DashMap<String, Filter>>,
+    idle_timeout: Duration,
+    now: Instant,
+) -> usize {
+    let mut clients = clients.write().await;
+    let idle: Vec<String> = clients
+        .iter()
+        .filter(|(client_id, client)| {
+            client.idle_for(now) > idle_timeout
+                && subscriptions.get(*client_id).is_none_or(|subs| subs.is_empty())
+        })
+        .map(|(client_id, _)| client_id.clone())
+        .collect();
+
+    for client_id in &idle {
+        if let Some(client) = clients.remove(client_id) {
+            warn!("Closing connection {} after {:?} without activity", client_id, client.idle_for(now));
+            client.close.cancel();
+        }
+    }
+    idle.len()
+}
+
+/// Periodically close idle connections, per `Config::connection_cleanup_interval_secs`
+/// and `Config::connection_idle_timeout_secs`
+pub fn start_connection_cleanup_task(state: AppState) {
+    let cleanup_interval = Duration::from_secs(state.config.connection_cleanup_interval_secs.max(1));
+    let idle_timeout = Duration::from_secs(state.config.connection_idle_timeout_secs);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cleanup_interval);
+        loop {
+            interval.tick().await;
+            let removed = cleanup_inactive_connections(&state.clients, &state.subscriptions, idle_timeout, Instant::now()).await;
+            if removed > 0 {
+                info!("Connection cleanup closed {} idle connections", removed);
+                state.metrics.record_connections_cleaned(removed);
+            }
+        }
+    });
+
+    info!(
+        "Connection cleanup task started (interval: {:?}, idle timeout: {:?})",
+        cleanup_interval, idle_timeout
+    );
+}
@@ -0,0 +1,64 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::app_state::AppState;
+use crate::database::RelayDatabase;
+
+/// Seconds a client refused for the connection limit is asked to wait
+pub const RETRY_AFTER_SECS: u64 = 60;
+
+/// One of the `max_total_connections` connections the relay accepts; the
+/// count is released when the slot is dropped, however the connection ends
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.open_connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Refusal of a connection over `max_total_connections`: `503 Service
+/// Unavailable` with a `Retry-After` header
+#[derive(Debug)]
+pub struct ConnectionLimitReached;
+
+impl IntoResponse for ConnectionLimitReached {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            "connection limit reached",
+        )
+            .into_response()
+    }
+}
+
+/// Take a connection slot before upgrading to a WebSocket. Once
+/// `max_total_connections` are open the upgrade is refused.
+pub fn acquire<D: RelayDatabase>(state: &AppState<D>) -> Result<ConnectionSlot, ConnectionLimitReached> {
+    let max = state.config.max_total_connections;
+    let open = state.open_connections.fetch_add(1, Ordering::AcqRel);
+    // The slot is created before checking so a refused attempt releases its count on drop
+    let slot = ConnectionSlot {
+        open_connections: state.open_connections.clone(),
+    };
+
+    if open >= max {
+        warn!("Refusing connection: {} connections are open (max {})", open, max);
+        state.metrics.record_connection_limit_reached();
+        return Err(ConnectionLimitReached);
+    }
+
+    Ok(slot)
+}
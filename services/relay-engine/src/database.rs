@@ -1,124 +1,1140 @@
-use nostr::{Event, Filter, JsonUtil};
-use sqlx::{PgPool, Row};
-use anyhow::Result;
+use nostr::{Event, EventId, Filter, JsonUtil};
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use anyhow::{anyhow, bail, Result};
+use lru::LruCache;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use tokio::sync::{mpsc, Mutex};
 use tracing::{debug, error};
 
+use crate::health::{DatabaseHealth, PROBE_TIMEOUT};
+use crate::metrics::Metrics;
+use crate::validation;
+
+pub mod circuit_breaker;
+pub mod event_cache;
+pub mod filter_builder;
+pub mod force_deleted;
+pub mod pagination;
+pub mod slow_query;
+
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitBreakerState};
+pub use event_cache::EventCache;
+pub use filter_builder::FilterSqlBuilder;
+pub use force_deleted::ForceDeletedEvents;
+pub use pagination::{Cursor, PagedEvents, QueryOptions};
+pub use slow_query::{SlowQuery, SlowQueryLogger, DEFAULT_SLOW_QUERY_THRESHOLD_MS};
+
+/// Default number of event IDs remembered by the duplicate check cache
+pub const DEFAULT_RECENT_IDS_CACHE_SIZE: usize = 50_000;
+
+// Exported rows read ahead of a slow consumer
+const EXPORT_BUFFER_SIZE: usize = 256;
+
+/// Most events accepted by a single `save_events_batch` call
+pub const MAX_BATCH_SIZE: usize = 500;
+
+// Rows removed per transaction by `prune_events`, bounding how many row locks it holds
+const PRUNE_BATCH_SIZE: i64 = 1000;
+
+/// Kinds kept regardless of age by `prune_events`: profiles, contact lists and relay lists
+pub const RETAINED_KINDS: [i32; 3] = [0, 3, 10002];
+
+/// The event storage operations relay logic is written against, so tests can
+/// run it over the in-memory `MockDatabase` instead of PostgreSQL
+pub trait RelayDatabase: Send + Sync + Clone + 'static {
+    fn save_event(&self, event: &Event) -> impl Future<Output = Result<()>> + Send;
+
+    fn event_exists(&self, event_id: &EventId) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Events matching `filter`, newest first
+    fn query_events(&self, filter: &Filter) -> impl Future<Output = Result<Vec<Event>>> + Send;
+
+    /// Delete the listed events written by `pubkey`, returning how many were removed
+    fn delete_events_by_author(&self, pubkey: &str, event_ids: Vec<String>) -> impl Future<Output = Result<u64>> + Send;
+}
+
+/// A NIP-28 public chat channel, keyed by the ID of its kind-40 creation event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub channel_id: String,
+    pub creator_pubkey: String,
+    /// The latest `name`/`about`/`picture` JSON from the creation or a metadata event
+    pub metadata: serde_json::Value,
+    /// `created_at` of the event the metadata came from
+    pub updated_at: i64,
+}
+
+/// A user's profile as indexed from their latest kind-0 event
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserProfile {
+    pub pubkey: String,
+    /// The `name`/`about`/`picture` JSON from the event's content
+    pub metadata: serde_json::Value,
+    /// When the profile was last written, in seconds since the epoch
+    pub updated_at: i64,
+}
+
 #[derive(Clone)]
 pub struct PostgresDatabase {
     pool: PgPool,
+    // Recently checked or stored event IDs and whether they are in the table,
+    // so duplicate checks for hot events skip the database round-trip
+    recent_ids: Arc<Mutex<LruCache<String, bool>>>,
+    metrics: Option<Metrics>,
+    circuit_breaker: CircuitBreaker,
+    // Total stored events as of the last `refresh_event_count`, so stats
+    // requests don't each count the whole table
+    event_count: Arc<AtomicU64>,
+    // Queries inside `guarded`, whether running or waiting for a connection
+    in_flight: Arc<AtomicUsize>,
+    event_cache: Option<EventCache>,
+    // Times the queries run outside a transaction, keeping the slow ones
+    queries: SlowQueryLogger,
+}
+
+// Counts a query as in flight until dropped, including when it is cancelled
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn start(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl PostgresDatabase {
     pub async fn new(database_url: &str) -> Result<Self> {
         let pool = PgPool::connect(database_url).await?;
-        Ok(Self { pool })
+        Ok(Self::from_pool(pool))
     }
 
-    pub async fn create_tables(&self) -> Result<()> {
-        // Create events table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS events (
-                id VARCHAR(64) PRIMARY KEY,
-                pubkey VARCHAR(64) NOT NULL,
-                created_at BIGINT NOT NULL,
-                kind INTEGER NOT NULL,
-                tags TEXT NOT NULL,
-                content TEXT NOT NULL,
-                sig VARCHAR(128) NOT NULL,
-                raw_event TEXT NOT NULL
-            );
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    /// Wrap an already configured pool
+    pub fn from_pool(pool: PgPool) -> Self {
+        Self {
+            queries: SlowQueryLogger::new(pool.clone(), DEFAULT_SLOW_QUERY_THRESHOLD_MS),
+            pool,
+            recent_ids: Arc::new(Mutex::new(Self::recent_ids_cache(DEFAULT_RECENT_IDS_CACHE_SIZE))),
+            metrics: None,
+            circuit_breaker: CircuitBreaker::new(CircuitBreakerConfig::default()),
+            event_count: Arc::new(AtomicU64::new(0)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            event_cache: None,
+        }
+    }
 
-        // Create indexes for better query performance
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_pubkey ON events(pubkey);")
-            .execute(&self.pool)
-            .await?;
+    /// Replace the duplicate check cache with an empty one holding up to `cache_size` IDs
+    pub fn with_recent_ids_cache_size(mut self, cache_size: usize) -> Self {
+        self.recent_ids = Arc::new(Mutex::new(Self::recent_ids_cache(cache_size)));
+        self
+    }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_created_at ON events(created_at);")
-            .execute(&self.pool)
-            .await?;
+    /// Report duplicate check cache hits and database fallbacks to `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_events_kind ON events(kind);")
-            .execute(&self.pool)
-            .await?;
+    /// Replace the circuit breaker guarding queries with a fresh, closed one
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = CircuitBreaker::new(config);
+        self
+    }
+
+    /// Keep queries slower than `threshold_ms` for `slow_queries`
+    pub fn with_slow_query_threshold(mut self, threshold_ms: u64) -> Self {
+        self.queries = SlowQueryLogger::new(self.pool.clone(), threshold_ms);
+        self
+    }
+
+    /// The most recent queries over the slow query threshold, newest first
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.queries.recent()
+    }
+
+    /// Write stored events through to `cache` and serve reads by ID from it
+    pub fn with_event_cache(mut self, cache: EventCache) -> Self {
+        self.event_cache = Some(cache);
+        self
+    }
+
+    // Run `operation` through the circuit breaker: refused outright while the
+    // breaker is open, and its outcome counts towards opening or closing it.
+    // Only errors saying the database is unreachable or overloaded count as
+    // failures; a query the database answered with an error shows it is up.
+    async fn guarded<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        if let Err(e) = self.circuit_breaker.allow() {
+            self.record_circuit_state();
+            return Err(e.into());
+        }
+
+        let in_flight = InFlight::start(&self.in_flight);
+        let result = operation.await;
+        drop(in_flight);
+        match &result {
+            Err(e) if is_unavailable(e) => self.circuit_breaker.record_failure(),
+            _ => self.circuit_breaker.record_success(),
+        }
+        self.record_circuit_state();
+        result
+    }
+
+    fn record_circuit_state(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_db_circuit_state(self.circuit_breaker.state());
+        }
+    }
+
+    /// Time a `SELECT 1` round-trip and report pool usage
+    pub async fn health_check(&self, warn_latency_ms: u64) -> DatabaseHealth {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(PROBE_TIMEOUT, sqlx::query("SELECT 1").execute(&self.queries)).await {
+            Ok(Ok(_)) => Ok(start.elapsed()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no response within {:?}", PROBE_TIMEOUT)),
+        };
+
+        let pool_size = self.pool.size();
+        let active_connections = pool_size.saturating_sub(self.pool.num_idle() as u32);
+        DatabaseHealth::from_probe(result, warn_latency_ms, pool_size, active_connections, self.circuit_breaker.state())
+    }
+
+    /// Report pool size, idle connections and queries waiting for a connection
+    /// to the metrics, then time checking a connection out. Waiting queries are
+    /// estimated as guarded queries in flight beyond the connections in use.
+    pub async fn sample_pool(&self) -> Result<Duration> {
+        let size = self.pool.size();
+        let idle = self.pool.num_idle();
+        let in_use = (size as usize).saturating_sub(idle);
+        let waiting = self.in_flight.load(Ordering::Relaxed).saturating_sub(in_use);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_db_pool_usage(size, idle, waiting);
+        }
 
-        debug!("Database tables created successfully");
+        let start = Instant::now();
+        let connection = tokio::time::timeout(PROBE_TIMEOUT, self.pool.acquire())
+            .await
+            .map_err(|_| anyhow!("no pool connection within {:?}", PROBE_TIMEOUT))??;
+        let elapsed = start.elapsed();
+        drop(connection);
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record_db_pool_acquire(elapsed.as_secs_f64());
+        }
+        Ok(elapsed)
+    }
+
+    /// Bring the schema up to date by applying the versioned migrations in
+    /// storage-layer that this database hasn't run yet. They are recorded in
+    /// `_sqlx_migrations`, the same history storage-layer's `MIGRATOR` keeps.
+    pub async fn migrate(&self) -> Result<()> {
+        sqlx::migrate!("../storage-layer/migrations").run(&self.pool).await?;
+
+        debug!("Database migrations applied successfully");
         Ok(())
     }
 
-    pub async fn save_event(&self, event: &Event) -> Result<()> {
-        debug!("Saving event {}", event.id);
+    /// Store many events with a single multi-row `INSERT`, returning the IDs of
+    /// those that weren't already stored. Replaceable events need the
+    /// transactional path in `replace_event` and are refused here.
+    pub async fn save_events_batch(&self, events: &[Event]) -> Result<Vec<EventId>> {
+        if events.is_empty() {
+            return Ok(Vec::new());
+        }
+        if events.len() > MAX_BATCH_SIZE {
+            bail!("batch of {} events exceeds the limit of {}", events.len(), MAX_BATCH_SIZE);
+        }
+        if let Some(event) = events.iter().find(|event| event.is_replaceable() || event.is_parameterized_replaceable()) {
+            bail!("replaceable event {} cannot be stored in a batch", event.id);
+        }
+
+        debug!("Saving batch of {} events", events.len());
+
+        let rows = self
+            .guarded(async {
+                let mut builder = QueryBuilder::<Postgres>::new(
+                    "WITH inserted AS (INSERT INTO events (id, pubkey, created_at, kind, tags, content, sig, raw_event, d_tag, expires_at, delegator) ",
+                );
+                builder.push_values(events, |mut row, event| {
+                    row.push_bind(event.id.to_string())
+                        .push_bind(event.pubkey.to_string())
+                        .push_bind(event.created_at.as_u64() as i64)
+                        .push_bind(event.kind.as_u32() as i32)
+                        .push_bind(sqlx::types::Json(&event.tags))
+                        .push_bind(&event.content)
+                        .push_bind(event.signature().to_string())
+                        .push_bind(event.as_json())
+                        .push_bind(Self::d_tag(event))
+                        .push_bind(event.expiration().map(|ts| ts.as_u64() as i64))
+                        .push_bind(Self::delegator(event));
+                });
+                builder.push(" ON CONFLICT (id) DO NOTHING RETURNING id)");
+
+                // Tags of the events this statement inserted
+                let (mut tag_event_ids, mut tag_names, mut tag_values) = (Vec::new(), Vec::new(), Vec::new());
+                for event in events {
+                    for (name, value) in Self::indexed_tags(event) {
+                        tag_event_ids.push(event.id.to_string());
+                        tag_names.push(name);
+                        tag_values.push(value);
+                    }
+                }
+                builder
+                    .push(", tagged AS (INSERT INTO event_tags (event_id, tag_name, tag_value) SELECT * FROM UNNEST(")
+                    .push_bind(tag_event_ids)
+                    .push("::VARCHAR[], ")
+                    .push_bind(tag_names)
+                    .push("::VARCHAR[], ")
+                    .push_bind(tag_values)
+                    .push("::TEXT[]) AS tag(event_id, tag_name, tag_value) WHERE tag.event_id IN (SELECT id FROM inserted))")
+                    .push(" SELECT id FROM inserted");
+
+                Ok(builder.build_query_scalar::<String>().fetch_all(&self.queries).await?)
+            })
+            .await?;
+
+        // Every event in the batch is stored now, whether or not this call inserted it
+        let mut recent_ids = self.recent_ids.lock().await;
+        for event in events {
+            recent_ids.put(event.id.to_string(), true);
+        }
+        drop(recent_ids);
+        self.cache_events(events).await;
+
+        let inserted = rows
+            .iter()
+            .map(EventId::from_hex)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        debug!("Saved {} of {} batched events", inserted.len(), events.len());
+        Ok(inserted)
+    }
+
+    /// Store a replaceable event (NIP-16), removing older versions from the same
+    /// pubkey and kind in the same transaction. Parameterized replaceable events
+    /// (NIP-33) are additionally matched on their 'd' tag. If a newer version is
+    /// already stored, the incoming event is dropped; between versions with the
+    /// same `created_at`, the one with the lowest ID is kept (NIP-01).
+    pub async fn replace_event(&self, event: &Event) -> Result<()> {
+        debug!("Replacing event {}", event.id);
+
+        self.guarded(async {
+            let pubkey = event.pubkey.to_string();
+            let kind = event.kind.as_u32() as i32;
+            let created_at = event.created_at.as_u64() as i64;
+            let d_tag = Self::d_tag(event);
+            let id = event.id.to_string();
+
+            let mut tx = self.pool.begin().await?;
+
+            let newer = sqlx::query(
+                r#"
+                SELECT COUNT(*) as count FROM events
+                WHERE pubkey = $1 AND kind = $2
+                  AND (created_at > $3 OR (created_at = $3 AND id < $5))
+                  AND ($4::VARCHAR IS NULL OR d_tag = $4)
+                "#,
+            )
+            .bind(&pubkey)
+            .bind(kind)
+            .bind(created_at)
+            .bind(&d_tag)
+            .bind(&id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let newer_count: i64 = newer.get("count");
+            if newer_count > 0 {
+                debug!("Newer version already stored, skipping event {}", event.id);
+                tx.commit().await?;
+                return Ok(());
+            }
+
+            let replaced: Vec<String> = match &d_tag {
+                Some(d_tag) => {
+                    sqlx::query_scalar(
+                        r#"
+                        DELETE FROM events
+                        WHERE pubkey = $1 AND kind = $2 AND d_tag = $3
+                          AND (created_at < $4 OR (created_at = $4 AND id > $5))
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(&pubkey)
+                    .bind(kind)
+                    .bind(d_tag)
+                    .bind(created_at)
+                    .bind(&id)
+                    .fetch_all(&mut *tx)
+                    .await?
+                }
+                None => {
+                    sqlx::query_scalar(
+                        r#"
+                        DELETE FROM events
+                        WHERE pubkey = $1 AND kind = $2
+                          AND (created_at < $3 OR (created_at = $3 AND id > $4))
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(&pubkey)
+                    .bind(kind)
+                    .bind(created_at)
+                    .bind(&id)
+                    .fetch_all(&mut *tx)
+                    .await?
+                }
+            };
+
+            Self::insert_event(&mut *tx, event).await?;
+
+            tx.commit().await?;
 
-        let tags_json = serde_json::to_string(&event.tags)?;
+            self.forget_ids(&replaced).await;
+            self.recent_ids.lock().await.put(event.id.to_string(), true);
+            self.cache_events(std::slice::from_ref(event)).await;
+
+            debug!("Replaced event {}", event.id);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_event<'e, E>(executor: E, event: &Event) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let tags_json = serde_json::to_value(&event.tags)?;
         let raw_event = event.as_json().to_string();
+        let (tag_names, tag_values): (Vec<String>, Vec<String>) = Self::indexed_tags(event).unzip();
 
+        // The event and its tag rows go in one statement; tags are only added
+        // when the event itself was inserted
         sqlx::query(
             r#"
-            INSERT INTO events (id, pubkey, created_at, kind, tags, content, sig, raw_event)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT (id) DO NOTHING
+            WITH inserted AS (
+                INSERT INTO events (id, pubkey, created_at, kind, tags, content, sig, raw_event, d_tag, expires_at, delegator)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (id) DO NOTHING
+                RETURNING id
+            )
+            INSERT INTO event_tags (event_id, tag_name, tag_value)
+            SELECT inserted.id, tag.tag_name, tag.tag_value
+            FROM inserted, UNNEST($12::VARCHAR[], $13::TEXT[]) AS tag(tag_name, tag_value)
             "#,
         )
         .bind(event.id.to_string())
         .bind(event.pubkey.to_string())
         .bind(event.created_at.as_u64() as i64)
         .bind(event.kind.as_u32() as i32)
-        .bind(tags_json)
+        .bind(sqlx::types::Json(tags_json))
         .bind(&event.content)
         .bind(event.signature().to_string())
         .bind(raw_event)
-        .execute(&self.pool)
+        .bind(Self::d_tag(event))
+        .bind(event.expiration().map(|ts| ts.as_u64() as i64))
+        .bind(Self::delegator(event))
+        .bind(tag_names)
+        .bind(tag_values)
+        .execute(executor)
         .await?;
 
+        Ok(())
+    }
+
+    // (name, value) rows for `event_tags`: the single-letter tags filters can
+    // match on, with their first value
+    fn indexed_tags(event: &Event) -> impl Iterator<Item = (String, String)> + '_ {
+        event.tags.iter().filter_map(|tag| {
+            let letter = tag.single_letter_tag()?;
+            let value = tag.content()?;
+            Some((letter.as_char().to_string(), value.to_string()))
+        })
+    }
+
+    // 'd' tag value used for NIP-33 deduplication, set only for parameterized
+    // replaceable events; one without a 'd' tag has an empty identifier
+    fn d_tag(event: &Event) -> Option<String> {
+        event
+            .is_parameterized_replaceable()
+            .then(|| event.identifier().unwrap_or_default().to_string())
+    }
+
+    // NIP-26 delegator, which author filters also match. The token is checked
+    // again here, so an event that skipped validation can't claim a delegator.
+    fn delegator(event: &Event) -> Option<String> {
+        validation::verified_delegator(event).ok().flatten().map(|delegator| delegator.to_hex())
+    }
+
+    // The filters' authors that have delegated events stored, for
+    // `FilterSqlBuilder::with_delegators`. Usually none, and the partial
+    // delegator index keeps this lookup cheap.
+    async fn stored_delegators<'e, E>(executor: E, filters: &[Filter]) -> Result<Vec<String>>
+    where
+        E: sqlx::Executor<'e, Database = Postgres>,
+    {
+        let authors: Vec<String> = filters
+            .iter()
+            .flat_map(|filter| filter.authors.iter().flatten())
+            .map(|author| author.to_hex())
+            .collect();
+        if authors.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let delegators = sqlx::query_scalar("SELECT DISTINCT delegator FROM events WHERE delegator = ANY($1)")
+            .bind(authors)
+            .fetch_all(executor)
+            .await?;
+        Ok(delegators)
+    }
+
+    /// Count events matching any of the filters (NIP-45)
+    pub async fn count_events(&self, filters: &[Filter]) -> Result<u64> {
+        debug!("Counting events with {} filters", filters.len());
+
+        self.guarded(async {
+            let delegators = Self::stored_delegators(&self.queries, filters).await?;
+            let mut query = FilterSqlBuilder::build_count(filters, &delegators);
+            let row = query.build().fetch_one(&self.queries).await?;
+
+            let count: i64 = row.get("count");
+            Ok(count as u64)
+        })
+        .await
+    }
+
+    /// Count every stored event. This scans the whole table; stats endpoints
+    /// should use `cached_event_count` instead.
+    pub async fn count_all_events(&self) -> Result<u64> {
+        self.guarded(async {
+            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM events")
+                .fetch_one(&self.queries)
+                .await?;
+            Ok(count as u64)
+        })
+        .await
+    }
+
+    /// Count events created after `since` (unix seconds)
+    pub async fn count_events_since(&self, since: i64) -> Result<u64> {
+        self.guarded(async {
+            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE created_at > $1")
+                .bind(since)
+                .fetch_one(&self.queries)
+                .await?;
+            Ok(count as u64)
+        })
+        .await
+    }
+
+    /// Recount stored events and cache the total for `cached_event_count`
+    pub async fn refresh_event_count(&self) -> Result<u64> {
+        let count = self.count_all_events().await?;
+        self.event_count.store(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    /// Stored events as of the last `refresh_event_count`
+    pub fn cached_event_count(&self) -> u64 {
+        self.event_count.load(Ordering::Relaxed)
+    }
+
+    /// Delete every event authored by `pubkey`, e.g. for a data erasure request.
+    /// Returns the number of rows removed.
+    pub async fn delete_all_events_by_pubkey(&self, pubkey: &str) -> Result<u64> {
+        self.guarded(async {
+            let deleted: Vec<String> = sqlx::query_scalar(&deleting_profiles("DELETE FROM events WHERE pubkey = $1"))
+                .bind(pubkey)
+                .fetch_all(&self.queries)
+                .await?;
+
+            self.forget_ids(&deleted).await;
+            debug!("Deleted all {} events by {}", deleted.len(), pubkey);
+            Ok(deleted.len() as u64)
+        })
+        .await
+    }
+
+    /// Delete a single event whoever wrote it, for an operator removing abusive
+    /// content. Returns whether it was stored.
+    pub async fn force_delete_event(&self, id: &str) -> Result<bool> {
+        self.guarded(async {
+            let deleted: Vec<String> = sqlx::query_scalar(&deleting_profiles("DELETE FROM events WHERE id = $1"))
+                .bind(id)
+                .fetch_all(&self.queries)
+                .await?;
+
+            self.forget_ids(&deleted).await;
+            debug!("Force deleted event {}", id);
+            Ok(!deleted.is_empty())
+        })
+        .await
+    }
+
+    /// Record an admin action in the moderation log
+    pub async fn log_moderation_action(&self, admin: &str, action: &str, target: &str) -> Result<()> {
+        self.guarded(async {
+            sqlx::query("INSERT INTO moderation_log (admin, action, target) VALUES ($1, $2, $3)")
+                .bind(admin)
+                .bind(action)
+                .bind(target)
+                .execute(&self.queries)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Pubkeys persisted in the blocklist
+    pub async fn load_blocked_pubkeys(&self) -> Result<Vec<String>> {
+        self.guarded(async {
+            let pubkeys = sqlx::query_scalar("SELECT pubkey FROM blocked_pubkeys ORDER BY blocked_at")
+                .fetch_all(&self.queries)
+                .await?;
+            Ok(pubkeys)
+        })
+        .await
+    }
+
+    pub async fn block_pubkey(&self, pubkey: &str) -> Result<()> {
+        self.guarded(async {
+            sqlx::query("INSERT INTO blocked_pubkeys (pubkey) VALUES ($1) ON CONFLICT (pubkey) DO NOTHING")
+                .bind(pubkey)
+                .execute(&self.queries)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns whether the pubkey was in the stored blocklist
+    pub async fn unblock_pubkey(&self, pubkey: &str) -> Result<bool> {
+        self.guarded(async {
+            let result = sqlx::query("DELETE FROM blocked_pubkeys WHERE pubkey = $1")
+                .bind(pubkey)
+                .execute(&self.queries)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    /// Record a channel's metadata. The first write creates the channel; later
+    /// ones only apply when they come from its creator and are newer than the
+    /// stored metadata. Returns whether anything was written.
+    pub async fn create_or_update_channel(&self, channel: &Channel) -> Result<bool> {
+        self.guarded(async {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO channels (channel_id, creator_pubkey, metadata, updated_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (channel_id) DO UPDATE
+                SET metadata = EXCLUDED.metadata, updated_at = EXCLUDED.updated_at
+                WHERE channels.creator_pubkey = EXCLUDED.creator_pubkey
+                  AND channels.updated_at < EXCLUDED.updated_at
+                "#,
+            )
+            .bind(&channel.channel_id)
+            .bind(&channel.creator_pubkey)
+            .bind(&channel.metadata)
+            .bind(channel.updated_at)
+            .execute(&self.queries)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    /// Apply kind-41 metadata to a channel already indexed here, when it's
+    /// from the channel's creator and newer than what is stored. Returns
+    /// false, changing nothing, for a channel this relay hasn't seen created.
+    pub async fn update_channel(&self, channel: &Channel) -> Result<bool> {
+        self.guarded(async {
+            let result = sqlx::query(
+                r#"
+                UPDATE channels
+                SET metadata = $3, updated_at = $4
+                WHERE channel_id = $1 AND creator_pubkey = $2 AND updated_at < $4
+                "#,
+            )
+            .bind(&channel.channel_id)
+            .bind(&channel.creator_pubkey)
+            .bind(&channel.metadata)
+            .bind(channel.updated_at)
+            .execute(&self.queries)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    pub async fn get_channel_metadata(&self, channel_id: &str) -> Result<Option<Channel>> {
+        self.guarded(async {
+            let row = sqlx::query("SELECT channel_id, creator_pubkey, metadata, updated_at FROM channels WHERE channel_id = $1")
+                .bind(channel_id)
+                .fetch_optional(&self.queries)
+                .await?;
+            Ok(row.map(|row| Channel {
+                channel_id: row.get("channel_id"),
+                creator_pubkey: row.get("creator_pubkey"),
+                metadata: row.get("metadata"),
+                updated_at: row.get("updated_at"),
+            }))
+        })
+        .await
+    }
+
+    /// Record a user's profile metadata, replacing what was stored for them
+    pub async fn upsert_profile(&self, pubkey: &str, metadata: serde_json::Value) -> Result<()> {
+        self.guarded(async {
+            sqlx::query(
+                r#"
+                INSERT INTO user_profiles (pubkey, metadata, updated_at)
+                VALUES ($1, $2, NOW())
+                ON CONFLICT (pubkey) DO UPDATE
+                SET metadata = EXCLUDED.metadata, updated_at = NOW()
+                "#,
+            )
+            .bind(pubkey)
+            .bind(metadata)
+            .execute(&self.queries)
+            .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Drop a user's indexed profile
+    pub async fn delete_profile(&self, pubkey: &str) -> Result<()> {
+        self.guarded(async {
+            sqlx::query("DELETE FROM user_profiles WHERE pubkey = $1")
+                .bind(pubkey)
+                .execute(&self.queries)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_profile(&self, pubkey: &str) -> Result<Option<UserProfile>> {
+        self.guarded(async {
+            let row = sqlx::query(
+                "SELECT pubkey, metadata, EXTRACT(EPOCH FROM updated_at)::BIGINT AS updated_at FROM user_profiles WHERE pubkey = $1",
+            )
+            .bind(pubkey)
+            .fetch_optional(&self.queries)
+            .await?;
+            Ok(row.map(|row| UserProfile {
+                pubkey: row.get("pubkey"),
+                metadata: row.get("metadata"),
+                updated_at: row.get("updated_at"),
+            }))
+        })
+        .await
+    }
+
+    /// Remove events whose NIP-40 expiration has passed. Returns the number of rows removed.
+    pub async fn delete_expired_events(&self) -> Result<u64> {
+        self.guarded(async {
+            let deleted: Vec<String> = sqlx::query_scalar(&deleting_profiles(
+                "DELETE FROM events WHERE expires_at IS NOT NULL AND expires_at <= EXTRACT(EPOCH FROM NOW())",
+            ))
+            .fetch_all(&self.queries)
+            .await?;
+
+            self.forget_ids(&deleted).await;
+            debug!("Deleted {} expired events", deleted.len());
+            Ok(deleted.len() as u64)
+        })
+        .await
+    }
+
+    /// Remove events created more than `retention_secs` ago, except the kinds in
+    /// `RETAINED_KINDS`. Rows are deleted in batches, each in its own transaction.
+    /// Returns the number of rows removed.
+    pub async fn prune_events(&self, retention_secs: u64) -> Result<u64> {
+        self.guarded(async {
+            let mut total = 0;
+            loop {
+                let mut tx = self.pool.begin().await?;
+                let deleted: Vec<String> = sqlx::query_scalar(
+                    r#"
+                    DELETE FROM events WHERE id IN (
+                        SELECT id FROM events
+                        WHERE created_at < EXTRACT(EPOCH FROM NOW()) - $1 AND kind <> ALL($2)
+                        LIMIT $3
+                    )
+                    RETURNING id
+                    "#,
+                )
+                .bind(retention_secs as i64)
+                .bind(&RETAINED_KINDS[..])
+                .bind(PRUNE_BATCH_SIZE)
+                .fetch_all(&mut *tx)
+                .await?;
+                tx.commit().await?;
+
+                self.forget_ids(&deleted).await;
+                total += deleted.len() as u64;
+                if (deleted.len() as i64) < PRUNE_BATCH_SIZE {
+                    break;
+                }
+            }
+            debug!("Pruned {} events past the retention window", total);
+            Ok(total)
+        })
+        .await
+    }
+
+    fn recent_ids_cache(cache_size: usize) -> LruCache<String, bool> {
+        LruCache::new(NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN))
+    }
+
+    // Drop deleted events from the duplicate check cache so they can be stored
+    // again, and from the event cache so they are no longer served
+    async fn forget_ids(&self, ids: &[String]) {
+        let mut recent_ids = self.recent_ids.lock().await;
+        for id in ids {
+            recent_ids.pop(id);
+        }
+        drop(recent_ids);
+
+        if let Some(cache) = &self.event_cache {
+            cache.invalidate(ids).await;
+        }
+    }
+
+    async fn cache_events(&self, events: &[Event]) {
+        if let Some(cache) = &self.event_cache {
+            cache.put(events).await;
+        }
+    }
+
+    /// A stored event by ID, read from the event cache when it has it
+    pub async fn get_event_by_id(&self, event_id: &EventId) -> Result<Option<Event>> {
+        if let Some(cache) = &self.event_cache {
+            if let Some(event) = cache.get(event_id).await {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_event_cache_hit("get_event_by_id");
+                }
+                return Ok(Some(event));
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.record_event_cache_miss("get_event_by_id");
+            }
+        }
+
+        let event = self.get_events(&Filter::new().id(*event_id)).await?.into_iter().next();
+        if let Some(event) = &event {
+            self.cache_events(std::slice::from_ref(event)).await;
+        }
+        Ok(event)
+    }
+
+    /// Stream every stored event matching `filter` as its raw JSON, oldest first,
+    /// ignoring the filter's limit. Rows are read one at a time, so exporting
+    /// the whole table doesn't hold it in memory.
+    pub fn export_events(&self, filter: &Filter) -> Result<BoxStream<'static, Result<String>>> {
+        self.circuit_breaker.allow()?;
+
+        let pool = self.pool.clone();
+        let circuit_breaker = self.circuit_breaker.clone();
+        let filter = filter.clone();
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER_SIZE);
+
+        tokio::spawn(async move {
+            let delegators = match Self::stored_delegators(&pool, std::slice::from_ref(&filter)).await {
+                Ok(delegators) => delegators,
+                Err(e) => {
+                    circuit_breaker.record_failure();
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            let mut query = FilterSqlBuilder::new(&filter).with_delegators(&delegators).build_export();
+            let mut rows = query.build_query_scalar::<String>().fetch(&pool);
+            while let Some(row) = rows.next().await {
+                let failed = row.is_err();
+                if failed {
+                    circuit_breaker.record_failure();
+                }
+                // Stop reading once the consumer goes away
+                if tx.send(row.map_err(Into::into)).await.is_err() || failed {
+                    return;
+                }
+            }
+            circuit_breaker.record_success();
+        });
+
+        Ok(stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|row| (row, rx)) }).boxed())
+    }
+
+    /// Fetch one page of events matching `filter`, newest first. Pass the
+    /// returned `next_cursor` as `until_exclusive_id` to continue.
+    pub async fn query_events_paged(&self, filter: &Filter, options: &QueryOptions) -> Result<PagedEvents> {
+        // A malformed cursor is the client's fault, not the database's
+        let until = options.until_cursor()?;
+        let since = options.since_cursor()?;
+
+        self.guarded(async {
+            let delegators = Self::stored_delegators(&self.queries, std::slice::from_ref(filter)).await?;
+            let builder = FilterSqlBuilder::new(filter).with_delegators(&delegators);
+            let mut query = builder.build_page(until.as_ref(), since.as_ref());
+            debug!("Executing query: {}", query.sql());
+
+            let raw_events: Vec<String> = query.build_query_scalar().fetch_all(&self.queries).await?;
+
+            let limit = builder.limit();
+            let has_more = raw_events.len() > limit;
+            let events: Vec<Event> = raw_events
+                .iter()
+                .take(limit)
+                .filter_map(|raw_event| match serde_json::from_str::<Event>(raw_event) {
+                    Ok(event) => Some(event),
+                    Err(e) => {
+                        error!("Failed to deserialize event: {}", e);
+                        None
+                    }
+                })
+                .collect();
+
+            let next_cursor = if has_more {
+                events.last().map(|event| Cursor::from_event(event).encode())
+            } else {
+                None
+            };
+
+            Ok(PagedEvents { events, has_more, next_cursor })
+        })
+        .await
+    }
+
+    /// The event `root_id` and the events replying to it, replies to those and
+    /// so on, up to `depth` replies deep and at most `limit` events, oldest
+    /// first. Only NIP-10 replies are followed, not mentions; see
+    /// `reply_targets`. Empty when the root isn't stored.
+    pub async fn get_event_thread(&self, root_id: &str, depth: u32, limit: usize) -> Result<Vec<Event>> {
+        self.guarded(async {
+            let root: Vec<String> = sqlx::query_scalar(
+                r#"
+                SELECT raw_event FROM events
+                WHERE id = $1
+                  AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))
+                "#,
+            )
+            .bind(root_id)
+            .fetch_all(&self.queries)
+            .await?;
+            let mut thread = parse_raw_events(&root);
+
+            // One level per query, each capped at what's left of `limit`, so a
+            // widely referenced event can't make the walk unbounded
+            let mut parents = vec![root_id.to_string()];
+            for _ in 0..depth {
+                let remaining = limit.saturating_sub(thread.len());
+                if thread.is_empty() || parents.is_empty() || remaining == 0 {
+                    break;
+                }
+
+                let raw_events: Vec<String> = sqlx::query_scalar(
+                    r#"
+                    SELECT raw_event FROM events
+                    WHERE id IN (SELECT event_id FROM event_tags WHERE tag_name = 'e' AND tag_value = ANY($1))
+                      AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))
+                    ORDER BY created_at ASC, id ASC
+                    LIMIT $2
+                    "#,
+                )
+                .bind(&parents)
+                .bind(remaining as i64)
+                .fetch_all(&self.queries)
+                .await?;
+
+                let replies: Vec<Event> = parse_raw_events(&raw_events)
+                    .into_iter()
+                    .filter(|event| reply_targets(event).iter().any(|target| parents.contains(target)))
+                    .filter(|event| thread.iter().all(|seen| seen.id != event.id))
+                    .collect();
+                parents = replies.iter().map(|event| event.id.to_hex()).collect();
+                thread.extend(replies);
+            }
+
+            thread.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+            Ok(thread)
+        })
+        .await
+    }
+
+    pub async fn get_events(&self, filter: &Filter) -> Result<Vec<Event>> {
+        debug!("Getting events with filter: {:?}", filter);
+
+        self.guarded(async {
+            let delegators = Self::stored_delegators(&self.queries, std::slice::from_ref(filter)).await?;
+            let mut query = FilterSqlBuilder::new(filter).with_delegators(&delegators).build();
+            debug!("Executing query: {}", query.sql());
+
+            let rows = query.build()
+                .fetch_all(&self.queries)
+                .await?;
+
+            let mut events = Vec::new();
+            for row in rows {
+                let raw_event_str: String = row.get("raw_event");
+                match serde_json::from_str::<Event>(&raw_event_str) {
+                    Ok(event) => events.push(event),
+                    Err(e) => error!("Failed to deserialize event: {}", e),
+                }
+            }
+
+            debug!("Found {} events matching filter", events.len());
+            Ok(events)
+        })
+        .await
+    }
+}
+
+impl RelayDatabase for PostgresDatabase {
+    async fn save_event(&self, event: &Event) -> Result<()> {
+        debug!("Saving event {}", event.id);
+
+        if event.is_parameterized_replaceable() {
+            return self.replace_event(event).await;
+        }
+
+        self.guarded(Self::insert_event(&self.queries, event)).await?;
+        self.recent_ids.lock().await.put(event.id.to_string(), true);
+        self.cache_events(std::slice::from_ref(event)).await;
+
         debug!("Saved event {}", event.id);
         Ok(())
     }
 
-    pub async fn event_exists(&self, event_id: &nostr::EventId) -> Result<bool> {
+    async fn event_exists(&self, event_id: &EventId) -> Result<bool> {
         debug!("Checking if event exists: {}", event_id);
 
-        let row = sqlx::query("SELECT COUNT(*) as count FROM events WHERE id = $1")
-            .bind(event_id.to_string())
-            .fetch_one(&self.pool)
+        let id = event_id.to_string();
+        if let Some(exists) = self.recent_ids.lock().await.get(&id).copied() {
+            if let Some(metrics) = &self.metrics {
+                metrics.record_id_cache_hit();
+            }
+            return Ok(exists);
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record_id_cache_miss();
+        }
+
+        let row = self
+            .guarded(async {
+                let row = sqlx::query("SELECT COUNT(*) as count FROM events WHERE id = $1")
+                    .bind(&id)
+                    .fetch_one(&self.queries)
+                    .await?;
+                Ok(row)
+            })
             .await?;
 
         let count: i64 = row.get("count");
+        self.recent_ids.lock().await.put(id, count > 0);
         Ok(count > 0)
     }
 
-    pub async fn query_events(&self, filter: &Filter) -> Result<Vec<Event>> {
+    async fn query_events(&self, filter: &Filter) -> Result<Vec<Event>> {
         self.get_events(filter).await
     }
 
-    pub async fn get_events(&self, filter: &Filter) -> Result<Vec<Event>> {
-        debug!("Getting events with filter: {:?}", filter);
+    /// Delete the given events, limited to those authored by `pubkey` (NIP-09).
+    /// Returns the number of rows removed.
+    async fn delete_events_by_author(&self, pubkey: &str, event_ids: Vec<String>) -> Result<u64> {
+        debug!("Deleting {} events for author {}", event_ids.len(), pubkey);
 
-        // Start building the query - simplified for cross-database compatibility
-        let mut query = String::from("SELECT raw_event FROM events WHERE 1=1");
+        self.guarded(async {
+            let deleted: Vec<String> = sqlx::query_scalar(&deleting_profiles("DELETE FROM events WHERE id = ANY($1) AND pubkey = $2"))
+                .bind(event_ids)
+                .bind(pubkey)
+                .fetch_all(&self.queries)
+                .await?;
 
-        // Add ordering and limit (simplified)
-        query.push_str(" ORDER BY created_at DESC LIMIT 100");
+            self.forget_ids(&deleted).await;
+            Ok(deleted.len() as u64)
+        })
+        .await
+    }
+}
 
-        debug!("Executing query: {}", query);
+// Run `delete`, a `DELETE FROM events`, dropping the indexed profile of anyone
+// whose kind-0 event it removed, and select the removed IDs
+fn deleting_profiles(delete: &str) -> String {
+    format!(
+        "WITH deleted AS ({} RETURNING id, pubkey, kind), \
+         profiles AS (DELETE FROM user_profiles WHERE pubkey IN (SELECT pubkey FROM deleted WHERE kind = 0)) \
+         SELECT id FROM deleted",
+        delete
+    )
+}
 
-        let rows = sqlx::query(&query)
-            .fetch_all(&self.pool)
-            .await?;
+/// Whether an error means the database couldn't be reached or couldn't take
+/// the query: connection and pool errors, timeouts, and the SQLSTATEs for lost
+/// connections (class 08), exhausted resources (class 53), a server shutting
+/// down (57P01–57P03) and cancelled statements (57014)
+pub fn is_unavailable(error: &anyhow::Error) -> bool {
+    if error.is::<tokio::time::error::Elapsed>() {
+        return true;
+    }
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed) => true,
+        Some(sqlx::Error::Database(e)) => e.code().is_some_and(|code| {
+            code.starts_with("08") || code.starts_with("53") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03" | "57014")
+        }),
+        _ => false,
+    }
+}
 
-        let mut events = Vec::new();
-        for row in rows {
-            let raw_event_str: String = row.get("raw_event");
-            match serde_json::from_str::<Event>(&raw_event_str) {
-                Ok(event) => events.push(event),
-                Err(e) => error!("Failed to deserialize event: {}", e),
+// Events from `raw_event` columns, skipping any that no longer deserialize
+fn parse_raw_events(raw_events: &[String]) -> Vec<Event> {
+    raw_events
+        .iter()
+        .filter_map(|raw_event| match serde_json::from_str::<Event>(raw_event) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                error!("Failed to deserialize event: {}", e);
+                None
             }
-        }
+        })
+        .collect()
+}
+
+/// Hex IDs of the events `event` replies to under NIP-10: its `e` tags marked
+/// `root` or `reply` or, in events using the older positional form without
+/// markers, its first and last `e` tags. Other `e` tags are mentions.
+pub fn reply_targets(event: &Event) -> Vec<String> {
+    let e_tags: Vec<&[String]> = event
+        .tags
+        .iter()
+        .map(|tag| tag.as_vec())
+        .filter(|tag| tag.len() >= 2 && tag[0] == "e")
+        .collect();
 
-        debug!("Found {} events matching filter", events.len());
-        Ok(events)
+    let marked = e_tags.iter().any(|tag| tag.get(3).is_some_and(|marker| !marker.is_empty()));
+    if marked {
+        return e_tags
+            .iter()
+            .filter(|tag| matches!(tag.get(3).map(String::as_str), Some("root" | "reply")))
+            .map(|tag| tag[1].clone())
+            .collect();
     }
+
+    let mut targets: Vec<String> = e_tags.first().into_iter().chain(e_tags.last()).map(|tag| tag[1].clone()).collect();
+    targets.dedup();
+    targets
 }
@@ -0,0 +1,214 @@
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Returned instead of running a query while the breaker is open
+#[derive(Debug, Error)]
+#[error("database unavailable: circuit breaker is open")]
+pub struct CircuitOpen;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreakerState {
+    /// Queries run normally
+    Closed,
+    /// Too many consecutive failures; queries are refused until `open_duration` passes
+    Open,
+    /// Trial period after being open; queries run, and a failure reopens the breaker
+    HalfOpen,
+}
+
+impl CircuitBreakerState {
+    /// Value exported by the `relay_db_circuit_state` gauge
+    pub fn as_gauge(self) -> i64 {
+        match self {
+            CircuitBreakerState::Closed => 0,
+            CircuitBreakerState::Open => 1,
+            CircuitBreakerState::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the breaker
+    pub failure_threshold: u32,
+    /// Consecutive successes while half-open that close it again
+    pub success_threshold: u32,
+    /// How long the breaker stays open before letting queries through again
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            success_threshold: 2,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitBreakerState,
+    failures: u32,
+    successes: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Stops sending queries to an overloaded database so failing calls don't
+/// keep tying up pool connections: `Closed → Open → HalfOpen → Closed`
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Arc::new(Mutex::new(Inner {
+                state: CircuitBreakerState::Closed,
+                failures: 0,
+                successes: 0,
+                opened_at: None,
+            })),
+        }
+    }
+
+    pub fn state(&self) -> CircuitBreakerState {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    /// Whether a call may go ahead now
+    pub fn allow(&self) -> Result<(), CircuitOpen> {
+        let mut inner = self.inner.lock().unwrap();
+        self.refresh(&mut inner);
+        match inner.state {
+            CircuitBreakerState::Open => Err(CircuitOpen),
+            CircuitBreakerState::Closed | CircuitBreakerState::HalfOpen => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitBreakerState::Closed => inner.failures = 0,
+            CircuitBreakerState::HalfOpen => {
+                inner.successes += 1;
+                if inner.successes >= self.config.success_threshold {
+                    inner.state = CircuitBreakerState::Closed;
+                    inner.failures = 0;
+                    inner.opened_at = None;
+                }
+            }
+            // A call admitted before the breaker opened
+            CircuitBreakerState::Open => {}
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitBreakerState::Closed => {
+                inner.failures += 1;
+                if inner.failures >= self.config.failure_threshold {
+                    Self::open(&mut inner);
+                }
+            }
+            CircuitBreakerState::HalfOpen => Self::open(&mut inner),
+            CircuitBreakerState::Open => {}
+        }
+    }
+
+    fn open(inner: &mut Inner) {
+        inner.state = CircuitBreakerState::Open;
+        inner.successes = 0;
+        inner.opened_at = Some(Instant::now());
+    }
+
+    // Move an open breaker to half-open once its timeout has passed
+    fn refresh(&self, inner: &mut Inner) {
+        if inner.state == CircuitBreakerState::Open
+            && inner.opened_at.is_some_and(|opened_at| opened_at.elapsed() >= self.config.open_duration)
+        {
+            inner.state = CircuitBreakerState::HalfOpen;
+            inner.successes = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            success_threshold: 2,
+            open_duration,
+        })
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        // A success resets the count
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitBreakerState::Closed);
+        assert!(breaker.allow().is_ok());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitBreakerState::Open);
+        assert!(breaker.allow().is_err());
+    }
+
+    #[test]
+    fn test_half_open_closes_after_successes() {
+        let breaker = breaker(Duration::ZERO);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        assert_eq!(breaker.state(), CircuitBreakerState::HalfOpen);
+        assert!(breaker.allow().is_ok());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitBreakerState::HalfOpen);
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitBreakerState::Closed);
+    }
+
+    #[test]
+    fn test_half_open_failure_reopens() {
+        let breaker = breaker(Duration::from_millis(20));
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow().is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitBreakerState::Open);
+        assert!(breaker.allow().is_err());
+    }
+
+    #[test]
+    fn test_gauge_values() {
+        assert_eq!(CircuitBreakerState::Closed.as_gauge(), 0);
+        assert_eq!(CircuitBreakerState::Open.as_gauge(), 1);
+        assert_eq!(CircuitBreakerState::HalfOpen.as_gauge(), 2);
+    }
+}
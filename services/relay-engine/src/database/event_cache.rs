@@ -0,0 +1,93 @@
+use anyhow::Result;
+use nostr::{Event, EventId, JsonUtil};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
+use tracing::warn;
+
+/// Redis key prefix of cached events
+pub const EVENT_KEY_PREFIX: &str = "nostr:event:";
+
+/// Redis cache of stored events by ID, written through on every save. Events
+/// never change once stored, so an entry only goes stale when the event is
+/// deleted, and deletions remove it.
+///
+/// The cache is an optimisation only: when Redis is unavailable reads fall
+/// through to PostgreSQL and writes are skipped.
+#[derive(Clone)]
+pub struct EventCache {
+    connection: MultiplexedConnection,
+    ttl_secs: u64,
+    max_bytes: usize,
+}
+
+impl EventCache {
+    pub async fn connect(redis_url: &str, ttl_secs: u64, max_bytes: usize) -> Result<Self> {
+        let client = Client::open(redis_url)?;
+        let connection = client.get_multiplexed_tokio_connection().await?;
+
+        Ok(Self {
+            connection,
+            ttl_secs,
+            max_bytes,
+        })
+    }
+
+    pub fn key(id: &str) -> String {
+        format!("{}{}", EVENT_KEY_PREFIX, id)
+    }
+
+    /// The cached event, if any. Unreadable entries count as misses.
+    pub async fn get(&self, id: &EventId) -> Option<Event> {
+        let mut connection = self.connection.clone();
+        let cached: Option<String> = match connection.get(Self::key(&id.to_hex())).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!("Failed to read cached event {}: {}", id, e);
+                return None;
+            }
+        };
+        cached.and_then(|json| Event::from_json(json).ok())
+    }
+
+    /// Cache events for the configured TTL. Events serialized larger than
+    /// `max_bytes` are left out, with a warning, so a few huge events can't
+    /// crowd out the hot ones.
+    pub async fn put(&self, events: &[Event]) {
+        let mut pipeline = redis::pipe();
+        let mut cached = 0;
+        for event in events {
+            let json = event.as_json();
+            if json.len() > self.max_bytes {
+                warn!(
+                    "Not caching event {}: {} bytes exceeds the event cache limit of {}",
+                    event.id,
+                    json.len(),
+                    self.max_bytes
+                );
+                continue;
+            }
+            pipeline.set_ex(Self::key(&event.id.to_hex()), json, self.ttl_secs).ignore();
+            cached += 1;
+        }
+        if cached == 0 {
+            return;
+        }
+
+        let mut connection = self.connection.clone();
+        if let Err(e) = pipeline.query_async::<_, ()>(&mut connection).await {
+            warn!("Failed to cache {} events: {}", cached, e);
+        }
+    }
+
+    /// Drop the given event IDs from the cache
+    pub async fn invalidate(&self, ids: &[String]) {
+        if ids.is_empty() {
+            return;
+        }
+
+        let keys: Vec<String> = ids.iter().map(|id| Self::key(id)).collect();
+        let mut connection = self.connection.clone();
+        if let Err(e) = connection.del::<_, ()>(keys).await {
+            warn!("Failed to invalidate {} cached events: {}", ids.len(), e);
+        }
+    }
+}
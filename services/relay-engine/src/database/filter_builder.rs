@@ -0,0 +1,438 @@
+use nostr::Filter;
+use sqlx::postgres::PgHasArrayType;
+use sqlx::{Encode, Postgres, QueryBuilder, Type};
+
+use super::pagination::Cursor;
+
+/// Default number of events returned when a filter doesn't specify a limit
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// Upper bound on the number of events a single filter can request
+pub const MAX_LIMIT: usize = 5000;
+
+/// Translates a NIP-01 `Filter` into a parameterized query against the `events` table.
+///
+/// Every user-supplied value goes through `push_bind`, so nothing from the filter
+/// is ever interpolated into the SQL text.
+pub struct FilterSqlBuilder<'a> {
+    filter: &'a Filter,
+    delegators: &'a [String],
+}
+
+impl<'a> FilterSqlBuilder<'a> {
+    pub fn new(filter: &'a Filter) -> Self {
+        Self { filter, delegators: &[] }
+    }
+
+    /// Authors (hex) known to have NIP-26 delegated events stored. Only these
+    /// also match on the `delegator` column; see `push_conditions`.
+    pub fn with_delegators(mut self, delegators: &'a [String]) -> Self {
+        self.delegators = delegators;
+        self
+    }
+
+    pub fn build(&self) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new("SELECT raw_event FROM events WHERE 1=1");
+
+        // NIP-40: expired events are never served
+        query.push(" AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))");
+
+        Self::push_conditions(self.filter, self.delegators, &mut query);
+
+        query.push(" ORDER BY created_at DESC LIMIT ").push_bind(self.limit() as i64);
+
+        query
+    }
+
+    /// Build one page of a keyset-paginated query, bounded by optional cursors.
+    /// Rows are ordered by `(created_at, id)` so pages never overlap, and one
+    /// row beyond the limit is fetched to tell whether another page follows.
+    pub fn build_page(&self, until: Option<&Cursor>, since: Option<&Cursor>) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new("SELECT raw_event FROM events WHERE 1=1");
+
+        // NIP-40: expired events are never served
+        query.push(" AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))");
+
+        Self::push_conditions(self.filter, self.delegators, &mut query);
+
+        if let Some(cursor) = until {
+            query
+                .push(" AND (created_at, id) < (")
+                .push_bind(cursor.created_at)
+                .push(", ")
+                .push_bind(cursor.id.clone())
+                .push(")");
+        }
+
+        if let Some(cursor) = since {
+            query
+                .push(" AND (created_at, id) > (")
+                .push_bind(cursor.created_at)
+                .push(", ")
+                .push_bind(cursor.id.clone())
+                .push(")");
+        }
+
+        query
+            .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(self.limit() as i64 + 1);
+
+        query
+    }
+
+    /// Build an unbounded query over every matching event, oldest first, for
+    /// exports; the filter's limit is ignored
+    pub fn build_export(&self) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new("SELECT raw_event FROM events WHERE 1=1");
+
+        // NIP-40: expired events are never served
+        query.push(" AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))");
+
+        Self::push_conditions(self.filter, self.delegators, &mut query);
+
+        query.push(" ORDER BY created_at ASC, id ASC");
+
+        query
+    }
+
+    /// Number of events the filter asks for, after defaults and the cap
+    pub fn limit(&self) -> usize {
+        self.filter.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+
+    /// Build a NIP-45 `COUNT` query: events matching any of the filters are
+    /// counted once, and filter limits are ignored. `delegators` is as for
+    /// `with_delegators`.
+    pub fn build_count(filters: &[Filter], delegators: &[String]) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) AS count FROM events WHERE 1=1");
+
+        // NIP-40: expired events are never served
+        query.push(" AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW()))");
+
+        if !filters.is_empty() {
+            query.push(" AND (");
+            for (i, filter) in filters.iter().enumerate() {
+                if i > 0 {
+                    query.push(" OR ");
+                }
+                query.push("(1=1");
+                Self::push_conditions(filter, delegators, &mut query);
+                query.push(")");
+            }
+            query.push(")");
+        }
+
+        query
+    }
+
+    fn push_conditions(filter: &Filter, delegators: &[String], query: &mut QueryBuilder<'static, Postgres>) {
+        if let Some(ids) = &filter.ids {
+            let ids: Vec<String> = ids.iter().map(|id| id.to_hex()).collect();
+            query.push(" AND id = ANY(").push_bind(ids).push(")");
+        }
+
+        // A single author or kind is compared with `=` rather than `= ANY(..)`:
+        // only then can the planner walk idx_events_pubkey_kind_created in
+        // `created_at DESC` order and stop at the limit instead of sorting
+        if let Some(authors) = &filter.authors {
+            let authors: Vec<String> = authors.iter().map(|pk| pk.to_hex()).collect();
+
+            // NIP-26: events signed on behalf of an author match it too. The
+            // OR costs that index walk, so it's only added for authors with
+            // delegated events stored
+            let delegated: Vec<String> = authors.iter().filter(|author| delegators.contains(author)).cloned().collect();
+            if delegated.is_empty() {
+                Self::push_any(query, "pubkey", authors);
+            } else {
+                query
+                    .push(" AND (pubkey = ANY(")
+                    .push_bind(authors)
+                    .push(") OR delegator = ANY(")
+                    .push_bind(delegated)
+                    .push("))");
+            }
+        }
+
+        if let Some(kinds) = &filter.kinds {
+            let kinds: Vec<i32> = kinds.iter().map(|kind| kind.as_u32() as i32).collect();
+            Self::push_any(query, "kind", kinds);
+        }
+
+        if let Some(since) = filter.since {
+            query.push(" AND created_at >= ").push_bind(since.as_u64() as i64);
+        }
+
+        if let Some(until) = filter.until {
+            query.push(" AND created_at <= ").push_bind(until.as_u64() as i64);
+        }
+
+        // NIP-50: case-insensitive substring search over content, the same
+        // match live events get, served by the content trigram index
+        if let Some(search) = &filter.search {
+            query
+                .push(" AND content ILIKE ")
+                .push_bind(format!("%{}%", Self::escape_like(search)));
+        }
+
+        // Tag filters (#e, #p, #t, ...): the event must carry at least one
+        // matching tag for every requested letter
+        for (tag, values) in filter.generic_tags.iter() {
+            let values: Vec<String> = values.iter().cloned().collect();
+
+            // Identifiers of parameterized replaceable events (NIP-33) are stored
+            // in their own indexed column; other kinds' 'd' tags are only in event_tags
+            if tag.as_char() == 'd' {
+                query
+                    .push(" AND (d_tag = ANY(")
+                    .push_bind(values.clone())
+                    .push(") OR EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = 'd' AND tag_value = ANY(")
+                    .push_bind(values)
+                    .push(")))");
+                continue;
+            }
+
+            if values.is_empty() {
+                query.push(" AND FALSE");
+                continue;
+            }
+
+            // Indexed lookup in event_tags; any of the requested values may match
+            query
+                .push(" AND EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = ")
+                .push_bind(tag.as_char().to_string());
+            Self::push_any(query, "tag_value", values);
+            query.push(")");
+        }
+    }
+
+    // Escape LIKE wildcards so search text is matched literally
+    fn escape_like(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    }
+
+    fn push_any<T>(query: &mut QueryBuilder<'static, Postgres>, column: &str, mut values: Vec<T>)
+    where
+        T: for<'q> Encode<'q, Postgres> + Type<Postgres> + PgHasArrayType + Send + 'static,
+    {
+        query.push(format_args!(" AND {} ", column));
+        if values.len() == 1 {
+            query.push("= ").push_bind(values.remove(0));
+        } else {
+            query.push("= ANY(").push_bind(values).push(")");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{Alphabet, EventId, Keys, Kind, SingleLetterTag, Timestamp};
+
+    fn sql_for(filter: &Filter) -> String {
+        FilterSqlBuilder::new(filter).build().sql().to_string()
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let sql = sql_for(&Filter::new());
+        assert_eq!(
+            sql,
+            "SELECT raw_event FROM events WHERE 1=1 \
+             AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW())) \
+             ORDER BY created_at DESC LIMIT $1"
+        );
+    }
+
+    #[test]
+    fn test_ids_clause() {
+        let filter = Filter::new().id(EventId::all_zeros());
+        let sql = sql_for(&filter);
+        assert!(sql.contains("AND id = ANY($1)"));
+        assert!(sql.ends_with("LIMIT $2"));
+    }
+
+    #[test]
+    fn test_authors_clause() {
+        let keys = Keys::generate();
+        let filter = Filter::new().author(keys.public_key());
+        assert!(sql_for(&filter).contains("AND pubkey = $1"));
+
+        let filter = Filter::new().authors([keys.public_key(), Keys::generate().public_key()]);
+        assert!(sql_for(&filter).contains("AND pubkey = ANY($1)"));
+    }
+
+    #[test]
+    fn test_delegator_clause_only_for_known_delegators() {
+        let delegator = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let filter = Filter::new().authors([delegator, other]);
+
+        let unrelated = vec![Keys::generate().public_key().to_hex()];
+        let sql = FilterSqlBuilder::new(&filter).with_delegators(&unrelated).build().sql().to_string();
+        assert!(sql.contains("AND pubkey = ANY($1) ORDER BY"));
+        assert!(!sql.contains("delegator"));
+
+        let delegators = vec![delegator.to_hex()];
+        let sql = FilterSqlBuilder::new(&filter).with_delegators(&delegators).build().sql().to_string();
+        assert!(sql.contains("AND (pubkey = ANY($1) OR delegator = ANY($2))"));
+        assert!(sql.ends_with("LIMIT $3"));
+
+        let sql = FilterSqlBuilder::build_count(&[filter], &delegators).sql().to_string();
+        assert!(sql.contains("OR delegator = ANY($2)"));
+    }
+
+    #[test]
+    fn test_kinds_clause() {
+        let filter = Filter::new().kinds([Kind::TextNote, Kind::Metadata]);
+        assert!(sql_for(&filter).contains("AND kind = ANY($1)"));
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        assert!(sql_for(&filter).contains("AND kind = $1"));
+    }
+
+    #[test]
+    fn test_since_until_clauses() {
+        let filter = Filter::new()
+            .since(Timestamp::from(1_000))
+            .until(Timestamp::from(2_000));
+        let sql = sql_for(&filter);
+        assert!(sql.contains("AND created_at >= $1"));
+        assert!(sql.contains("AND created_at <= $2"));
+        assert!(sql.ends_with("LIMIT $3"));
+    }
+
+    #[test]
+    fn test_e_and_p_tag_clauses() {
+        let keys = Keys::generate();
+        let filter = Filter::new()
+            .event(EventId::all_zeros())
+            .pubkey(keys.public_key());
+        let sql = sql_for(&filter);
+        assert_eq!(sql.matches("EXISTS (SELECT 1 FROM event_tags").count(), 2);
+        assert!(sql.contains(
+            "AND EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = $1 AND tag_value = $2)"
+        ));
+        assert!(sql.contains(
+            "AND EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = $3 AND tag_value = $4)"
+        ));
+    }
+
+    #[test]
+    fn test_arbitrary_single_letter_tag_clause() {
+        let filter = Filter::new()
+            .custom_tag(SingleLetterTag::lowercase(Alphabet::T), ["nostr", "rust"]);
+        let sql = sql_for(&filter);
+        assert!(sql.contains(
+            "AND EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = $1 AND tag_value = ANY($2))"
+        ));
+    }
+
+    #[test]
+    fn test_search_combined_with_kind_and_author() {
+        let keys = Keys::generate();
+        let filter = Filter::new()
+            .kind(Kind::TextNote)
+            .author(keys.public_key())
+            .search("nostr relay");
+        let sql = sql_for(&filter);
+        assert!(sql.contains("AND pubkey = $1 AND kind = $2 AND content ILIKE $3"));
+        assert!(sql.ends_with("LIMIT $4"));
+    }
+
+    #[test]
+    fn test_search_wildcards_are_escaped() {
+        assert_eq!(FilterSqlBuilder::escape_like("nostr relay"), "nostr relay");
+        assert_eq!(FilterSqlBuilder::escape_like("100%_done"), "100\\%\\_done");
+        assert_eq!(FilterSqlBuilder::escape_like("C:\\path"), "C:\\\\path");
+    }
+
+    #[test]
+    fn test_d_tag_clause_uses_column() {
+        let filter = Filter::new().identifier("my-article");
+        let sql = sql_for(&filter);
+        assert!(sql.contains(
+            "AND (d_tag = ANY($1) OR EXISTS (SELECT 1 FROM event_tags \
+             WHERE event_id = events.id AND tag_name = 'd' AND tag_value = ANY($2)))"
+        ));
+    }
+
+    #[test]
+    fn test_all_clauses_combined() {
+        let keys = Keys::generate();
+        let filter = Filter::new()
+            .id(EventId::all_zeros())
+            .author(keys.public_key())
+            .kind(Kind::TextNote)
+            .since(Timestamp::from(1_000))
+            .until(Timestamp::from(2_000))
+            .pubkey(keys.public_key())
+            .limit(10);
+        let sql = sql_for(&filter);
+        assert_eq!(
+            sql,
+            "SELECT raw_event FROM events WHERE 1=1 \
+             AND (expires_at IS NULL OR expires_at > EXTRACT(EPOCH FROM NOW())) \
+             AND id = ANY($1) AND pubkey = $2 AND kind = $3 \
+             AND created_at >= $4 AND created_at <= $5 \
+             AND EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND tag_name = $6 AND tag_value = $7) \
+             ORDER BY created_at DESC LIMIT $8"
+        );
+    }
+
+    #[test]
+    fn test_page_query_with_cursors() {
+        let filter = Filter::new().kind(Kind::TextNote).limit(50);
+        let cursor = Cursor {
+            created_at: 1_000,
+            id: "00".repeat(32),
+        };
+
+        let sql = FilterSqlBuilder::new(&filter).build_page(None, None).sql().to_string();
+        assert!(sql.ends_with("AND kind = $1 ORDER BY created_at DESC, id DESC LIMIT $2"));
+
+        let sql = FilterSqlBuilder::new(&filter)
+            .build_page(Some(&cursor), Some(&cursor))
+            .sql()
+            .to_string();
+        assert!(sql.contains(
+            "AND kind = $1 AND (created_at, id) < ($2, $3) AND (created_at, id) > ($4, $5) \
+             ORDER BY created_at DESC, id DESC LIMIT $6"
+        ));
+    }
+
+    #[test]
+    fn test_values_are_never_interpolated() {
+        let filter = Filter::new()
+            .custom_tag(SingleLetterTag::lowercase(Alphabet::T), ["'; DROP TABLE events; --"]);
+        assert!(!sql_for(&filter).contains("DROP TABLE"));
+    }
+
+    #[test]
+    fn test_count_query_ignores_limit() {
+        let filter = Filter::new().kind(Kind::TextNote).limit(10);
+        let sql = FilterSqlBuilder::build_count(&[filter], &[]).sql().to_string();
+        assert!(sql.starts_with("SELECT COUNT(*) AS count FROM events WHERE 1=1"));
+        assert!(sql.contains("AND ((1=1 AND kind = $1))"));
+        assert!(!sql.contains("LIMIT"));
+    }
+
+    #[test]
+    fn test_count_query_ors_multiple_filters() {
+        let keys = Keys::generate();
+        let filters = [
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().author(keys.public_key()).since(Timestamp::from(1_000)),
+        ];
+        let sql = FilterSqlBuilder::build_count(&filters, &[]).sql().to_string();
+        assert!(sql.contains("AND ((1=1 AND kind = $1) OR (1=1 AND pubkey = $2 AND created_at >= $3))"));
+    }
+
+    #[test]
+    fn test_export_query_is_unbounded_and_oldest_first() {
+        let filter = Filter::new().kind(Kind::TextNote).until(Timestamp::from(2_000)).limit(10);
+        let sql = FilterSqlBuilder::new(&filter).build_export().sql().to_string();
+        assert!(sql.contains("AND kind = $1 AND created_at <= $2"));
+        assert!(sql.ends_with("ORDER BY created_at ASC, id ASC"));
+        assert!(!sql.contains("LIMIT"));
+    }
+}
@@ -0,0 +1,77 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use anyhow::Result;
+use nostr::EventId;
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
+use tracing::warn;
+
+/// Redis set of event IDs removed by the relay operator
+pub const FORCE_DELETED_KEY: &str = "nostr:force_deleted";
+
+/// Events an operator removed with the admin API. Publishing one again is
+/// acknowledged but it is never stored, so the author can't tell it is being
+/// dropped. Kept in Redis when it's configured, so every relay instance sees
+/// them, and always in memory on the instance that removed them.
+#[derive(Debug, Default)]
+pub struct ForceDeletedEvents {
+    redis: Option<MultiplexedConnection>,
+    local: Mutex<HashSet<EventId>>,
+}
+
+impl ForceDeletedEvents {
+    pub async fn connect(redis_url: &str) -> Result<Self> {
+        let client = Client::open(redis_url)?;
+        Ok(Self {
+            redis: Some(client.get_multiplexed_tokio_connection().await?),
+            local: Mutex::default(),
+        })
+    }
+
+    pub async fn insert(&self, id: &EventId) -> Result<()> {
+        self.local.lock().unwrap().insert(*id);
+        if let Some(redis) = &self.redis {
+            let mut redis = redis.clone();
+            redis.sadd::<_, _, ()>(FORCE_DELETED_KEY, id.to_hex()).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the event was removed. When Redis can't be read, only removals
+    /// made by this instance are known.
+    pub async fn contains(&self, id: &EventId) -> bool {
+        if self.local.lock().unwrap().contains(id) {
+            return true;
+        }
+        let Some(redis) = &self.redis else {
+            return false;
+        };
+
+        let mut redis = redis.clone();
+        match redis.sismember(FORCE_DELETED_KEY, id.to_hex()).await {
+            Ok(removed) => removed,
+            Err(e) => {
+                warn!("Failed to check whether event {} was removed: {}", id, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Kind};
+
+    #[tokio::test]
+    async fn test_remembers_removed_events_without_redis() {
+        let keys = Keys::generate();
+        let removed = EventBuilder::new(Kind::TextNote, "removed", []).to_event(&keys).unwrap();
+        let kept = EventBuilder::new(Kind::TextNote, "kept", []).to_event(&keys).unwrap();
+
+        let force_deleted = ForceDeletedEvents::default();
+        force_deleted.insert(&removed.id).await.unwrap();
+
+        assert!(force_deleted.contains(&removed.id).await);
+        assert!(!force_deleted.contains(&kept.id).await);
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use nostr::Event;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Position in the `created_at DESC, id DESC` ordering of query results.
+///
+/// Clients see it as an opaque base64 string; keyset pagination on
+/// `(created_at, id)` stays cheap at any depth, unlike `OFFSET`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: i64,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn from_event(event: &Event) -> Self {
+        Self {
+            created_at: event.created_at.as_u64() as i64,
+            id: event.id.to_hex(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.created_at, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid cursor: {}", cursor);
+
+        let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (created_at, id) = decoded.split_once(':').ok_or_else(invalid)?;
+
+        let created_at = created_at.parse().map_err(|_| invalid())?;
+        if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            created_at,
+            id: id.to_lowercase(),
+        })
+    }
+}
+
+/// Paging bounds passed alongside a `Filter`. Both hold cursors as returned
+/// in `PagedEvents::next_cursor`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueryOptions {
+    /// Only return events strictly older than this cursor (the next page)
+    pub until_exclusive_id: Option<String>,
+    /// Only return events strictly newer than this cursor (polling for new events)
+    pub since_exclusive_id: Option<String>,
+}
+
+impl QueryOptions {
+    pub fn until_cursor(&self) -> Result<Option<Cursor>> {
+        self.until_exclusive_id.as_deref().map(Cursor::decode).transpose()
+    }
+
+    pub fn since_cursor(&self) -> Result<Option<Cursor>> {
+        self.since_exclusive_id.as_deref().map(Cursor::decode).transpose()
+    }
+}
+
+/// One page of query results, newest first
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PagedEvents {
+    /// Nostr events as defined by NIP-01
+    #[schema(value_type = Vec<Object>)]
+    pub events: Vec<Event>,
+    /// Whether more events match beyond this page
+    pub has_more: bool,
+    /// Pass as `until_exclusive_id` to fetch the next page
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor {
+            created_at: 1_700_000_000,
+            id: "ab".repeat(32),
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_invalid_cursors_are_rejected() {
+        assert!(Cursor::decode("not base64!").is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("no separator")).is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("soon:abcd")).is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("1700000000:abcd")).is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(format!("1700000000:{}", "zz".repeat(32)))).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use futures_util::{
+    future::BoxFuture,
+    stream::{self, BoxStream, StreamExt},
+};
+use serde::Serialize;
+use sqlx::{
+    postgres::{PgQueryResult, PgRow, PgStatement, PgTypeInfo},
+    Describe, Either, Execute, Executor, PgPool, Postgres,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Threshold used when none is configured
+pub const DEFAULT_SLOW_QUERY_THRESHOLD_MS: u64 = 100;
+
+/// Number of slow queries kept for inspection
+pub const SLOW_QUERY_HISTORY: usize = 100;
+
+/// A query that took longer than the configured threshold
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SlowQuery {
+    pub query: String,
+    pub duration_ms: u64,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Wraps a `PgPool` and times every query run through it, keeping the most
+/// recent ones over the threshold. Use `&logger` anywhere a pool is accepted
+/// as an executor. Queries inside a transaction run on its connection and
+/// aren't timed.
+#[derive(Debug, Clone)]
+pub struct SlowQueryLogger {
+    pool: PgPool,
+    threshold_ms: u64,
+    recent: Arc<Mutex<VecDeque<SlowQuery>>>,
+}
+
+impl SlowQueryLogger {
+    pub fn new(pool: PgPool, threshold_ms: u64) -> Self {
+        Self {
+            pool,
+            threshold_ms,
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(SLOW_QUERY_HISTORY))),
+        }
+    }
+
+    pub fn threshold_ms(&self) -> u64 {
+        self.threshold_ms
+    }
+
+    /// The most recent slow queries, newest first
+    pub fn recent(&self) -> Vec<SlowQuery> {
+        self.recent.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    fn record(&self, query: &str, elapsed: Duration) {
+        let duration_ms = elapsed.as_millis() as u64;
+        if duration_ms <= self.threshold_ms {
+            return;
+        }
+
+        warn!("slow_query duration={}ms query={}", duration_ms, query);
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == SLOW_QUERY_HISTORY {
+            recent.pop_front();
+        }
+        recent.push_back(SlowQuery {
+            query: query.to_string(),
+            duration_ms,
+            recorded_at: Utc::now(),
+        });
+    }
+}
+
+impl<'c> Executor<'c> for &'c SlowQueryLogger {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(self, query: E) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: Execute<'q, Postgres> + 'q,
+    {
+        let sql = query.sql().to_string();
+        let start = Instant::now();
+        let mut rows = self.pool.fetch_many(query);
+
+        // The query is done once the underlying stream is exhausted
+        stream::poll_fn(move |cx| {
+            let poll = rows.poll_next_unpin(cx);
+            if let Poll::Ready(None) = poll {
+                self.record(&sql, start.elapsed());
+            }
+            poll
+        })
+        .boxed()
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(self, query: E) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: Execute<'q, Postgres> + 'q,
+    {
+        let sql = query.sql().to_string();
+        Box::pin(async move {
+            let start = Instant::now();
+            let row = self.pool.fetch_optional(query).await;
+            self.record(&sql, start.elapsed());
+            row
+        })
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Postgres>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.describe(sql)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[tokio::test]
+    async fn test_history_keeps_the_newest_queries() {
+        let pool = PgPoolOptions::new().connect_lazy("postgresql://localhost/unused").unwrap();
+        let logger = SlowQueryLogger::new(pool, 0);
+
+        for i in 0..SLOW_QUERY_HISTORY + 5 {
+            logger.record(&format!("SELECT {}", i), Duration::from_millis(10));
+        }
+        // Queries at the threshold aren't slow
+        logger.record("SELECT fast", Duration::from_millis(0));
+
+        let recent = logger.recent();
+        assert_eq!(recent.len(), SLOW_QUERY_HISTORY);
+        assert_eq!(recent[0].query, format!("SELECT {}", SLOW_QUERY_HISTORY + 4));
+        assert_eq!(recent[SLOW_QUERY_HISTORY - 1].query, "SELECT 5");
+    }
+}
@@ -14,7 +14,6 @@ use tracing::{info, warn};
 use tower_http::cors::{CorsLayer, Any};
 
 mod config;
-mod mock_database;
 
 use config::Config;
 
@@ -0,0 +1,438 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use nostr::{Event, EventId, Filter, Kind, PublicKey, Timestamp};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::{
+    app_state::AppState,
+    auth::nip98::Nip98Auth,
+    client_ip,
+    database::{PagedEvents, QueryOptions, RelayDatabase},
+    nip42, validation,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_events, list_events_page, get_event, list_replies, get_thread),
+    components(schemas(PagedEvents)),
+    info(title = "Pleb.One Relay events API", description = "Read-only HTTP access to stored events")
+)]
+pub struct EventsApiDoc;
+
+/// Build a filter from `/api/events` query parameters. List parameters are
+/// comma-separated; `limit` is capped at `max_limit`.
+pub fn parse_filter(params: &HashMap<String, String>, max_limit: usize) -> Result<Filter, String> {
+    let mut filter = Filter::new();
+
+    if let Some(kinds) = params.get("kinds") {
+        let kinds = split_list(kinds)
+            .map(|kind| kind.parse::<u16>().map(Kind::from).map_err(|_| format!("invalid kind: {}", kind)))
+            .collect::<Result<Vec<_>, _>>()?;
+        filter = filter.kinds(kinds);
+    }
+    if let Some(authors) = params.get("authors") {
+        let authors = split_list(authors)
+            .map(|author| PublicKey::from_hex(author).map_err(|_| format!("invalid author: {}", author)))
+            .collect::<Result<Vec<_>, _>>()?;
+        filter = filter.authors(authors);
+    }
+    if let Some(ids) = params.get("ids") {
+        let ids = split_list(ids)
+            .map(|id| EventId::from_hex(id).map_err(|_| format!("invalid id: {}", id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        filter = filter.ids(ids);
+    }
+    if let Some(since) = params.get("since") {
+        filter = filter.since(parse_timestamp("since", since)?);
+    }
+    if let Some(until) = params.get("until") {
+        filter = filter.until(parse_timestamp("until", until)?);
+    }
+    if let Some(limit) = params.get("limit") {
+        let limit: usize = limit.parse().map_err(|_| format!("invalid limit: {}", limit))?;
+        filter = filter.limit(limit.min(max_limit));
+    }
+    if let Some(search) = params.get("search") {
+        filter = filter.search(search);
+    }
+
+    Ok(filter)
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+fn parse_timestamp(name: &str, value: &str) -> Result<Timestamp, String> {
+    value
+        .parse::<u64>()
+        .map(Timestamp::from)
+        .map_err(|_| format!("invalid {}: {}", name, value))
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+// HTTP queries share the per-IP query rate limit with REQ and COUNT, and
+// requests signed under NIP-98 also the per-pubkey one. Without a peer address
+// (e.g. when served without connect info) every request counts against one
+// unspecified address.
+async fn check_rate_limit(
+    state: &AppState,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+    reader: Option<&PublicKey>,
+) -> Result<(), Response> {
+    let peer = connect_info.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip());
+    let client_ip = client_ip::resolve_client_ip(peer, headers, &state.config);
+
+    match state.rate_limiter.check_query_rate(client_ip).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::TOO_MANY_REQUESTS.into_response()),
+        Err(e) => {
+            error!("Failed to check query rate for {}: {}", client_ip, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    }
+
+    let Some(reader) = reader else {
+        return Ok(());
+    };
+    match state.rate_limiter.check_query_rate_pubkey(&reader.to_hex()).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            state.metrics.record_rate_limit_pubkey();
+            Err(StatusCode::TOO_MANY_REQUESTS.into_response())
+        }
+        Err(e) => {
+            error!("Failed to check query rate for pubkey {}: {}", reader, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+// The pubkey a request was signed by under NIP-98, which stands in for NIP-42
+// auth over HTTP. A relay requiring auth refuses unsigned requests.
+fn reader_pubkey(state: &AppState, reader: Option<Nip98Auth>) -> Result<Option<PublicKey>, StatusCode> {
+    if state.config.auth_required && reader.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(reader.map(|reader| reader.pubkey))
+}
+
+// Refuse a filter REQ would refuse
+fn check_filter(state: &AppState, filter: &Filter) -> Result<(), String> {
+    validation::validate_filters(std::slice::from_ref(filter), &state.config)
+        .inspect_err(|_| state.metrics.record_invalid_filter_rejection())
+}
+
+// Run a filter the way a REQ would be: refused if REQ would refuse it, and with
+// protected kinds only returned to their author and recipients
+async fn query(state: &AppState, filter: Filter, reader: Option<&PublicKey>) -> Result<Vec<Event>, Response> {
+    check_filter(state, &filter).map_err(bad_request)?;
+
+    let mut events = state.database.query_events(&filter).await.map_err(|e| {
+        state.metrics.record_database_error();
+        error!("Failed to query events over HTTP: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE.into_response()
+    })?;
+    events.retain(|event| nip42::can_read(event, reader, &state.config));
+    Ok(events)
+}
+
+/// Stored events matching the query, newest first. DMs and other protected
+/// kinds are only returned to requests their author or a recipient signed
+/// under NIP-98.
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    params(
+        ("kinds" = Option<String>, Query, description = "Comma-separated event kinds"),
+        ("authors" = Option<String>, Query, description = "Comma-separated hex pubkeys"),
+        ("ids" = Option<String>, Query, description = "Comma-separated hex event IDs"),
+        ("since" = Option<u64>, Query, description = "Oldest created_at, in unix seconds"),
+        ("until" = Option<u64>, Query, description = "Newest created_at, in unix seconds"),
+        ("limit" = Option<usize>, Query, description = "Most events returned, capped at the relay's max_limit"),
+        ("search" = Option<String>, Query, description = "NIP-50 search, a case-insensitive substring of content"),
+    ),
+    responses(
+        (status = 200, description = "JSON array of events, newest first"),
+        (status = 400, description = "Malformed or refused query"),
+        (status = 401, description = "The relay requires NIP-98 auth"),
+        (status = 429, description = "Query rate limit exceeded"),
+    )
+)]
+pub async fn list_events(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Event>>, Response> {
+    let reader = reader_pubkey(&state, reader).map_err(IntoResponse::into_response)?;
+    check_rate_limit(&state, connect_info, &headers, reader.as_ref()).await?;
+    let filter = parse_filter(&params, state.config.max_limit).map_err(bad_request)?;
+
+    state.metrics.record_query_received();
+    Ok(Json(query(&state, filter, reader.as_ref()).await?))
+}
+
+/// One page of the stored events matching the query, newest first. Pass the
+/// returned `next_cursor` as `until_exclusive_id` to fetch the next page.
+/// Protected kinds the reader may not see are left out of a page, which can
+/// leave it shorter than `limit` though more events follow.
+#[utoipa::path(
+    get,
+    path = "/api/events/page",
+    params(
+        ("kinds" = Option<String>, Query, description = "Comma-separated event kinds"),
+        ("authors" = Option<String>, Query, description = "Comma-separated hex pubkeys"),
+        ("ids" = Option<String>, Query, description = "Comma-separated hex event IDs"),
+        ("since" = Option<u64>, Query, description = "Oldest created_at, in unix seconds"),
+        ("until" = Option<u64>, Query, description = "Newest created_at, in unix seconds"),
+        ("limit" = Option<usize>, Query, description = "Most events per page, capped at the relay's max_limit"),
+        ("search" = Option<String>, Query, description = "NIP-50 search, a case-insensitive substring of content"),
+        ("until_exclusive_id" = Option<String>, Query, description = "Cursor of the previous page; only older events are returned"),
+        ("since_exclusive_id" = Option<String>, Query, description = "Cursor of the newest event seen; only newer events are returned"),
+    ),
+    responses(
+        (status = 200, description = "One page of events, newest first", body = PagedEvents),
+        (status = 400, description = "Malformed cursor, or malformed or refused query"),
+        (status = 401, description = "The relay requires NIP-98 auth"),
+        (status = 429, description = "Query rate limit exceeded"),
+    )
+)]
+pub async fn list_events_page(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<PagedEvents>, Response> {
+    let reader = reader_pubkey(&state, reader).map_err(IntoResponse::into_response)?;
+    check_rate_limit(&state, connect_info, &headers, reader.as_ref()).await?;
+    let filter = parse_filter(&params, state.config.max_limit).map_err(bad_request)?;
+    let options = QueryOptions {
+        until_exclusive_id: params.get("until_exclusive_id").cloned(),
+        since_exclusive_id: params.get("since_exclusive_id").cloned(),
+    };
+    options.until_cursor().and(options.since_cursor()).map_err(|e| bad_request(e.to_string()))?;
+    check_filter(&state, &filter).map_err(bad_request)?;
+
+    state.metrics.record_query_received();
+    let mut page = state.database.query_events_paged(&filter, &options).await.map_err(|e| {
+        state.metrics.record_database_error();
+        error!("Failed to query a page of events over HTTP: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE.into_response()
+    })?;
+    // The cursor was taken before filtering, so paging still covers every event
+    page.events.retain(|event| nip42::can_read(event, reader.as_ref(), &state.config));
+    Ok(Json(page))
+}
+
+/// A single stored event
+#[utoipa::path(
+    get,
+    path = "/api/events/{id}",
+    params(("id" = String, Path, description = "Hex event ID")),
+    responses(
+        (status = 200, description = "The event"),
+        (status = 400, description = "Malformed event ID"),
+        (status = 401, description = "The relay requires NIP-98 auth"),
+        (status = 404, description = "No such event, or one the reader may not see"),
+        (status = 429, description = "Query rate limit exceeded"),
+    )
+)]
+pub async fn get_event(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Event>, Response> {
+    let reader = reader_pubkey(&state, reader).map_err(IntoResponse::into_response)?;
+    check_rate_limit(&state, connect_info, &headers, reader.as_ref()).await?;
+    let id = EventId::from_hex(&id).map_err(|_| bad_request(format!("invalid id: {}", id)))?;
+
+    state.metrics.record_query_received();
+    match state.database.get_event_by_id(&id).await {
+        Ok(Some(event)) if nip42::can_read(&event, reader.as_ref(), &state.config) => Ok(Json(event)),
+        Ok(_) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => {
+            state.metrics.record_database_error();
+            error!("Failed to fetch event {} over HTTP: {}", id, e);
+            Err(StatusCode::SERVICE_UNAVAILABLE.into_response())
+        }
+    }
+}
+
+/// Stored events with an `e` tag referencing the event, newest first. Takes
+/// the same query parameters as `/api/events` to narrow them down.
+#[utoipa::path(
+    get,
+    path = "/api/events/{id}/replies",
+    params(
+        ("id" = String, Path, description = "Hex event ID"),
+        ("kinds" = Option<String>, Query, description = "Comma-separated event kinds"),
+        ("limit" = Option<usize>, Query, description = "Most events returned, capped at the relay's max_limit"),
+    ),
+    responses(
+        (status = 200, description = "JSON array of events, newest first"),
+        (status = 400, description = "Malformed event ID, or malformed or refused query"),
+        (status = 401, description = "The relay requires NIP-98 auth"),
+        (status = 429, description = "Query rate limit exceeded"),
+    )
+)]
+pub async fn list_replies(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Event>>, Response> {
+    let reader = reader_pubkey(&state, reader).map_err(IntoResponse::into_response)?;
+    check_rate_limit(&state, connect_info, &headers, reader.as_ref()).await?;
+    let id = EventId::from_hex(&id).map_err(|_| bad_request(format!("invalid id: {}", id)))?;
+    let filter = parse_filter(&params, state.config.max_limit).map_err(bad_request)?.event(id);
+
+    state.metrics.record_query_received();
+    Ok(Json(query(&state, filter, reader.as_ref()).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThreadQuery {
+    pub depth: Option<u32>,
+}
+
+/// The event and its replies, replies to those replies and so on, oldest
+/// first. `depth` defaults to and is capped at the relay's `max_thread_depth`,
+/// and at most `max_limit` events are returned. Events the reader may not see
+/// under NIP-42 are left out, like in `/api/events`.
+#[utoipa::path(
+    get,
+    path = "/api/events/{id}/thread",
+    params(
+        ("id" = String, Path, description = "Hex ID of the thread's root event"),
+        ("depth" = Option<u32>, Query, description = "Most reply levels followed below the root"),
+    ),
+    responses(
+        (status = 200, description = "JSON array of the thread's events, oldest first"),
+        (status = 400, description = "Malformed event ID or depth"),
+        (status = 401, description = "The relay requires NIP-98 auth"),
+        (status = 404, description = "No such event, or one the reader may not see"),
+        (status = 429, description = "Query rate limit exceeded"),
+    )
+)]
+pub async fn get_thread(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<ThreadQuery>,
+) -> Result<Json<Vec<Event>>, Response> {
+    let reader = reader_pubkey(&state, reader).map_err(IntoResponse::into_response)?;
+    check_rate_limit(&state, connect_info, &headers, reader.as_ref()).await?;
+    let id = EventId::from_hex(&id).map_err(|_| bad_request(format!("invalid id: {}", id)))?;
+    let max_depth = state.config.max_thread_depth;
+    let depth = params.depth.map_or(max_depth, |depth| depth.min(max_depth));
+
+    state.metrics.record_query_received();
+    match state.database.get_event_thread(&id.to_hex(), depth, state.config.max_limit).await {
+        Ok(mut events) => {
+            events.retain(|event| nip42::can_read(event, reader.as_ref(), &state.config));
+            if !events.iter().any(|event| event.id == id) {
+                return Err(StatusCode::NOT_FOUND.into_response());
+            }
+            Ok(Json(events))
+        }
+        Err(e) => {
+            state.metrics.record_database_error();
+            error!("Failed to fetch thread {} over HTTP: {}", id, e);
+            Err(StatusCode::SERVICE_UNAVAILABLE.into_response())
+        }
+    }
+}
+
+// The events API description, generated from the handlers' annotations
+async fn openapi_yaml() -> Response {
+    match EventsApiDoc::openapi().to_yaml() {
+        Ok(yaml) => ([(CONTENT_TYPE, "application/yaml")], yaml).into_response(),
+        Err(e) => {
+            error!("Failed to render OpenAPI document: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Router setup for the events API
+pub fn create_events_api_router() -> Router<AppState> {
+    Router::new()
+        .route("/api/events", get(list_events))
+        .route("/api/events/page", get(list_events_page))
+        .route("/api/events/:id", get(get_event))
+        .route("/api/events/:id/replies", get(list_replies))
+        .route("/api/events/:id/thread", get(get_thread))
+        .route("/api/openapi.yaml", get(openapi_yaml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let keys = Keys::generate();
+        let filter = parse_filter(
+            &params(&[
+                ("kinds", "1,7"),
+                ("authors", &keys.public_key().to_hex()),
+                ("since", "1700000000"),
+                ("limit", "5000"),
+                ("search", "nostr"),
+            ]),
+            500,
+        )
+        .unwrap();
+
+        let note = EventBuilder::text_note("hello", [])
+            .custom_created_at(Timestamp::from(1_700_000_001))
+            .to_event(&keys)
+            .unwrap();
+        assert!(filter.match_event(&note));
+        assert_eq!(filter.since, Some(Timestamp::from(1_700_000_000)));
+        assert_eq!(filter.limit, Some(500));
+        assert_eq!(filter.search.as_deref(), Some("nostr"));
+    }
+
+    #[test]
+    fn test_parse_filter_rejects_malformed_values() {
+        assert_eq!(parse_filter(&params(&[("kinds", "1,x")]), 500).unwrap_err(), "invalid kind: x");
+        assert_eq!(parse_filter(&params(&[("authors", "abc")]), 500).unwrap_err(), "invalid author: abc");
+        assert_eq!(parse_filter(&params(&[("until", "yesterday")]), 500).unwrap_err(), "invalid until: yesterday");
+    }
+
+    #[test]
+    fn test_openapi_document_lists_paths() {
+        let yaml = EventsApiDoc::openapi().to_yaml().unwrap();
+        assert!(yaml.contains("/api/events/{id}/replies"));
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use futures_util::{Stream, StreamExt};
+use nostr::Event;
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::health::{CacheHealth, PROBE_TIMEOUT};
+
+/// Redis channel that relay instances publish accepted events to
+pub const EVENTS_CHANNEL: &str = "relay:events";
+
+/// Envelope published to Redis; `origin` lets an instance skip its own events
+#[derive(Debug, Serialize, Deserialize)]
+struct FanoutMessage {
+    origin: String,
+    event: Event,
+}
+
+/// Shares accepted events between relay instances over Redis pub/sub, so a
+/// subscription on one instance sees events received by another
+#[derive(Clone)]
+pub struct EventFanout {
+    client: Client,
+    publisher: MultiplexedConnection,
+    instance_id: String,
+}
+
+impl EventFanout {
+    pub async fn connect(redis_url: &str) -> Result<Self> {
+        let client = Client::open(redis_url)?;
+        let publisher = client.get_multiplexed_tokio_connection().await?;
+
+        Ok(Self {
+            client,
+            publisher,
+            instance_id: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// Identifier attached to events published by this instance
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Publish an event for the other instances to broadcast locally
+    pub async fn publish(&self, event: &Event) -> Result<()> {
+        let payload = serde_json::to_string(&FanoutMessage {
+            origin: self.instance_id.clone(),
+            event: event.clone(),
+        })?;
+
+        let mut publisher = self.publisher.clone();
+        publisher.publish::<_, _, ()>(EVENTS_CHANNEL, payload).await?;
+        Ok(())
+    }
+
+    /// Time a Redis `PING` round-trip
+    pub async fn health_check(&self, warn_latency_ms: u64) -> CacheHealth {
+        let mut publisher = self.publisher.clone();
+        let start = std::time::Instant::now();
+        let ping = redis::cmd("PING");
+        let result = match tokio::time::timeout(PROBE_TIMEOUT, ping.query_async::<_, String>(&mut publisher)).await {
+            Ok(Ok(_)) => Ok(start.elapsed()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no response within {:?}", PROBE_TIMEOUT)),
+        };
+
+        CacheHealth::from_probe(result, warn_latency_ms)
+    }
+
+    /// Redis memory in use and its `maxmemory` limit, in bytes; the limit is 0
+    /// when Redis is unbounded
+    pub async fn memory_info(&self) -> Result<(u64, u64)> {
+        let mut publisher = self.publisher.clone();
+        let mut info = redis::cmd("INFO");
+        info.arg("memory");
+        let info = tokio::time::timeout(PROBE_TIMEOUT, info.query_async::<_, String>(&mut publisher))
+            .await
+            .map_err(|_| anyhow::anyhow!("no response within {:?}", PROBE_TIMEOUT))??;
+
+        parse_memory_info(&info).ok_or_else(|| anyhow::anyhow!("INFO memory is missing used_memory or maxmemory"))
+    }
+
+    /// Subscribe to events published by other instances. Events this instance
+    /// published and malformed payloads are skipped.
+    pub async fn subscribe(&self) -> Result<impl Stream<Item = Event>> {
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(EVENTS_CHANNEL).await?;
+
+        let instance_id = self.instance_id.clone();
+        Ok(pubsub.into_on_message().filter_map(move |msg| {
+            let message = msg
+                .get_payload::<String>()
+                .map_err(anyhow::Error::from)
+                .and_then(|payload| Ok(serde_json::from_str::<FanoutMessage>(&payload)?));
+
+            let event = match message {
+                Ok(message) if message.origin != instance_id => Some(message.event),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Ignoring malformed fanout message: {}", e);
+                    None
+                }
+            };
+            futures_util::future::ready(event)
+        }))
+    }
+}
+
+/// `(used_memory, maxmemory)` from the output of Redis' `INFO memory`
+pub fn parse_memory_info(info: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        info.lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.parse().ok())
+    };
+    Some((field("used_memory")?, field("maxmemory")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_info() {
+        let info = "# Memory\r\nused_memory:1048576\r\nused_memory_human:1.00M\r\nused_memory_rss:2097152\r\nmaxmemory:268435456\r\nmaxmemory_human:256.00M\r\n";
+        assert_eq!(parse_memory_info(info), Some((1048576, 268435456)));
+
+        // Without a limit configured Redis reports maxmemory:0
+        assert_eq!(parse_memory_info("used_memory:512\nmaxmemory:0\n"), Some((512, 0)));
+
+        assert_eq!(parse_memory_info("used_memory:512\n"), None);
+        assert_eq!(parse_memory_info("used_memory:lots\nmaxmemory:0\n"), None);
+        assert_eq!(parse_memory_info(""), None);
+    }
+}
@@ -0,0 +1,177 @@
+use axum::{http::StatusCode, Json};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::app_state::AppState;
+use crate::database::CircuitBreakerState;
+
+/// Upper bound on a single health probe, so a hung backend can't hang `/health`
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseHealth {
+    pub connected: bool,
+    pub pool_size: u32,
+    pub active_connections: u32,
+    /// Round-trip time of a `SELECT 1`, when it completed
+    pub latency_ms: Option<f64>,
+    pub last_error: Option<String>,
+    /// Queries are refused while the circuit breaker is open
+    pub circuit_state: CircuitBreakerState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheHealth {
+    pub connected: bool,
+    /// Round-trip time of a `PING`, when it completed
+    pub latency_ms: Option<f64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: String,
+    pub timestamp: i64,
+    pub database: DatabaseHealth,
+    /// Present only when Redis is configured
+    pub cache: Option<CacheHealth>,
+}
+
+// Judge a timed round-trip: failures and responses slower than the
+// threshold both count as unhealthy
+fn evaluate_probe(result: Result<Duration, String>, warn_latency_ms: u64) -> (bool, Option<f64>, Option<String>) {
+    match result {
+        Ok(elapsed) => {
+            let latency_ms = elapsed.as_secs_f64() * 1000.0;
+            if latency_ms > warn_latency_ms as f64 {
+                let error = format!("latency {:.1}ms exceeds {}ms", latency_ms, warn_latency_ms);
+                (false, Some(latency_ms), Some(error))
+            } else {
+                (true, Some(latency_ms), None)
+            }
+        }
+        Err(e) => (false, None, Some(e)),
+    }
+}
+
+impl DatabaseHealth {
+    pub fn from_probe(
+        result: Result<Duration, String>,
+        warn_latency_ms: u64,
+        pool_size: u32,
+        active_connections: u32,
+        circuit_state: CircuitBreakerState,
+    ) -> Self {
+        let (connected, latency_ms, last_error) = evaluate_probe(result, warn_latency_ms);
+        Self {
+            connected,
+            pool_size,
+            active_connections,
+            latency_ms,
+            last_error,
+            circuit_state,
+        }
+    }
+}
+
+impl CacheHealth {
+    pub fn from_probe(result: Result<Duration, String>, warn_latency_ms: u64) -> Self {
+        let (connected, latency_ms, last_error) = evaluate_probe(result, warn_latency_ms);
+        Self {
+            connected,
+            latency_ms,
+            last_error,
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn new(database: DatabaseHealth, cache: Option<CacheHealth>) -> Self {
+        let healthy = database.connected
+            && database.circuit_state != CircuitBreakerState::Open
+            && cache.as_ref().is_none_or(|cache| cache.connected);
+        Self {
+            status: if healthy { "healthy" } else { "unhealthy" }.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            database,
+            cache,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.status == "healthy"
+    }
+}
+
+/// Probe the database and, if configured, Redis
+pub async fn check(state: &AppState) -> HealthReport {
+    let warn_latency_ms = state.config.health_warn_latency_ms;
+    let database = state.database.health_check(warn_latency_ms).await;
+    let cache = match &state.fanout {
+        Some(fanout) => Some(fanout.health_check(warn_latency_ms).await),
+        None => None,
+    };
+
+    HealthReport::new(database, cache)
+}
+
+/// `GET /health`: 200 when every component is healthy, 503 otherwise
+pub async fn health_response(state: &AppState) -> (StatusCode, Json<HealthReport>) {
+    let report = check(state).await;
+    let status = if report.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fast_probe_is_healthy() {
+        let health = DatabaseHealth::from_probe(Ok(Duration::from_millis(3)), 500, 10, 2, CircuitBreakerState::Closed);
+        assert!(health.connected);
+        assert_eq!(health.latency_ms, Some(3.0));
+        assert!(health.last_error.is_none());
+    }
+
+    #[test]
+    fn test_slow_probe_is_unhealthy() {
+        let health = DatabaseHealth::from_probe(Ok(Duration::from_millis(750)), 500, 10, 2, CircuitBreakerState::Closed);
+        assert!(!health.connected);
+        assert_eq!(health.latency_ms, Some(750.0));
+        assert_eq!(health.last_error.as_deref(), Some("latency 750.0ms exceeds 500ms"));
+    }
+
+    #[test]
+    fn test_failed_probe_is_unhealthy() {
+        let health = CacheHealth::from_probe(Err("connection refused".to_string()), 500);
+        assert!(!health.connected);
+        assert!(health.latency_ms.is_none());
+        assert_eq!(health.last_error.as_deref(), Some("connection refused"));
+    }
+
+    #[test]
+    fn test_report_requires_every_component_healthy() {
+        let database = DatabaseHealth::from_probe(Ok(Duration::from_millis(1)), 500, 10, 1, CircuitBreakerState::Closed);
+        let slow_cache = CacheHealth::from_probe(Ok(Duration::from_millis(900)), 500);
+
+        assert!(HealthReport::new(database.clone(), None).is_healthy());
+        assert!(!HealthReport::new(database, Some(slow_cache)).is_healthy());
+
+        let down = DatabaseHealth::from_probe(Err("timed out".to_string()), 500, 10, 0, CircuitBreakerState::Closed);
+        assert!(!HealthReport::new(down, None).is_healthy());
+    }
+
+    #[test]
+    fn test_open_circuit_is_unhealthy() {
+        let database = DatabaseHealth::from_probe(Ok(Duration::from_millis(1)), 500, 10, 1, CircuitBreakerState::Open);
+        let report = HealthReport::new(database, None);
+        assert!(!report.is_healthy());
+        assert_eq!(serde_json::to_value(&report).unwrap()["database"]["circuit_state"], "open");
+    }
+}
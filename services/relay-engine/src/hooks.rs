@@ -0,0 +1,56 @@
+use nostr::{Event, Kind};
+use tracing::{debug, error, info_span, Instrument};
+
+use crate::{app_state::AppState, database::RelayDatabase, nip28, profile, relay_list};
+
+/// Bring everything derived from stored events up to date after `event` is
+/// stored, whether it came from a client or an import
+pub async fn after_store(event: &Event, state: &AppState) {
+    // NIP-09: a deletion event removes the referenced events by the same author
+    if event.kind == Kind::EventDeletion {
+        handle_deletion_event(event, state).await;
+    }
+
+    // Keep the profiles table in step with kind-0 metadata
+    if event.kind == Kind::Metadata {
+        profile::index_profile(event, state).await;
+    }
+
+    // NIP-65: keep the outbox routing index current
+    if event.kind == Kind::RelayList {
+        relay_list::update_relay_list_index(event, state).await;
+    }
+
+    // NIP-28: keep the channels table in step with channel metadata
+    if matches!(event.kind, Kind::ChannelCreation | Kind::ChannelMetadata) {
+        nip28::index_channel(event, state).await;
+    }
+}
+
+// Delete the events referenced by a kind-5 event's 'e' tags. Only events from the
+// deletion event's own pubkey are removed; failures are logged, not reported.
+async fn handle_deletion_event(event: &Event, state: &AppState) {
+    let event_ids: Vec<String> = event.event_ids().map(|id| id.to_hex()).collect();
+    if event_ids.is_empty() {
+        return;
+    }
+
+    let deletion = state
+        .database
+        .delete_events_by_author(&event.pubkey.to_hex(), event_ids)
+        .instrument(info_span!("delete_events", latency_metric_name = "database_write"))
+        .await;
+    match deletion {
+        Ok(deleted) => {
+            debug!("Deletion event {} removed {} events", event.id, deleted);
+            // The author's relay list may have been among them
+            if deleted > 0 {
+                relay_list::evict_relay_list(&event.pubkey.to_hex(), state).await;
+            }
+        }
+        Err(e) => {
+            state.metrics.record_database_error();
+            error!("Failed to apply deletion event {}: {}", event.id, e);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::Instant;
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::metrics::Metrics;
+
+/// Span field naming the metric a span's duration is recorded to
+pub const LATENCY_METRIC_FIELD: &str = "latency_metric_name";
+
+/// Span field carrying the event kind, for metrics labeled by kind
+pub const EVENT_KIND_FIELD: &str = "event_kind";
+
+/// A latency metric a span can be timed into, by its `latency_metric_name`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyMetric {
+    /// Handling one client message, from parsing to the last reply
+    MessageProcessing,
+    /// Handling an EVENT, labeled by the span's `event_kind`
+    EventProcessing,
+    /// Signature and relay policy checks on an event
+    EventValidation,
+    /// Storing an event
+    DatabaseWrite,
+    /// Querying or counting stored events
+    DatabaseRead,
+    /// Handling a REQ or COUNT
+    QueryProcessing,
+}
+
+impl LatencyMetric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "message_processing" => Some(Self::MessageProcessing),
+            "event_processing" => Some(Self::EventProcessing),
+            "event_validation" => Some(Self::EventValidation),
+            "database_write" => Some(Self::DatabaseWrite),
+            "database_read" => Some(Self::DatabaseRead),
+            "query_processing" => Some(Self::QueryProcessing),
+            _ => None,
+        }
+    }
+
+    fn record(self, metrics: &Metrics, event_kind: Option<u16>, seconds: f64) {
+        match self {
+            Self::MessageProcessing => metrics.record_message_processed(seconds),
+            Self::EventProcessing => {
+                if let Some(kind) = event_kind {
+                    metrics.record_event_processed(kind, seconds);
+                }
+            }
+            Self::EventValidation => metrics.record_event_validation(seconds),
+            Self::DatabaseWrite | Self::DatabaseRead => metrics.record_database_operation(seconds),
+            Self::QueryProcessing => metrics.record_query_processed(seconds),
+        }
+    }
+}
+
+// Stored in the extensions of a span that names a latency metric
+struct SpanTiming {
+    metric: LatencyMetric,
+    event_kind: Option<u16>,
+    opened_at: Instant,
+}
+
+#[derive(Default)]
+struct LatencyFields {
+    metric: Option<LatencyMetric>,
+    event_kind: Option<u16>,
+}
+
+impl Visit for LatencyFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == LATENCY_METRIC_FIELD {
+            self.metric = LatencyMetric::from_name(value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == EVENT_KIND_FIELD {
+            self.event_kind = u16::try_from(value).ok();
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == EVENT_KIND_FIELD {
+            self.event_kind = u16::try_from(value).ok();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Records the duration of every span carrying a `latency_metric_name` field
+/// to the matching `Metrics` method when the span exits for the last time.
+/// An instrumented future enters and exits its span on each poll, so the
+/// duration runs from the span's creation to its close rather than summing
+/// the time spent polled.
+///
+/// ```ignore
+/// #[instrument(skip_all, fields(latency_metric_name = "message_processing"))]
+/// async fn handle_client_message(...) { ... }
+/// ```
+pub struct LatencyLayer {
+    metrics: Metrics,
+}
+
+impl LatencyLayer {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for LatencyLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = LatencyFields::default();
+        attrs.record(&mut fields);
+        let (Some(metric), Some(span)) = (fields.metric, ctx.span(id)) else {
+            return;
+        };
+        span.extensions_mut().insert(SpanTiming {
+            metric,
+            event_kind: fields.event_kind,
+            opened_at: Instant::now(),
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<SpanTiming>() {
+            let mut fields = LatencyFields::default();
+            values.record(&mut fields);
+            timing.event_kind = fields.event_kind.or(timing.event_kind);
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let timing = span.extensions_mut().remove::<SpanTiming>();
+        if let Some(timing) = timing {
+            let seconds = timing.opened_at.elapsed().as_secs_f64();
+            timing.metric.record(&self.metrics, timing.event_kind, seconds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{field, info_span, Instrument};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    fn with_layer(metrics: &Metrics, f: impl FnOnce()) {
+        let subscriber = Registry::default().with(LatencyLayer::new(metrics.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+    }
+
+    #[test]
+    fn test_metric_names() {
+        let names = [
+            ("message_processing", LatencyMetric::MessageProcessing),
+            ("event_processing", LatencyMetric::EventProcessing),
+            ("event_validation", LatencyMetric::EventValidation),
+            ("database_write", LatencyMetric::DatabaseWrite),
+            ("database_read", LatencyMetric::DatabaseRead),
+            ("query_processing", LatencyMetric::QueryProcessing),
+        ];
+        for (name, metric) in names {
+            assert_eq!(LatencyMetric::from_name(name), Some(metric));
+        }
+        assert_eq!(LatencyMetric::from_name("unknown"), None);
+        assert_eq!(LatencyMetric::from_name(""), None);
+    }
+
+    #[test]
+    fn test_span_close_records_its_metric() {
+        let metrics = Metrics::new().unwrap();
+        with_layer(&metrics, || {
+            info_span!("handle_client_message", latency_metric_name = "message_processing").in_scope(|| {});
+            info_span!("validate_event", latency_metric_name = "event_validation").in_scope(|| {});
+            info_span!("validate_event", latency_metric_name = "event_validation").in_scope(|| {});
+            info_span!("save_event", latency_metric_name = "database_write").in_scope(|| {});
+            info_span!("query_events", latency_metric_name = "database_read").in_scope(|| {});
+            info_span!("handle_req_message", latency_metric_name = "query_processing").in_scope(|| {});
+        });
+
+        assert_eq!(metrics.message_processing_time.get_sample_count(), 1);
+        assert_eq!(metrics.event_validation_time.get_sample_count(), 2);
+        assert_eq!(metrics.database_query_time.get_sample_count(), 2);
+        assert_eq!(metrics.database_operations.get(), 2.0);
+        assert_eq!(metrics.query_processing_time.get_sample_count(), 1);
+    }
+
+    #[test]
+    fn test_spans_without_a_known_metric_are_ignored() {
+        let metrics = Metrics::new().unwrap();
+        with_layer(&metrics, || {
+            info_span!("broadcast_event").in_scope(|| {});
+            info_span!("check_duplicate", latency_metric_name = "duplicate_check").in_scope(|| {});
+        });
+
+        let rendered = metrics.render().unwrap();
+        assert!(rendered
+            .lines()
+            .filter(|line| line.contains("_seconds_count"))
+            .all(|line| line.ends_with(" 0")));
+    }
+
+    #[test]
+    fn test_event_processing_is_labeled_by_kind() {
+        let metrics = Metrics::new().unwrap();
+        with_layer(&metrics, || {
+            info_span!("handle_event_message", latency_metric_name = "event_processing", event_kind = 1u16).in_scope(|| {});
+            info_span!("handle_event_message", latency_metric_name = "event_processing", event_kind = 7u16).in_scope(|| {});
+
+            // The kind may also be recorded after the span is created
+            let span = info_span!("handle_event_message", latency_metric_name = "event_processing", event_kind = field::Empty);
+            span.record(EVENT_KIND_FIELD, 7u16);
+            drop(span);
+        });
+
+        assert_eq!(metrics.event_processing_time.with_label_values(&["1"]).get_sample_count(), 1);
+        assert_eq!(metrics.event_processing_time.with_label_values(&["7"]).get_sample_count(), 2);
+    }
+
+    #[test]
+    fn test_re_entered_span_is_recorded_once() {
+        let metrics = Metrics::new().unwrap();
+        with_layer(&metrics, || {
+            let span = info_span!("handle_client_message", latency_metric_name = "message_processing");
+            for _ in 0..3 {
+                span.in_scope(|| {});
+            }
+            assert_eq!(metrics.message_processing_time.get_sample_count(), 0);
+            drop(span);
+        });
+
+        assert_eq!(metrics.message_processing_time.get_sample_count(), 1);
+    }
+
+    // An instrumented future exits its span at every await; only its completion is timed
+    #[tokio::test]
+    async fn test_instrumented_future_is_timed_across_awaits() {
+        let metrics = Metrics::new().unwrap();
+        let subscriber = Registry::default().with(LatencyLayer::new(metrics.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        async {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            tokio::task::yield_now().await;
+        }
+        .instrument(info_span!("save_event", latency_metric_name = "database_write"))
+        .await;
+
+        assert_eq!(metrics.database_query_time.get_sample_count(), 1);
+        assert!(metrics.database_query_time.get_sample_sum() >= 0.02);
+    }
+}
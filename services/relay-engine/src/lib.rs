@@ -1,11 +1,40 @@
 // Nostr Relay Engine Library
 // High-performance relay implementation using rust-nostr
 
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod client_ip;
 pub mod config;
+pub mod config_watcher;
+pub mod connection_limit;
+pub mod connection_cleanup;
 pub mod database;
+pub mod events_api;
+pub mod fanout;
+pub mod health;
+pub mod hooks;
+pub mod latency;
 pub mod metrics;
+pub mod nip11;
+pub mod nip28;
+pub mod nip42;
+pub mod nip57;
+pub mod openapi;
+pub mod outbound;
+pub mod profile;
 pub mod rate_limiter;
+pub mod relay_list;
+pub mod sse;
+pub mod subscription;
+pub mod subscription_index;
+#[cfg(feature = "tokio-metrics")]
+pub mod task_metrics;
 pub mod app_state;
+pub mod telemetry;
+pub mod tls;
+pub mod validation;
 pub mod test_utils;
 pub mod mock_database;
 
@@ -20,45 +49,35 @@ use axum::{
     routing::get,
     Router,
     extract::State,
-    response::Json,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
 };
-use serde_json::{json, Value};
 
 // Create the main application router
 pub fn create_app(state: AppState) -> Router {
-    Router::new()
+    let router = Router::new()
         .route("/", get(relay_info))
         .route("/metrics", get(metrics_handler))
         .route("/health", get(health_check))
-        .with_state(state)
+        .merge(metrics::create_metrics_api_router())
+        .merge(metrics::create_storage_metrics_router())
+        .merge(admin::create_admin_router(state.clone()))
+        .merge(relay_list::create_relay_list_router())
+        .merge(profile::create_profile_router())
+        .merge(events_api::create_events_api_router())
+        .merge(openapi::create_openapi_router())
+        .merge(sse::create_sse_router())
+        .merge(nip11::create_nip05_router());
+
+    #[cfg(feature = "tokio-metrics")]
+    let router = router.merge(task_metrics::create_tokio_metrics_router());
+
+    router.with_state(state)
 }
 
 // Relay info endpoint (NIP-11)
-async fn relay_info(State(state): State<AppState>) -> Json<Value> {
-    Json(json!({
-        "name": state.config.relay_name,
-        "description": state.config.relay_description,
-        "pubkey": state.config.relay_pubkey,
-        "contact": state.config.relay_contact,
-        "supported_nips": [1, 2, 9, 11, 12, 15, 16, 20, 22, 28, 33],
-        "software": "NrelayOne",
-        "version": env!("CARGO_PKG_VERSION"),
-        "limitation": {
-            "max_message_length": 65536,
-            "max_subscriptions": 20, // Default value since config is private
-            "max_filters": 100,
-            "max_limit": 5000,
-            "max_subid_length": 100,
-            "min_prefix": 4,
-            "max_event_tags": 100,
-            "max_content_length": 8196,
-            "min_pow_difficulty": 0,
-            "auth_required": false,
-            "payment_required": false
-        },
-        "payments_url": null,
-        "fees": {}
-    }))
+async fn relay_info(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    nip11::relay_info_response(&*state.mutable_config.read().await, &headers)
 }
 
 // Metrics endpoint
@@ -66,10 +85,7 @@ async fn metrics_handler(State(state): State<AppState>) -> String {
     state.metrics.render().unwrap_or_else(|_| "# Metrics unavailable\n".to_string())
 }
 
-// Health check endpoint
-async fn health_check() -> Json<Value> {
-    Json(json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().timestamp()
-    }))
+// Health check endpoint: probes the database and Redis
+async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    health::health_response(&state).await
 }
@@ -1,4 +1,7 @@
-use prometheus::{Counter, Histogram, HistogramOpts, IntGauge, Registry, Encoder, TextEncoder};
+use prometheus::{
+    core::Collector, Counter, CounterVec, Histogram, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry,
+    Encoder, TextEncoder,
+};
 use anyhow::Result;
 use axum::{
     extract::State,
@@ -8,42 +11,140 @@ use axum::{
     Router,
 };
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use utoipa::ToSchema;
+
+use crate::app_state::AppState;
+use crate::database::{CircuitBreakerState, RelayDatabase};
+use nostr::Filter;
+use std::{collections::{BTreeMap, HashMap}, time::SystemTime};
+use tracing::error;
+
+/// Event kinds that get their own `kind` label value. Clients can publish
+/// any kind, so every other kind is counted under `other` to keep the number
+/// of series bounded.
+pub const LABELLED_KINDS: &[u16] = &[
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 16, 40, 41, 42, 43, 44, 1063, 1311, 1984, 1985, 9734, 9735, 10000, 10001, 10002,
+    13194, 22242, 23194, 23195, 24133, 27235, 30000, 30001, 30008, 30009, 30023, 30024, 30078, 30311, 31922,
+    31923, 31989, 31990,
+];
+
+/// The `kind` label value an event kind is counted under
+pub fn kind_label(kind: u16) -> String {
+    if LABELLED_KINDS.contains(&kind) {
+        kind.to_string()
+    } else {
+        "other".to_string()
+    }
+}
 
 #[derive(Clone)]
 pub struct Metrics {
     pub registry: Registry,
+    /// Value of the `relay_instance` label on every metric, telling apart the
+    /// instances of a multi-instance deployment
+    pub instance_id: Option<String>,
     
     // Connection metrics
     pub active_connections: IntGauge,
     pub total_connections: Counter,
     pub connection_duration: Histogram,
+    pub connection_queue_depth: IntGaugeVec,
+    pub connections_cleaned: Counter,
+    pub slow_subscriber_timeouts: Counter,
+    pub bytes_sent: Counter,
+    pub bytes_received: Counter,
+    pub message_processing_time: Histogram,
     
     // Event metrics
-    pub events_received: Counter,
-    pub events_stored: Counter,
-    pub events_rejected: Counter,
-    pub event_processing_time: Histogram,
+    pub events_received: CounterVec,
+    pub events_stored: CounterVec,
+    pub events_rejected: CounterVec,
+    pub event_processing_time: HistogramVec,
+    pub event_validation_time: Histogram,
+    pub event_size_bytes: HistogramVec,
+    pub events_ephemeral_broadcast: Counter,
+    pub events_expired_deleted: Counter,
+    pub events_pruned: Counter,
     
     // Query metrics
     pub queries_received: Counter,
     pub query_processing_time: Histogram,
+    pub query_result_count: HistogramVec,
     pub subscription_count: IntGauge,
     
     // Rate limiting metrics
     pub rate_limited_connections: Counter,
+    pub connection_limit_reached: Counter,
     pub rate_limited_events: Counter,
+    pub rate_limited_pubkeys: Counter,
+    pub pubkey_subscription_limit_rejections: Counter,
+    pub content_filtered: Counter,
+    pub nip44_validation_failures: Counter,
+    pub zap_events: Counter,
+    pub spam_rejected: Counter,
+    pub events_rejected_url_limit: Counter,
+    pub events_rejected_blocked_domain: Counter,
+    pub invalid_filter_rejections: Counter,
     
     // Database metrics
     pub database_operations: Counter,
     pub database_errors: Counter,
     pub database_query_time: Histogram,
+    pub db_circuit_state: IntGauge,
+    pub db_pool_size: IntGauge,
+    pub db_pool_idle: IntGauge,
+    pub db_pool_acquire_queue_depth: IntGauge,
+    pub db_pool_acquire_duration_seconds: Histogram,
+    
+    // Cache metrics
+    pub cache_memory_used_bytes: IntGauge,
+    pub cache_memory_max_bytes: IntGauge,
+    pub sig_cache_hits: Counter,
+    pub sig_cache_misses: Counter,
+    pub id_cache_hits: Counter,
+    pub id_cache_misses: Counter,
+    pub event_cache_hits: CounterVec,
+    pub event_cache_misses: CounterVec,
 }
 
+/// Coarse shape of a filter, used as a low-cardinality metrics label. The most
+/// selective condition present wins.
+pub fn filter_type(filter: &Filter) -> &'static str {
+    if filter.ids.is_some() {
+        "ids"
+    } else if filter.search.is_some() {
+        "search"
+    } else if filter.authors.is_some() {
+        "authors"
+    } else if !filter.generic_tags.is_empty() {
+        "tags"
+    } else if filter.kinds.is_some() {
+        "kinds"
+    } else if filter.since.is_some() || filter.until.is_some() {
+        "time_range"
+    } else {
+        "all"
+    }
+}
+
+/// Label carrying the relay instance ID on every metric of `Metrics::for_instance`
+pub const INSTANCE_LABEL: &str = "relay_instance";
+
 impl Metrics {
     pub fn new() -> Result<Self> {
-        let registry = Registry::new();
-        
+        Self::with_registry(Registry::new(), None)
+    }
+
+    /// Metrics labelled `relay_instance="<instance_id>"`, so scrapes of
+    /// several relay instances behind a load balancer stay distinguishable.
+    /// Prometheus sets `instance` itself at scrape time, so that name is left to it.
+    pub fn for_instance(instance_id: &str) -> Result<Self> {
+        let labels = HashMap::from([(INSTANCE_LABEL.to_string(), instance_id.to_string())]);
+        let registry = Registry::new_custom(None, Some(labels))?;
+        Self::with_registry(registry, Some(instance_id.to_string()))
+    }
+
+    fn with_registry(registry: Registry, instance_id: Option<String>) -> Result<Self> {
         // Connection metrics
         let active_connections = IntGauge::new(
             "relay_active_connections",
@@ -63,31 +164,98 @@ impl Metrics {
         ))?;
         registry.register(Box::new(connection_duration.clone()))?;
         
-        // Event metrics
-        let events_received = Counter::new(
-            "relay_events_received_total",
-            "Total number of events received"
+        let connection_queue_depth = IntGaugeVec::new(
+            Opts::new("relay_connection_queue_depth", "Messages waiting in each connection's outbound queue"),
+            &["connection_id"]
+        )?;
+        registry.register(Box::new(connection_queue_depth.clone()))?;
+        
+        let connections_cleaned = Counter::new(
+            "relay_connections_cleaned_total",
+            "Idle connections closed by the cleanup task"
+        )?;
+        registry.register(Box::new(connections_cleaned.clone()))?;
+        
+        let slow_subscriber_timeouts = Counter::new(
+            "relay_slow_subscriber_timeouts_total",
+            "Connections closed because the client didn't accept a message within the send timeout"
+        )?;
+        registry.register(Box::new(slow_subscriber_timeouts.clone()))?;
+        
+        let bytes_sent = Counter::new(
+            "relay_bytes_sent_total",
+            "Total bytes of WebSocket messages sent to clients"
+        )?;
+        registry.register(Box::new(bytes_sent.clone()))?;
+        
+        let bytes_received = Counter::new(
+            "relay_bytes_received_total",
+            "Total bytes of WebSocket messages received from clients"
+        )?;
+        registry.register(Box::new(bytes_received.clone()))?;
+        
+        let message_processing_time = Histogram::with_opts(HistogramOpts::new(
+            "relay_message_processing_seconds",
+            "Time to handle a client message"
+        ))?;
+        registry.register(Box::new(message_processing_time.clone()))?;
+        
+        // Event metrics, labeled by event kind
+        let events_received = CounterVec::new(
+            Opts::new("relay_events_received_total", "Total number of events received"),
+            &["kind"]
         )?;
         registry.register(Box::new(events_received.clone()))?;
         
-        let events_stored = Counter::new(
-            "relay_events_stored_total",
-            "Total number of events successfully stored"
+        let events_stored = CounterVec::new(
+            Opts::new("relay_events_stored_total", "Total number of events successfully stored"),
+            &["kind"]
         )?;
         registry.register(Box::new(events_stored.clone()))?;
         
-        let events_rejected = Counter::new(
-            "relay_events_rejected_total",
-            "Total number of events rejected"
+        let events_rejected = CounterVec::new(
+            Opts::new("relay_events_rejected_total", "Total number of events rejected"),
+            &["kind"]
         )?;
         registry.register(Box::new(events_rejected.clone()))?;
         
-        let event_processing_time = Histogram::with_opts(HistogramOpts::new(
-            "relay_event_processing_seconds",
-            "Time to process an event"
-        ))?;
+        let event_processing_time = HistogramVec::new(
+            HistogramOpts::new("relay_event_processing_seconds", "Time to process an event"),
+            &["kind"]
+        )?;
         registry.register(Box::new(event_processing_time.clone()))?;
         
+        let event_validation_time = Histogram::with_opts(HistogramOpts::new(
+            "relay_event_validation_seconds",
+            "Time to check an event's signature and relay policy"
+        ))?;
+        registry.register(Box::new(event_validation_time.clone()))?;
+        
+        let event_size_bytes = HistogramVec::new(
+            HistogramOpts::new("relay_event_size_bytes", "Serialized size of accepted events")
+                .buckets(vec![128.0, 512.0, 1024.0, 4096.0, 16384.0, 65536.0]),
+            &["kind"]
+        )?;
+        registry.register(Box::new(event_size_bytes.clone()))?;
+        
+        let events_ephemeral_broadcast = Counter::new(
+            "relay_events_ephemeral_broadcast_total",
+            "Total number of ephemeral events broadcast without storage"
+        )?;
+        registry.register(Box::new(events_ephemeral_broadcast.clone()))?;
+        
+        let events_expired_deleted = Counter::new(
+            "relay_events_expired_deleted_total",
+            "Total number of expired events removed from storage"
+        )?;
+        registry.register(Box::new(events_expired_deleted.clone()))?;
+        
+        let events_pruned = Counter::new(
+            "relay_events_pruned_total",
+            "Total number of events removed by the retention window"
+        )?;
+        registry.register(Box::new(events_pruned.clone()))?;
+        
         // Query metrics
         let queries_received = Counter::new(
             "relay_queries_received_total",
@@ -101,6 +269,13 @@ impl Metrics {
         ))?;
         registry.register(Box::new(query_processing_time.clone()))?;
         
+        let query_result_count = HistogramVec::new(
+            HistogramOpts::new("relay_query_result_count", "Stored events returned per REQ filter")
+                .buckets(vec![0.0, 1.0, 10.0, 100.0, 1000.0, 5000.0]),
+            &["filter_type"]
+        )?;
+        registry.register(Box::new(query_result_count.clone()))?;
+        
         let subscription_count = IntGauge::new(
             "relay_active_subscriptions",
             "Number of active subscriptions"
@@ -114,12 +289,72 @@ impl Metrics {
         )?;
         registry.register(Box::new(rate_limited_connections.clone()))?;
         
+        let connection_limit_reached = Counter::new(
+            "relay_connection_limit_reached_total",
+            "Connections refused because max_total_connections were open"
+        )?;
+        registry.register(Box::new(connection_limit_reached.clone()))?;
+        
         let rate_limited_events = Counter::new(
             "relay_rate_limited_events_total",
             "Total number of rate limited events"
         )?;
         registry.register(Box::new(rate_limited_events.clone()))?;
         
+        let rate_limited_pubkeys = Counter::new(
+            "relay_rate_limited_pubkeys_total",
+            "Total number of requests rate limited by pubkey"
+        )?;
+        registry.register(Box::new(rate_limited_pubkeys.clone()))?;
+        
+        let pubkey_subscription_limit_rejections = Counter::new(
+            "relay_pubkey_subscription_limit_rejections_total",
+            "Subscriptions refused because their pubkey already had the most allowed open"
+        )?;
+        registry.register(Box::new(pubkey_subscription_limit_rejections.clone()))?;
+        
+        let content_filtered = Counter::new(
+            "relay_content_filtered_total",
+            "Total number of events rejected by the content filters"
+        )?;
+        registry.register(Box::new(content_filtered.clone()))?;
+        
+        let nip44_validation_failures = Counter::new(
+            "relay_nip44_validation_failures_total",
+            "Encrypted events rejected for a malformed NIP-44 payload"
+        )?;
+        registry.register(Box::new(nip44_validation_failures.clone()))?;
+        
+        let zap_events = Counter::new(
+            "relay_zap_events_total",
+            "Zap requests and receipts stored"
+        )?;
+        registry.register(Box::new(zap_events.clone()))?;
+        
+        let spam_rejected = Counter::new(
+            "relay_spam_rejected_total",
+            "Total number of text notes rejected for scoring above the spam threshold"
+        )?;
+        registry.register(Box::new(spam_rejected.clone()))?;
+        
+        let events_rejected_url_limit = Counter::new(
+            "relay_events_rejected_url_limit_total",
+            "Text notes rejected for containing more URLs than allowed"
+        )?;
+        registry.register(Box::new(events_rejected_url_limit.clone()))?;
+        
+        let events_rejected_blocked_domain = Counter::new(
+            "relay_events_rejected_blocked_domain_total",
+            "Text notes rejected for linking to a blocked domain"
+        )?;
+        registry.register(Box::new(events_rejected_blocked_domain.clone()))?;
+        
+        let invalid_filter_rejections = Counter::new(
+            "relay_invalid_filter_rejections_total",
+            "Total number of REQs refused for invalid or abusive filters"
+        )?;
+        registry.register(Box::new(invalid_filter_rejections.clone()))?;
+        
         // Database metrics
         let database_operations = Counter::new(
             "relay_database_operations_total",
@@ -139,23 +374,139 @@ impl Metrics {
         ))?;
         registry.register(Box::new(database_query_time.clone()))?;
         
+        let db_circuit_state = IntGauge::new(
+            "relay_db_circuit_state",
+            "Database circuit breaker state: 0 closed, 1 open, 2 half-open"
+        )?;
+        registry.register(Box::new(db_circuit_state.clone()))?;
+        
+        let db_pool_size = IntGauge::new(
+            "relay_db_pool_size",
+            "Connections currently open in the database pool"
+        )?;
+        registry.register(Box::new(db_pool_size.clone()))?;
+        
+        let db_pool_idle = IntGauge::new(
+            "relay_db_pool_idle",
+            "Open database connections not checked out"
+        )?;
+        registry.register(Box::new(db_pool_idle.clone()))?;
+        
+        let db_pool_acquire_queue_depth = IntGauge::new(
+            "relay_db_pool_acquire_queue_depth",
+            "Database queries waiting for a pool connection"
+        )?;
+        registry.register(Box::new(db_pool_acquire_queue_depth.clone()))?;
+        
+        let db_pool_acquire_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "relay_db_pool_acquire_duration_seconds",
+            "Time to check a connection out of the database pool"
+        ))?;
+        registry.register(Box::new(db_pool_acquire_duration_seconds.clone()))?;
+        
+        // Cache metrics
+        let cache_memory_used_bytes = IntGauge::new(
+            "relay_cache_memory_used_bytes",
+            "Memory used by the Redis instance behind event fanout"
+        )?;
+        registry.register(Box::new(cache_memory_used_bytes.clone()))?;
+        
+        let cache_memory_max_bytes = IntGauge::new(
+            "relay_cache_memory_max_bytes",
+            "maxmemory limit of the Redis instance behind event fanout; 0 when unbounded"
+        )?;
+        registry.register(Box::new(cache_memory_max_bytes.clone()))?;
+        
+        let sig_cache_hits = Counter::new(
+            "relay_sig_cache_hits_total",
+            "Total number of event signatures found in the verification cache"
+        )?;
+        registry.register(Box::new(sig_cache_hits.clone()))?;
+        
+        let sig_cache_misses = Counter::new(
+            "relay_sig_cache_misses_total",
+            "Total number of event signatures verified on a cache miss"
+        )?;
+        registry.register(Box::new(sig_cache_misses.clone()))?;
+        
+        let id_cache_hits = Counter::new(
+            "relay_id_cache_hits_total",
+            "Total number of duplicate checks answered from the event ID cache"
+        )?;
+        registry.register(Box::new(id_cache_hits.clone()))?;
+        
+        let id_cache_misses = Counter::new(
+            "relay_id_cache_misses_total",
+            "Total number of duplicate checks that fell back to the database"
+        )?;
+        registry.register(Box::new(id_cache_misses.clone()))?;
+        
+        // Redis event cache lookups, labeled by database method
+        let event_cache_hits = CounterVec::new(
+            Opts::new("relay_event_cache_hits_total", "Total number of event reads answered from the Redis event cache"),
+            &["method"]
+        )?;
+        registry.register(Box::new(event_cache_hits.clone()))?;
+        
+        let event_cache_misses = CounterVec::new(
+            Opts::new("relay_event_cache_misses_total", "Total number of event reads that fell back to the database"),
+            &["method"]
+        )?;
+        registry.register(Box::new(event_cache_misses.clone()))?;
+        
         Ok(Self {
             registry,
+            instance_id,
             active_connections,
             total_connections,
             connection_duration,
+            connection_queue_depth,
+            connections_cleaned,
+            slow_subscriber_timeouts,
+            bytes_sent,
+            bytes_received,
+            message_processing_time,
             events_received,
             events_stored,
             events_rejected,
             event_processing_time,
+            event_validation_time,
+            event_size_bytes,
+            events_ephemeral_broadcast,
+            events_expired_deleted,
+            events_pruned,
             queries_received,
             query_processing_time,
+            query_result_count,
             subscription_count,
             rate_limited_connections,
+            connection_limit_reached,
             rate_limited_events,
+            rate_limited_pubkeys,
+            pubkey_subscription_limit_rejections,
+            content_filtered,
+            nip44_validation_failures,
+            zap_events,
+            spam_rejected,
+            events_rejected_url_limit,
+            events_rejected_blocked_domain,
+            invalid_filter_rejections,
             database_operations,
             database_errors,
             database_query_time,
+            db_circuit_state,
+            db_pool_size,
+            db_pool_idle,
+            db_pool_acquire_queue_depth,
+            db_pool_acquire_duration_seconds,
+            cache_memory_used_bytes,
+            cache_memory_max_bytes,
+            sig_cache_hits,
+            sig_cache_misses,
+            id_cache_hits,
+            id_cache_misses,
+            event_cache_hits,
+            event_cache_misses,
         })
     }
     
@@ -169,18 +520,100 @@ impl Metrics {
         self.connection_duration.observe(duration);
     }
     
-    pub fn record_event_received(&self) {
-        self.events_received.inc();
+    pub fn record_connections_cleaned(&self, count: usize) {
+        self.connections_cleaned.inc_by(count as f64);
     }
     
-    pub fn record_event_stored(&self, processing_time: f64) {
-        self.events_stored.inc();
-        self.event_processing_time.observe(processing_time);
+    pub fn record_event_received(&self, kind: u16) {
+        self.events_received.with_label_values(&[&kind_label(kind)]).inc();
     }
     
-    pub fn record_event_rejected(&self, processing_time: f64) {
-        self.events_rejected.inc();
-        self.event_processing_time.observe(processing_time);
+    pub fn record_event_stored(&self, kind: u16) {
+        self.events_stored.with_label_values(&[&kind_label(kind)]).inc();
+    }
+    
+    pub fn record_event_processed(&self, kind: u16, processing_time: f64) {
+        self.event_processing_time.with_label_values(&[&kind_label(kind)]).observe(processing_time);
+    }
+    
+    pub fn record_event_validation(&self, duration: f64) {
+        self.event_validation_time.observe(duration);
+    }
+    
+    pub fn record_event_size(&self, kind: u16, size: usize) {
+        self.event_size_bytes.with_label_values(&[&kind_label(kind)]).observe(size as f64);
+    }
+    
+    pub fn record_ephemeral_broadcast(&self) {
+        self.events_ephemeral_broadcast.inc();
+    }
+    
+    pub fn record_expired_events_deleted(&self, count: u64) {
+        self.events_expired_deleted.inc_by(count as f64);
+    }
+    
+    pub fn record_events_pruned(&self, count: u64) {
+        self.events_pruned.inc_by(count as f64);
+    }
+    
+    pub fn record_sig_cache_hit(&self) {
+        self.sig_cache_hits.inc();
+    }
+    
+    pub fn record_sig_cache_miss(&self) {
+        self.sig_cache_misses.inc();
+    }
+    
+    pub fn record_id_cache_hit(&self) {
+        self.id_cache_hits.inc();
+    }
+    
+    pub fn record_id_cache_miss(&self) {
+        self.id_cache_misses.inc();
+    }
+    
+    pub fn record_event_cache_hit(&self, method: &str) {
+        self.event_cache_hits.with_label_values(&[method]).inc();
+    }
+    
+    pub fn record_event_cache_miss(&self, method: &str) {
+        self.event_cache_misses.with_label_values(&[method]).inc();
+    }
+    
+    pub fn record_content_filtered(&self) {
+        self.content_filtered.inc();
+    }
+    
+    pub fn record_spam_rejected(&self) {
+        self.spam_rejected.inc();
+    }
+    
+    pub fn record_url_limit_rejected(&self) {
+        self.events_rejected_url_limit.inc();
+    }
+    
+    pub fn record_blocked_domain_rejected(&self) {
+        self.events_rejected_blocked_domain.inc();
+    }
+    
+    pub fn record_invalid_filter_rejection(&self) {
+        self.invalid_filter_rejections.inc();
+    }
+    
+    pub fn record_nip44_validation_failure(&self) {
+        self.nip44_validation_failures.inc();
+    }
+    
+    pub fn record_zap_event(&self) {
+        self.zap_events.inc();
+    }
+    
+    pub fn record_pubkey_subscription_limit_rejection(&self) {
+        self.pubkey_subscription_limit_rejections.inc();
+    }
+    
+    pub fn record_event_rejected(&self, kind: u16) {
+        self.events_rejected.with_label_values(&[&kind_label(kind)]).inc();
     }
     
     pub fn record_query_received(&self) {
@@ -191,6 +624,10 @@ impl Metrics {
         self.query_processing_time.observe(processing_time);
     }
     
+    pub fn record_query_results(&self, filter: &Filter, count: usize) {
+        self.query_result_count.with_label_values(&[filter_type(filter)]).observe(count as f64);
+    }
+    
     pub fn record_subscription_start(&self) {
         self.subscription_count.inc();
     }
@@ -203,10 +640,34 @@ impl Metrics {
         self.rate_limited_connections.inc();
     }
     
+    pub fn record_slow_subscriber_timeout(&self) {
+        self.slow_subscriber_timeouts.inc();
+    }
+    
+    pub fn record_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.inc_by(bytes as f64);
+    }
+    
+    pub fn record_bytes_received(&self, bytes: usize) {
+        self.bytes_received.inc_by(bytes as f64);
+    }
+    
+    pub fn record_message_processed(&self, processing_time: f64) {
+        self.message_processing_time.observe(processing_time);
+    }
+    
+    pub fn record_connection_limit_reached(&self) {
+        self.connection_limit_reached.inc();
+    }
+    
     pub fn record_rate_limit_event(&self) {
         self.rate_limited_events.inc();
     }
     
+    pub fn record_rate_limit_pubkey(&self) {
+        self.rate_limited_pubkeys.inc();
+    }
+    
     pub fn record_database_operation(&self, duration: f64) {
         self.database_operations.inc();
         self.database_query_time.observe(duration);
@@ -216,6 +677,34 @@ impl Metrics {
         self.database_errors.inc();
     }
     
+    pub fn set_connection_queue_depth(&self, connection_id: &str, depth: usize) {
+        self.connection_queue_depth.with_label_values(&[connection_id]).set(depth as i64);
+    }
+    
+    /// Drop the queue depth series of a closed connection
+    pub fn remove_connection_queue_depth(&self, connection_id: &str) {
+        let _ = self.connection_queue_depth.remove_label_values(&[connection_id]);
+    }
+    
+    pub fn set_db_circuit_state(&self, state: CircuitBreakerState) {
+        self.db_circuit_state.set(state.as_gauge());
+    }
+    
+    pub fn set_db_pool_usage(&self, size: u32, idle: usize, waiting: usize) {
+        self.db_pool_size.set(size as i64);
+        self.db_pool_idle.set(idle as i64);
+        self.db_pool_acquire_queue_depth.set(waiting as i64);
+    }
+    
+    pub fn set_cache_memory(&self, used: u64, max: u64) {
+        self.cache_memory_used_bytes.set(used as i64);
+        self.cache_memory_max_bytes.set(max as i64);
+    }
+    
+    pub fn record_db_pool_acquire(&self, duration: f64) {
+        self.db_pool_acquire_duration_seconds.observe(duration);
+    }
+    
     pub fn render(&self) -> Result<String> {
         let encoder = TextEncoder::new();
         let metric_families = self.registry.gather();
@@ -228,6 +717,7 @@ impl Metrics {
     pub fn get_api_metrics(&self) -> ApiMetrics {
         ApiMetrics {
             relay_status: RelayStatus {
+                instance: self.instance_id.clone(),
                 active_connections: self.active_connections.get() as u64,
                 total_connections: self.total_connections.get() as u64,
                 uptime_seconds: SystemTime::now()
@@ -237,10 +727,11 @@ impl Metrics {
                 status: if self.active_connections.get() > 0 { "healthy" } else { "idle" }.to_string(),
             },
             events: EventMetrics {
-                events_received: self.events_received.get() as u64,
-                events_stored: self.events_stored.get() as u64,
-                events_rejected: self.events_rejected.get() as u64,
+                events_received: counter_total(&self.events_received) as u64,
+                events_stored: counter_total(&self.events_stored) as u64,
+                events_rejected: counter_total(&self.events_rejected) as u64,
                 avg_processing_time_ms: self.get_avg_processing_time(),
+                by_kind: self.get_events_by_kind(),
             },
             performance: PerformanceMetrics {
                 queries_received: self.queries_received.get() as u64,
@@ -254,15 +745,36 @@ impl Metrics {
     }
     
     fn get_avg_processing_time(&self) -> f64 {
-        // Get sample count and sum from histogram
-        let sample_count = self.event_processing_time.get_sample_count();
+        // Get sample count and sum across every kind's histogram
+        let (sample_count, sample_sum) = self
+            .event_processing_time
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .fold((0, 0.0), |(count, sum), metric| {
+                let histogram = metric.get_histogram();
+                (count + histogram.get_sample_count(), sum + histogram.get_sample_sum())
+            });
         if sample_count > 0 {
-            let sample_sum = self.event_processing_time.get_sample_sum();
             (sample_sum / sample_count as f64) * 1000.0 // Convert to milliseconds
         } else {
             0.0
         }
     }
+
+    fn get_events_by_kind(&self) -> BTreeMap<String, KindEventMetrics> {
+        let mut by_kind: BTreeMap<String, KindEventMetrics> = BTreeMap::new();
+        for (kind, value) in counter_values(&self.events_received) {
+            by_kind.entry(kind).or_default().received = value as u64;
+        }
+        for (kind, value) in counter_values(&self.events_stored) {
+            by_kind.entry(kind).or_default().stored = value as u64;
+        }
+        for (kind, value) in counter_values(&self.events_rejected) {
+            by_kind.entry(kind).or_default().rejected = value as u64;
+        }
+        by_kind
+    }
     
     fn get_avg_query_time(&self) -> f64 {
         let sample_count = self.query_processing_time.get_sample_count();
@@ -275,31 +787,60 @@ impl Metrics {
     }
 }
 
+// Current value of each label set of a single-label counter
+fn counter_values(counter: &CounterVec) -> Vec<(String, f64)> {
+    counter
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| {
+            let label = metric.get_label().first().map(|l| l.get_value().to_string()).unwrap_or_default();
+            (label, metric.get_counter().get_value())
+        })
+        .collect()
+}
+
+fn counter_total(counter: &CounterVec) -> f64 {
+    counter_values(counter).iter().map(|(_, value)| value).sum()
+}
+
 // API Data Structures
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ApiMetrics {
     pub relay_status: RelayStatus,
     pub events: EventMetrics,
     pub performance: PerformanceMetrics,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RelayStatus {
+    /// The relay instance reporting, when it was given an instance ID
+    #[serde(default)]
+    pub instance: Option<String>,
     pub active_connections: u64,
     pub total_connections: u64,
     pub uptime_seconds: u64,
     pub status: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct EventMetrics {
     pub events_received: u64,
     pub events_stored: u64,
     pub events_rejected: u64,
     pub avg_processing_time_ms: f64,
+    /// Event counts keyed by event kind, with kinds outside `LABELLED_KINDS` under `other`
+    pub by_kind: BTreeMap<String, KindEventMetrics>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct KindEventMetrics {
+    pub received: u64,
+    pub stored: u64,
+    pub rejected: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PerformanceMetrics {
     pub queries_received: u64,
     pub active_subscriptions: u64,
@@ -309,34 +850,97 @@ pub struct PerformanceMetrics {
     pub avg_query_time_ms: f64,
 }
 
+/// Stored event totals; `total_events` is refreshed periodically rather than
+/// counted on each request
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StorageMetrics {
+    pub total_events: u64,
+    pub events_last_24h: u64,
+}
+
 // API Handlers
-pub async fn get_relay_status(State(state): State<crate::app_state::AppState>) -> Result<Json<RelayStatus>, StatusCode> {
+#[utoipa::path(
+    get,
+    path = "/api/metrics/relay-status",
+    tag = "metrics",
+    responses((status = 200, description = "Connection counts and uptime", body = RelayStatus))
+)]
+pub async fn get_relay_status<D: RelayDatabase>(State(state): State<AppState<D>>) -> Result<Json<RelayStatus>, StatusCode> {
     let metrics = state.metrics.get_api_metrics();
     Ok(Json(metrics.relay_status))
 }
 
-pub async fn get_event_metrics(State(state): State<crate::app_state::AppState>) -> Result<Json<EventMetrics>, StatusCode> {
+#[utoipa::path(
+    get,
+    path = "/api/metrics/events",
+    tag = "metrics",
+    responses((status = 200, description = "Events received, stored and rejected, in total and by kind", body = EventMetrics))
+)]
+pub async fn get_event_metrics<D: RelayDatabase>(State(state): State<AppState<D>>) -> Result<Json<EventMetrics>, StatusCode> {
     let metrics = state.metrics.get_api_metrics();
     Ok(Json(metrics.events))
 }
 
-pub async fn get_performance_metrics(State(state): State<crate::app_state::AppState>) -> Result<Json<PerformanceMetrics>, StatusCode> {
+#[utoipa::path(
+    get,
+    path = "/api/metrics/performance",
+    tag = "metrics",
+    responses((status = 200, description = "Query, subscription and database activity", body = PerformanceMetrics))
+)]
+pub async fn get_performance_metrics<D: RelayDatabase>(State(state): State<AppState<D>>) -> Result<Json<PerformanceMetrics>, StatusCode> {
     let metrics = state.metrics.get_api_metrics();
     Ok(Json(metrics.performance))
 }
 
-pub async fn get_all_metrics(State(state): State<crate::app_state::AppState>) -> Result<Json<ApiMetrics>, StatusCode> {
+#[utoipa::path(
+    get,
+    path = "/api/metrics/all",
+    tag = "metrics",
+    responses((status = 200, description = "Every in-process metric group", body = ApiMetrics))
+)]
+pub async fn get_all_metrics<D: RelayDatabase>(State(state): State<AppState<D>>) -> Result<Json<ApiMetrics>, StatusCode> {
     let metrics = state.metrics.get_api_metrics();
     Ok(Json(metrics))
 }
 
-// Router setup for API endpoints
-pub fn create_metrics_api_router() -> Router<crate::app_state::AppState> {
+#[utoipa::path(
+    get,
+    path = "/api/metrics/storage",
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Stored event totals", body = StorageMetrics),
+        (status = 500, description = "Database unavailable"),
+    )
+)]
+pub async fn get_storage_metrics(State(state): State<AppState>) -> Result<Json<StorageMetrics>, StatusCode> {
+    let day_ago = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+        - 86400;
+    let events_last_24h = state.database.count_events_since(day_ago).await.map_err(|e| {
+        error!("Failed to count recent events: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(StorageMetrics {
+        total_events: state.database.cached_event_count(),
+        events_last_24h,
+    }))
+}
+
+// Router setup for API endpoints; these only read the in-process metrics
+pub fn create_metrics_api_router<D: RelayDatabase>() -> Router<AppState<D>> {
     Router::new()
-        .route("/api/metrics/relay-status", get(get_relay_status))
-        .route("/api/metrics/events", get(get_event_metrics))
-        .route("/api/metrics/performance", get(get_performance_metrics))
-        .route("/api/metrics/all", get(get_all_metrics))
+        .route("/api/metrics/relay-status", get(get_relay_status::<D>))
+        .route("/api/metrics/events", get(get_event_metrics::<D>))
+        .route("/api/metrics/performance", get(get_performance_metrics::<D>))
+        .route("/api/metrics/all", get(get_all_metrics::<D>))
+}
+
+/// Stored event totals, counted in PostgreSQL
+pub fn create_storage_metrics_router() -> Router<AppState> {
+    Router::new().route("/api/metrics/storage", get(get_storage_metrics))
 }
 
 #[cfg(test)]
@@ -350,15 +954,43 @@ mod tests {
         // Verify all metrics are initialized
         assert_eq!(metrics.active_connections.get(), 0); // IntGauge returns i64
         assert_eq!(metrics.total_connections.get(), 0.0); // Counter returns f64
-        assert_eq!(metrics.events_received.get(), 0.0);
-        assert_eq!(metrics.events_stored.get(), 0.0);
-        assert_eq!(metrics.events_rejected.get(), 0.0);
+        assert_eq!(counter_total(&metrics.events_received), 0.0);
+        assert_eq!(counter_total(&metrics.events_stored), 0.0);
+        assert_eq!(counter_total(&metrics.events_rejected), 0.0);
+        assert_eq!(metrics.events_ephemeral_broadcast.get(), 0.0);
+        assert_eq!(metrics.events_expired_deleted.get(), 0.0);
+        assert_eq!(metrics.events_pruned.get(), 0.0);
         assert_eq!(metrics.queries_received.get(), 0.0);
         assert_eq!(metrics.subscription_count.get(), 0); // IntGauge returns i64
         assert_eq!(metrics.rate_limited_connections.get(), 0.0);
+        assert_eq!(metrics.connection_limit_reached.get(), 0.0);
         assert_eq!(metrics.rate_limited_events.get(), 0.0);
+        assert_eq!(metrics.rate_limited_pubkeys.get(), 0.0);
+        assert_eq!(metrics.pubkey_subscription_limit_rejections.get(), 0.0);
+        assert_eq!(metrics.content_filtered.get(), 0.0);
+        assert_eq!(metrics.nip44_validation_failures.get(), 0.0);
+        assert_eq!(metrics.zap_events.get(), 0.0);
+        assert_eq!(metrics.spam_rejected.get(), 0.0);
+        assert_eq!(metrics.events_rejected_url_limit.get(), 0.0);
+        assert_eq!(metrics.events_rejected_blocked_domain.get(), 0.0);
+        assert_eq!(metrics.invalid_filter_rejections.get(), 0.0);
         assert_eq!(metrics.database_operations.get(), 0.0);
         assert_eq!(metrics.database_errors.get(), 0.0);
+        assert_eq!(metrics.db_circuit_state.get(), 0);
+        assert_eq!(metrics.db_pool_size.get(), 0);
+        assert_eq!(metrics.db_pool_acquire_queue_depth.get(), 0);
+        assert_eq!(metrics.cache_memory_used_bytes.get(), 0);
+        assert_eq!(metrics.cache_memory_max_bytes.get(), 0);
+        assert_eq!(metrics.sig_cache_hits.get(), 0.0);
+        assert_eq!(metrics.sig_cache_misses.get(), 0.0);
+        assert_eq!(metrics.id_cache_hits.get(), 0.0);
+        assert_eq!(metrics.id_cache_misses.get(), 0.0);
+        assert_eq!(metrics.connections_cleaned.get(), 0.0);
+        assert_eq!(metrics.slow_subscriber_timeouts.get(), 0.0);
+        assert_eq!(metrics.bytes_sent.get(), 0.0);
+        assert_eq!(metrics.bytes_received.get(), 0.0);
+        assert_eq!(metrics.message_processing_time.get_sample_count(), 0);
+        assert_eq!(metrics.event_validation_time.get_sample_count(), 0);
     }
 
     #[test]
@@ -383,22 +1015,186 @@ mod tests {
         assert_eq!(metrics.active_connections.get(), 0);
     }
 
+    #[test]
+    fn test_connection_queue_depth() {
+        let metrics = Metrics::new().expect("Failed to create metrics");
+
+        metrics.set_connection_queue_depth("conn-1", 12);
+        metrics.set_connection_queue_depth("conn-2", 3);
+        assert_eq!(metrics.connection_queue_depth.with_label_values(&["conn-1"]).get(), 12);
+
+        metrics.remove_connection_queue_depth("conn-1");
+        let rendered = metrics.render().unwrap();
+        assert!(!rendered.contains("connection_id=\"conn-1\""));
+        assert!(rendered.contains("relay_connection_queue_depth{connection_id=\"conn-2\"} 3"));
+    }
+
     #[test]
     fn test_event_metrics() {
         let metrics = Metrics::new().expect("Failed to create metrics");
         
         // Test event received
-        metrics.record_event_received();
-        metrics.record_event_received();
-        assert_eq!(metrics.events_received.get(), 2.0);
+        metrics.record_event_received(1);
+        metrics.record_event_received(1);
+        assert_eq!(metrics.events_received.with_label_values(&["1"]).get(), 2.0);
 
         // Test event stored
-        metrics.record_event_stored(0.1);
-        assert_eq!(metrics.events_stored.get(), 1.0);
+        metrics.record_event_stored(1);
+        assert_eq!(metrics.events_stored.with_label_values(&["1"]).get(), 1.0);
 
         // Test event rejected
-        metrics.record_event_rejected(0.05);
-        assert_eq!(metrics.events_rejected.get(), 1.0);
+        metrics.record_event_rejected(1);
+        assert_eq!(metrics.events_rejected.with_label_values(&["1"]).get(), 1.0);
+
+        // Test ephemeral broadcast
+        metrics.record_ephemeral_broadcast();
+        assert_eq!(metrics.events_ephemeral_broadcast.get(), 1.0);
+
+        // Test expired event cleanup
+        metrics.record_expired_events_deleted(3);
+        assert_eq!(metrics.events_expired_deleted.get(), 3.0);
+        
+        metrics.record_events_pruned(1500);
+        assert_eq!(metrics.events_pruned.get(), 1500.0);
+    }
+
+    #[test]
+    fn test_event_metrics_per_kind() {
+        let metrics = Metrics::new().expect("Failed to create metrics");
+        let kinds: Vec<u16> = vec![0, 1, 3, 4, 5, 6, 7, 1984, 9735, 30023];
+
+        // Kind at index i receives i + 1 events, one of which is rejected
+        for (i, kind) in kinds.iter().enumerate() {
+            for _ in 0..=i {
+                metrics.record_event_received(*kind);
+                metrics.record_event_stored(*kind);
+                metrics.record_event_processed(*kind, 0.01);
+            }
+            metrics.record_event_rejected(*kind);
+            metrics.record_event_processed(*kind, 0.01);
+        }
+
+        for (i, kind) in kinds.iter().enumerate() {
+            let label = kind.to_string();
+            assert_eq!(metrics.events_received.with_label_values(&[&label]).get(), (i + 1) as f64);
+            assert_eq!(metrics.events_stored.with_label_values(&[&label]).get(), (i + 1) as f64);
+            assert_eq!(metrics.events_rejected.with_label_values(&[&label]).get(), 1.0);
+            assert_eq!(
+                metrics.event_processing_time.with_label_values(&[&label]).get_sample_count(),
+                (i + 2) as u64
+            );
+        }
+
+        let api_metrics = metrics.get_api_metrics();
+        assert_eq!(api_metrics.events.events_received, 55);
+        assert_eq!(api_metrics.events.events_rejected, 10);
+        assert_eq!(api_metrics.events.by_kind.len(), 10);
+        assert_eq!(
+            api_metrics.events.by_kind["9735"],
+            KindEventMetrics { received: 9, stored: 9, rejected: 1 }
+        );
+
+        let rendered = metrics.render().expect("Failed to render metrics");
+        assert!(rendered.contains("relay_events_received_total{kind=\"30023\"} 10"));
+    }
+
+    #[test]
+    fn test_unlisted_kinds_share_a_label() {
+        let metrics = Metrics::new().expect("Failed to create metrics");
+        for kind in [1, 12345, 40000, 65535] {
+            metrics.record_event_received(kind);
+            metrics.record_event_size(kind, 100);
+        }
+
+        assert_eq!(kind_label(9735), "9735");
+        assert_eq!(kind_label(12345), "other");
+        assert_eq!(metrics.events_received.with_label_values(&["1"]).get(), 1.0);
+        assert_eq!(metrics.events_received.with_label_values(&["other"]).get(), 3.0);
+        assert_eq!(metrics.event_size_bytes.with_label_values(&["other"]).get_sample_count(), 3);
+
+        let rendered = metrics.render().expect("Failed to render metrics");
+        assert!(!rendered.contains("kind=\"12345\""));
+        assert_eq!(metrics.get_api_metrics().events.by_kind.len(), 2);
+    }
+
+    // Cumulative count of each bucket, by upper bound
+    fn bucket_counts(histogram: &Histogram) -> Vec<(f64, u64)> {
+        use prometheus::core::Metric;
+        histogram
+            .metric()
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .map(|bucket| (bucket.get_upper_bound(), bucket.get_cumulative_count()))
+            .collect()
+    }
+
+    #[test]
+    fn test_event_size_buckets() {
+        let metrics = Metrics::new().expect("Failed to create metrics");
+        for size in [100, 100, 600, 5_000, 100_000] {
+            metrics.record_event_size(1, size);
+        }
+        metrics.record_event_size(30023, 20_000);
+
+        let text_notes = metrics.event_size_bytes.with_label_values(&["1"]);
+        assert_eq!(
+            bucket_counts(&text_notes),
+            vec![(128.0, 2), (512.0, 2), (1024.0, 3), (4096.0, 3), (16384.0, 4), (65536.0, 4)]
+        );
+        // Larger than every bucket: only counted in +Inf
+        assert_eq!(text_notes.get_sample_count(), 5);
+        assert_eq!(text_notes.get_sample_sum(), 105_800.0);
+
+        let articles = metrics.event_size_bytes.with_label_values(&["30023"]);
+        assert_eq!(bucket_counts(&articles)[4], (16384.0, 0));
+        assert_eq!(bucket_counts(&articles)[5], (65536.0, 1));
+    }
+
+    #[test]
+    fn test_query_result_count_by_filter_type() {
+        use nostr::{EventId, Keys, Kind};
+
+        let metrics = Metrics::new().expect("Failed to create metrics");
+        let by_author = Filter::new().author(Keys::generate().public_key()).kind(Kind::TextNote);
+        metrics.record_query_results(&by_author, 0);
+        metrics.record_query_results(&by_author, 7);
+        metrics.record_query_results(&by_author, 250);
+        metrics.record_query_results(&Filter::new().id(EventId::all_zeros()), 1);
+
+        let authors = metrics.query_result_count.with_label_values(&["authors"]);
+        assert_eq!(
+            bucket_counts(&authors),
+            vec![(0.0, 1), (1.0, 1), (10.0, 2), (100.0, 2), (1000.0, 3), (5000.0, 3)]
+        );
+        assert_eq!(metrics.query_result_count.with_label_values(&["ids"]).get_sample_count(), 1);
+
+        assert_eq!(filter_type(&Filter::new()), "all");
+        assert_eq!(filter_type(&Filter::new().kind(Kind::TextNote)), "kinds");
+        assert_eq!(filter_type(&Filter::new().hashtag("nostr").kind(Kind::TextNote)), "tags");
+        assert_eq!(filter_type(&Filter::new().search("relay").author(Keys::generate().public_key())), "search");
+    }
+
+    #[test]
+    fn test_cache_metrics() {
+        let metrics = Metrics::new().expect("Failed to create metrics");
+
+        metrics.record_sig_cache_miss();
+        metrics.record_sig_cache_hit();
+        metrics.record_sig_cache_hit();
+        assert_eq!(metrics.sig_cache_hits.get(), 2.0);
+        assert_eq!(metrics.sig_cache_misses.get(), 1.0);
+
+        metrics.record_id_cache_miss();
+        metrics.record_id_cache_hit();
+        assert_eq!(metrics.id_cache_hits.get(), 1.0);
+        assert_eq!(metrics.id_cache_misses.get(), 1.0);
+
+        metrics.record_event_cache_miss("get_event_by_id");
+        metrics.record_event_cache_hit("get_event_by_id");
+        metrics.record_event_cache_hit("get_event_by_id");
+        assert_eq!(metrics.event_cache_hits.with_label_values(&["get_event_by_id"]).get(), 2.0);
+        assert_eq!(metrics.event_cache_misses.with_label_values(&["get_event_by_id"]).get(), 1.0);
     }
 
     #[test]
@@ -441,6 +1237,9 @@ mod tests {
 
         metrics.record_rate_limit_event();
         assert_eq!(metrics.rate_limited_events.get(), 1.0);
+        
+        metrics.record_rate_limit_pubkey();
+        assert_eq!(metrics.rate_limited_pubkeys.get(), 1.0);
     }
 
     #[test]
@@ -462,7 +1261,7 @@ mod tests {
         
         // Add some data
         metrics.record_connection_start();
-        metrics.record_event_received();
+        metrics.record_event_received(1);
         metrics.record_query_received();
         
         let rendered = metrics.render().expect("Failed to render metrics");
@@ -478,6 +1277,29 @@ mod tests {
         assert!(rendered.contains("# TYPE"));
     }
 
+    #[test]
+    fn test_instance_label_distinguishes_instances() {
+        let first = Metrics::for_instance("relay-1").unwrap();
+        let second = Metrics::for_instance("relay-2").unwrap();
+        first.record_connection_start();
+        second.record_event_received(1);
+
+        let first_rendered = first.render().unwrap();
+        let second_rendered = second.render().unwrap();
+        assert!(first_rendered.contains("relay_total_connections{relay_instance=\"relay-1\"} 1"));
+        assert!(second_rendered
+            .lines()
+            .any(|line| line.starts_with("relay_events_received_total{") && line.contains("relay_instance=\"relay-2\"")));
+        assert!(!first_rendered.contains("relay-2"));
+        assert!(!second_rendered.contains("relay-1"));
+        assert_eq!(first.get_api_metrics().relay_status.instance.as_deref(), Some("relay-1"));
+
+        // Without an instance ID, metrics carry no instance label
+        let unlabelled = Metrics::new().unwrap();
+        assert!(!unlabelled.render().unwrap().contains("instance="));
+        assert_eq!(unlabelled.get_api_metrics().relay_status.instance, None);
+    }
+
     #[test]
     fn test_metrics_histogram_observations() {
         let metrics = Metrics::new().expect("Failed to create metrics");
@@ -487,11 +1309,12 @@ mod tests {
         metrics.record_connection_end(1.0);
         metrics.record_connection_end(60.0);
         
-        metrics.record_event_stored(0.001);
-        metrics.record_event_stored(0.1);
-        metrics.record_event_stored(1.0);
+        metrics.record_event_processed(1, 0.001);
+        metrics.record_event_processed(1, 0.1);
+        metrics.record_event_processed(7, 1.0);
         
-        metrics.record_event_rejected(0.002);
+        metrics.record_event_validation(0.002);
+        metrics.record_message_processed(0.003);
         metrics.record_query_processed(0.5);
         metrics.record_database_operation(0.01);
         
@@ -514,7 +1337,7 @@ mod tests {
             let handle = thread::spawn(move || {
                 for _ in 0..100 {
                     metrics_clone.record_connection_start();
-                    metrics_clone.record_event_received();
+                    metrics_clone.record_event_received(1);
                     metrics_clone.record_query_received();
                     if i % 2 == 0 {
                         metrics_clone.record_connection_end(0.1);
@@ -531,7 +1354,7 @@ mod tests {
         
         // Verify metrics were updated (exact values depend on scheduling)
         assert!(metrics.total_connections.get() > 0.0);
-        assert!(metrics.events_received.get() > 0.0);
+        assert!(counter_total(&metrics.events_received) > 0.0);
         assert!(metrics.queries_received.get() > 0.0);
     }
 }
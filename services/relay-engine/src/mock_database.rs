@@ -1,39 +1,97 @@
 use anyhow::Result;
-use nostr::Event;
-use sqlx::{Pool, Sqlite, Row};
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use nostr::{Event, EventId, Filter};
 use std::sync::Arc;
+use tokio::sync::RwLock;
 
-#[derive(Clone)]
+use crate::database::RelayDatabase;
+
+/// In-memory event store for tests that shouldn't need PostgreSQL
+#[derive(Clone, Default)]
 pub struct MockDatabase {
-    // In-memory storage for development
     events: Arc<RwLock<Vec<Event>>>,
-    subscriptions: Arc<RwLock<HashMap<String, Vec<String>>>>,
 }
 
 impl MockDatabase {
     pub fn new() -> Self {
-        Self {
-            events: Arc::new(RwLock::new(Vec::new())),
-            subscriptions: Arc::new(RwLock::new(HashMap::new())),
-        }
+        Self::default()
     }
 
-    pub async fn store_event(&self, event: Event) -> Result<()> {
+    pub async fn get_event_count(&self) -> Result<i64> {
+        let events = self.events.read().await;
+        Ok(events.len() as i64)
+    }
+}
+
+impl RelayDatabase for MockDatabase {
+    async fn save_event(&self, event: &Event) -> Result<()> {
         let mut events = self.events.write().await;
-        events.push(event);
+        if !events.iter().any(|stored| stored.id == event.id) {
+            events.push(event.clone());
+        }
         Ok(())
     }
 
-    pub async fn get_events(&self, _filters: &[nostr::Filter]) -> Result<Vec<Event>> {
-        let events = self.events.read().await;
-        // Return last 10 events for demo
-        Ok(events.iter().rev().take(10).cloned().collect())
+    async fn event_exists(&self, event_id: &EventId) -> Result<bool> {
+        Ok(self.events.read().await.iter().any(|event| event.id == *event_id))
     }
 
-    pub async fn get_event_count(&self) -> Result<i64> {
-        let events = self.events.read().await;
-        Ok(events.len() as i64)
+    async fn query_events(&self, filter: &Filter) -> Result<Vec<Event>> {
+        let mut matching: Vec<Event> = self
+            .events
+            .read()
+            .await
+            .iter()
+            .filter(|event| filter.match_event(event))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|event| std::cmp::Reverse(event.created_at));
+        if let Some(limit) = filter.limit {
+            matching.truncate(limit);
+        }
+        Ok(matching)
+    }
+
+    async fn delete_events_by_author(&self, pubkey: &str, event_ids: Vec<String>) -> Result<u64> {
+        let mut events = self.events.write().await;
+        let before = events.len();
+        events.retain(|event| event.pubkey.to_hex() != pubkey || !event_ids.contains(&event.id.to_hex()));
+        Ok((before - events.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Kind, Timestamp};
+
+    #[tokio::test]
+    async fn test_mock_database_round_trip() {
+        let database = MockDatabase::new();
+        let keys = Keys::generate();
+        let events: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::new(Kind::TextNote, format!("note {}", i), [])
+                    .custom_created_at(Timestamp::from(1_000 + i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        for event in &events {
+            database.save_event(event).await.unwrap();
+        }
+        database.save_event(&events[0]).await.unwrap();
+        assert_eq!(database.get_event_count().await.unwrap(), 3);
+        assert!(database.event_exists(&events[1].id).await.unwrap());
+
+        // Newest first, up to the limit
+        let found = database.query_events(&Filter::new().author(keys.public_key()).limit(2)).await.unwrap();
+        assert_eq!(found, vec![events[2].clone(), events[1].clone()]);
+        assert!(database.query_events(&Filter::new().kind(Kind::Metadata)).await.unwrap().is_empty());
+
+        // Only the author's own events are deleted
+        let ids = vec![events[0].id.to_hex()];
+        assert_eq!(database.delete_events_by_author(&Keys::generate().public_key().to_hex(), ids.clone()).await.unwrap(), 0);
+        assert_eq!(database.delete_events_by_author(&keys.public_key().to_hex(), ids).await.unwrap(), 1);
+        assert!(!database.event_exists(&events[0].id).await.unwrap());
     }
 }
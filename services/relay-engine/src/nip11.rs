@@ -0,0 +1,194 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use nostr::{
+    hashes::{sha256::Hash as Sha256Hash, Hash},
+    key, Keys,
+};
+use nostr::secp256k1::Message;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::app_state::AppState;
+use crate::config::Config;
+
+/// Media type of the NIP-11 relay information document
+pub const NOSTR_JSON: &str = "application/nostr+json";
+
+/// Serve the relay information document, with the NIP-11 media type when the
+/// client asks for it
+pub fn relay_info_response(config: &Config, headers: &HeaderMap) -> Response {
+    let mut document = relay_info_document(config);
+    if let Some(keys) = signing_keys(config) {
+        match sign_relay_info(&document, keys) {
+            Ok(sig) => document["sig"] = Value::String(sig),
+            Err(e) => error!("Failed to sign relay information document: {}", e),
+        }
+    }
+    let mut response = Json(document).into_response();
+
+    let wants_nostr_json = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(NOSTR_JSON));
+    if wants_nostr_json {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(NOSTR_JSON));
+    }
+
+    response
+}
+
+/// Build the NIP-11 document advertised for this relay's configuration
+pub fn relay_info_document(config: &Config) -> Value {
+    json!({
+        "name": config.relay_name,
+        "description": config.relay_description,
+        "pubkey": config.relay_pubkey,
+        "contact": config.relay_contact,
+        "supported_nips": [1, 2, 9, 11, 12, 13, 15, 16, 20, 22, 26, 28, 33, 40, 42, 45, 50, 57],
+        "software": "NrelayOne",
+        "version": env!("CARGO_PKG_VERSION"),
+        "limitation": {
+            "max_message_length": config.max_message_length,
+            "max_subscriptions": config.max_subscriptions,
+            "max_filters": config.max_filters,
+            "max_limit": config.max_limit,
+            "max_subid_length": config.max_subid_length,
+            "min_prefix": config.min_prefix,
+            "max_event_tags": config.max_event_tags,
+            "max_content_length": config.max_content_length,
+            "min_pow_difficulty": config.min_pow_difficulty,
+            "auth_required": config.auth_required || !config.auth_required_kinds.is_empty(),
+            "payment_required": config.payment_required,
+            // Non-standard: kinds this relay accepts, when restricted by the operator
+            "accepted_kinds": config.kind_allowlist,
+            "blocked_kinds": config.kind_blocklist
+        },
+        "payments_url": config.payments_url,
+        "fees": config.fees.clone().unwrap_or_else(|| json!({}))
+    })
+}
+
+/// The keys signing the relay information document: the configured keys, when
+/// they belong to the advertised relay pubkey
+pub fn signing_keys(config: &Config) -> Option<&Keys> {
+    config
+        .relay_keys
+        .as_ref()
+        .filter(|keys| config.relay_pubkey.as_deref() == Some(keys.public_key().to_hex().as_str()))
+}
+
+/// Schnorr-sign a relay information document: the signature covers the
+/// SHA-256 hash of the document serialized as compact JSON, without a `sig`
+/// field. Returns the signature as hex, to be served as the document's `sig`.
+pub fn sign_relay_info(doc: &Value, keys: &Keys) -> Result<String, key::Error> {
+    let sig = keys.sign_schnorr(&relay_info_message(doc))?;
+    Ok(sig.to_string())
+}
+
+/// The message a relay information document's `sig` signs
+pub fn relay_info_message(doc: &Value) -> Message {
+    let mut unsigned = doc.clone();
+    if let Some(fields) = unsigned.as_object_mut() {
+        fields.remove("sig");
+    }
+    let hash = Sha256Hash::hash(unsigned.to_string().as_bytes());
+    Message::from_digest(hash.to_byte_array())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Nip05Query {
+    pub name: Option<String>,
+}
+
+/// NIP-05 names served by the relay: its operator pubkey as the root
+/// identifier `_`, when configured
+pub fn nip05_document(config: &Config, name: Option<&str>) -> Value {
+    let mut names = HashMap::new();
+    if let Some(pubkey) = &config.relay_pubkey {
+        if name.is_none_or(|name| name == "_") {
+            names.insert("_", pubkey.as_str());
+        }
+    }
+    json!({ "names": names })
+}
+
+// NIP-05 requires the document to be readable from web clients on any origin
+async fn nostr_json(State(state): State<AppState>, Query(query): Query<Nip05Query>) -> Response {
+    let document = nip05_document(&*state.mutable_config.read().await, query.name.as_deref());
+    ([(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")], Json(document)).into_response()
+}
+
+// Router setup for the NIP-05 identifier of the relay operator
+pub fn create_nip05_router() -> Router<AppState> {
+    Router::new().route("/.well-known/nostr.json", get(nostr_json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::SECP256K1;
+    use nostr::secp256k1::schnorr::Signature;
+    use std::str::FromStr;
+
+    fn signing_config() -> (Config, Keys) {
+        let keys = Keys::generate();
+        let mut config = Config::from_env();
+        config.relay_pubkey = Some(keys.public_key().to_hex());
+        config.relay_keys = Some(keys.clone());
+        (config, keys)
+    }
+
+    async fn served_document(config: &Config) -> Value {
+        let response = relay_info_response(config, &HeaderMap::new());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_relay_info_signature_verifies_with_relay_pubkey() {
+        let (config, keys) = signing_config();
+        let document = served_document(&config).await;
+
+        let sig = Signature::from_str(document["sig"].as_str().unwrap()).unwrap();
+        let pubkey = *keys.public_key();
+        assert!(SECP256K1.verify_schnorr(&sig, &relay_info_message(&document), &pubkey).is_ok());
+
+        // Any change to the document invalidates the signature
+        let mut tampered = document.clone();
+        tampered["name"] = json!("Another relay");
+        assert!(SECP256K1.verify_schnorr(&sig, &relay_info_message(&tampered), &pubkey).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_relay_info_unsigned_without_matching_keys() {
+        let (mut config, _) = signing_config();
+        config.relay_pubkey = Some(Keys::generate().public_key().to_hex());
+        assert!(served_document(&config).await.get("sig").is_none());
+
+        config.relay_keys = None;
+        assert!(served_document(&config).await.get("sig").is_none());
+    }
+
+    #[test]
+    fn test_nip05_document() {
+        let (config, keys) = signing_config();
+        let pubkey = keys.public_key().to_hex();
+
+        assert_eq!(nip05_document(&config, None), json!({ "names": { "_": pubkey } }));
+        assert_eq!(nip05_document(&config, Some("_")), json!({ "names": { "_": pubkey } }));
+        assert_eq!(nip05_document(&config, Some("alice")), json!({ "names": {} }));
+
+        let mut unconfigured = config.clone();
+        unconfigured.relay_pubkey = None;
+        assert_eq!(nip05_document(&unconfigured, None), json!({ "names": {} }));
+    }
+}
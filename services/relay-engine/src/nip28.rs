@@ -0,0 +1,61 @@
+use nostr::{Event, Kind};
+use tracing::{debug, error};
+
+use crate::{app_state::AppState, database::Channel, validation};
+
+/// The channel row a stored kind-40 creation or kind-41 metadata event
+/// describes. Both have been through `validation::validate_event_kind`, so
+/// their content is a JSON object and a metadata event names its channel.
+pub fn channel_from_event(event: &Event) -> Option<Channel> {
+    let channel_id = match event.kind {
+        Kind::ChannelCreation => event.id.to_hex(),
+        Kind::ChannelMetadata => validation::tag_value(event, "e")?.to_string(),
+        _ => return None,
+    };
+
+    Some(Channel {
+        channel_id,
+        creator_pubkey: event.pubkey.to_hex(),
+        metadata: serde_json::from_str(&event.content).ok()?,
+        updated_at: event.created_at.as_u64() as i64,
+    })
+}
+
+/// Metadata updates for a channel created on this relay must come from its
+/// creator. Channels created elsewhere are unknown here, so their updates are
+/// accepted, but they never create a channel row.
+/// Returns the NIP-20 `OK` message to send back when the event is rejected.
+pub async fn check_metadata_update(event: &Event, state: &AppState) -> Result<(), String> {
+    let Some(update) = channel_from_event(event) else {
+        return Ok(());
+    };
+
+    match state.database.get_channel_metadata(&update.channel_id).await {
+        Ok(Some(channel)) if channel.creator_pubkey == update.creator_pubkey => Ok(()),
+        Ok(Some(_)) => Err("blocked: only the channel creator can update its metadata".to_string()),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            error!("Failed to look up channel {}: {}", update.channel_id, e);
+            Err("error: failed to look up channel".to_string())
+        }
+    }
+}
+
+/// Bring the channels table up to date after a channel event is stored
+pub async fn index_channel(event: &Event, state: &AppState) {
+    let Some(channel) = channel_from_event(event) else {
+        return;
+    };
+
+    // Only a creation event starts a row, so metadata for a channel created
+    // elsewhere can't claim it for its author
+    let indexed = if event.kind == Kind::ChannelCreation {
+        state.database.create_or_update_channel(&channel).await
+    } else {
+        state.database.update_channel(&channel).await
+    };
+    match indexed {
+        Ok(updated) => debug!("Indexed channel {} from event {} (updated: {})", channel.channel_id, event.id, updated),
+        Err(e) => error!("Failed to index channel {} from event {}: {}", channel.channel_id, event.id, e),
+    }
+}
@@ -0,0 +1,302 @@
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use futures_util::{Sink, SinkExt};
+use nostr::{Alphabet, Event, Filter, Kind, PublicKey, SingleLetterTag, TagKind, Timestamp};
+
+use crate::config::Config;
+
+/// How far an AUTH event's `created_at` may drift from the relay's clock, in seconds
+pub const AUTH_WINDOW_SECS: u64 = 600;
+
+/// Close reason for a client that didn't answer the challenge in time
+pub const UNAUTHENTICATED: &str = "auth-required: unauthenticated";
+
+/// NIP-42 state of one connection: the challenge it was issued and, once it
+/// has answered it, the pubkey it authenticated as
+pub struct ConnectionAuth {
+    challenge: String,
+    pubkey: Option<PublicKey>,
+}
+
+impl ConnectionAuth {
+    /// A connection with a fresh challenge: 32 random bytes, hex-encoded
+    pub fn new() -> Self {
+        let challenge = rand::random::<[u8; 32]>().iter().map(|byte| format!("{:02x}", byte)).collect();
+        Self { challenge, pubkey: None }
+    }
+
+    /// The challenge the client must sign; it stays the same for the whole connection
+    pub fn challenge(&self) -> &str {
+        &self.challenge
+    }
+
+    pub fn pubkey(&self) -> Option<&PublicKey> {
+        self.pubkey.as_ref()
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.pubkey.is_some()
+    }
+
+    /// Check a client's `AUTH` event against this connection's challenge and, if
+    /// it is valid, authenticate the connection as its author. Returns the
+    /// NIP-42 `OK` message to send back when the event is rejected.
+    ///
+    /// The `relay` tag isn't checked, since the relay doesn't know the URL
+    /// clients reach it through behind a proxy.
+    pub fn authenticate(&mut self, event: &Event) -> Result<(), String> {
+        if event.kind != Kind::Authentication {
+            return Err("invalid: not an authentication event".to_string());
+        }
+        if event.verify().is_err() {
+            return Err("invalid: bad signature".to_string());
+        }
+
+        let now = Timestamp::now().as_u64();
+        if event.created_at.as_u64().abs_diff(now) > AUTH_WINDOW_SECS {
+            return Err("invalid: created_at is too far from the current time".to_string());
+        }
+
+        let challenge = event
+            .tags
+            .iter()
+            .find(|tag| tag.kind() == TagKind::Challenge)
+            .and_then(|tag| tag.content());
+        if challenge != Some(self.challenge.as_str()) {
+            return Err("invalid: challenge does not match".to_string());
+        }
+
+        self.pubkey = Some(event.pubkey);
+        Ok(())
+    }
+}
+
+impl Default for ConnectionAuth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether the operator requires authentication before accepting events of this kind
+pub fn requires_auth(event: &Event, config: &Config) -> bool {
+    config.auth_required_kinds.contains(&event.kind.as_u64())
+}
+
+/// Refuse events of an auth-required kind from a connection that hasn't
+/// authenticated. Returns the NIP-42 `OK` message to send back, after which the
+/// client should be sent the connection's challenge again.
+pub fn check_event_auth(event: &Event, auth: &ConnectionAuth, config: &Config) -> Result<(), String> {
+    if requires_auth(event, config) && !auth.is_authenticated() {
+        return Err("auth-required: authentication is required to publish this kind".to_string());
+    }
+    Ok(())
+}
+
+/// Refuse subscriptions asking for kinds the operator reserves for
+/// authenticated readers, such as DMs, from a connection that hasn't
+/// authenticated. Returns the `CLOSED` message to send back, after which the
+/// client should be sent the connection's challenge again.
+pub fn check_filters_auth(filters: &[Filter], auth: &ConnectionAuth, config: &Config) -> Result<(), String> {
+    let protected = filters.iter().any(|filter| {
+        filter.kinds.as_ref().is_some_and(|kinds| {
+            kinds.iter().any(|kind| config.auth_required_read_kinds.contains(&kind.as_u64()))
+        })
+    });
+    if protected && !auth.is_authenticated() {
+        return Err("auth-required: authentication is required to read this kind".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `reader`, the pubkey a connection authenticated as if any, may be
+/// sent `event`. Events of `auth_required_read_kinds` only go to their author
+/// and the pubkeys they `p`-tag; filters without `kinds` can still match them,
+/// so this is checked on every event served, stored or live.
+pub fn can_read(event: &Event, reader: Option<&PublicKey>, config: &Config) -> bool {
+    if !config.auth_required_read_kinds.contains(&event.kind.as_u64()) {
+        return true;
+    }
+    reader.is_some_and(|reader| event.pubkey == *reader || event.public_keys().any(|tagged| tagged == reader))
+}
+
+/// Refuse COUNTs that could include events `can_read` would withhold. A count
+/// can't be filtered event by event, so every filter that may match a
+/// protected kind must be limited to the reader's own events or those tagging
+/// them. Returns the `CLOSED` message to send back.
+pub fn check_count_auth(filters: &[Filter], auth: &ConnectionAuth, config: &Config) -> Result<(), String> {
+    let readable = filters
+        .iter()
+        .filter(|filter| may_match_protected(filter, config))
+        .all(|filter| auth.pubkey().is_some_and(|reader| scoped_to_reader(filter, reader)));
+    if !readable {
+        return Err("auth-required: authentication is required to count this kind".to_string());
+    }
+    Ok(())
+}
+
+// Whether a filter could match an event of a kind reserved for authenticated readers
+fn may_match_protected(filter: &Filter, config: &Config) -> bool {
+    match &filter.kinds {
+        Some(kinds) if !kinds.is_empty() => {
+            kinds.iter().any(|kind| config.auth_required_read_kinds.contains(&kind.as_u64()))
+        }
+        _ => !config.auth_required_read_kinds.is_empty(),
+    }
+}
+
+// Whether a filter only matches events written by or addressed to `reader`
+fn scoped_to_reader(filter: &Filter, reader: &PublicKey) -> bool {
+    let authors = filter
+        .authors
+        .as_ref()
+        .is_some_and(|authors| authors.len() == 1 && authors.contains(reader));
+    let recipients = filter
+        .generic_tags
+        .get(&SingleLetterTag::lowercase(Alphabet::P))
+        .is_some_and(|values| values.len() == 1 && values.contains(&reader.to_hex()));
+    authors || recipients
+}
+
+/// Close the WebSocket of a client that didn't authenticate within
+/// `auth_timeout_secs` of being challenged
+pub async fn close_unauthenticated<S>(sink: &mut S) -> anyhow::Result<()>
+where
+    S: Sink<Message, Error = axum::Error> + Unpin,
+{
+    let frame = CloseFrame {
+        code: close_code::POLICY,
+        reason: UNAUTHENTICATED.into(),
+    };
+    sink.send(Message::Close(Some(frame))).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Tag, TagStandard, Url};
+
+    fn auth_event(keys: &Keys, challenge: &str) -> Event {
+        EventBuilder::auth(challenge, Url::parse("wss://relay.example.com").unwrap())
+            .to_event(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_challenges_are_random_hex() {
+        let challenge = ConnectionAuth::new().challenge().to_string();
+        assert_eq!(challenge.len(), 64);
+        assert!(challenge.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert_ne!(ConnectionAuth::new().challenge(), challenge);
+    }
+
+    #[test]
+    fn test_authenticate_with_matching_challenge() {
+        let keys = Keys::generate();
+        let mut auth = ConnectionAuth::new();
+        assert!(!auth.is_authenticated());
+
+        let event = auth_event(&keys, auth.challenge());
+        auth.authenticate(&event).unwrap();
+
+        assert_eq!(auth.pubkey(), Some(&keys.public_key()));
+    }
+
+    #[test]
+    fn test_authenticate_rejects_wrong_challenge_and_kind() {
+        let keys = Keys::generate();
+        let mut auth = ConnectionAuth::new();
+
+        let wrong_challenge = auth_event(&keys, "not-the-challenge");
+        assert_eq!(auth.authenticate(&wrong_challenge).unwrap_err(), "invalid: challenge does not match");
+
+        let note = EventBuilder::new(
+            Kind::TextNote,
+            "",
+            [Tag::from_standardized(TagStandard::Challenge(auth.challenge().to_string()))],
+        )
+        .to_event(&keys)
+        .unwrap();
+        assert_eq!(auth.authenticate(&note).unwrap_err(), "invalid: not an authentication event");
+
+        assert!(!auth.is_authenticated());
+    }
+
+    #[test]
+    fn test_authenticate_rejects_stale_event() {
+        let keys = Keys::generate();
+        let mut auth = ConnectionAuth::new();
+
+        let stale = EventBuilder::auth(auth.challenge(), Url::parse("wss://relay.example.com").unwrap())
+            .custom_created_at(Timestamp::now() - AUTH_WINDOW_SECS * 2)
+            .to_event(&keys)
+            .unwrap();
+
+        assert!(auth.authenticate(&stale).unwrap_err().starts_with("invalid: created_at"));
+    }
+
+    #[test]
+    fn test_reading_protected_kinds_requires_auth() {
+        let mut config = Config::from_env();
+        config.auth_required_read_kinds = vec![4, 44];
+        let keys = Keys::generate();
+        let mut auth = ConnectionAuth::new();
+
+        let dms = vec![Filter::new().kind(Kind::TextNote), Filter::new().kind(Kind::EncryptedDirectMessage)];
+        let notes = vec![Filter::new().kind(Kind::TextNote), Filter::new().author(keys.public_key())];
+        assert!(check_filters_auth(&dms, &auth, &config).unwrap_err().starts_with("auth-required: "));
+        assert!(check_filters_auth(&[Filter::new().kind(Kind::from(44))], &auth, &config).is_err());
+        assert!(check_filters_auth(&notes, &auth, &config).is_ok());
+
+        auth.authenticate(&auth_event(&keys, auth.challenge())).unwrap();
+        assert!(check_filters_auth(&dms, &auth, &config).is_ok());
+
+        config.auth_required_read_kinds.clear();
+        assert!(check_filters_auth(&dms, &ConnectionAuth::new(), &config).is_ok());
+    }
+
+    #[test]
+    fn test_protected_events_go_to_author_and_recipients() {
+        let mut config = Config::from_env();
+        config.auth_required_read_kinds = vec![4, 44];
+        let (author, recipient, stranger) = (Keys::generate(), Keys::generate(), Keys::generate());
+
+        let dm = EventBuilder::new(
+            Kind::EncryptedDirectMessage,
+            "ciphertext",
+            [Tag::public_key(recipient.public_key())],
+        )
+        .to_event(&author)
+        .unwrap();
+        assert!(!can_read(&dm, None, &config));
+        assert!(!can_read(&dm, Some(&stranger.public_key()), &config));
+        assert!(can_read(&dm, Some(&author.public_key()), &config));
+        assert!(can_read(&dm, Some(&recipient.public_key()), &config));
+
+        let note = EventBuilder::text_note("hello", []).to_event(&author).unwrap();
+        assert!(can_read(&note, None, &config));
+    }
+
+    #[test]
+    fn test_counting_protected_kinds_is_scoped_to_reader() {
+        let mut config = Config::from_env();
+        config.auth_required_read_kinds = vec![4, 44];
+        let keys = Keys::generate();
+        let mut auth = ConnectionAuth::new();
+
+        let notes = [Filter::new().kind(Kind::TextNote)];
+        let own_dms = [Filter::new().kind(Kind::EncryptedDirectMessage).pubkey(keys.public_key())];
+        let any_kind = [Filter::new().author(Keys::generate().public_key())];
+        assert!(check_count_auth(&notes, &auth, &config).is_ok());
+        assert!(check_count_auth(&own_dms, &auth, &config).unwrap_err().starts_with("auth-required: "));
+        assert!(check_count_auth(&any_kind, &auth, &config).is_err());
+
+        auth.authenticate(&auth_event(&keys, auth.challenge())).unwrap();
+        assert!(check_count_auth(&own_dms, &auth, &config).is_ok());
+        assert!(check_count_auth(&[Filter::new().author(keys.public_key())], &auth, &config).is_ok());
+        assert!(check_count_auth(&any_kind, &auth, &config).is_err());
+        assert!(check_count_auth(&[Filter::new().kind(Kind::EncryptedDirectMessage)], &auth, &config).is_err());
+
+        config.auth_required_read_kinds.clear();
+        assert!(check_count_auth(&any_kind, &ConnectionAuth::new(), &config).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+use nostr::{Event, EventId, Kind, PublicKey, Url};
+
+use crate::validation::tag_value;
+
+/// Rejection reason for a zap request or receipt missing a required part
+pub const MALFORMED_ZAP: &str = "invalid: malformed zap";
+
+/// How far a zap receipt's `created_at` may be before its invoice's
+/// timestamp, allowing for the wallet's and the zapper's clocks disagreeing
+pub const ZAP_RECEIPT_MAX_SKEW_SECS: u64 = 60;
+
+/// How long after its invoice was created a zap receipt may be. A receipt is
+/// created when the invoice is paid, which can be well after it was issued.
+pub const ZAP_RECEIPT_MAX_PAYMENT_DELAY_SECS: u64 = 86_400;
+
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// 5-bit groups holding the invoice timestamp at the start of a BOLT11 data part
+const BOLT11_TIMESTAMP_GROUPS: usize = 7;
+
+/// Structural checks for NIP-57 zap requests (kind 9734) and zap receipts
+/// (kind 9735). The relay doesn't talk to Lightning, so the invoice is only
+/// read, never checked against a payment.
+#[derive(Debug, Clone, Copy)]
+pub struct ZapValidator {
+    pub max_skew_secs: u64,
+    pub max_payment_delay_secs: u64,
+}
+
+impl Default for ZapValidator {
+    fn default() -> Self {
+        Self {
+            max_skew_secs: ZAP_RECEIPT_MAX_SKEW_SECS,
+            max_payment_delay_secs: ZAP_RECEIPT_MAX_PAYMENT_DELAY_SECS,
+        }
+    }
+}
+
+impl ZapValidator {
+    pub fn validate(&self, event: &Event) -> Result<(), String> {
+        let valid = match event.kind {
+            Kind::ZapRequest => self.is_valid_request(event),
+            Kind::ZapReceipt => self.is_valid_receipt(event),
+            _ => true,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(MALFORMED_ZAP.to_string())
+        }
+    }
+
+    /// A zap request names its recipient and the relays the receipt goes to.
+    /// Its content is an optional comment, so any content is accepted.
+    pub fn is_valid_request(&self, event: &Event) -> bool {
+        let has_relay = event
+            .tags
+            .iter()
+            .map(|tag| tag.as_vec())
+            .find(|tag| tag.first().is_some_and(|name| name == "relays"))
+            .is_some_and(|tag| tag[1..].iter().any(|relay| Url::parse(relay).is_ok()));
+
+        has_recipient(event) && has_relay
+    }
+
+    /// A zap receipt names its recipient and carries the paid invoice. The
+    /// receipt is created when the invoice is paid, so between the invoice's
+    /// creation and `max_payment_delay_secs` later. Zaps of a profile rather
+    /// than an event have no `e` tag; when there is one it must be an event ID.
+    pub fn is_valid_receipt(&self, event: &Event) -> bool {
+        let zapped_event = tag_value(event, "e").is_none_or(|id| EventId::from_hex(id).is_ok());
+        let created_at = event.created_at.as_u64();
+        let invoice_matches = tag_value(event, "bolt11").and_then(bolt11_timestamp).is_some_and(|timestamp| {
+            created_at >= timestamp.saturating_sub(self.max_skew_secs)
+                && created_at <= timestamp.saturating_add(self.max_payment_delay_secs)
+        });
+
+        zapped_event && has_recipient(event) && invoice_matches
+    }
+}
+
+fn has_recipient(event: &Event) -> bool {
+    tag_value(event, "p").is_some_and(|pubkey| PublicKey::from_hex(pubkey).is_ok())
+}
+
+/// The creation timestamp of a BOLT11 invoice, in Unix seconds: the first 35
+/// bits of its bech32 data part. The checksum isn't verified.
+pub fn bolt11_timestamp(invoice: &str) -> Option<u64> {
+    let invoice = invoice.to_ascii_lowercase();
+    let (hrp, data) = invoice.rsplit_once('1')?;
+    if !hrp.starts_with("ln") || data.len() < BOLT11_TIMESTAMP_GROUPS {
+        return None;
+    }
+
+    let groups: Vec<u64> = data
+        .bytes()
+        .map(|byte| BECH32_CHARSET.iter().position(|&c| c == byte).map(|group| group as u64))
+        .collect::<Option<_>>()?;
+
+    Some(groups[..BOLT11_TIMESTAMP_GROUPS].iter().fold(0, |timestamp, group| (timestamp << 5) | group))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Tag, Timestamp};
+
+    // From the BOLT11 examples; created at 1496314658
+    const INVOICE: &str = "lnbc1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq8rkx3yf5tcsyz3d73gafnh3cax9rn449d9p5uxz9ezhhypd0elx87sjle52x86fux2ypatgddc6k63n7erqz25le42c4u4ecky03ylcqca784w";
+    const INVOICE_CREATED_AT: u64 = 1_496_314_658;
+
+    fn tag(values: &[&str]) -> Tag {
+        Tag::parse(values).unwrap()
+    }
+
+    fn event(kind: Kind, created_at: u64, tags: Vec<Tag>) -> Event {
+        EventBuilder::new(kind, "", tags)
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bolt11_timestamp() {
+        assert_eq!(bolt11_timestamp(INVOICE), Some(INVOICE_CREATED_AT));
+        assert_eq!(bolt11_timestamp(&INVOICE.to_uppercase()), Some(INVOICE_CREATED_AT));
+        assert_eq!(bolt11_timestamp("lnbc1pvjl"), None);
+        assert_eq!(bolt11_timestamp("lnbc1pvjluezb"), None);
+        assert_eq!(bolt11_timestamp("npub1pvjluezpp5"), None);
+        assert_eq!(bolt11_timestamp("not an invoice"), None);
+    }
+
+    #[test]
+    fn test_zap_request() {
+        let validator = ZapValidator::default();
+        let recipient = Keys::generate().public_key();
+        let request = |tags| event(Kind::ZapRequest, INVOICE_CREATED_AT, tags);
+
+        let valid = request(vec![Tag::public_key(recipient), tag(&["relays", "wss://relay.example.com", "wss://other.example"])]);
+        assert!(validator.is_valid_request(&valid));
+        assert!(validator.validate(&valid).is_ok());
+
+        assert!(!validator.is_valid_request(&request(vec![tag(&["relays", "wss://relay.example.com"])])));
+        assert!(!validator.is_valid_request(&request(vec![Tag::public_key(recipient)])));
+        assert!(!validator.is_valid_request(&request(vec![Tag::public_key(recipient), tag(&["relays"])])));
+        let not_urls = request(vec![Tag::public_key(recipient), tag(&["relays", "relay.example.com"])]);
+        assert_eq!(validator.validate(&not_urls), Err(MALFORMED_ZAP.to_string()));
+    }
+
+    #[test]
+    fn test_zap_receipt() {
+        let validator = ZapValidator::default();
+        let recipient = Tag::public_key(Keys::generate().public_key());
+        let zapped = Tag::event(EventId::all_zeros());
+        let bolt11 = tag(&["bolt11", INVOICE]);
+        let receipt = |created_at, tags| event(Kind::ZapReceipt, created_at, tags);
+
+        let valid = receipt(INVOICE_CREATED_AT + 60, vec![zapped.clone(), recipient.clone(), bolt11.clone()]);
+        assert!(validator.is_valid_receipt(&valid));
+        assert!(validator.validate(&valid).is_ok());
+        assert!(validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT - 60, vec![zapped.clone(), recipient.clone(), bolt11.clone()])));
+
+        // Paid an hour after the invoice was issued
+        let paid_later = INVOICE_CREATED_AT + 3600;
+        assert!(validator.is_valid_receipt(&receipt(paid_later, vec![zapped.clone(), recipient.clone(), bolt11.clone()])));
+        let last_moment = INVOICE_CREATED_AT + ZAP_RECEIPT_MAX_PAYMENT_DELAY_SECS;
+        assert!(validator.is_valid_receipt(&receipt(last_moment, vec![zapped.clone(), recipient.clone(), bolt11.clone()])));
+
+        let too_late = receipt(last_moment + 1, vec![zapped.clone(), recipient.clone(), bolt11.clone()]);
+        assert_eq!(validator.validate(&too_late), Err(MALFORMED_ZAP.to_string()));
+        let too_early = receipt(INVOICE_CREATED_AT - 61, vec![zapped.clone(), recipient.clone(), bolt11.clone()]);
+        assert!(!validator.is_valid_receipt(&too_early));
+
+        // A profile zap has no zapped event, but a malformed one is refused
+        assert!(validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT, vec![recipient.clone(), bolt11.clone()])));
+        let bad_event = tag(&["e", "not-an-event-id"]);
+        assert!(!validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT, vec![bad_event, recipient.clone(), bolt11.clone()])));
+        assert!(!validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT, vec![zapped.clone(), bolt11])));
+        assert!(!validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT, vec![zapped.clone(), recipient.clone()])));
+        let bad_invoice = tag(&["bolt11", "lnbc-not-bech32"]);
+        assert!(!validator.is_valid_receipt(&receipt(INVOICE_CREATED_AT, vec![zapped, recipient, bad_invoice])));
+    }
+}
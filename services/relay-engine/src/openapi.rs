@@ -0,0 +1,77 @@
+use axum::Router;
+use utoipa::{
+    openapi::{
+        self,
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{admin, app_state::AppState, events_api::EventsApiDoc, metrics};
+
+/// Where the OpenAPI document is served; the Swagger UI loads it from here
+pub const OPENAPI_JSON_PATH: &str = "/api/openapi.json";
+
+/// Where the Swagger UI is served
+pub const SWAGGER_UI_PATH: &str = "/api/docs";
+
+/// The admin and metrics APIs. `api_doc` adds the events API to make the
+/// document the relay serves.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Pleb.One Relay API", description = "Admin, metrics and events endpoints of the relay"),
+    paths(
+        admin::list_blocked_pubkeys,
+        admin::block_pubkey,
+        admin::unblock_pubkey,
+        admin::delete_pubkey_events,
+        admin::force_delete_event,
+        admin::list_connections,
+        admin::disconnect_connection,
+        admin::export_events,
+        admin::import_events,
+        admin::list_slow_queries,
+        metrics::get_relay_status,
+        metrics::get_event_metrics,
+        metrics::get_performance_metrics,
+        metrics::get_all_metrics,
+        metrics::get_storage_metrics,
+    ),
+    modifiers(&AdminSecuritySchemes),
+    tags(
+        (name = "admin", description = "Moderation and data management; needs `Authorization: Bearer <RELAY_ADMIN_TOKEN>` or a NIP-98 event signed by one of `RELAY_ADMIN_PUBKEYS`"),
+        (name = "metrics", description = "Relay activity and storage totals"),
+    )
+)]
+pub struct ApiDoc;
+
+// The credentials the admin endpoints' `security` refers to
+struct AdminSecuritySchemes;
+
+impl Modify for AdminSecuritySchemes {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "nip98",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "`Nostr <base64 kind-27235 event>`, signed for the request URL and method (NIP-98)",
+            ))),
+        );
+    }
+}
+
+/// The OpenAPI document for every documented endpoint
+pub fn api_doc() -> openapi::OpenApi {
+    ApiDoc::openapi().merge_from(EventsApiDoc::openapi())
+}
+
+// Router setup for the OpenAPI document and the Swagger UI that browses it
+pub fn create_openapi_router() -> Router<AppState> {
+    SwaggerUi::new(SWAGGER_UI_PATH).url(OPENAPI_JSON_PATH, api_doc()).into()
+}
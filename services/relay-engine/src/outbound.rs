@@ -0,0 +1,222 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use futures_util::{Sink, SinkExt};
+use nostr::{RelayMessage, SubscriptionId};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// Reason given to a client whose outbound queue overflowed
+pub const SLOW_SUBSCRIBER: &str = "slow subscriber";
+
+/// Queues messages for delivery to one connection. A full queue means the
+/// client isn't reading fast enough to keep up with its subscriptions: rather
+/// than silently dropping events, the connection is flagged to be closed.
+#[derive(Clone)]
+pub struct ClientSender {
+    messages: mpsc::Sender<RelayMessage>,
+    overflowed: CancellationToken,
+}
+
+impl ClientSender {
+    /// Queue `message` without waiting. Returns false if the queue is full,
+    /// which flags the connection as a slow subscriber, or already closed.
+    pub fn try_send(&self, message: RelayMessage) -> bool {
+        match self.messages.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.cancel();
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Messages waiting to be written to the socket
+    pub fn queue_depth(&self) -> usize {
+        self.messages.max_capacity() - self.messages.capacity()
+    }
+}
+
+/// Receiving half of a connection's outbound queue, owned by its socket task
+pub struct OutboundQueue {
+    pub messages: mpsc::Receiver<RelayMessage>,
+    /// Cancelled once a message couldn't be queued because the queue was full
+    pub overflowed: CancellationToken,
+}
+
+pub fn channel(capacity: usize) -> (ClientSender, OutboundQueue) {
+    let (messages, receiver) = mpsc::channel(capacity.max(1));
+    let overflowed = CancellationToken::new();
+    let sender = ClientSender {
+        messages,
+        overflowed: overflowed.clone(),
+    };
+    (sender, OutboundQueue { messages: receiver, overflowed })
+}
+
+/// The write half of a connection's socket, counting the bytes of every text
+/// message written to it into the connection's total and the relay-wide metric
+pub struct MeteredSink<S> {
+    inner: S,
+    bytes_sent: Arc<AtomicU64>,
+    metrics: Metrics,
+    send_timeout: Duration,
+}
+
+impl<S> MeteredSink<S> {
+    pub fn new(inner: S, bytes_sent: Arc<AtomicU64>, metrics: Metrics, send_timeout: Duration) -> Self {
+        Self { inner, bytes_sent, metrics, send_timeout }
+    }
+}
+
+impl<S> MeteredSink<S>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Write `message` to the socket, waiting at most the send timeout for the
+    /// client to take it. A client that doesn't is a slow subscriber: the
+    /// timeout is counted and returned as an error so its connection is closed
+    /// instead of holding up the messages queued behind it.
+    pub async fn send_timed(&mut self, message: Message) -> anyhow::Result<()> {
+        match timeout(self.send_timeout, self.send(message)).await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => {
+                warn!("Client didn't accept a message within {:?}", self.send_timeout);
+                self.metrics.record_slow_subscriber_timeout();
+                Err(anyhow::anyhow!("Send timeout"))
+            }
+        }
+    }
+}
+
+impl<S: Sink<Message> + Unpin> Sink<Message> for MeteredSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        if let Message::Text(text) = &message {
+            self.bytes_sent.fetch_add(text.len() as u64, Ordering::Relaxed);
+            self.metrics.record_bytes_sent(text.len());
+        }
+        Pin::new(&mut self.inner).start_send(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Tell a client that fell behind why its subscriptions are ending, then close
+/// the WebSocket. Messages still queued for it are discarded.
+pub async fn close_slow_subscriber<S>(sink: &mut S, subscription_ids: impl IntoIterator<Item = String>) -> anyhow::Result<()>
+where
+    S: Sink<Message, Error = axum::Error> + Unpin,
+{
+    for subscription_id in subscription_ids {
+        let closed = RelayMessage::Closed {
+            subscription_id: SubscriptionId::new(subscription_id),
+            message: SLOW_SUBSCRIBER.to_string(),
+        };
+        sink.send(Message::Text(serde_json::to_string(&closed)?)).await?;
+    }
+
+    let frame = CloseFrame {
+        code: close_code::POLICY,
+        reason: SLOW_SUBSCRIBER.into(),
+    };
+    sink.send(Message::Close(Some(frame))).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notice(message: &str) -> RelayMessage {
+        RelayMessage::Notice {
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_full_queue_flags_overflow() {
+        let (sender, mut queue) = channel(2);
+
+        assert!(sender.try_send(notice("one")));
+        assert!(sender.try_send(notice("two")));
+        assert_eq!(sender.queue_depth(), 2);
+        assert!(!queue.overflowed.is_cancelled());
+
+        assert!(!sender.try_send(notice("three")));
+        assert!(queue.overflowed.is_cancelled());
+
+        queue.messages.try_recv().unwrap();
+        assert_eq!(sender.queue_depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metered_sink_counts_text_bytes() {
+        let metrics = Metrics::new().unwrap();
+        let bytes_sent = Arc::new(AtomicU64::new(0));
+        let mut sink = MeteredSink::new(futures_util::sink::drain(), bytes_sent.clone(), metrics.clone(), Duration::from_secs(5));
+
+        sink.send(Message::Text("[\"EOSE\",\"sub\"]".to_string())).await.unwrap();
+        sink.send(Message::Text("hello".to_string())).await.unwrap();
+        // Control frames aren't part of the relay protocol traffic
+        sink.send(Message::Ping(vec![0; 32])).await.unwrap();
+
+        assert_eq!(bytes_sent.load(Ordering::Relaxed), 19);
+        assert_eq!(metrics.bytes_sent.get(), 19.0);
+    }
+
+    #[tokio::test]
+    async fn test_send_timeout_counts_slow_subscriber() {
+        let metrics = Metrics::new().unwrap();
+        let timeout = Duration::from_millis(20);
+
+        let mut sink = MeteredSink::new(futures_util::sink::drain(), Arc::default(), metrics.clone(), timeout);
+        sink.send_timed(Message::Text("hello".to_string())).await.unwrap();
+        assert_eq!(metrics.slow_subscriber_timeouts.get(), 0.0);
+
+        // A client that never reads leaves every write pending
+        let stalled = Box::pin(futures_util::sink::unfold((), |_, _: Message| {
+            std::future::pending::<Result<(), std::convert::Infallible>>()
+        }));
+        let mut sink = MeteredSink::new(stalled, Arc::default(), metrics.clone(), timeout);
+        assert!(sink.send_timed(Message::Text("hello".to_string())).await.is_err());
+        assert_eq!(metrics.slow_subscriber_timeouts.get(), 1.0);
+    }
+
+    #[test]
+    fn test_closed_queue_is_not_an_overflow() {
+        let (sender, queue) = channel(2);
+        let overflowed = queue.overflowed.clone();
+        drop(queue);
+
+        assert!(!sender.try_send(notice("gone")));
+        assert!(!overflowed.is_cancelled());
+    }
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Router,
+};
+use nostr::{Event, Filter, Kind, PublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{app_state::AppState, database::RelayDatabase};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub pubkey: String,
+    pub metadata: serde_json::Value,
+    /// When the profile was last indexed, in seconds since the epoch
+    pub updated_at: i64,
+}
+
+/// The profile metadata in a kind-0 event's content, when it is a JSON object
+pub fn parse_profile(event: &Event) -> Option<serde_json::Value> {
+    serde_json::from_str(&event.content)
+        .ok()
+        .filter(serde_json::Value::is_object)
+}
+
+/// Refresh the user's indexed profile after a kind-0 event is stored. The
+/// newest stored event is read back, so a stale profile arriving late never
+/// overwrites a newer one, and a newest profile that isn't a JSON object
+/// clears the indexed one. Deleting a kind-0 event drops its indexed profile
+/// in the same query.
+pub async fn index_profile(event: &Event, state: &AppState) {
+    if let Err(e) = load_profile(&event.pubkey, state).await {
+        error!("Failed to index profile {}: {}", event.id, e);
+    }
+}
+
+async fn load_profile(pubkey: &PublicKey, state: &AppState) -> anyhow::Result<()> {
+    let filter = Filter::new().author(*pubkey).kind(Kind::Metadata).limit(1);
+    let Some(event) = state.database.query_events(&filter).await?.into_iter().next() else {
+        return state.database.delete_profile(&pubkey.to_hex()).await;
+    };
+
+    let Some(metadata) = parse_profile(&event) else {
+        debug!("Not indexing profile {}: content is not a JSON object", event.id);
+        return state.database.delete_profile(&pubkey.to_hex()).await;
+    };
+    state.database.upsert_profile(&pubkey.to_hex(), metadata).await
+}
+
+pub async fn get_profile_handler(
+    State(state): State<AppState>,
+    Path(pubkey): Path<String>,
+) -> Result<Json<ProfileResponse>, StatusCode> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    match state.database.get_profile(&pubkey.to_hex()).await {
+        Ok(Some(profile)) => Ok(Json(ProfileResponse {
+            pubkey: profile.pubkey,
+            metadata: profile.metadata,
+            updated_at: profile.updated_at,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to load profile for {}: {}", pubkey, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Router setup for kind-0 profile lookups
+pub fn create_profile_router() -> Router<AppState> {
+    Router::new().route("/api/users/:pubkey/profile", get(get_profile_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    fn metadata_event(content: &str) -> Event {
+        EventBuilder::new(Kind::Metadata, content, []).to_event(&Keys::generate()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = parse_profile(&metadata_event(r#"{"name":"pleb","about":"stacking sats"}"#)).unwrap();
+        assert_eq!(profile["name"], "pleb");
+        assert_eq!(profile["about"], "stacking sats");
+
+        for content in ["", "not json", "[1,2]", "\"pleb\"", "42"] {
+            assert_eq!(parse_profile(&metadata_event(content)), None, "{:?} should not parse", content);
+        }
+    }
+}
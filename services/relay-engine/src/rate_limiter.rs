@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use anyhow::Result;
 use tracing::{debug, warn};
@@ -11,6 +11,13 @@ pub struct RateLimitConfig {
     pub events_per_minute: u32,
     pub queries_per_minute: u32,
     pub connections_per_ip: u32,
+    pub events_per_minute_per_pubkey: u32,
+    pub queries_per_minute_per_pubkey: u32,
+    /// Number of events or queries a client can send in a burst before the
+    /// per-minute refill rate applies
+    pub burst_capacity: u32,
+    /// Bytes an IP may send and receive per UTC day; 0 means unlimited
+    pub daily_limit_bytes: u64,
     pub cleanup_interval: Duration,
 }
 
@@ -20,38 +27,106 @@ impl Default for RateLimitConfig {
             events_per_minute: 60,
             queries_per_minute: 120,
             connections_per_ip: 10,
+            events_per_minute_per_pubkey: 60,
+            queries_per_minute_per_pubkey: 120,
+            burst_capacity: 20,
+            daily_limit_bytes: 0,
             cleanup_interval: Duration::from_secs(300), // 5 minutes
         }
     }
 }
 
+/// Token bucket: holds up to `capacity` tokens and refills continuously at
+/// `refill_rate` tokens per second. Each request consumes a token.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    capacity: f64,
+    refill_rate: f64,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, per_minute: u32) -> Self {
+        let capacity = f64::from(capacity);
+        Self {
+            tokens: capacity,
+            last_refill: Instant::now(),
+            capacity,
+            refill_rate: f64::from(per_minute) / 60.0,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn try_consume(&mut self, amount: f64) -> bool {
+        self.refill();
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+
+    // A full bucket carries no state worth keeping
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Days since the Unix epoch, which roll over at midnight UTC
+fn utc_day() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SECS_PER_DAY
+}
+
+/// Bytes an IP has sent and received over WebSockets during one UTC day
+#[derive(Debug)]
+struct DailyBandwidthBucket {
+    day: u64,
+    bytes: u64,
+}
+
+impl DailyBandwidthBucket {
+    fn new(day: u64) -> Self {
+        Self { day, bytes: 0 }
+    }
+
+    /// Count `bytes` against `day`, starting over if the bucket is from an earlier day
+    fn add(&mut self, day: u64, bytes: u64) -> u64 {
+        if self.day != day {
+            *self = Self::new(day);
+        }
+        self.bytes = self.bytes.saturating_add(bytes);
+        self.bytes
+    }
+}
+
 #[derive(Debug)]
 struct RateLimitEntry {
-    events: Vec<Instant>,
-    queries: Vec<Instant>,
+    events: TokenBucket,
+    queries: TokenBucket,
     connections: u32,
-    last_cleanup: Instant,
 }
 
 impl RateLimitEntry {
-    fn new() -> Self {
+    fn new(burst_capacity: u32, events_per_minute: u32, queries_per_minute: u32) -> Self {
         Self {
-            events: Vec::new(),
-            queries: Vec::new(),
+            events: TokenBucket::new(burst_capacity, events_per_minute),
+            queries: TokenBucket::new(burst_capacity, queries_per_minute),
             connections: 0,
-            last_cleanup: Instant::now(),
         }
     }
 
-    fn cleanup_old_entries(&mut self, window: Duration) {
-        let cutoff = Instant::now() - window;
-        self.events.retain(|&time| time > cutoff);
-        self.queries.retain(|&time| time > cutoff);
-        self.last_cleanup = Instant::now();
-    }
-
-    fn should_cleanup(&self, cleanup_interval: Duration) -> bool {
-        Instant::now() - self.last_cleanup > cleanup_interval
+    fn is_idle(&mut self) -> bool {
+        self.connections == 0 && self.events.is_full() && self.queries.is_full()
     }
 }
 
@@ -59,91 +134,141 @@ impl RateLimitEntry {
 pub struct RateLimiter {
     config: RateLimitConfig,
     entries: Arc<RwLock<HashMap<IpAddr, RateLimitEntry>>>,
+    pubkey_entries: Arc<RwLock<HashMap<String, RateLimitEntry>>>,
+    bandwidth: Arc<RwLock<HashMap<IpAddr, DailyBandwidthBucket>>>,
 }
 
 impl RateLimiter {
     pub fn new(config: RateLimitConfig) -> Self {
         let entries = Arc::new(RwLock::new(HashMap::new()));
+        let pubkey_entries = Arc::new(RwLock::new(HashMap::new()));
+        let bandwidth = Arc::new(RwLock::new(HashMap::new()));
         
         // Start cleanup task
         let cleanup_entries = Arc::clone(&entries);
+        let cleanup_pubkey_entries = Arc::clone(&pubkey_entries);
+        let cleanup_bandwidth = Arc::clone(&bandwidth);
         let cleanup_config = config.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(cleanup_config.cleanup_interval);
             loop {
                 interval.tick().await;
                 Self::cleanup_task(&cleanup_entries, &cleanup_config).await;
+                Self::cleanup_task(&cleanup_pubkey_entries, &cleanup_config).await;
+                // Buckets from an earlier day would start over anyway
+                let today = utc_day();
+                cleanup_bandwidth.write().await.retain(|_ip, bucket: &mut DailyBandwidthBucket| bucket.day == today);
             }
         });
 
-        Self { config, entries }
+        Self { config, entries, pubkey_entries, bandwidth }
     }
 
-    async fn cleanup_task(
-        entries: &Arc<RwLock<HashMap<IpAddr, RateLimitEntry>>>,
+    async fn cleanup_task<K: std::hash::Hash + Eq>(
+        entries: &Arc<RwLock<HashMap<K, RateLimitEntry>>>,
         _config: &RateLimitConfig,
     ) {
         let mut entries_guard = entries.write().await;
-        let window = Duration::from_secs(60);
-        
-        // Clean up old entries and remove empty ones
-        entries_guard.retain(|_ip, entry| {
-            entry.cleanup_old_entries(window);
-            
-            // Keep entry if it has recent activity or active connections
-            !entry.events.is_empty() 
-                || !entry.queries.is_empty() 
-                || entry.connections > 0
-        });
-        
-        debug!("Rate limiter cleanup completed. Active IPs: {}", entries_guard.len());
+
+        // Drop entries whose buckets have refilled and that hold no connections
+        entries_guard.retain(|_key, entry| !entry.is_idle());
+
+        debug!("Rate limiter cleanup completed. Active entries: {}", entries_guard.len());
+    }
+
+    fn ip_entry(&self) -> RateLimitEntry {
+        RateLimitEntry::new(
+            self.config.burst_capacity,
+            self.config.events_per_minute,
+            self.config.queries_per_minute,
+        )
+    }
+
+    fn pubkey_entry(&self) -> RateLimitEntry {
+        RateLimitEntry::new(
+            self.config.burst_capacity,
+            self.config.events_per_minute_per_pubkey,
+            self.config.queries_per_minute_per_pubkey,
+        )
     }
 
     pub async fn check_event_rate(&self, ip: IpAddr) -> Result<bool> {
         let mut entries = self.entries.write().await;
-        let entry = entries.entry(ip).or_insert_with(RateLimitEntry::new);
-
-        // Cleanup if needed
-        if entry.should_cleanup(self.config.cleanup_interval) {
-            entry.cleanup_old_entries(Duration::from_secs(60));
-        }
+        let entry = entries.entry(ip).or_insert_with(|| self.ip_entry());
 
-        // Check rate limit
-        if entry.events.len() >= self.config.events_per_minute as usize {
+        if !entry.events.try_consume(1.0) {
             warn!("Event rate limit exceeded for IP: {}", ip);
             return Ok(false);
         }
 
-        // Add current request
-        entry.events.push(Instant::now());
-        debug!("Event recorded for IP: {}. Count: {}", ip, entry.events.len());
+        debug!("Event recorded for IP: {}. Tokens left: {:.1}", ip, entry.events.tokens);
         Ok(true)
     }
 
     pub async fn check_query_rate(&self, ip: IpAddr) -> Result<bool> {
         let mut entries = self.entries.write().await;
-        let entry = entries.entry(ip).or_insert_with(RateLimitEntry::new);
+        let entry = entries.entry(ip).or_insert_with(|| self.ip_entry());
 
-        // Cleanup if needed
-        if entry.should_cleanup(self.config.cleanup_interval) {
-            entry.cleanup_old_entries(Duration::from_secs(60));
+        if !entry.queries.try_consume(1.0) {
+            warn!("Query rate limit exceeded for IP: {}", ip);
+            return Ok(false);
         }
 
-        // Check rate limit
-        if entry.queries.len() >= self.config.queries_per_minute as usize {
-            warn!("Query rate limit exceeded for IP: {}", ip);
+        debug!("Query recorded for IP: {}. Tokens left: {:.1}", ip, entry.queries.tokens);
+        Ok(true)
+    }
+
+    pub async fn check_event_rate_pubkey(&self, pubkey: &str) -> Result<bool> {
+        let mut entries = self.pubkey_entries.write().await;
+        let entry = entries.entry(pubkey.to_string()).or_insert_with(|| self.pubkey_entry());
+
+        if !entry.events.try_consume(1.0) {
+            warn!("Event rate limit exceeded for pubkey: {}", pubkey);
+            return Ok(false);
+        }
+
+        debug!("Event recorded for pubkey: {}. Tokens left: {:.1}", pubkey, entry.events.tokens);
+        Ok(true)
+    }
+
+    pub async fn check_query_rate_pubkey(&self, pubkey: &str) -> Result<bool> {
+        let mut entries = self.pubkey_entries.write().await;
+        let entry = entries.entry(pubkey.to_string()).or_insert_with(|| self.pubkey_entry());
+
+        if !entry.queries.try_consume(1.0) {
+            warn!("Query rate limit exceeded for pubkey: {}", pubkey);
+            return Ok(false);
+        }
+
+        debug!("Query recorded for pubkey: {}. Tokens left: {:.1}", pubkey, entry.queries.tokens);
+        Ok(true)
+    }
+
+    /// Count bytes an IP sent or received against its daily bandwidth limit.
+    /// Returns false once the IP has gone over the limit for the current UTC day.
+    pub async fn record_bandwidth(&self, ip: IpAddr, bytes: u64) -> Result<bool> {
+        if self.config.daily_limit_bytes == 0 {
+            return Ok(true);
+        }
+
+        let today = utc_day();
+        let mut bandwidth = self.bandwidth.write().await;
+        let used = bandwidth
+            .entry(ip)
+            .or_insert_with(|| DailyBandwidthBucket::new(today))
+            .add(today, bytes);
+
+        if used > self.config.daily_limit_bytes {
+            warn!("Daily bandwidth limit exceeded for IP: {}. Used: {} bytes", ip, used);
             return Ok(false);
         }
 
-        // Add current request
-        entry.queries.push(Instant::now());
-        debug!("Query recorded for IP: {}. Count: {}", ip, entry.queries.len());
         Ok(true)
     }
 
     pub async fn check_connection_limit(&self, ip: IpAddr) -> Result<bool> {
         let mut entries = self.entries.write().await;
-        let entry = entries.entry(ip).or_insert_with(RateLimitEntry::new);
+        let entry = entries.entry(ip).or_insert_with(|| self.ip_entry());
 
         if entry.connections >= self.config.connections_per_ip {
             warn!("Connection limit exceeded for IP: {}. Current: {}", ip, entry.connections);
@@ -155,7 +280,7 @@ impl RateLimiter {
 
     pub async fn add_connection(&self, ip: IpAddr) -> Result<()> {
         let mut entries = self.entries.write().await;
-        let entry = entries.entry(ip).or_insert_with(RateLimitEntry::new);
+        let entry = entries.entry(ip).or_insert_with(|| self.ip_entry());
         entry.connections += 1;
         debug!("Connection added for IP: {}. Total: {}", ip, entry.connections);
         Ok(())
@@ -173,14 +298,14 @@ impl RateLimiter {
     }
 
     pub async fn get_stats(&self) -> Result<RateLimitStats> {
-        let entries = self.entries.read().await;
+        let mut entries = self.entries.write().await;
         let mut total_connections = 0;
         let mut total_active_ips = 0;
         let mut max_connections_per_ip = 0;
 
-        for entry in entries.values() {
+        for entry in entries.values_mut() {
             total_connections += entry.connections;
-            if entry.connections > 0 || !entry.events.is_empty() || !entry.queries.is_empty() {
+            if !entry.is_idle() {
                 total_active_ips += 1;
             }
             if entry.connections > max_connections_per_ip {
@@ -226,6 +351,10 @@ mod tests {
         assert_eq!(config.events_per_minute, 60);
         assert_eq!(config.queries_per_minute, 120);
         assert_eq!(config.connections_per_ip, 10);
+        assert_eq!(config.events_per_minute_per_pubkey, 60);
+        assert_eq!(config.queries_per_minute_per_pubkey, 120);
+        assert_eq!(config.burst_capacity, 20);
+        assert_eq!(config.daily_limit_bytes, 0);
         assert_eq!(config.cleanup_interval, Duration::from_secs(300));
     }
 
@@ -246,6 +375,10 @@ mod tests {
             events_per_minute: 3,
             queries_per_minute: 120,
             connections_per_ip: 10,
+            events_per_minute_per_pubkey: 60,
+            queries_per_minute_per_pubkey: 120,
+            burst_capacity: 3,
+            daily_limit_bytes: 0,
             cleanup_interval: Duration::from_secs(300),
         };
         let limiter = RateLimiter::new(config);
@@ -266,6 +399,10 @@ mod tests {
             events_per_minute: 60,
             queries_per_minute: 2,
             connections_per_ip: 10,
+            events_per_minute_per_pubkey: 60,
+            queries_per_minute_per_pubkey: 120,
+            burst_capacity: 2,
+            daily_limit_bytes: 0,
             cleanup_interval: Duration::from_secs(300),
         };
         let limiter = RateLimiter::new(config);
@@ -285,6 +422,10 @@ mod tests {
             events_per_minute: 60,
             queries_per_minute: 120,
             connections_per_ip: 2,
+            events_per_minute_per_pubkey: 60,
+            queries_per_minute_per_pubkey: 120,
+            burst_capacity: 20,
+            daily_limit_bytes: 0,
             cleanup_interval: Duration::from_secs(300),
         };
         let limiter = RateLimiter::new(config);
@@ -339,6 +480,10 @@ mod tests {
             events_per_minute: 2,
             queries_per_minute: 120,
             connections_per_ip: 10,
+            events_per_minute_per_pubkey: 60,
+            queries_per_minute_per_pubkey: 120,
+            burst_capacity: 2,
+            daily_limit_bytes: 0,
             cleanup_interval: Duration::from_secs(300),
         };
         let limiter = RateLimiter::new(config);
@@ -356,6 +501,38 @@ mod tests {
         assert!(!limiter.check_event_rate(ip2).await.unwrap()); // Rate limited
     }
 
+    #[tokio::test]
+    async fn test_pubkey_rate_limiting() {
+        let config = RateLimitConfig {
+            events_per_minute: 60,
+            queries_per_minute: 120,
+            connections_per_ip: 10,
+            events_per_minute_per_pubkey: 2,
+            queries_per_minute_per_pubkey: 1,
+            burst_capacity: 2,
+            daily_limit_bytes: 0,
+            cleanup_interval: Duration::from_secs(300),
+        };
+        let limiter = RateLimiter::new(config);
+        let pubkey1 = "a".repeat(64);
+        let pubkey2 = "b".repeat(64);
+
+        // Pubkey1 uses its event and query bursts
+        assert!(limiter.check_event_rate_pubkey(&pubkey1).await.unwrap());
+        assert!(limiter.check_event_rate_pubkey(&pubkey1).await.unwrap());
+        assert!(!limiter.check_event_rate_pubkey(&pubkey1).await.unwrap()); // Rate limited
+        assert!(limiter.check_query_rate_pubkey(&pubkey1).await.unwrap());
+        assert!(limiter.check_query_rate_pubkey(&pubkey1).await.unwrap());
+        assert!(!limiter.check_query_rate_pubkey(&pubkey1).await.unwrap()); // Rate limited
+
+        // Pubkey2 should still have its full limit
+        assert!(limiter.check_event_rate_pubkey(&pubkey2).await.unwrap());
+        assert!(limiter.check_query_rate_pubkey(&pubkey2).await.unwrap());
+
+        // Per-IP limits are tracked separately
+        assert!(limiter.check_event_rate(test_ip()).await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_stats_tracking() {
         let config = RateLimitConfig::default();
@@ -377,33 +554,101 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_rate_limit_entry_cleanup() {
-        let mut entry = RateLimitEntry::new();
-        let old_time = Instant::now() - Duration::from_secs(120);
-        let recent_time = Instant::now();
+    async fn test_token_bucket_refill() {
+        let mut bucket = TokenBucket::new(2, 60); // one token per second
+
+        assert!(bucket.try_consume(1.0));
+        assert!(bucket.try_consume(1.0));
+        assert!(!bucket.try_consume(1.0));
+
+        // Simulate 1.5 seconds passing: one and a half tokens come back
+        bucket.last_refill = Instant::now() - Duration::from_millis(1500);
+        assert!(bucket.try_consume(1.0));
+        assert!(!bucket.try_consume(1.0));
+
+        // Refilling never exceeds the burst capacity
+        bucket.last_refill = Instant::now() - Duration::from_secs(600);
+        assert!(bucket.is_full());
+        assert!(bucket.try_consume(1.0));
+        assert!(bucket.try_consume(1.0));
+        assert!(!bucket.try_consume(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_entry_idle() {
+        let mut entry = RateLimitEntry::new(5, 60, 120);
+
+        // Fresh entries have full buckets and nothing to track
+        assert!(entry.is_idle());
 
-        // Add some old and recent entries
-        entry.events.push(old_time);
-        entry.events.push(recent_time);
-        entry.queries.push(old_time);
-        entry.queries.push(recent_time);
+        entry.events.try_consume(1.0);
+        assert!(!entry.is_idle());
 
-        // Cleanup should remove old entries
-        entry.cleanup_old_entries(Duration::from_secs(60));
+        // Once the bucket refills the entry can be dropped again
+        entry.events.last_refill = Instant::now() - Duration::from_secs(60);
+        assert!(entry.is_idle());
 
-        assert_eq!(entry.events.len(), 1);
-        assert_eq!(entry.queries.len(), 1);
+        entry.connections = 1;
+        assert!(!entry.is_idle());
     }
 
     #[tokio::test]
-    async fn test_rate_limit_entry_should_cleanup() {
-        let mut entry = RateLimitEntry::new();
-        
-        // Just created, should not need cleanup
-        assert!(!entry.should_cleanup(Duration::from_secs(300)));
+    async fn test_burst_then_sustained_rate() {
+        let config = RateLimitConfig {
+            events_per_minute: 60,
+            burst_capacity: 5,
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config);
+        let ip = test_ip();
+
+        // A burst up to capacity is allowed, then the client must wait for refills
+        for _ in 0..5 {
+            assert!(limiter.check_event_rate(ip).await.unwrap());
+        }
+        assert!(!limiter.check_event_rate(ip).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(limiter.check_event_rate(ip).await.unwrap());
+        assert!(!limiter.check_event_rate(ip).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_daily_bandwidth_limit() {
+        let config = RateLimitConfig {
+            daily_limit_bytes: 1000,
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config);
+
+        assert!(limiter.record_bandwidth(test_ip(), 600).await.unwrap());
+        assert!(limiter.record_bandwidth(test_ip(), 400).await.unwrap());
+        assert!(!limiter.record_bandwidth(test_ip(), 1).await.unwrap());
+        // Once over the limit, the IP stays over it for the rest of the day
+        assert!(!limiter.record_bandwidth(test_ip(), 0).await.unwrap());
+
+        // Other IPs have their own allowance
+        assert!(limiter.record_bandwidth(test_ip2(), 1000).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_bandwidth_is_not_tracked() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+
+        assert!(limiter.record_bandwidth(test_ip(), u64::MAX).await.unwrap());
+        assert!(limiter.record_bandwidth(test_ip(), u64::MAX).await.unwrap());
+        assert!(limiter.bandwidth.read().await.is_empty());
+    }
+
+    #[test]
+    fn test_bandwidth_bucket_resets_each_day() {
+        let mut bucket = DailyBandwidthBucket::new(100);
+
+        assert_eq!(bucket.add(100, 500), 500);
+        assert_eq!(bucket.add(100, 500), 1000);
 
-        // Simulate time passing
-        entry.last_cleanup = Instant::now() - Duration::from_secs(400);
-        assert!(entry.should_cleanup(Duration::from_secs(300)));
+        // The first bytes after midnight UTC start a new day's count
+        assert_eq!(bucket.add(101, 200), 200);
+        assert_eq!(bucket.day, 101);
     }
 }
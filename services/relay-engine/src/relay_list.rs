@@ -0,0 +1,129 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Router,
+};
+use nostr::{nips::nip65, Event, Filter, Kind, PublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{app_state::AppState, database::RelayDatabase};
+
+/// A relay from a NIP-65 relay list. Without a marker the user both reads
+/// from and writes to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayUrl {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelayListResponse {
+    pub pubkey: String,
+    pub relays: Vec<RelayUrl>,
+}
+
+/// Relays named by the `r` tags of a kind 10002 event
+pub fn parse_relay_list(event: &Event) -> Vec<RelayUrl> {
+    nip65::extract_relay_list(event)
+        .into_iter()
+        .map(|(url, metadata)| RelayUrl {
+            url: url.to_string(),
+            marker: metadata.as_ref().map(|metadata| metadata.to_string()),
+        })
+        .collect()
+}
+
+/// Refresh the in-memory index after a relay list is stored. The newest stored
+/// list is read back, so a stale list arriving late never overwrites a newer one.
+pub async fn update_relay_list_index(event: &Event, state: &AppState) {
+    if let Err(e) = load_relay_list(&event.pubkey, state).await {
+        error!("Failed to index relay list {}: {}", event.id, e);
+    }
+}
+
+/// Look up a user's relay list, falling back to the database on an index miss
+pub async fn get_relay_list(pubkey: &PublicKey, state: &AppState) -> anyhow::Result<Option<Vec<RelayUrl>>> {
+    if let Some(relays) = state.pubkey_relays.read().await.get(&pubkey.to_hex()) {
+        return Ok(Some(relays.clone()));
+    }
+    load_relay_list(pubkey, state).await
+}
+
+/// Drop a user's relay list from the index after their events are deleted;
+/// the next lookup reads whatever is still stored
+pub async fn evict_relay_list(pubkey: &str, state: &AppState) {
+    state.pubkey_relays.write().await.remove(pubkey);
+}
+
+async fn load_relay_list(pubkey: &PublicKey, state: &AppState) -> anyhow::Result<Option<Vec<RelayUrl>>> {
+    let filter = Filter::new().author(*pubkey).kind(Kind::RelayList).limit(1);
+    let Some(event) = state.database.query_events(&filter).await?.into_iter().next() else {
+        evict_relay_list(&pubkey.to_hex(), state).await;
+        return Ok(None);
+    };
+
+    let relays = parse_relay_list(&event);
+    state.pubkey_relays.write().await.insert(pubkey.to_hex(), relays.clone());
+    Ok(Some(relays))
+}
+
+pub async fn get_relay_list_handler(
+    State(state): State<AppState>,
+    Path(pubkey): Path<String>,
+) -> Result<Json<RelayListResponse>, StatusCode> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    match get_relay_list(&pubkey, &state).await {
+        Ok(Some(relays)) => Ok(Json(RelayListResponse {
+            pubkey: pubkey.to_hex(),
+            relays,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to load relay list for {}: {}", pubkey, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Router setup for NIP-65 relay list lookups
+pub fn create_relay_list_router() -> Router<AppState> {
+    Router::new().route("/api/relay-lists/:pubkey", get(get_relay_list_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Tag, TagStandard, Url};
+    use nostr::nips::nip65::RelayMetadata;
+
+    #[test]
+    fn test_parse_relay_list() {
+        let relay = |url: &str, metadata| {
+            Tag::from_standardized(TagStandard::RelayMetadata {
+                relay_url: Url::parse(url).unwrap(),
+                metadata,
+            })
+        };
+        let tags = [
+            relay("wss://both.example.com", None),
+            relay("wss://read.example.com", Some(RelayMetadata::Read)),
+            relay("wss://write.example.com", Some(RelayMetadata::Write)),
+            Tag::hashtag("ignored"),
+        ];
+        let event = EventBuilder::new(Kind::RelayList, "", tags)
+            .to_event(&Keys::generate())
+            .unwrap();
+
+        let relays = parse_relay_list(&event);
+        assert_eq!(relays.len(), 3);
+        assert_eq!(relays[0].marker, None);
+        assert_eq!(relays[1].marker.as_deref(), Some("read"));
+        assert_eq!(relays[2].marker.as_deref(), Some("write"));
+        assert!(relays[2].url.starts_with("wss://write.example.com"));
+    }
+}
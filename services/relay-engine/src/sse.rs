@@ -0,0 +1,268 @@
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use nostr::{Event, EventId, Filter, JsonUtil, Kind, PublicKey};
+use serde::Deserialize;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
+
+use crate::{
+    app_state::AppState, auth::nip98::Nip98Auth, client_ip, config::Config, database::RelayDatabase, nip42,
+    rate_limiter::RateLimiter,
+};
+
+/// Events buffered for SSE clients; a client further behind than this skips
+/// the events it missed
+pub const EVENT_FEED_CAPACITY: usize = 1024;
+
+/// Most stored events replayed to a client reconnecting with `Last-Event-ID`
+pub const MAX_REPLAY_EVENTS: usize = 500;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamQuery {
+    /// Comma-separated event kinds
+    pub kinds: Option<String>,
+    /// Comma-separated hex pubkeys
+    pub authors: Option<String>,
+}
+
+impl StreamQuery {
+    /// The filter live events must match; an empty query matches everything
+    pub fn to_filter(&self) -> Result<Filter, StatusCode> {
+        let mut filter = Filter::new();
+        if let Some(kinds) = &self.kinds {
+            let kinds = split_list(kinds)
+                .map(|kind| kind.parse::<u16>().map(Kind::from))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            filter = filter.kinds(kinds);
+        }
+        if let Some(authors) = &self.authors {
+            let authors = split_list(authors)
+                .map(PublicKey::from_hex)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            filter = filter.authors(authors);
+        }
+        Ok(filter)
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+// Each event is sent with its ID, so a reconnecting browser's `Last-Event-ID`
+// says where to resume
+fn sse_event(event: &Event) -> SseEvent {
+    SseEvent::default().id(event.id.to_hex()).data(event.as_json())
+}
+
+/// An SSE stream held against its IP's connection limit, given back when
+/// the stream is dropped
+struct StreamSlot {
+    rate_limiter: RateLimiter,
+    client_ip: IpAddr,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let (rate_limiter, client_ip) = (self.rate_limiter.clone(), self.client_ip);
+        tokio::spawn(async move {
+            let _ = rate_limiter.remove_connection(client_ip).await;
+        });
+    }
+}
+
+/// What an SSE client is sent: events matching its query that it may read
+/// under NIP-42
+struct StreamFilter {
+    filter: Filter,
+    reader: Option<PublicKey>,
+    config: Config,
+}
+
+impl StreamFilter {
+    fn matches(&self, event: &Event) -> bool {
+        self.filter.match_event(event) && nip42::can_read(event, self.reader.as_ref(), &self.config)
+    }
+}
+
+/// Stream events matching the query as they are delivered to subscribers. A
+/// client reconnecting with `Last-Event-ID` first gets the stored matching
+/// events published since that event.
+///
+/// Streams count against the per-IP connection and query limits like
+/// WebSocket connections do. There is no NIP-42 challenge over HTTP, so a
+/// client reads DMs and other protected kinds by signing the request under
+/// NIP-98, which a relay requiring auth insists on.
+pub async fn stream_events(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    reader: Option<Nip98Auth>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, StatusCode> {
+    if state.config.auth_required && reader.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let filter = StreamFilter {
+        filter: query.to_filter()?,
+        reader: reader.map(|reader| reader.pubkey),
+        config: state.config.clone(),
+    };
+
+    let peer = connect_info.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip());
+    let client_ip = client_ip::resolve_client_ip(peer, &headers, &state.config);
+    let slot = open_stream(&state, client_ip).await?;
+
+    // Subscribe before replaying so nothing published in between is missed
+    let receiver = state.event_feed.subscribe();
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|id| EventId::from_hex(id.trim()).ok());
+    let mut replayed = match last_event_id {
+        Some(last_event_id) => replay_since(&state, &filter.filter, &last_event_id).await,
+        None => Vec::new(),
+    };
+    replayed.retain(|event| filter.matches(event));
+
+    let live = stream::unfold((receiver, filter, slot), |(mut receiver, filter, slot)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if filter.matches(&event) => return Some((event, (receiver, filter, slot))),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => debug!("SSE client fell behind, skipped {} events", skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .take_until(state.shutdown.clone().cancelled_owned());
+
+    let events = stream::iter(replayed).chain(live).map(|event| Ok(sse_event(&event)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// Charge a new stream against the IP's query rate and take one of its connections
+async fn open_stream(state: &AppState, client_ip: IpAddr) -> Result<StreamSlot, StatusCode> {
+    let allowed = async {
+        Ok::<_, anyhow::Error>(
+            state.rate_limiter.check_query_rate(client_ip).await?
+                && state.rate_limiter.check_connection_limit(client_ip).await?,
+        )
+    };
+    match allowed.await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Refused SSE stream from {}: rate limited", client_ip);
+            state.metrics.record_rate_limit_connection();
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        Err(e) => {
+            error!("Failed to check SSE limits for {}: {}", client_ip, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let _ = state.rate_limiter.add_connection(client_ip).await;
+    Ok(StreamSlot {
+        rate_limiter: state.rate_limiter.clone(),
+        client_ip,
+    })
+}
+
+// Stored events matching `filter` created since `last_event_id`, oldest first
+// and at most the newest `MAX_REPLAY_EVENTS`. Nothing is replayed for an
+// unknown ID. Events sharing the last event's timestamp may be sent again.
+async fn replay_since(state: &AppState, filter: &Filter, last_event_id: &EventId) -> Vec<Event> {
+    let last_event = match state.database.get_event_by_id(last_event_id).await {
+        Ok(event) => event,
+        Err(e) => {
+            error!("Failed to look up SSE Last-Event-ID {}: {}", last_event_id, e);
+            None
+        }
+    };
+    let Some(last_event) = last_event else {
+        return Vec::new();
+    };
+
+    let since = filter.clone().since(last_event.created_at).limit(MAX_REPLAY_EVENTS);
+    match state.database.query_events(&since).await {
+        Ok(mut events) => {
+            events.retain(|event| event.id != last_event.id);
+            events.reverse();
+            events
+        }
+        Err(e) => {
+            error!("Failed to replay events for SSE client: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Router setup for the SSE feed
+pub fn create_sse_router() -> Router<AppState> {
+    Router::new().route("/stream/events", get(stream_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Tag};
+
+    #[test]
+    fn test_stream_query_to_filter() {
+        let keys = Keys::generate();
+        let query = StreamQuery {
+            kinds: Some("1, 7".to_string()),
+            authors: Some(keys.public_key().to_hex()),
+        };
+        let filter = query.to_filter().unwrap();
+
+        let note = EventBuilder::text_note("hello", []).to_event(&keys).unwrap();
+        let metadata = EventBuilder::new(Kind::Metadata, "{}", []).to_event(&keys).unwrap();
+        let other = EventBuilder::text_note("hello", []).to_event(&Keys::generate()).unwrap();
+        assert!(filter.match_event(&note));
+        assert!(!filter.match_event(&metadata));
+        assert!(!filter.match_event(&other));
+
+        assert!(StreamQuery::default().to_filter().unwrap().match_event(&other));
+    }
+
+    #[test]
+    fn test_stream_query_rejects_malformed_values() {
+        let bad_kind = StreamQuery { kinds: Some("1,note".to_string()), authors: None };
+        assert_eq!(bad_kind.to_filter().unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let bad_author = StreamQuery { kinds: None, authors: Some("abc".to_string()) };
+        assert_eq!(bad_author.to_filter().unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_stream_withholds_protected_kinds() {
+        let mut config = Config::from_env();
+        config.auth_required_read_kinds = vec![4];
+        let (author, recipient) = (Keys::generate(), Keys::generate());
+        let dm = EventBuilder::new(Kind::EncryptedDirectMessage, "ciphertext", [Tag::public_key(recipient.public_key())])
+            .to_event(&author)
+            .unwrap();
+        let stream = |reader: Option<PublicKey>| StreamFilter { filter: Filter::new(), reader, config: config.clone() };
+
+        assert!(!stream(None).matches(&dm));
+        assert!(!stream(Some(Keys::generate().public_key())).matches(&dm));
+        assert!(stream(Some(recipient.public_key())).matches(&dm));
+        assert!(stream(None).matches(&EventBuilder::text_note("hello", []).to_event(&author).unwrap()));
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use nostr::{Event, Filter, PublicKey, RelayMessage, SubscriptionId};
+use tracing::debug;
+
+use crate::app_state::{AppState, ConnectedClient};
+use crate::database::RelayDatabase;
+use crate::nip42;
+use crate::subscription_index::SubscriptionKey;
+use crate::validation;
+
+/// CLOSED reason sent once a subscription has received its `limit` of live events
+pub const LIMIT_REACHED: &str = "reason: limit reached";
+
+/// CLOSED reason sent for a subscription replaced by a REQ reusing its ID
+pub const REPLACED: &str = "duplicate: subscription replaced";
+
+/// How a REQ's filters were stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registration {
+    Added,
+    /// An open subscription with the same ID was replaced
+    Replaced,
+    /// The client already has `max_subscriptions` open; nothing was stored
+    TooMany,
+    /// The client's pubkey already has `max_subscriptions_per_pubkey` open
+    /// across its connections; nothing was stored
+    TooManyForPubkey,
+}
+
+/// How many live events a subscription may still be sent. Without it, a
+/// client that forgets to CLOSE a `limit`ed subscription is streamed to forever.
+#[derive(Debug)]
+pub struct SubscriptionLimit {
+    limit: u64,
+    delivered: AtomicU64,
+}
+
+/// What to do with the next matching event for a limited subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Send,
+    /// Send it, then close the subscription
+    SendAndClose,
+    /// The limit was already reached
+    Skip,
+}
+
+impl SubscriptionLimit {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            delivered: AtomicU64::new(0),
+        }
+    }
+
+    /// The limit of a subscription with these filters: the largest `limit`
+    /// among them. Subscriptions with an unlimited filter, or one asking only
+    /// for new events with `limit: 0`, stream until the client closes them.
+    pub fn for_filters(filters: &[Filter]) -> Option<Self> {
+        filters
+            .iter()
+            .map(|filter| filter.limit.filter(|limit| *limit > 0))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .max()
+            .map(|limit| Self::new(limit as u64))
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Count one more event towards the limit
+    pub fn record_delivery(&self) -> Delivery {
+        let delivered = self.delivered.fetch_add(1, Ordering::Relaxed) + 1;
+        match delivered.cmp(&self.limit) {
+            std::cmp::Ordering::Less => Delivery::Send,
+            std::cmp::Ordering::Equal => Delivery::SendAndClose,
+            std::cmp::Ordering::Greater => Delivery::Skip,
+        }
+    }
+}
+
+/// Number of distinct subscription IDs held by a client (filters are keyed `<sub_id>:<index>`)
+pub fn subscription_count(client_subs: &DashMap<String, Filter>) -> usize {
+    client_subs
+        .iter()
+        .filter_map(|entry| entry.key().rsplit_once(':').map(|(sub_id, _)| sub_id.to_string()))
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Whether a filter key `<sub_id>:<index>` belongs to `subscription_id`.
+/// Subscription IDs may themselves contain `:`, so the ID is compared whole.
+fn is_filter_of(key: &str, subscription_id: &str) -> bool {
+    key.rsplit_once(':').is_some_and(|(sub_id, _)| sub_id == subscription_id)
+}
+
+/// Store a REQ's filters under `subscription_id`. NIP-01: a REQ reusing the ID
+/// of an open subscription replaces it, so the old filters are dropped first.
+///
+/// Subscriptions of an authenticated client, whose hex `pubkey` is given, also
+/// count against that pubkey's limit across all its connections.
+pub fn register_subscription<D: RelayDatabase>(
+    state: &AppState<D>,
+    client_id: &str,
+    subscription_id: &str,
+    filters: &[Filter],
+    pubkey: Option<&str>,
+) -> Registration {
+    // Replacing a subscription already counted against the pubkey doesn't take another slot
+    let claimed = match pubkey {
+        Some(pubkey) if counted_pubkey(state, client_id, subscription_id).as_deref() != Some(pubkey) => {
+            if !claim_pubkey_slot(state, pubkey) {
+                return Registration::TooManyForPubkey;
+            }
+            Some(pubkey)
+        }
+        _ => None,
+    };
+
+    let registration = {
+        let client_subs = state.subscriptions.entry(client_id.to_string()).or_default();
+
+        let before_count = client_subs.len();
+        client_subs.retain(|key, _| !is_filter_of(key, subscription_id));
+        let replaced_count = before_count - client_subs.len();
+
+        // Enforce the per-connection subscription limit for new subscription IDs
+        if replaced_count == 0 && subscription_count(&client_subs) >= state.config.max_subscriptions {
+            if let Some(pubkey) = claimed {
+                release_pubkey_slot(state, pubkey);
+            }
+            return Registration::TooMany;
+        }
+
+        for _ in 0..replaced_count {
+            state.metrics.record_subscription_end();
+        }
+        for (i, filter) in filters.iter().enumerate() {
+            client_subs.insert(format!("{}:{}", subscription_id, i), filter.clone());
+        }
+
+        if replaced_count > 0 {
+            Registration::Replaced
+        } else {
+            Registration::Added
+        }
+    };
+
+    if let Some(pubkey) = claimed {
+        release_counted_pubkey(state, client_id, subscription_id);
+        state
+            .subscription_pubkeys
+            .entry(client_id.to_string())
+            .or_default()
+            .insert(subscription_id.to_string(), pubkey.to_string());
+    }
+
+    state
+        .subscription_index
+        .write()
+        .unwrap()
+        .add_subscription(client_id, subscription_id, filters);
+
+    // A limited subscription is closed once it has streamed `limit` live events
+    let client_limits = state.subscription_limits.entry(client_id.to_string()).or_default();
+    match SubscriptionLimit::for_filters(filters) {
+        Some(limit) => client_limits.insert(subscription_id.to_string(), limit),
+        None => client_limits.remove(subscription_id).map(|(_, limit)| limit),
+    };
+
+    registration
+}
+
+/// Remove one of a client's subscriptions, returning how many filters it had
+pub fn remove_subscription<D: RelayDatabase>(state: &AppState<D>, client_id: &str, subscription_id: &str) -> usize {
+    release_counted_pubkey(state, client_id, subscription_id);
+    if let Some(limits) = state.subscription_limits.get(client_id) {
+        limits.remove(subscription_id);
+    }
+    state.subscription_index.write().unwrap().remove_subscription(client_id, subscription_id);
+
+    let Some(client_subs) = state.subscriptions.get(client_id) else {
+        return 0;
+    };
+    let before_count = client_subs.len();
+    client_subs.retain(|key, _| !is_filter_of(key, subscription_id));
+    let removed_count = before_count - client_subs.len();
+
+    // Update metrics for each removed subscription
+    for _ in 0..removed_count {
+        state.metrics.record_subscription_end();
+    }
+    removed_count
+}
+
+/// Remove every subscription of a disconnected client
+pub fn cleanup_client_subscriptions<D: RelayDatabase>(state: &AppState<D>, client_id: &str) {
+    state.subscription_limits.remove(client_id);
+    release_client_pubkey_slots(state, client_id);
+    state.subscription_index.write().unwrap().remove_client(client_id);
+    if let Some((_, client_subs)) = state.subscriptions.remove(client_id) {
+        // Update metrics for all removed subscriptions
+        for _ in 0..client_subs.len() {
+            state.metrics.record_subscription_end();
+        }
+        debug!("Cleaned up {} subscriptions for client {}", client_subs.len(), client_id);
+    }
+}
+
+/// Give back the pubkey subscription slots held by a disconnected client
+pub fn release_client_pubkey_slots<D: RelayDatabase>(state: &AppState<D>, client_id: &str) {
+    if let Some((_, counted)) = state.subscription_pubkeys.remove(client_id) {
+        for (_, pubkey) in counted {
+            release_pubkey_slot(state, &pubkey);
+        }
+    }
+}
+
+/// Subscriptions open for a hex pubkey across all its connections
+pub fn pubkey_subscription_count<D: RelayDatabase>(state: &AppState<D>, pubkey: &str) -> usize {
+    state
+        .pubkey_subscription_counts
+        .get(pubkey)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+fn counted_pubkey<D: RelayDatabase>(state: &AppState<D>, client_id: &str, subscription_id: &str) -> Option<String> {
+    state
+        .subscription_pubkeys
+        .get(client_id)?
+        .get(subscription_id)
+        .map(|pubkey| pubkey.clone())
+}
+
+// Take one of the pubkey's subscription slots, unless they're all in use
+fn claim_pubkey_slot<D: RelayDatabase>(state: &AppState<D>, pubkey: &str) -> bool {
+    let max = state.config.max_subscriptions_per_pubkey;
+    state
+        .pubkey_subscription_counts
+        .entry(pubkey.to_string())
+        .or_default()
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| (open < max).then_some(open + 1))
+        .is_ok()
+}
+
+fn release_pubkey_slot<D: RelayDatabase>(state: &AppState<D>, pubkey: &str) {
+    if let Some(count) = state.pubkey_subscription_counts.get(pubkey) {
+        let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| open.checked_sub(1));
+    }
+    // Pubkeys with nothing open aren't kept around
+    state
+        .pubkey_subscription_counts
+        .remove_if(pubkey, |_, count| count.load(Ordering::Relaxed) == 0);
+}
+
+// Release the slot a subscription holds, if it was opened by an authenticated client
+fn release_counted_pubkey<D: RelayDatabase>(state: &AppState<D>, client_id: &str, subscription_id: &str) {
+    let pubkey = state
+        .subscription_pubkeys
+        .get(client_id)
+        .and_then(|counted| counted.remove(subscription_id))
+        .map(|(_, pubkey)| pubkey);
+    if let Some(pubkey) = pubkey {
+        release_pubkey_slot(state, &pubkey);
+    }
+}
+
+/// Queue a live event for one of a client's subscriptions. When that uses up
+/// the subscription's limit, it is removed and the client is sent CLOSED.
+/// Events the client may not read under NIP-42 are skipped.
+pub fn deliver_event<D: RelayDatabase>(state: &AppState<D>, client: &ConnectedClient, client_id: &str, subscription_id: &str, event: &Event) {
+    if !nip42::can_read(event, client.authenticated_pubkey().as_ref(), &state.config) {
+        return;
+    }
+
+    let (delivery, limit) = state
+        .subscription_limits
+        .get(client_id)
+        .and_then(|limits| limits.get(subscription_id).map(|limit| (limit.record_delivery(), limit.limit())))
+        .unwrap_or((Delivery::Send, 0));
+    if delivery == Delivery::Skip {
+        return;
+    }
+
+    let message = RelayMessage::Event {
+        subscription_id: SubscriptionId::new(subscription_id),
+        event: Box::new(event.clone()),
+    };
+    if !client.sender.try_send(message) {
+        debug!("Dropping event {} for client {}: outbound queue full or closed", event.id, client_id);
+    }
+
+    if delivery == Delivery::SendAndClose {
+        debug!("Subscription {} of client {} reached its limit of {} events", subscription_id, client_id, limit);
+        remove_subscription(state, client_id, subscription_id);
+        client.sender.try_send(RelayMessage::Closed {
+            subscription_id: SubscriptionId::new(subscription_id),
+            message: LIMIT_REACHED.to_string(),
+        });
+    }
+}
+
+/// Whether a live event matches `filter`. rust-nostr's `match_event` ignores
+/// NIP-50 `search`; live events use a case-insensitive substring match on
+/// content. An author filter also matches events delegated by one of its
+/// authors (NIP-26), as stored events do.
+pub fn filter_matches(filter: &Filter, event: &Event, delegator: Option<&PublicKey>) -> bool {
+    let matched = filter.match_event(event)
+        || delegator.is_some_and(|delegator| {
+            filter.authors.as_ref().is_some_and(|authors| authors.contains(delegator))
+                && Filter { authors: None, ..filter.clone() }.match_event(event)
+        });
+
+    matched
+        && filter
+            .search
+            .as_ref()
+            .is_none_or(|search| event.content.to_lowercase().contains(&search.to_lowercase()))
+}
+
+/// Send an event to every other connected client with a matching subscription
+pub async fn broadcast_event_to_subscribers<D: RelayDatabase>(event: &Event, state: &AppState<D>, own_client_id: &str) {
+    // Sending fails only when no SSE client is listening
+    let _ = state.event_feed.send(event.clone());
+
+    // Only the subscriptions the index offers as candidates have their filters
+    // run. Matches are collected first so the index lock isn't held while sending.
+    let delegator = validation::verified_delegator(event).ok().flatten();
+    let matches: Vec<SubscriptionKey> = state
+        .subscription_index
+        .read()
+        .unwrap()
+        .matching(event, delegator.as_ref(), |filter, event| filter_matches(filter, event, delegator.as_ref()))
+        .into_iter()
+        .filter(|key| key.client_id != own_client_id)
+        .collect();
+
+    let clients = state.clients.read().await;
+    for key in matches {
+        if let Some(client) = clients.get(&key.client_id) {
+            deliver_event(state, client, &key.client_id, &key.subscription_id, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_from_filters() {
+        let limit = |filters: &[Filter]| SubscriptionLimit::for_filters(filters).map(|limit| limit.limit());
+
+        assert_eq!(limit(&[Filter::new().limit(3)]), Some(3));
+        assert_eq!(limit(&[Filter::new().limit(3), Filter::new().limit(10)]), Some(10));
+        assert_eq!(limit(&[Filter::new().limit(3), Filter::new()]), None);
+        assert_eq!(limit(&[Filter::new().limit(0)]), None);
+        assert_eq!(limit(&[Filter::new()]), None);
+    }
+
+    #[test]
+    fn test_record_delivery() {
+        let limit = SubscriptionLimit::new(2);
+
+        assert_eq!(limit.record_delivery(), Delivery::Send);
+        assert_eq!(limit.record_delivery(), Delivery::SendAndClose);
+        assert_eq!(limit.record_delivery(), Delivery::Skip);
+    }
+
+    #[test]
+    fn test_filter_keys_match_whole_subscription_ids() {
+        assert!(is_filter_of("feed:0", "feed"));
+        assert!(is_filter_of("feed:12", "feed"));
+        assert!(is_filter_of("a:b:0", "a:b"));
+        assert!(!is_filter_of("a:b:0", "a"));
+        assert!(!is_filter_of("feed2:0", "feed"));
+        assert!(!is_filter_of("feed", "feed"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, HashSet};
+
+use nostr::{Event, Filter, PublicKey};
+
+/// One client's subscription, as the index refers to it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionKey {
+    pub client_id: String,
+    pub subscription_id: String,
+}
+
+impl SubscriptionKey {
+    pub fn new(client_id: &str, subscription_id: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            subscription_id: subscription_id.to_string(),
+        }
+    }
+}
+
+/// Open subscriptions inverted by event kind and author, so a broadcast only
+/// runs the filters that could match an event instead of every filter.
+///
+/// Each filter is indexed once: under its authors when it names any, else
+/// under its kinds, else in `catch_all`. A filter indexed under other authors
+/// or kinds can't match the event, so an event's candidates are its author's
+/// entry, its kind's entry and `catch_all`, plus the NIP-26 delegator's entry
+/// for a delegated event. Subscriptions are referred to by
+/// slot number inside the index, which keeps the sets cheap to hash.
+#[derive(Debug, Default)]
+pub struct SubscriptionIndex {
+    /// Indexed subscriptions by slot; removed ones leave a `None` for reuse
+    slots: Vec<Option<(SubscriptionKey, Vec<Filter>)>>,
+    free_slots: Vec<usize>,
+    slot_by_key: HashMap<SubscriptionKey, usize>,
+    /// Subscription IDs by client, for dropping a client's subscriptions at once
+    clients: HashMap<String, HashSet<String>>,
+    kind_index: HashMap<u64, HashSet<usize>>,
+    author_index: HashMap<String, HashSet<usize>>,
+    catch_all: HashSet<usize>,
+}
+
+impl SubscriptionIndex {
+    /// Index a subscription's filters, replacing any it already had
+    pub fn add_subscription(&mut self, client_id: &str, subscription_id: &str, filters: &[Filter]) {
+        let key = SubscriptionKey::new(client_id, subscription_id);
+        self.remove(&key);
+
+        let slot = self.free_slots.pop().unwrap_or_else(|| {
+            self.slots.push(None);
+            self.slots.len() - 1
+        });
+        for filter in filters {
+            match (&filter.authors, &filter.kinds) {
+                (Some(authors), _) if !authors.is_empty() => {
+                    for author in authors {
+                        self.author_index.entry(author.to_hex()).or_default().insert(slot);
+                    }
+                }
+                (_, Some(kinds)) if !kinds.is_empty() => {
+                    for kind in kinds {
+                        self.kind_index.entry(kind.as_u64()).or_default().insert(slot);
+                    }
+                }
+                _ => {
+                    self.catch_all.insert(slot);
+                }
+            }
+        }
+
+        self.clients
+            .entry(client_id.to_string())
+            .or_default()
+            .insert(subscription_id.to_string());
+        self.slot_by_key.insert(key.clone(), slot);
+        self.slots[slot] = Some((key, filters.to_vec()));
+    }
+
+    pub fn remove_subscription(&mut self, client_id: &str, subscription_id: &str) {
+        self.remove(&SubscriptionKey::new(client_id, subscription_id));
+    }
+
+    /// Drop every subscription of a disconnected client
+    pub fn remove_client(&mut self, client_id: &str) {
+        let Some(subscription_ids) = self.clients.remove(client_id) else {
+            return;
+        };
+        for subscription_id in subscription_ids {
+            self.remove(&SubscriptionKey::new(client_id, &subscription_id));
+        }
+    }
+
+    fn remove(&mut self, key: &SubscriptionKey) {
+        let Some(slot) = self.slot_by_key.remove(key) else {
+            return;
+        };
+        let Some((_, filters)) = self.slots[slot].take() else {
+            return;
+        };
+        self.free_slots.push(slot);
+
+        if let Some(subscription_ids) = self.clients.get_mut(&key.client_id) {
+            subscription_ids.remove(&key.subscription_id);
+            if subscription_ids.is_empty() {
+                self.clients.remove(&key.client_id);
+            }
+        }
+
+        for filter in &filters {
+            for author in filter.authors.iter().flatten() {
+                remove_from(&mut self.author_index, &author.to_hex(), slot);
+            }
+            for kind in filter.kinds.iter().flatten() {
+                remove_from(&mut self.kind_index, &kind.as_u64(), slot);
+            }
+        }
+        self.catch_all.remove(&slot);
+    }
+
+    /// Slots of the subscriptions that may match `event`: a superset of the
+    /// matching ones, possibly with repeats
+    fn candidates<'a>(&'a self, event: &Event, delegator: Option<&PublicKey>) -> impl Iterator<Item = usize> + 'a {
+        let by_author = self.author_index.get(&event.pubkey.to_hex());
+        let by_delegator = delegator.and_then(|delegator| self.author_index.get(&delegator.to_hex()));
+        let by_kind = self.kind_index.get(&event.kind.as_u64());
+        by_author
+            .into_iter()
+            .chain(by_delegator)
+            .chain(by_kind)
+            .flatten()
+            .chain(&self.catch_all)
+            .copied()
+    }
+
+    /// Subscriptions with a filter for which `matches` holds, checking only
+    /// the candidates for `event`. `delegator` is the event's verified NIP-26
+    /// delegator, if any.
+    pub fn matching<F>(&self, event: &Event, delegator: Option<&PublicKey>, matches: F) -> Vec<SubscriptionKey>
+    where
+        F: Fn(&Filter, &Event) -> bool,
+    {
+        let mut seen = vec![false; self.slots.len()];
+        let mut matched = Vec::new();
+        for slot in self.candidates(event, delegator) {
+            if std::mem::replace(&mut seen[slot], true) {
+                continue;
+            }
+            if let Some((key, filters)) = &self.slots[slot] {
+                if filters.iter().any(|filter| matches(filter, event)) {
+                    matched.push(key.clone());
+                }
+            }
+        }
+        matched
+    }
+}
+
+fn remove_from<K>(index: &mut HashMap<K, HashSet<usize>>, entry: &K, slot: usize)
+where
+    K: std::hash::Hash + Eq,
+{
+    if let Some(slots) = index.get_mut(entry) {
+        slots.remove(&slot);
+        if slots.is_empty() {
+            index.remove(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys, Kind};
+
+    fn matching(index: &SubscriptionIndex, event: &Event) -> Vec<String> {
+        let mut ids: Vec<_> = index
+            .matching(event, None, Filter::match_event)
+            .into_iter()
+            .map(|key| key.subscription_id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_index_finds_matching_subscriptions() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let mut index = SubscriptionIndex::default();
+        index.add_subscription("c1", "alice", &[Filter::new().author(alice.public_key())]);
+        index.add_subscription("c1", "alice-notes", &[Filter::new().author(alice.public_key()).kind(Kind::TextNote)]);
+        index.add_subscription("c2", "notes", &[Filter::new().kind(Kind::TextNote)]);
+        index.add_subscription("c2", "reactions", &[Filter::new().kind(Kind::Reaction)]);
+        index.add_subscription("c3", "everything", &[Filter::new()]);
+        index.add_subscription("c3", "bob-or-reactions", &[Filter::new().author(bob.public_key()), Filter::new().kind(Kind::Reaction)]);
+
+        let note = EventBuilder::text_note("hi", []).to_event(&alice).unwrap();
+        assert_eq!(matching(&index, &note), vec!["alice", "alice-notes", "everything", "notes"]);
+
+        let reaction = EventBuilder::new(Kind::Reaction, "+", []).to_event(&alice).unwrap();
+        assert_eq!(matching(&index, &reaction), vec!["alice", "bob-or-reactions", "everything", "reactions"]);
+
+        let metadata = EventBuilder::new(Kind::Metadata, "{}", []).to_event(&bob).unwrap();
+        assert_eq!(matching(&index, &metadata), vec!["bob-or-reactions", "everything"]);
+    }
+
+    #[test]
+    fn test_candidates_skip_other_authors_and_kinds() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let mut index = SubscriptionIndex::default();
+        index.add_subscription("c1", "bob", &[Filter::new().author(bob.public_key()).kind(Kind::TextNote)]);
+        index.add_subscription("c1", "reactions", &[Filter::new().kind(Kind::Reaction)]);
+
+        let note = EventBuilder::text_note("hi", []).to_event(&alice).unwrap();
+        assert_eq!(index.candidates(&note, None).count(), 0);
+    }
+
+    #[test]
+    fn test_candidates_include_delegator_subscriptions() {
+        let delegator = Keys::generate();
+        let delegatee = Keys::generate();
+        let mut index = SubscriptionIndex::default();
+        index.add_subscription("c1", "delegator", &[Filter::new().author(delegator.public_key())]);
+
+        let note = EventBuilder::text_note("hi", []).to_event(&delegatee).unwrap();
+        assert_eq!(index.candidates(&note, None).count(), 0);
+        assert_eq!(index.candidates(&note, Some(&delegator.public_key())).count(), 1);
+    }
+
+    #[test]
+    fn test_replacing_and_removing_subscriptions() {
+        let alice = Keys::generate();
+        let note = EventBuilder::text_note("hi", []).to_event(&alice).unwrap();
+        let mut index = SubscriptionIndex::default();
+
+        index.add_subscription("c1", "feed", &[Filter::new().kind(Kind::TextNote)]);
+        index.add_subscription("c1", "feed", &[Filter::new().kind(Kind::Reaction)]);
+        assert_eq!(index.slot_by_key.len(), 1);
+        assert!(matching(&index, &note).is_empty());
+
+        index.add_subscription("c1", "all", &[Filter::new()]);
+        index.add_subscription("c2", "all", &[Filter::new()]);
+        index.remove_subscription("c1", "all");
+        assert_eq!(index.matching(&note, None, Filter::match_event), vec![SubscriptionKey::new("c2", "all")]);
+
+        index.remove_client("c1");
+        index.remove_client("c2");
+        assert!(index.slot_by_key.is_empty() && index.slots.iter().all(Option::is_none));
+        assert!(index.kind_index.is_empty() && index.author_index.is_empty() && index.catch_all.is_empty());
+        assert!(index.clients.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use axum::{response::Json, routing::get, Router};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    GaugeVec, IntGaugeVec, Opts,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+use tokio_metrics::{RuntimeIntervals, RuntimeMonitor, TaskMonitorCore};
+
+/// WebSocket connection tasks, from upgrade to close
+pub static WEBSOCKET_TASKS: TaskMonitorCore = TaskMonitorCore::new();
+
+/// Handling of a single client message, from parsing to the last reply
+pub static CLIENT_MESSAGE_TASKS: TaskMonitorCore = TaskMonitorCore::new();
+
+// Metrics of the runtime since the previous `/metrics/tokio` request
+static RUNTIME_INTERVALS: OnceLock<Mutex<RuntimeIntervals>> = OnceLock::new();
+
+/// Monitored task types by the label they are reported under
+pub fn task_monitors() -> [(&'static str, &'static TaskMonitorCore); 2] {
+    [("websocket", &WEBSOCKET_TASKS), ("client_message", &CLIENT_MESSAGE_TASKS)]
+}
+
+/// Start sampling the current runtime for `/metrics/tokio`. Later calls do nothing.
+pub fn start_runtime_monitor() {
+    RUNTIME_INTERVALS.get_or_init(|| {
+        let monitor = RuntimeMonitor::new(&tokio::runtime::Handle::current());
+        Mutex::new(monitor.intervals())
+    });
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskTypeMetrics {
+    /// Tasks instrumented so far
+    pub instrumented_count: u64,
+    /// Tasks that have completed or been dropped
+    pub dropped_count: u64,
+    pub mean_poll_seconds: f64,
+    pub mean_idle_seconds: f64,
+    pub slow_poll_ratio: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuntimeSnapshot {
+    pub workers_count: usize,
+    pub live_tasks_count: usize,
+    pub global_queue_depth: usize,
+    /// Share of the interval the workers spent busy
+    pub busy_ratio: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokioMetricsResponse {
+    /// Runtime metrics since the previous request; absent until the monitor is started
+    pub runtime: Option<RuntimeSnapshot>,
+    /// Cumulative metrics per monitored task type
+    pub tasks: BTreeMap<String, TaskTypeMetrics>,
+}
+
+/// Current metrics of the runtime and every monitored task type
+pub fn snapshot() -> TokioMetricsResponse {
+    let runtime = RUNTIME_INTERVALS
+        .get()
+        .and_then(|intervals| intervals.lock().unwrap().next())
+        .map(|metrics| RuntimeSnapshot {
+            workers_count: metrics.workers_count,
+            live_tasks_count: metrics.live_tasks_count,
+            global_queue_depth: metrics.global_queue_depth,
+            busy_ratio: metrics.busy_ratio(),
+        });
+
+    let tasks = task_monitors()
+        .into_iter()
+        .map(|(task, monitor)| {
+            let metrics = monitor.cumulative();
+            let task_metrics = TaskTypeMetrics {
+                instrumented_count: metrics.instrumented_count,
+                dropped_count: metrics.dropped_count,
+                mean_poll_seconds: metrics.mean_poll_duration().as_secs_f64(),
+                mean_idle_seconds: metrics.mean_idle_duration().as_secs_f64(),
+                slow_poll_ratio: metrics.slow_poll_ratio(),
+            };
+            (task.to_string(), task_metrics)
+        })
+        .collect();
+
+    TokioMetricsResponse { runtime, tasks }
+}
+
+async fn tokio_metrics_handler() -> Json<TokioMetricsResponse> {
+    Json(snapshot())
+}
+
+// Router setup for the async task metrics endpoint; it reads no relay state
+pub fn create_tokio_metrics_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new().route("/metrics/tokio", get(tokio_metrics_handler))
+}
+
+/// Reports the monitored task types to Prometheus, read fresh on every scrape
+pub struct TaskMetricsCollector {
+    instrumented: IntGaugeVec,
+    dropped: IntGaugeVec,
+    mean_poll: GaugeVec,
+    mean_idle: GaugeVec,
+}
+
+impl TaskMetricsCollector {
+    pub fn new() -> prometheus::Result<Self> {
+        Ok(Self {
+            instrumented: IntGaugeVec::new(
+                Opts::new("relay_tokio_tasks_instrumented", "Async tasks instrumented so far, by task type"),
+                &["task"],
+            )?,
+            dropped: IntGaugeVec::new(
+                Opts::new("relay_tokio_tasks_dropped", "Async tasks completed or dropped, by task type"),
+                &["task"],
+            )?,
+            mean_poll: GaugeVec::new(
+                Opts::new("relay_tokio_task_mean_poll_seconds", "Mean time an async task spends in a poll, by task type"),
+                &["task"],
+            )?,
+            mean_idle: GaugeVec::new(
+                Opts::new("relay_tokio_task_mean_idle_seconds", "Mean time an async task waits to be woken, by task type"),
+                &["task"],
+            )?,
+        })
+    }
+}
+
+impl Collector for TaskMetricsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        [
+            self.instrumented.desc(),
+            self.dropped.desc(),
+            self.mean_poll.desc(),
+            self.mean_idle.desc(),
+        ]
+        .concat()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        for (task, monitor) in task_monitors() {
+            let metrics = monitor.cumulative();
+            self.instrumented.with_label_values(&[task]).set(metrics.instrumented_count as i64);
+            self.dropped.with_label_values(&[task]).set(metrics.dropped_count as i64);
+            self.mean_poll.with_label_values(&[task]).set(metrics.mean_poll_duration().as_secs_f64());
+            self.mean_idle.with_label_values(&[task]).set(metrics.mean_idle_duration().as_secs_f64());
+        }
+
+        [
+            self.instrumented.collect(),
+            self.dropped.collect(),
+            self.mean_poll.collect(),
+            self.mean_idle.collect(),
+        ]
+        .concat()
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::{
+    config::{Config, LogFormat, LogRotation},
+    latency::LatencyLayer,
+    metrics::Metrics,
+};
+
+/// Install the global subscriber: span latencies recorded to `metrics`, log
+/// lines filtered by `RUST_LOG`, or the configured log level when it is unset,
+/// in the configured format and to the configured file or stdout, plus span
+/// export over OTLP when built with `tracing-otlp` and an endpoint is set.
+///
+/// The filter applies to logs and exported spans only, so latencies are
+/// recorded whatever the log level.
+///
+/// Logs written to a file go through a background writer; keep the returned
+/// guard alive until shutdown so buffered lines are flushed.
+pub fn init(config: &Config, metrics: &Metrics) -> anyhow::Result<Option<WorkerGuard>> {
+    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.log_level))?;
+    let (log_layer, guard) = match &config.log_file {
+        Some(path) => {
+            let (writer, guard) = tracing_appender::non_blocking(rolling_file(path, LogRotation::Daily)?);
+            (log_layer(config.log_format, writer, false), Some(guard))
+        }
+        None => (log_layer(config.log_format, std::io::stdout, true), None),
+    };
+    let registry = tracing_subscriber::registry().with(LatencyLayer::new(metrics.clone()));
+
+    #[cfg(feature = "tracing-otlp")]
+    if let Ok(endpoint) = std::env::var(otlp::ENDPOINT_ENV) {
+        let tracer = otlp::tracer(&endpoint)?;
+        let export_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+        registry.with(log_layer.and_then(export_layer).with_filter(filter)).try_init()?;
+        tracing::info!("Exporting traces to {}", endpoint);
+        return Ok(guard);
+    }
+
+    registry.with(log_layer.with_filter(filter)).try_init()?;
+    Ok(guard)
+}
+
+/// The formatting layer for `format`, writing to `writer`, with terminal colors
+/// when `ansi` is set. JSON lines carry the current span and every span it is
+/// nested in, so fields such as a connection's `request_id` appear on each
+/// line logged for it.
+pub fn log_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().with_current_span(true).with_span_list(true).boxed(),
+    }
+}
+
+/// Rotated log files named after `path`, e.g. `relay.log.2024-01-31` when
+/// rotated daily, creating its directory if needed
+pub fn rolling_file(path: &Path, rotation: LogRotation) -> anyhow::Result<tracing_appender::rolling::RollingFileAppender> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("log file path {} has no file name", path.display()))?;
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    std::fs::create_dir_all(directory)?;
+    let rotation = match rotation {
+        LogRotation::Daily => tracing_appender::rolling::Rotation::DAILY,
+        LogRotation::Hourly => tracing_appender::rolling::Rotation::HOURLY,
+    };
+    Ok(tracing_appender::rolling::RollingFileAppender::new(rotation, directory, file_name))
+}
+
+/// Flush spans still waiting in the export batch
+pub fn shutdown() {
+    #[cfg(feature = "tracing-otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "tracing-otlp")]
+mod otlp {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+
+    /// Collector endpoint; spans are only exported when it is set
+    pub const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+    /// Service name reported on exported spans
+    pub const SERVICE_NAME: &str = "relay-engine";
+
+    pub fn tracer(endpoint: &str) -> anyhow::Result<trace::Tracer> {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(
+                trace::config().with_resource(Resource::new(vec![KeyValue::new("service.name", SERVICE_NAME)])),
+            )
+            .install_batch(runtime::Tokio)?;
+        Ok(tracer)
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing::info_span;
+    use tracing_subscriber::Registry;
+
+    // Collects everything written by the log layer
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_lines(format: LogFormat) -> String {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = Registry::default().with(log_layer(format, move || writer.clone(), false));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _connection = info_span!("connection", request_id = "req-1").entered();
+            let _message = info_span!("handle_client_message", message_type = "REQ").entered();
+            tracing::info!("subscription opened");
+        });
+
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_json_lines_carry_the_span_list() {
+        let output = log_lines(LogFormat::Json);
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!(line["fields"]["message"], "subscription opened");
+        assert_eq!(line["span"]["name"], "handle_client_message");
+        assert_eq!(line["spans"][0]["name"], "connection");
+        assert_eq!(line["spans"][0]["request_id"], "req-1");
+        assert_eq!(line["spans"][1]["message_type"], "REQ");
+    }
+
+    #[test]
+    fn test_text_lines_name_the_spans() {
+        let output = log_lines(LogFormat::Text);
+        assert!(output.contains("connection{request_id=\"req-1\"}"));
+        assert!(output.contains("subscription opened"));
+        assert!(serde_json::from_str::<serde_json::Value>(output.trim()).is_err());
+    }
+}
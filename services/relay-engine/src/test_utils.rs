@@ -1,23 +1,47 @@
-use crate::{config::Config, database::PostgresDatabase, metrics::Metrics, rate_limiter::{RateLimiter, RateLimitConfig}, app_state::AppState};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
+use crate::{config::Config, database::RelayDatabase, mock_database::MockDatabase, metrics::Metrics, rate_limiter::{RateLimiter, RateLimitConfig}, app_state::AppState, validation::new_sig_cache, sse::EVENT_FEED_CAPACITY};
+use dashmap::DashMap;
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+use tokio::{sync::{broadcast, RwLock}, task::JoinSet};
+use tokio_util::sync::CancellationToken;
 
-/// Create a test AppState for development and testing
-pub async fn create_mock_app_state() -> anyhow::Result<AppState> {
+/// Create a test AppState backed by the in-memory `MockDatabase`, so it needs
+/// no database to be running
+pub async fn create_mock_app_state() -> anyhow::Result<AppState<MockDatabase>> {
+    create_app_state_with(MockDatabase::new())
+}
+
+/// Create a test AppState around `database`, configured from the environment
+/// with default rate limits
+pub fn create_app_state_with<D: RelayDatabase>(database: D) -> anyhow::Result<AppState<D>> {
     let config = Config::from_env();
     let metrics = Metrics::new()?;
     let rate_limit_config = RateLimitConfig::default();
     let rate_limiter = RateLimiter::new(rate_limit_config);
-    
-    // Create in-memory database for testing
-    let database = PostgresDatabase::new("sqlite::memory:").await?;
-    database.create_tables().await?;
-    
+
     Ok(AppState {
         database,
-        subscriptions: Arc::new(RwLock::new(HashMap::new())),
+        subscriptions: Arc::new(DashMap::new()),
+        subscription_limits: Arc::new(DashMap::new()),
+        subscription_index: Arc::default(),
+        clients: Arc::new(RwLock::new(HashMap::new())),
         rate_limiter,
         metrics,
+        sig_cache: new_sig_cache(config.sig_cache_size),
+        shutdown: CancellationToken::new(),
+        connections: Arc::new(Mutex::new(JoinSet::new())),
+        open_connections: Arc::default(),
+        fanout: None,
+        pubkey_blocklist: Arc::new(RwLock::new(HashSet::new())),
+        pubkey_relays: Arc::new(RwLock::new(HashMap::new())),
+        event_batcher: None,
+        event_feed: broadcast::channel(EVENT_FEED_CAPACITY).0,
+        audit_log: None,
+        mutable_config: Arc::new(RwLock::new(config.clone())),
+        http_auth_nonces: Arc::default(),
+        pubkey_subscription_counts: Arc::default(),
+        subscription_pubkeys: Arc::default(),
+        force_deleted_events: Arc::default(),
+        content_filters: Arc::new(Vec::new()),
         config,
     })
 }
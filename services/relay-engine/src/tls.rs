@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Context, Result};
+use axum::{extract::ConnectInfo, Router};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request};
+use hyper_util::rt::TokioIo;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, UnixTime},
+    ServerConfig,
+};
+use std::{fs::File, io::BufReader, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, time::timeout};
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, warn};
+
+use crate::config::TlsConfig;
+
+/// How long a client gets to complete the TLS handshake
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build a TLS acceptor from the configured certificate and key. Unless
+/// `accept_invalid_certs` is set, a certificate that doesn't chain to a public
+/// root (such as a self-signed one) or has expired is refused at startup.
+pub fn load_acceptor(tls: &TlsConfig, accept_invalid_certs: bool) -> Result<TlsAcceptor> {
+    let certs = load_certs(&tls.cert_path)?;
+    let key = load_key(&tls.key_path)?;
+
+    if let Err(e) = verify_certificate(&certs) {
+        if !accept_invalid_certs {
+            return Err(e.context("set ACCEPT_INVALID_CERTS=true to serve it anyway"));
+        }
+        warn!("Serving an untrusted TLS certificate: {:#}", e);
+    }
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("TLS certificate and private key don't match")?;
+    // WebSocket upgrades need HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("failed to open TLS certificate {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to parse TLS certificate {}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("failed to open TLS private key {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("failed to parse TLS private key {}", path.display()))?
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
+
+// Check the chain against the Mozilla root store, as a browser would
+fn verify_certificate(certs: &[CertificateDer<'static>]) -> Result<()> {
+    let (leaf, intermediates) = certs.split_first().ok_or_else(|| anyhow!("empty certificate chain"))?;
+    let leaf = webpki::EndEntityCert::try_from(leaf).map_err(|e| anyhow!("invalid TLS certificate: {}", e))?;
+
+    leaf.verify_for_usage(
+        webpki::ALL_VERIFICATION_ALGS,
+        webpki_roots::TLS_SERVER_ROOTS,
+        intermediates,
+        UnixTime::now(),
+        webpki::KeyUsage::server_auth(),
+        None,
+        None,
+    )
+    .map_err(|e| anyhow!("TLS certificate failed verification: {}", e))?;
+
+    Ok(())
+}
+
+/// Serve `app` over TLS until the listener fails. Each connection gets its own
+/// task; like `axum::serve`, the peer address is available as `ConnectInfo`.
+pub async fn serve(listener: TcpListener, acceptor: TlsAcceptor, app: Router) -> std::io::Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream = match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", addr, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {} timed out", addr);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo::<SocketAddr>(addr));
+                app.clone().call(request)
+            });
+
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades()
+                .await
+            {
+                debug!("Connection from {} ended with error: {}", addr, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_temp(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_self_signed_certificate_requires_opt_in() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_file = write_temp(&cert.cert.pem());
+        let key_file = write_temp(&cert.key_pair.serialize_pem());
+        let tls = TlsConfig {
+            cert_path: cert_file.path().to_path_buf(),
+            key_path: key_file.path().to_path_buf(),
+        };
+
+        assert!(load_acceptor(&tls, false).is_err());
+        assert!(load_acceptor(&tls, true).is_ok());
+    }
+
+    #[test]
+    fn test_missing_files_are_reported() {
+        let tls = TlsConfig {
+            cert_path: "/nonexistent/cert.pem".into(),
+            key_path: "/nonexistent/key.pem".into(),
+        };
+        let error = load_acceptor(&tls, true).err().unwrap();
+        assert!(error.to_string().contains("/nonexistent/cert.pem"));
+    }
+}
@@ -0,0 +1,1015 @@
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lru::LruCache;
+use nostr::hashes::{sha256::Hash as Sha256Hash, Hash};
+use nostr::nips::nip26::{Condition, Conditions};
+use nostr::secp256k1::{schnorr::Signature, Message};
+use nostr::{Event, EventId, Filter, Kind, PublicKey, Tag, Url, SECP256K1};
+use regex::Regex;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::nip57::ZapValidator;
+
+/// Signatures that have already been verified, keyed by event ID
+pub type SigCache = Mutex<LruCache<EventId, Signature>>;
+
+pub fn new_sig_cache(capacity: usize) -> Arc<SigCache> {
+    let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+    Arc::new(Mutex::new(LruCache::new(capacity)))
+}
+
+/// Verify an event's ID and signature, skipping the Schnorr check when the same
+/// signature was already verified for this ID. The ID is always recomputed, since
+/// it is what binds the cached signature to the event's content.
+/// Returns whether the signature was served from the cache.
+pub async fn verify_event_cached(event: &Event, cache: &SigCache) -> Result<bool, nostr::event::Error> {
+    event.verify_id()?;
+
+    let signature = event.signature();
+    if cache.lock().await.get(&event.id) == Some(&signature) {
+        return Ok(true);
+    }
+
+    event.verify_signature_with_ctx(&SECP256K1)?;
+    cache.lock().await.put(event.id, signature);
+    Ok(false)
+}
+
+/// Size limits on an event's tags. These are cheap, so they run before the
+/// signature is verified and oversized events never reach secp256k1.
+pub fn check_tag_limits(event: &Event, config: &Config) -> Result<(), String> {
+    if event.tags.len() > config.max_event_tags {
+        return Err(format!("invalid: too many tags (max {})", config.max_event_tags));
+    }
+
+    let too_long = event
+        .tags
+        .iter()
+        .filter(|tag| !is_zap_receipt_payload(event, tag))
+        .flat_map(|tag| tag.as_vec())
+        .any(|value| value.len() > config.max_tag_value_length);
+    if too_long {
+        return Err(format!("invalid: tag value too long (max {} bytes)", config.max_tag_value_length));
+    }
+
+    Ok(())
+}
+
+// NIP-57: a zap receipt's `description` holds the whole zap request as JSON
+// and its `bolt11` the invoice, both routinely longer than other tag values.
+// The event size limit still bounds them.
+fn is_zap_receipt_payload(event: &Event, tag: &Tag) -> bool {
+    event.kind == Kind::ZapReceipt && matches!(tag.as_vec().first().map(String::as_str), Some("description" | "bolt11"))
+}
+
+/// `OK` message for a NIP-44 encrypted event whose content isn't a v2 payload
+pub const MALFORMED_ENCRYPTED_PAYLOAD: &str = "invalid: malformed encrypted payload";
+
+/// NIP-44 payload version accepted
+const NIP44_VERSION: u8 = 2;
+/// Smallest decoded NIP-44 payload: version byte, 32-byte nonce and 32-byte MAC
+const NIP44_MIN_PAYLOAD_LEN: usize = 1 + 32 + 32;
+
+/// Relay policy checks applied to an event after its signature has been verified.
+/// Returns the NIP-20 `OK` message to send back when the event is rejected.
+pub fn validate_event(event: &Event, config: &Config) -> Result<(), String> {
+    // PostgreSQL text columns can't store NUL characters
+    if has_nul(&event.content) || event.tags.iter().flat_map(|tag| tag.as_vec()).any(|value| has_nul(value)) {
+        return Err("invalid: event contains NUL characters".to_string());
+    }
+
+    // NIP-40: events that have already expired are dropped on publish
+    if event.is_expired() {
+        return Err("invalid: event has expired".to_string());
+    }
+
+    // Operator kind policy: the blocklist wins over the allowlist
+    let kind = event.kind.as_u64();
+    if config.kind_blocklist.contains(&kind) {
+        return Err("blocked: kind not permitted".to_string());
+    }
+    if let Some(allowlist) = &config.kind_allowlist {
+        if !allowlist.contains(&kind) {
+            return Err("blocked: kind not permitted".to_string());
+        }
+    }
+
+    // NIP-13: require a minimum proof-of-work on the event ID
+    if config.min_pow_difficulty > 0 && pow_difficulty(&event.id) < u32::from(config.min_pow_difficulty) {
+        return Err("pow: insufficient difficulty".to_string());
+    }
+
+    // NIP-26: an event claiming to be published on another pubkey's behalf
+    // must carry a token that pubkey signed
+    verified_delegator(event)?;
+
+    validate_event_kind(event)
+}
+
+/// `OK` message for a `delegation` tag that can't be parsed
+pub const MALFORMED_DELEGATION: &str = "invalid: malformed delegation tag";
+
+/// NIP-26: the pubkey an event was published on behalf of, once the token in
+/// its `["delegation", <delegator>, <conditions>, <token>]` tag verifies and
+/// the event meets the token's conditions. `None` for an event without a
+/// delegation tag; the error is the `OK` message to reject the event with.
+pub fn verified_delegator(event: &Event) -> Result<Option<PublicKey>, String> {
+    let Some(tag) = event
+        .tags
+        .iter()
+        .map(|tag| tag.as_vec())
+        .find(|tag| tag.first().is_some_and(|name| name == "delegation"))
+    else {
+        return Ok(None);
+    };
+    let [_, delegator, conditions, token] = tag else {
+        return Err(MALFORMED_DELEGATION.to_string());
+    };
+    let delegator = PublicKey::from_hex(delegator).map_err(|_| MALFORMED_DELEGATION)?;
+    let token = Signature::from_str(token).map_err(|_| MALFORMED_DELEGATION)?;
+    let parsed_conditions = Conditions::from_str(conditions).map_err(|_| MALFORMED_DELEGATION)?;
+
+    // The token signs the conditions exactly as they appear in the tag
+    let delegation = format!("nostr:delegation:{}:{}", event.pubkey, conditions);
+    let message = Message::from_digest(Sha256Hash::hash(delegation.as_bytes()).to_byte_array());
+    SECP256K1
+        .verify_schnorr(&token, &message, &delegator)
+        .map_err(|_| "invalid: delegation token does not verify")?;
+
+    let created_at = event.created_at.as_u64();
+    let conditions_met = parsed_conditions.inner().into_iter().all(|condition| match condition {
+        Condition::Kind(kind) => event.kind.as_u16() == kind,
+        Condition::CreatedBefore(before) => created_at < before,
+        Condition::CreatedAfter(after) => created_at > after,
+    });
+    if !conditions_met {
+        return Err("invalid: event does not meet the delegation conditions".to_string());
+    }
+
+    Ok(Some(delegator))
+}
+
+fn has_nul(value: &str) -> bool {
+    value.contains('\0')
+}
+
+/// Structural rules of kinds whose content or tags carry meaning the relay
+/// relies on. Returns the NIP-20 `OK` message to send back when the event is rejected.
+pub fn validate_event_kind(event: &Event) -> Result<(), String> {
+    match event.kind {
+        // NIP-28: channel metadata is a JSON object, and a new channel needs a name
+        Kind::ChannelCreation => {
+            let metadata = json_object(&event.content).ok_or("invalid: channel metadata must be a JSON object")?;
+            if !metadata.get("name").is_some_and(serde_json::Value::is_string) {
+                return Err("invalid: channel creation requires a name".to_string());
+            }
+        }
+        Kind::ChannelMetadata => {
+            json_object(&event.content).ok_or("invalid: channel metadata must be a JSON object")?;
+            if tag_value(event, "e").and_then(|id| EventId::from_hex(id).ok()).is_none() {
+                return Err("invalid: channel metadata must reference its channel with an e tag".to_string());
+            }
+        }
+        // Messages point at their channel with a root `e` tag carrying a relay hint
+        Kind::ChannelMessage => {
+            let has_root = event.tags.iter().map(|tag| tag.as_vec()).any(|tag| {
+                matches!(tag, [name, id, relay, marker, ..]
+                    if name == "e" && marker == "root" && !relay.is_empty() && EventId::from_hex(id).is_ok())
+            });
+            if !has_root {
+                return Err("invalid: channel message requires a root e tag with a relay URL".to_string());
+            }
+        }
+        Kind::ChannelHideMessage if tag_value(event, "e").and_then(|id| EventId::from_hex(id).ok()).is_none() => {
+            return Err("invalid: hiding a message requires an e tag".to_string());
+        }
+        Kind::ChannelMuteUser if tag_value(event, "p").and_then(|pubkey| PublicKey::from_hex(pubkey).ok()).is_none() => {
+            return Err("invalid: muting a user requires a p tag".to_string());
+        }
+        // NIP-44: encrypted DMs and gift wraps carry a v2 payload. Only its shape
+        // is checked; the relay passes it on without decrypting it.
+        Kind::PrivateDirectMessage | Kind::GiftWrap if !is_nip44_payload(&event.content) => {
+            return Err(MALFORMED_ENCRYPTED_PAYLOAD.to_string());
+        }
+        // NIP-57: zap requests and receipts carry their recipient, relays and invoice in tags
+        Kind::ZapRequest | Kind::ZapReceipt => ZapValidator::default().validate(event)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Whether `content` is a base64 NIP-44 v2 payload long enough to hold its
+/// version byte, nonce and MAC
+pub fn is_nip44_payload(content: &str) -> bool {
+    STANDARD
+        .decode(content)
+        .is_ok_and(|payload| payload.len() >= NIP44_MIN_PAYLOAD_LEN && payload[0] == NIP44_VERSION)
+}
+
+/// The first value of the event's first `name` tag
+pub fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event
+        .tags
+        .iter()
+        .map(|tag| tag.as_vec())
+        .find(|tag| tag.first().is_some_and(|tag_name| tag_name == name))
+        .and_then(|tag| tag.get(1))
+        .map(String::as_str)
+}
+
+fn json_object(content: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::from_str(content).ok()? {
+        serde_json::Value::Object(object) => Some(object),
+        _ => None,
+    }
+}
+
+/// Compile `RELAY_CONTENT_FILTERS` at startup; an invalid pattern is a
+/// configuration error rather than something to skip silently
+pub fn compile_content_filters(patterns: &[String]) -> anyhow::Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern).with_context(|| format!("invalid content filter `{}`", pattern)))
+        .collect()
+}
+
+/// The first content filter matching a text note (kind 1), if any. Other kinds
+/// carry structured content and are never filtered.
+pub fn matching_content_filter<'a>(event: &Event, filters: &'a [Regex]) -> Option<&'a Regex> {
+    if event.kind != Kind::TextNote {
+        return None;
+    }
+    filters.iter().find(|filter| filter.is_match(&event.content))
+}
+
+/// Phrases typical of spam, matched case-insensitively
+pub const SPAM_KEYWORDS: [&str; 8] = [
+    "buy now", "click here", "limited time", "act fast",
+    "free money", "guaranteed", "no risk", "instant",
+];
+
+// Weights of the signals summed by `spam_score`
+const SPAM_KEYWORD_WEIGHT: f64 = 0.3;
+const SPAM_URL_WEIGHT: f64 = 0.1;
+const SPAM_ALL_CAPS_WEIGHT: f64 = 0.2;
+const SPAM_REPEAT_RUN_WEIGHT: f64 = 0.1;
+const SPAM_SHORT_NON_ASCII_WEIGHT: f64 = 0.4;
+
+// Letters needed before shouting counts, and the uppercase share that counts as shouting
+const ALL_CAPS_MIN_LETTERS: usize = 10;
+const ALL_CAPS_RATIO: f64 = 0.8;
+// Identical characters in a row that count as a repeat run, e.g. "!!!!!"
+const REPEAT_RUN_LENGTH: usize = 5;
+// Characters below which content made only of non-ASCII characters is suspicious
+const SHORT_CONTENT_CHARS: usize = 8;
+
+/// Sum of weighted spam signals in `content`: each spam keyword (+0.3), each
+/// URL (+0.1), mostly uppercase letters (+0.2), each run of a repeated
+/// character (+0.1), and very short content with no ASCII characters (+0.4)
+pub fn spam_score(content: &str) -> f64 {
+    let lower = content.to_lowercase();
+    let keywords = SPAM_KEYWORDS.iter().filter(|keyword| lower.contains(*keyword)).count();
+    let urls = lower.matches("http://").count() + lower.matches("https://").count();
+
+    let letters = content.chars().filter(|c| c.is_alphabetic()).count();
+    let uppercase = content.chars().filter(|c| c.is_uppercase()).count();
+    let all_caps = letters >= ALL_CAPS_MIN_LETTERS && uppercase as f64 / letters as f64 >= ALL_CAPS_RATIO;
+
+    let mut repeat_runs = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for c in content.chars() {
+        run = if Some(c) == previous { run + 1 } else { 1 };
+        if run == REPEAT_RUN_LENGTH && !c.is_whitespace() {
+            repeat_runs += 1;
+        }
+        previous = Some(c);
+    }
+
+    let visible = content.trim();
+    let short_non_ascii = !visible.is_empty()
+        && visible.chars().count() < SHORT_CONTENT_CHARS
+        && !visible.chars().any(|c| c.is_ascii());
+
+    let mut score = keywords as f64 * SPAM_KEYWORD_WEIGHT
+        + urls as f64 * SPAM_URL_WEIGHT
+        + repeat_runs as f64 * SPAM_REPEAT_RUN_WEIGHT;
+    if all_caps {
+        score += SPAM_ALL_CAPS_WEIGHT;
+    }
+    if short_non_ascii {
+        score += SPAM_SHORT_NON_ASCII_WEIGHT;
+    }
+    score
+}
+
+/// The spam score of a text note (kind 1) if it is above `threshold`. Other
+/// kinds carry structured content and are never scored.
+pub fn excessive_spam_score(event: &Event, threshold: f64) -> Option<f64> {
+    if event.kind != Kind::TextNote {
+        return None;
+    }
+    Some(spam_score(&event.content)).filter(|score| *score > threshold)
+}
+
+// An http(s) URL runs until whitespace, an angle bracket or a double quote
+// Commas end a URL, so a comma-separated list counts as several
+static URL_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"https?://[^\s<>",]+"#).unwrap());
+
+// Punctuation closing the sentence or bracket a URL was written in
+const URL_TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', '\''];
+
+/// Every http(s) URL in `content`, in order, without trailing punctuation
+pub fn extract_urls(content: &str) -> Vec<String> {
+    URL_PATTERN
+        .find_iter(content)
+        .map(|url| url.as_str().trim_end_matches(URL_TRAILING_PUNCTUATION).to_string())
+        .collect()
+}
+
+/// Why a text note's links got it rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlViolation {
+    /// More URLs than `max_urls_per_event`
+    TooMany(usize),
+    /// A URL on one of `blocked_url_domains`, with the host it matched
+    BlockedDomain(String),
+}
+
+impl UrlViolation {
+    /// The NIP-20 `OK` message to send back
+    pub fn message(&self) -> &'static str {
+        match self {
+            UrlViolation::TooMany(_) => "invalid: too many URLs",
+            UrlViolation::BlockedDomain(_) => "blocked: URL domain not permitted",
+        }
+    }
+}
+
+/// The first URL rule a text note (kind 1) breaks, if any. Other kinds carry
+/// structured content and are never checked.
+pub fn url_violation(event: &Event, config: &Config) -> Option<UrlViolation> {
+    if event.kind != Kind::TextNote {
+        return None;
+    }
+
+    let urls = extract_urls(&event.content);
+    if config.max_urls_per_event > 0 && urls.len() > config.max_urls_per_event {
+        return Some(UrlViolation::TooMany(urls.len()));
+    }
+
+    urls.iter()
+        .filter_map(|url| Url::parse(url).ok()?.host_str().map(str::to_string))
+        .find(|host| config.blocked_url_domains.iter().any(|domain| is_within_domain(host, domain)))
+        .map(UrlViolation::BlockedDomain)
+}
+
+// `domain` itself or any of its subdomains; hosts come lowercased from `Url`,
+// and a fully qualified name's trailing dot is ignored on either side
+fn is_within_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    host.trim_end_matches('.')
+        .strip_suffix(domain.as_str())
+        .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+}
+
+// Largest ID, author and kind lists accepted in a single filter
+const MAX_FILTER_IDS: usize = 1000;
+const MAX_FILTER_AUTHORS: usize = 1000;
+const MAX_FILTER_KINDS: usize = 20;
+
+// Longest since..until window queried without an ID, author or kind constraint
+const MAX_UNCONSTRAINED_RANGE_SECS: u64 = 86400 * 30;
+
+/// The same checks as nostr-types' `FilterValidator::validate_subscription_filters`,
+/// with the filter count and limit bounded by the relay config as well.
+/// Returns the NIP-01 `CLOSED` message to send back when the REQ is refused.
+pub fn validate_filters(filters: &[Filter], config: &Config) -> Result<(), String> {
+    if filters.is_empty() {
+        return Err("error: at least one filter is required".to_string());
+    }
+    if filters.len() > config.max_filters {
+        return Err(format!("error: too many filters (max {})", config.max_filters));
+    }
+
+    for filter in filters {
+        if let Some(limit) = filter.limit {
+            if limit > config.max_limit {
+                return Err(format!("error: filter limit too high (max {})", config.max_limit));
+            }
+        }
+
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since > until {
+                return Err("error: invalid time range: since > until".to_string());
+            }
+            let unconstrained = filter.ids.is_none() && filter.authors.is_none() && filter.kinds.is_none();
+            if unconstrained && until.as_u64() - since.as_u64() > MAX_UNCONSTRAINED_RANGE_SECS {
+                return Err("error: time range too large without other constraints".to_string());
+            }
+        }
+
+        if filter.ids.as_ref().is_some_and(|ids| ids.len() > MAX_FILTER_IDS) {
+            return Err("error: too many ids in filter".to_string());
+        }
+        if filter.authors.as_ref().is_some_and(|authors| authors.len() > MAX_FILTER_AUTHORS) {
+            return Err("error: too many authors in filter".to_string());
+        }
+        if filter.kinds.as_ref().is_some_and(|kinds| kinds.len() > MAX_FILTER_KINDS) {
+            return Err("error: too many kinds in filter".to_string());
+        }
+
+        let nul = filter.search.as_deref().is_some_and(has_nul)
+            || filter.generic_tags.values().flatten().any(|value| has_nul(value));
+        if nul {
+            return Err("error: filter contains NUL characters".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reject client messages larger than the configured limit before they are parsed
+pub fn validate_message_size(message: &str, config: &Config) -> Result<(), String> {
+    if message.len() > config.max_message_length {
+        return Err("message too large".to_string());
+    }
+
+    Ok(())
+}
+
+/// Number of leading zero bits in an event ID (NIP-13)
+pub fn pow_difficulty(id: &EventId) -> u32 {
+    let mut count = 0;
+    for byte in id.as_bytes() {
+        if *byte == 0 {
+            count += 8;
+        } else {
+            count += byte.leading_zeros();
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::nips::nip26::sign_delegation;
+    use nostr::{EventBuilder, Keys, Kind, Tag, Timestamp};
+
+    fn test_config(min_pow_difficulty: u8) -> Config {
+        let mut config = Config::from_env();
+        config.min_pow_difficulty = min_pow_difficulty;
+        config
+    }
+
+    fn id_with_prefix(prefix: &[u8]) -> EventId {
+        let mut bytes = [0xffu8; 32];
+        bytes[..prefix.len()].copy_from_slice(prefix);
+        EventId::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_pow_difficulty_counts_leading_zero_bits() {
+        assert_eq!(pow_difficulty(&id_with_prefix(&[])), 0);
+        assert_eq!(pow_difficulty(&id_with_prefix(&[0x7f])), 1);
+        assert_eq!(pow_difficulty(&id_with_prefix(&[0x00, 0x0f])), 12);
+        assert_eq!(pow_difficulty(&id_with_prefix(&[0x00, 0x00, 0x01])), 23);
+        assert_eq!(pow_difficulty(&EventId::all_zeros()), 256);
+    }
+
+    #[test]
+    fn test_validate_event_pow_threshold() {
+        let keys = Keys::generate();
+        let signed = EventBuilder::new(Kind::TextNote, "pow test", [])
+            .to_event(&keys)
+            .unwrap();
+
+        // Craft an ID with exactly 20 leading zero bits
+        let event = Event::new(
+            id_with_prefix(&[0x00, 0x00, 0x0f]),
+            signed.pubkey,
+            signed.created_at,
+            signed.kind,
+            signed.tags.clone(),
+            signed.content.clone(),
+            signed.signature(),
+        );
+
+        assert!(validate_event(&event, &test_config(0)).is_ok());
+        assert!(validate_event(&event, &test_config(20)).is_ok());
+        assert_eq!(
+            validate_event(&event, &test_config(21)),
+            Err("pow: insufficient difficulty".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_event_rejects_expired() {
+        let keys = Keys::generate();
+        let expired = EventBuilder::new(Kind::TextNote, "gone", [Tag::expiration(Timestamp::from(1_000))])
+            .to_event(&keys)
+            .unwrap();
+        let live = EventBuilder::new(Kind::TextNote, "still here", [Tag::expiration(Timestamp::now() + 3600)])
+            .to_event(&keys)
+            .unwrap();
+
+        assert_eq!(
+            validate_event(&expired, &test_config(0)),
+            Err("invalid: event has expired".to_string())
+        );
+        assert!(validate_event(&live, &test_config(0)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_event_rejects_nul_characters() {
+        let keys = Keys::generate();
+        let in_content = EventBuilder::text_note("before\0after", []).to_event(&keys).unwrap();
+        let in_tag = EventBuilder::text_note("hello", [Tag::hashtag("a\0b")]).to_event(&keys).unwrap();
+
+        for event in [in_content, in_tag] {
+            assert_eq!(validate_event(&event, &test_config(0)).unwrap_err(), "invalid: event contains NUL characters");
+        }
+    }
+
+    fn delegation_tag(delegator: &Keys, delegatee: &Keys, conditions: &str) -> Tag {
+        let token = sign_delegation(delegator, &delegatee.public_key(), &Conditions::from_str(conditions).unwrap()).unwrap();
+        Tag::parse(&["delegation", &delegator.public_key().to_hex(), conditions, &token.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_verified_delegator() {
+        let delegator = Keys::generate();
+        let delegatee = Keys::generate();
+        let now = Timestamp::now().as_u64();
+        let conditions = format!("kind=1&created_at>{}&created_at<{}", now - 60, now + 60);
+
+        let plain = EventBuilder::text_note("hi", []).to_event(&delegatee).unwrap();
+        assert_eq!(verified_delegator(&plain), Ok(None));
+
+        let tag = delegation_tag(&delegator, &delegatee, &conditions);
+        let delegated = EventBuilder::text_note("hi", [tag.clone()]).to_event(&delegatee).unwrap();
+        assert_eq!(verified_delegator(&delegated), Ok(Some(delegator.public_key())));
+        assert!(validate_event(&delegated, &test_config(0)).is_ok());
+
+        // The token only covers the delegatee it was signed for
+        let stolen = EventBuilder::text_note("hi", [tag]).to_event(&Keys::generate()).unwrap();
+        assert_eq!(
+            validate_event(&stolen, &test_config(0)).unwrap_err(),
+            "invalid: delegation token does not verify"
+        );
+    }
+
+    #[test]
+    fn test_delegation_conditions_are_enforced() {
+        let delegator = Keys::generate();
+        let delegatee = Keys::generate();
+        let now = Timestamp::now().as_u64();
+
+        let wrong_kind = EventBuilder::new(Kind::Reaction, "+", [delegation_tag(&delegator, &delegatee, "kind=1")])
+            .to_event(&delegatee)
+            .unwrap();
+        let expired = EventBuilder::text_note("hi", [delegation_tag(&delegator, &delegatee, &format!("created_at<{}", now - 60))])
+            .to_event(&delegatee)
+            .unwrap();
+        let not_yet = EventBuilder::text_note("hi", [delegation_tag(&delegator, &delegatee, &format!("created_at>{}", now + 60))])
+            .to_event(&delegatee)
+            .unwrap();
+
+        for event in [wrong_kind, expired, not_yet] {
+            assert_eq!(
+                validate_event(&event, &test_config(0)).unwrap_err(),
+                "invalid: event does not meet the delegation conditions"
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_delegation_tag() {
+        let delegator = Keys::generate();
+        let delegatee = Keys::generate();
+        let token = sign_delegation(&delegator, &delegatee.public_key(), &Conditions::from_str("kind=1").unwrap()).unwrap();
+        let delegator_hex = delegator.public_key().to_hex();
+        let token = token.to_string();
+
+        for tag in [
+            vec!["delegation", delegator_hex.as_str(), "kind=1"],
+            vec!["delegation", "not-a-pubkey", "kind=1", token.as_str()],
+            vec!["delegation", delegator_hex.as_str(), "kind=one", token.as_str()],
+            vec!["delegation", delegator_hex.as_str(), "kind=1", "not-a-signature"],
+        ] {
+            let event = EventBuilder::text_note("hi", [Tag::parse(&tag).unwrap()]).to_event(&delegatee).unwrap();
+            assert_eq!(validate_event(&event, &test_config(0)).unwrap_err(), MALFORMED_DELEGATION);
+        }
+    }
+
+    #[test]
+    fn test_validate_event_kind_lists() {
+        let keys = Keys::generate();
+        let note = EventBuilder::new(Kind::TextNote, "hello", []).to_event(&keys).unwrap();
+        let dm = EventBuilder::new(Kind::EncryptedDirectMessage, "secret", []).to_event(&keys).unwrap();
+        let blocked = Err("blocked: kind not permitted".to_string());
+
+        let mut config = test_config(0);
+        config.kind_blocklist = vec![4];
+        assert!(validate_event(&note, &config).is_ok());
+        assert_eq!(validate_event(&dm, &config), blocked);
+
+        config.kind_blocklist.clear();
+        config.kind_allowlist = Some(vec![0, 1]);
+        assert!(validate_event(&note, &config).is_ok());
+        assert_eq!(validate_event(&dm, &config), blocked);
+
+        // A kind on both lists is blocked
+        config.kind_blocklist = vec![1];
+        assert_eq!(validate_event(&note, &config), blocked);
+    }
+
+    #[test]
+    fn test_compile_content_filters() {
+        let filters = compile_content_filters(&["(?i)free sats".to_string(), "^gm$".to_string()]).unwrap();
+        assert_eq!(filters.len(), 2);
+        assert!(compile_content_filters(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_content_filter_fails_startup() {
+        let error = compile_content_filters(&["ok".to_string(), "unclosed (group".to_string()]).unwrap_err();
+        assert!(error.to_string().contains("unclosed (group"));
+    }
+
+    #[test]
+    fn test_content_filters_only_apply_to_text_notes() {
+        let keys = Keys::generate();
+        let filters = compile_content_filters(&["^gm$".to_string(), "(?i)free sats".to_string()]).unwrap();
+        let spam = EventBuilder::new(Kind::TextNote, "Claim your FREE SATS now", []).to_event(&keys).unwrap();
+        let note = EventBuilder::new(Kind::TextNote, "gm friends", []).to_event(&keys).unwrap();
+        let profile = EventBuilder::new(Kind::Metadata, "{\"about\":\"free sats\"}", []).to_event(&keys).unwrap();
+
+        assert_eq!(matching_content_filter(&spam, &filters).map(Regex::as_str), Some("(?i)free sats"));
+        assert!(matching_content_filter(&note, &filters).is_none());
+        assert!(matching_content_filter(&profile, &filters).is_none());
+    }
+
+    #[test]
+    fn test_spam_score_flags_spam() {
+        let samples = [
+            "BUY NOW!!!!! Click here for FREE MONEY, guaranteed: https://scam.example https://scam.example/2",
+            "Limited time offer, act fast: https://x.example https://y.example https://z.example",
+        ];
+        for sample in samples {
+            assert!(spam_score(sample) > 0.8, "{} scored {}", sample, spam_score(sample));
+        }
+    }
+
+    #[test]
+    fn test_spam_score_passes_legitimate_content() {
+        let samples = [
+            "gm nostr, what is everyone building this weekend?",
+            "Wrote up my notes on running a relay: https://blog.example/relay",
+            "こんにちは、今日はいい天気ですね",
+            "Just shipped v2.0 of the client. Release notes: https://example.com/notes",
+            "",
+        ];
+        for sample in samples {
+            assert!(spam_score(sample) <= 0.8, "{} scored {}", sample, spam_score(sample));
+        }
+    }
+
+    #[test]
+    fn test_spam_score_signals() {
+        assert!((spam_score("click here") - 0.3).abs() < 1e-9);
+        assert!((spam_score("see https://a.example and http://b.example") - 0.2).abs() < 1e-9);
+        assert!((spam_score("THIS IS VERY IMPORTANT NEWS") - 0.2).abs() < 1e-9);
+        assert!((spam_score("wow!!!!! and ?????") - 0.2).abs() < 1e-9);
+        assert!((spam_score("ですです") - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_excessive_spam_score_only_applies_to_text_notes() {
+        let keys = Keys::generate();
+        let content = "BUY NOW!!!!! guaranteed, no risk, instant free money";
+        let note = EventBuilder::new(Kind::TextNote, content, []).to_event(&keys).unwrap();
+        let long_form = EventBuilder::new(Kind::LongFormTextNote, content, []).to_event(&keys).unwrap();
+
+        assert!(excessive_spam_score(&note, 0.8).is_some());
+        assert!(excessive_spam_score(&note, 100.0).is_none());
+        assert!(excessive_spam_score(&long_form, 0.8).is_none());
+    }
+
+    #[test]
+    fn test_extract_urls_matches_every_url_shape() {
+        let schemes = ["http", "https"];
+        let hosts = ["example.com", "sub.example.co.uk", "127.0.0.1", "[::1]", "xn--nxasmq6b.example"];
+        let ports = ["", ":8080", ":443"];
+        let paths = ["", "/", "/notes/2024/01", "/a_b-c~d.html", "/%E2%9A%A1"];
+        let queries = ["", "?q=nostr", "?a=1&b=two&empty="];
+        let fragments = ["", "#top", "#section-2"];
+
+        for scheme in schemes {
+            for host in hosts {
+                for port in ports {
+                    for path in paths {
+                        for query in queries {
+                            for fragment in fragments {
+                                let url = format!("{}://{}{}{}{}{}", scheme, host, port, path, query, fragment);
+                                let content = format!("see {} and <{}> or \"{}\"\n{}", url, url, url, url);
+                                assert_eq!(extract_urls(&content), vec![url.clone(); 4], "{}", content);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_urls_ignores_other_text() {
+        assert!(extract_urls("").is_empty());
+        assert!(extract_urls("no links here, just example.com and ftp://files.example").is_empty());
+        assert!(extract_urls("http:// and https:/broken").is_empty());
+        assert_eq!(
+            extract_urls("two in a row: https://a.example,https://b.example"),
+            vec!["https://a.example".to_string(), "https://b.example".to_string()]
+        );
+        assert_eq!(
+            extract_urls("see https://a.example/path. (or https://b.example)!"),
+            vec!["https://a.example/path".to_string(), "https://b.example".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_url_limit() {
+        let keys = Keys::generate();
+        let mut config = test_config(0);
+        config.max_urls_per_event = 2;
+        let links = |count: usize| (0..count).map(|i| format!("https://{}.example", i)).collect::<Vec<_>>().join(" ");
+        let two = EventBuilder::new(Kind::TextNote, links(2), []).to_event(&keys).unwrap();
+        let three = EventBuilder::new(Kind::TextNote, links(3), []).to_event(&keys).unwrap();
+        let long_form = EventBuilder::new(Kind::LongFormTextNote, links(3), []).to_event(&keys).unwrap();
+
+        assert_eq!(url_violation(&two, &config), None);
+        assert_eq!(url_violation(&three, &config), Some(UrlViolation::TooMany(3)));
+        assert_eq!(UrlViolation::TooMany(3).message(), "invalid: too many URLs");
+        assert_eq!(url_violation(&long_form, &config), None);
+
+        // 0 means unlimited
+        config.max_urls_per_event = 0;
+        assert_eq!(url_violation(&three, &config), None);
+    }
+
+    #[test]
+    fn test_blocked_url_domains_include_subdomains() {
+        let keys = Keys::generate();
+        let mut config = test_config(0);
+        config.blocked_url_domains = vec!["spam.example".to_string()];
+        let note = |content: &str| EventBuilder::new(Kind::TextNote, content, []).to_event(&keys).unwrap();
+
+        assert_eq!(
+            url_violation(&note("buy here https://spam.example/offer"), &config),
+            Some(UrlViolation::BlockedDomain("spam.example".to_string()))
+        );
+        assert_eq!(
+            url_violation(&note("or https://WWW.Spam.Example:8443/?ref=1"), &config),
+            Some(UrlViolation::BlockedDomain("www.spam.example".to_string()))
+        );
+        // Punctuation, a trailing dot on the host or a comma can't hide the domain
+        for content in [
+            "https://spam.example.",
+            "https://spam.example./offer",
+            "(https://spam.example)",
+            "https://ok.example,https://spam.example",
+        ] {
+            assert!(
+                matches!(url_violation(&note(content), &config), Some(UrlViolation::BlockedDomain(_))),
+                "{} was allowed",
+                content
+            );
+        }
+        // Similar names and mentions without a URL are fine
+        assert_eq!(url_violation(&note("https://notspam.example https://spam.example.org"), &config), None);
+        assert_eq!(url_violation(&note("I got spam from spam.example"), &config), None);
+        assert_eq!(
+            UrlViolation::BlockedDomain("spam.example".to_string()).message(),
+            "blocked: URL domain not permitted"
+        );
+    }
+
+    #[test]
+    fn test_validate_message_size() {
+        let config = test_config(0);
+        assert_eq!(config.max_message_length, 65536);
+
+        assert!(validate_message_size(&"a".repeat(65536), &config).is_ok());
+        assert_eq!(
+            validate_message_size(&"a".repeat(66000), &config),
+            Err("message too large".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_event_cached() {
+        let keys = Keys::generate();
+        let event = EventBuilder::new(Kind::TextNote, "cache me", [])
+            .to_event(&keys)
+            .unwrap();
+        let cache = new_sig_cache(10);
+
+        assert!(!verify_event_cached(&event, &cache).await.unwrap());
+        assert!(verify_event_cached(&event, &cache).await.unwrap());
+
+        // Same ID with someone else's signature must not be served from the cache
+        let other = EventBuilder::new(Kind::TextNote, "other", [])
+            .to_event(&keys)
+            .unwrap();
+        let forged = Event::new(
+            event.id,
+            event.pubkey,
+            event.created_at,
+            event.kind,
+            event.tags.clone(),
+            event.content.clone(),
+            other.signature(),
+        );
+        assert!(verify_event_cached(&forged, &cache).await.is_err());
+
+        // Tampered content no longer matches the cached ID
+        let tampered = Event::new(
+            event.id,
+            event.pubkey,
+            event.created_at,
+            event.kind,
+            event.tags.clone(),
+            "tampered".to_string(),
+            event.signature(),
+        );
+        assert!(verify_event_cached(&tampered, &cache).await.is_err());
+    }
+
+    #[test]
+    fn test_validate_filters() {
+        let mut config = test_config(0);
+        config.max_filters = 100;
+        config.max_limit = 5000;
+
+        assert!(validate_filters(&[Filter::new().kind(Kind::TextNote).limit(100)], &config).is_ok());
+        assert_eq!(validate_filters(&[], &config).unwrap_err(), "error: at least one filter is required");
+
+        assert!(validate_filters(&vec![Filter::new(); 100], &config).is_ok());
+        assert_eq!(
+            validate_filters(&vec![Filter::new(); 101], &config).unwrap_err(),
+            "error: too many filters (max 100)"
+        );
+        config.max_filters = 2;
+        assert_eq!(
+            validate_filters(&vec![Filter::new(); 3], &config).unwrap_err(),
+            "error: too many filters (max 2)"
+        );
+
+        assert_eq!(
+            validate_filters(&[Filter::new().limit(10_000)], &config).unwrap_err(),
+            "error: filter limit too high (max 5000)"
+        );
+        assert_eq!(
+            validate_filters(&[Filter::new().since(Timestamp::from(200)).until(Timestamp::from(100))], &config)
+                .unwrap_err(),
+            "error: invalid time range: since > until"
+        );
+        // A single instant is a valid range
+        let instant = Filter::new().since(Timestamp::from(100)).until(Timestamp::from(100));
+        assert!(validate_filters(&[instant], &config).is_ok());
+
+        // A window over 30 days needs another constraint
+        let wide = Filter::new().since(Timestamp::from(0)).until(Timestamp::from(86400 * 31));
+        assert!(validate_filters(std::slice::from_ref(&wide), &config).is_err());
+        assert!(validate_filters(&[wide.kind(Kind::TextNote)], &config).is_ok());
+
+        let kinds = (0..21).map(Kind::from).collect::<Vec<_>>();
+        assert_eq!(
+            validate_filters(&[Filter::new().kinds(kinds)], &config).unwrap_err(),
+            "error: too many kinds in filter"
+        );
+
+        assert!(validate_filters(&[Filter::new().search("a\0b")], &config).is_err());
+        assert!(validate_filters(&[Filter::new().hashtag("a\0b")], &config).is_err());
+    }
+
+    #[test]
+    fn test_channel_event_structure() {
+        let keys = Keys::generate();
+        let event = |kind: Kind, content: &str, tags: &[&[&str]]| {
+            let tags = tags.iter().map(|tag| Tag::parse(tag).unwrap());
+            EventBuilder::new(kind, content, tags).to_event(&keys).unwrap()
+        };
+        let channel = EventId::all_zeros().to_hex();
+        let channel = channel.as_str();
+        let pubkey = keys.public_key().to_hex();
+
+        // Kind 40: JSON content with a name
+        assert!(validate_event_kind(&event(Kind::ChannelCreation, r#"{"name":"rust","about":"chat"}"#, &[])).is_ok());
+        assert!(validate_event_kind(&event(Kind::ChannelCreation, r#"{"about":"chat"}"#, &[])).is_err());
+        assert!(validate_event_kind(&event(Kind::ChannelCreation, "rust", &[])).is_err());
+
+        // Kind 41: JSON content and the channel's e tag
+        assert!(validate_event_kind(&event(Kind::ChannelMetadata, r#"{"about":"new"}"#, &[&["e", channel]])).is_ok());
+        assert!(validate_event_kind(&event(Kind::ChannelMetadata, r#"{"about":"new"}"#, &[])).is_err());
+
+        // Kind 42: a root e tag with a relay URL
+        let root = ["e", channel, "wss://relay.example.com", "root"];
+        assert!(validate_event_kind(&event(Kind::ChannelMessage, "hi", &[&root])).is_ok());
+        assert!(validate_event_kind(&event(Kind::ChannelMessage, "hi", &[&["e", channel, "", "root"]])).is_err());
+        assert!(validate_event_kind(&event(Kind::ChannelMessage, "hi", &[&["e", channel, "wss://relay.example.com", "reply"]])).is_err());
+        assert!(validate_event_kind(&event(Kind::ChannelMessage, "hi", &[&["e", channel]])).is_err());
+
+        // Kinds 43 and 44: the hidden message and the muted user
+        assert!(validate_event_kind(&event(Kind::ChannelHideMessage, "", &[&["e", channel]])).is_ok());
+        assert!(validate_event_kind(&event(Kind::ChannelHideMessage, "", &[&["p", &pubkey]])).is_err());
+        assert!(validate_event_kind(&event(Kind::ChannelMuteUser, "", &[&["p", &pubkey]])).is_ok());
+        assert!(validate_event_kind(&event(Kind::ChannelMuteUser, "", &[&["e", channel]])).is_err());
+
+        // Other kinds aren't checked
+        assert!(validate_event_kind(&event(Kind::TextNote, "{", &[])).is_ok());
+    }
+
+    #[test]
+    fn test_check_tag_limits() {
+        let keys = Keys::generate();
+        let mut config = test_config(0);
+        config.max_event_tags = 2;
+        config.max_tag_value_length = 8;
+        let event = |tags: &[&[&str]]| {
+            let tags = tags.iter().map(|tag| Tag::parse(tag).unwrap());
+            EventBuilder::new(Kind::TextNote, "hi", tags).to_event(&keys).unwrap()
+        };
+
+        assert!(check_tag_limits(&event(&[&["t", "nostr"], &["t", "rust"]]), &config).is_ok());
+        assert_eq!(
+            check_tag_limits(&event(&[&["t", "a"], &["t", "b"], &["t", "c"]]), &config),
+            Err("invalid: too many tags (max 2)".to_string())
+        );
+        assert_eq!(
+            check_tag_limits(&event(&[&["t", "toolongvalue"]]), &config),
+            Err("invalid: tag value too long (max 8 bytes)".to_string())
+        );
+
+        // A zap receipt's embedded zap request and invoice aren't held to the limit
+        let receipt = |tags: &[&[&str]]| {
+            let tags = tags.iter().map(|tag| Tag::parse(tag).unwrap());
+            EventBuilder::new(Kind::ZapReceipt, "", tags).to_event(&keys).unwrap()
+        };
+        assert!(check_tag_limits(&receipt(&[&["description", "{\"kind\":9734}"], &["bolt11", "lnbc10u1pjexample"]]), &config).is_ok());
+        assert!(check_tag_limits(&receipt(&[&["p", "toolongvalue"]]), &config).is_err());
+        assert!(check_tag_limits(&event(&[&["description", "toolongvalue"]]), &config).is_err());
+    }
+
+    #[test]
+    fn test_nip44_payload_format() {
+        let payload = |version: u8, len: usize| {
+            let mut bytes = vec![0xab; len];
+            bytes[0] = version;
+            STANDARD.encode(bytes)
+        };
+
+        assert!(is_nip44_payload(&payload(2, 99)));
+        assert!(is_nip44_payload(&payload(2, NIP44_MIN_PAYLOAD_LEN)));
+        assert!(!is_nip44_payload(&payload(2, NIP44_MIN_PAYLOAD_LEN - 1)));
+        assert!(!is_nip44_payload(&payload(1, 99)));
+        assert!(!is_nip44_payload("not base64!"));
+        assert!(!is_nip44_payload(""));
+        // NIP-04 ciphertext
+        assert!(!is_nip44_payload("dGVzdA==?iv=AAAAAAAAAAAAAAAAAAAAAA=="));
+
+        let keys = Keys::generate();
+        let event = |kind: Kind, content: &str| EventBuilder::new(kind, content, []).to_event(&keys).unwrap();
+        assert!(validate_event_kind(&event(Kind::PrivateDirectMessage, &payload(2, 99))).is_ok());
+        assert!(validate_event_kind(&event(Kind::GiftWrap, &payload(2, 99))).is_ok());
+        assert_eq!(
+            validate_event_kind(&event(Kind::PrivateDirectMessage, "hello")),
+            Err(MALFORMED_ENCRYPTED_PAYLOAD.to_string())
+        );
+        assert_eq!(
+            validate_event_kind(&event(Kind::GiftWrap, &payload(1, 99))),
+            Err(MALFORMED_ENCRYPTED_PAYLOAD.to_string())
+        );
+    }
+
+    #[test]
+    fn test_zaps_are_validated() {
+        let keys = Keys::generate();
+        let recipient = Tag::public_key(Keys::generate().public_key());
+        let relays = Tag::parse(&["relays", "wss://relay.example.com"]).unwrap();
+
+        let request = EventBuilder::new(Kind::ZapRequest, "", [recipient.clone(), relays]).to_event(&keys).unwrap();
+        assert!(validate_event_kind(&request).is_ok());
+        let request = EventBuilder::new(Kind::ZapRequest, "great post", [recipient.clone()]).to_event(&keys).unwrap();
+        assert_eq!(validate_event_kind(&request), Err(crate::nip57::MALFORMED_ZAP.to_string()));
+        let receipt = EventBuilder::new(Kind::ZapReceipt, "", [recipient]).to_event(&keys).unwrap();
+        assert_eq!(validate_event_kind(&receipt), Err(crate::nip57::MALFORMED_ZAP.to_string()));
+    }
+}
@@ -0,0 +1,51 @@
+// The watcher reads the process environment, so this lives in its own test
+// binary where no other test changes the same variables.
+use relay_engine::config::Config;
+use relay_engine::config_watcher::ConfigWatcher;
+use relay_engine::nip11;
+
+use axum::http::HeaderMap;
+use std::{env, sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[tokio::test]
+async fn test_env_changes_visible_within_two_refresh_cycles() {
+    env::set_var("RELAY_NAME", "Before Reload");
+    env::set_var("RELAY_CONTACT", "before@example.com");
+    env::remove_var("RELAY_KIND_BLOCKLIST");
+    env::set_var("RELAY_MIN_POW_DIFFICULTY", "0");
+    env::set_var("PORT", "7001");
+
+    let startup = Config::from_env();
+    let mutable_config = Arc::new(RwLock::new(startup.clone()));
+    let watcher = ConfigWatcher::new(mutable_config.clone(), REFRESH_INTERVAL).spawn();
+
+    env::set_var("RELAY_NAME", "After Reload");
+    env::set_var("RELAY_CONTACT", "after@example.com");
+    env::set_var("RELAY_KIND_BLOCKLIST", "4,1059");
+    env::set_var("RELAY_MIN_POW_DIFFICULTY", "8");
+    env::set_var("PORT", "7002");
+    tokio::time::sleep(REFRESH_INTERVAL * 2 + REFRESH_INTERVAL / 2).await;
+
+    {
+        let config = mutable_config.read().await;
+        assert_eq!(config.relay_name, "After Reload");
+        assert_eq!(config.relay_contact.as_deref(), Some("after@example.com"));
+        assert_eq!(config.kind_blocklist, vec![4, 1059]);
+        assert_eq!(config.min_pow_difficulty, 8);
+        // Settings that need a restart keep their startup values
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.database_url, startup.database_url);
+
+        let response = nip11::relay_info_response(&config, &HeaderMap::new());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(document["name"], "After Reload");
+        assert_eq!(document["contact"], "after@example.com");
+        assert_eq!(document["limitation"]["min_pow_difficulty"], 8);
+    }
+
+    watcher.abort();
+}
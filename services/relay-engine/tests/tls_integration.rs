@@ -0,0 +1,150 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use futures_util::{SinkExt, StreamExt};
+use relay_engine::{config::TlsConfig, tls};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use std::{io::Write, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, net::TcpStream, time::timeout};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::{client_async, tungstenite::Message as TungsteniteMessage};
+
+/// Trusts exactly one certificate, the way a client pins a self-signed relay
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    cert: CertificateDer<'static>,
+    provider: CryptoProvider,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("unexpected certificate".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+async fn echo_handler(ws: WebSocketUpgrade, ConnectInfo(addr): ConnectInfo<SocketAddr>) -> Response {
+    ws.on_upgrade(move |mut socket: WebSocket| async move {
+        while let Some(Ok(Message::Text(text))) = socket.recv().await {
+            if socket.send(Message::Text(format!("{} from {}", text, addr.ip()))).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn write_temp(contents: &str) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(contents.as_bytes()).unwrap();
+    file
+}
+
+#[tokio::test]
+async fn test_websocket_over_tls_with_self_signed_cert() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_file = write_temp(&cert.cert.pem());
+    let key_file = write_temp(&cert.key_pair.serialize_pem());
+    let tls_config = TlsConfig {
+        cert_path: cert_file.path().to_path_buf(),
+        key_path: key_file.path().to_path_buf(),
+    };
+
+    let acceptor = tls::load_acceptor(&tls_config, true).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/", get(echo_handler));
+    tokio::spawn(tls::serve(listener, acceptor, app));
+
+    let provider = ring::default_provider();
+    let verifier = PinnedCertVerifier {
+        cert: cert.cert.der().clone(),
+        provider: provider.clone(),
+    };
+    let client_config = ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(client_config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+
+    let url = format!("wss://localhost:{}/", addr.port());
+    let (mut ws, _) = client_async(url, stream).await.unwrap();
+
+    ws.send(TungsteniteMessage::Text("hello".to_string())).await.unwrap();
+    let reply = timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!(reply, TungsteniteMessage::Text("hello from 127.0.0.1".to_string()));
+}
+
+#[tokio::test]
+async fn test_plain_connections_are_refused_by_tls_listener() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_file = write_temp(&cert.cert.pem());
+    let key_file = write_temp(&cert.key_pair.serialize_pem());
+    let tls_config = TlsConfig {
+        cert_path: cert_file.path().to_path_buf(),
+        key_path: key_file.path().to_path_buf(),
+    };
+
+    let acceptor = tls::load_acceptor(&tls_config, true).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/", get(echo_handler));
+    tokio::spawn(tls::serve(listener, acceptor, app));
+
+    let result = timeout(
+        Duration::from_secs(5),
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/", addr.port())),
+    )
+    .await
+    .unwrap();
+    assert!(result.is_err());
+}
@@ -0,0 +1,59 @@
+// Integration tests for async task monitoring; run with `--features tokio-metrics`
+#![cfg(feature = "tokio-metrics")]
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use relay_engine::metrics::Metrics;
+use relay_engine::task_metrics::{
+    create_tokio_metrics_router, start_runtime_monitor, TaskMetricsCollector, TokioMetricsResponse,
+    CLIENT_MESSAGE_TASKS, WEBSOCKET_TASKS,
+};
+use std::time::Duration;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn test_tokio_metrics_endpoint_reports_tasks() {
+    start_runtime_monitor();
+    for _ in 0..3 {
+        CLIENT_MESSAGE_TASKS
+            .instrument(tokio::time::sleep(Duration::from_millis(10)))
+            .await;
+    }
+
+    let response = create_tokio_metrics_router::<()>()
+        .oneshot(Request::builder().uri("/metrics/tokio").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let metrics: TokioMetricsResponse = serde_json::from_slice(&body).unwrap();
+
+    let runtime = metrics.runtime.expect("runtime monitor was started");
+    assert!(runtime.workers_count >= 1);
+
+    // Other tests in this binary share the monitors, so counts are lower bounds
+    let client_messages = &metrics.tasks["client_message"];
+    assert!(client_messages.instrumented_count >= 3);
+    assert!(client_messages.dropped_count >= 3);
+    assert!(client_messages.mean_idle_seconds > 0.0);
+    assert!(metrics.tasks.contains_key("websocket"));
+}
+
+#[tokio::test]
+async fn test_task_metrics_in_prometheus_registry() {
+    let metrics = Metrics::new().unwrap();
+    metrics.registry.register(Box::new(TaskMetricsCollector::new().unwrap())).unwrap();
+
+    WEBSOCKET_TASKS.instrument(tokio::task::yield_now()).await;
+
+    let rendered = metrics.render().unwrap();
+    let instrumented = rendered
+        .lines()
+        .find_map(|line| line.strip_prefix("relay_tokio_tasks_instrumented{task=\"websocket\"} "))
+        .expect("websocket task series is exported");
+    assert!(instrumented.parse::<i64>().unwrap() >= 1);
+    assert!(rendered.contains("relay_tokio_task_mean_poll_seconds{task=\"client_message\"}"));
+}
@@ -1,18 +1,35 @@
 // Integration tests for WebSocket relay functionality
 use relay_engine::{AppState, Config};
+use relay_engine::config::LogFormat;
+use relay_engine::app_state::{ConnectedClient, ConnectionMetadata};
+use relay_engine::connection_cleanup::cleanup_inactive_connections;
 use relay_engine::database::PostgresDatabase;
 use relay_engine::metrics::Metrics;
+use relay_engine::nip42::{self, ConnectionAuth};
+use relay_engine::outbound::{self, SLOW_SUBSCRIBER};
 use relay_engine::rate_limiter::{RateLimiter, RateLimitConfig};
+use relay_engine::sse::EVENT_FEED_CAPACITY;
+use relay_engine::validation::{self, new_sig_cache};
 
 use axum::{
-    extract::ws::{Message, WebSocket},
+    extract::{ws::{Message, WebSocket}, WebSocketUpgrade},
+    http::HeaderMap,
+    response::Response,
+    routing::get,
     Router,
 };
-use nostr::{ClientMessage, EventBuilder, Filter, Keys, Kind, RelayMessage, SubscriptionId};
+use futures_util::{SinkExt, StreamExt};
+use nostr::{ClientMessage, EventBuilder, Filter, Keys, Kind, RelayMessage, SubscriptionId, Timestamp, Url};
 use serde_json;
-use std::{collections::HashMap, sync::Arc};
-use tokio::{net::TcpListener, sync::RwLock, time::Duration};
+use dashmap::DashMap;
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}, time::Instant};
+use tokio::{net::{TcpListener, TcpStream}, sync::{broadcast, RwLock}, task::JoinSet, time::Duration};
+use tokio_util::sync::CancellationToken;
 use tokio_test;
+use tokio_tungstenite::{
+    tungstenite::{protocol::frame::coding::CloseCode, Message as TungsteniteMessage},
+    MaybeTlsStream, WebSocketStream,
+};
 use uuid::Uuid;
 
 // Helper function to create test app state
@@ -24,6 +41,71 @@ async fn create_test_app_state() -> AppState {
         relay_description: "Test relay for integration tests".to_string(),
         relay_pubkey: None,
         relay_contact: None,
+        max_subscriptions: 20,
+        min_pow_difficulty: 0,
+        expiration_cleanup_interval_secs: 60,
+        max_message_length: 65536,
+        websocket_ping_interval: 30,
+        websocket_pong_timeout: 90,
+        sig_cache_size: 10000,
+        recent_ids_cache_size: 50000,
+        shutdown_timeout: 30,
+        send_timeout: 5,
+        redis_url: None,
+        max_filters: 100,
+        max_limit: 5000,
+        max_subid_length: 100,
+        min_prefix: 4,
+        max_event_tags: 100,
+        max_content_length: 8196,
+        auth_required: false,
+        auth_required_kinds: Vec::new(),
+        auth_required_read_kinds: vec![4, 44],
+        auth_timeout_secs: 60,
+        payment_required: false,
+        payments_url: None,
+        fees: None,
+        health_warn_latency_ms: 500,
+        kind_allowlist: None,
+        kind_blocklist: Vec::new(),
+        admin_token: None,
+        admin_pubkeys: Vec::new(),
+        blocked_pubkeys: Vec::new(),
+        content_filters: Vec::new(),
+        tls: None,
+        accept_invalid_certs: false,
+        db_circuit_failure_threshold: 5,
+        db_circuit_success_threshold: 2,
+        db_circuit_open_secs: 30,
+        slow_query_threshold_ms: 100,
+        max_outbound_queue: 1000,
+        trusted_proxies: Vec::new(),
+        real_ip_header: "X-Forwarded-For".to_string(),
+        connection_cleanup_interval_secs: 60,
+        connection_idle_timeout_secs: 300,
+        event_retention_days: None,
+        event_prune_interval_secs: 3600,
+        spam_score_threshold: 0.8,
+        max_urls_per_event: 20,
+        blocked_url_domains: Vec::new(),
+        event_cache_ttl_secs: 3600,
+        event_cache_max_bytes: 65536,
+        relay_keys: None,
+        log_format: LogFormat::Text,
+        log_level: "info".to_string(),
+        log_file: None,
+        max_tag_value_length: 1024,
+        max_total_connections: 10000,
+        audit_log_enabled: false,
+        audit_log_path: "audit/events.log".into(),
+        audit_log_rotation: Default::default(),
+        metadata_refresh_interval_secs: 300,
+        config_file: None,
+        max_thread_depth: 10,
+        daily_bandwidth_limit_bytes: 0,
+        max_subscriptions_per_pubkey: 100,
+        relay_instance_id: "test-relay".to_string(),
+        public_url: None,
     };
 
     // Note: In real tests, you'd want to use a test database
@@ -32,12 +114,31 @@ async fn create_test_app_state() -> AppState {
     let rate_limiter = RateLimiter::new(RateLimitConfig::default());
     
     AppState {
+        sig_cache: new_sig_cache(config.sig_cache_size),
+        shutdown: CancellationToken::new(),
+        connections: Arc::new(Mutex::new(JoinSet::new())),
+        open_connections: Arc::default(),
+        fanout: None,
+        pubkey_blocklist: Arc::new(RwLock::new(HashSet::new())),
+        pubkey_relays: Arc::new(RwLock::new(HashMap::new())),
+        event_batcher: None,
+        event_feed: broadcast::channel(EVENT_FEED_CAPACITY).0,
+        audit_log: None,
+        mutable_config: Arc::new(RwLock::new(config.clone())),
+        http_auth_nonces: Arc::default(),
+        pubkey_subscription_counts: Arc::default(),
+        subscription_pubkeys: Arc::default(),
+        force_deleted_events: Arc::default(),
+        content_filters: Arc::new(Vec::new()),
         config,
         database: PostgresDatabase::new("sqlite::memory:").await.unwrap_or_else(|_| {
             // Fallback for test environment - we'll mock this
             todo!("Use mock database for tests")
         }),
-        subscriptions: Arc::new(RwLock::new(HashMap::new())),
+        subscriptions: Arc::new(DashMap::new()),
+        subscription_limits: Arc::new(DashMap::new()),
+        subscription_index: Arc::default(),
+        clients: Arc::new(RwLock::new(HashMap::new())),
         rate_limiter,
         metrics,
     }
@@ -335,3 +436,356 @@ async fn test_concurrent_subscription_management() {
         assert!(client_subs.is_empty());
     }
 }
+
+#[tokio::test]
+async fn test_slow_subscriber_is_closed_gracefully() {
+    // Stands in for the relay's connection loop: fill the queue of a client that
+    // isn't reading, then close it the way the relay does on overflow
+    async fn handler(ws: WebSocketUpgrade) -> Response {
+        ws.on_upgrade(|mut socket: WebSocket| async move {
+            let (sender, queue) = outbound::channel(3);
+            let mut queued = 0;
+            while sender.try_send(RelayMessage::Notice { message: format!("event {}", queued) }) {
+                queued += 1;
+            }
+            assert_eq!(queued, 3);
+            assert_eq!(sender.queue_depth(), 3);
+
+            queue.overflowed.cancelled().await;
+            outbound::close_slow_subscriber(&mut socket, vec!["feed".to_string()]).await.unwrap();
+        })
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/", get(handler))).await.unwrap();
+    });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+
+    let closed = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+    let closed: RelayMessage = serde_json::from_str(closed.to_text().unwrap()).unwrap();
+    assert_eq!(
+        closed,
+        RelayMessage::Closed {
+            subscription_id: SubscriptionId::new("feed"),
+            message: SLOW_SUBSCRIBER.to_string(),
+        }
+    );
+
+    // The queued events are discarded and the socket gets a close frame with a reason
+    match tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap() {
+        TungsteniteMessage::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Policy);
+            assert_eq!(frame.reason, SLOW_SUBSCRIBER);
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_auth_required_kinds() {
+    // Stands in for the relay's connection loop: challenge the client on connect,
+    // answer AUTH and refuse DMs until the connection has authenticated
+    async fn handler(ws: WebSocketUpgrade) -> Response {
+        ws.on_upgrade(|mut socket: WebSocket| async move {
+            let mut config = Config::from_env();
+            config.auth_required_kinds = vec![4];
+            let mut auth = ConnectionAuth::new();
+
+            let challenge = RelayMessage::Auth { challenge: auth.challenge().to_string() };
+            socket.send(Message::Text(serde_json::to_string(&challenge).unwrap())).await.unwrap();
+
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let replies = match serde_json::from_str::<ClientMessage>(&text).unwrap() {
+                    ClientMessage::Auth(event) => {
+                        let result = auth.authenticate(&event);
+                        vec![RelayMessage::Ok {
+                            event_id: event.id,
+                            status: result.is_ok(),
+                            message: result.err().unwrap_or_default(),
+                        }]
+                    }
+                    ClientMessage::Event(event) => match nip42::check_event_auth(&event, &auth, &config) {
+                        Ok(()) => vec![RelayMessage::Ok { event_id: event.id, status: true, message: String::new() }],
+                        Err(reason) => vec![
+                            RelayMessage::Auth { challenge: auth.challenge().to_string() },
+                            RelayMessage::Ok { event_id: event.id, status: false, message: reason },
+                        ],
+                    },
+                    _ => Vec::new(),
+                };
+                for reply in replies {
+                    socket.send(Message::Text(serde_json::to_string(&reply).unwrap())).await.unwrap();
+                }
+            }
+        })
+    }
+
+    async fn recv(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> RelayMessage {
+        let message = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn send(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>, message: ClientMessage) {
+        ws.send(TungsteniteMessage::Text(serde_json::to_string(&message).unwrap())).await.unwrap();
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/", get(handler))).await.unwrap();
+    });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+    let challenge = match recv(&mut ws).await {
+        RelayMessage::Auth { challenge } => challenge,
+        other => panic!("expected an auth challenge, got {:?}", other),
+    };
+
+    let keys = Keys::generate();
+    let dm = EventBuilder::new(Kind::EncryptedDirectMessage, "ciphertext", [])
+        .to_event(&keys)
+        .unwrap();
+
+    // Unauthenticated: the challenge is re-issued and the DM refused
+    send(&mut ws, ClientMessage::event(dm.clone())).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::Auth { challenge: challenge.clone() });
+    match recv(&mut ws).await {
+        RelayMessage::Ok { event_id, status, message } => {
+            assert_eq!(event_id, dm.id);
+            assert!(!status);
+            assert!(message.starts_with("auth-required: "));
+        }
+        other => panic!("expected OK, got {:?}", other),
+    }
+
+    // Other kinds don't need authentication
+    let note = EventBuilder::new(Kind::TextNote, "public", []).to_event(&keys).unwrap();
+    send(&mut ws, ClientMessage::event(note.clone())).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::Ok { event_id: note.id, status: true, message: String::new() });
+
+    // Authenticated: the same DM is accepted
+    let auth_event = EventBuilder::auth(challenge, Url::parse("ws://localhost").unwrap())
+        .to_event(&keys)
+        .unwrap();
+    send(&mut ws, ClientMessage::auth(auth_event.clone())).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::Ok { event_id: auth_event.id, status: true, message: String::new() });
+
+    send(&mut ws, ClientMessage::event(dm.clone())).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::Ok { event_id: dm.id, status: true, message: String::new() });
+}
+
+#[tokio::test]
+async fn test_auth_challenge_on_connect() {
+    // Stands in for the relay's connection loop with auth required: challenge the
+    // client on connect, refuse DM subscriptions until it authenticates and drop
+    // it if it doesn't answer the challenge in time
+    async fn handler(ws: WebSocketUpgrade) -> Response {
+        ws.on_upgrade(|mut socket: WebSocket| async move {
+            let mut config = Config::from_env();
+            config.auth_required = true;
+            config.auth_required_read_kinds = vec![4, 44];
+            let mut auth = ConnectionAuth::new();
+
+            let challenge = RelayMessage::Auth { challenge: auth.challenge().to_string() };
+            socket.send(Message::Text(serde_json::to_string(&challenge).unwrap())).await.unwrap();
+
+            let deadline = tokio::time::sleep(Duration::from_millis(500));
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    message = socket.next() => {
+                        let Some(Ok(Message::Text(text))) = message else { break };
+                        let replies = match serde_json::from_str::<ClientMessage>(&text).unwrap() {
+                            ClientMessage::Auth(event) => {
+                                let result = auth.authenticate(&event);
+                                vec![RelayMessage::Ok {
+                                    event_id: event.id,
+                                    status: result.is_ok(),
+                                    message: result.err().unwrap_or_default(),
+                                }]
+                            }
+                            ClientMessage::Req { subscription_id, filters } => {
+                                match nip42::check_filters_auth(&filters, &auth, &config) {
+                                    Ok(()) => vec![RelayMessage::EndOfStoredEvents(subscription_id)],
+                                    Err(message) => vec![
+                                        RelayMessage::Auth { challenge: auth.challenge().to_string() },
+                                        RelayMessage::Closed { subscription_id, message },
+                                    ],
+                                }
+                            }
+                            _ => Vec::new(),
+                        };
+                        for reply in replies {
+                            socket.send(Message::Text(serde_json::to_string(&reply).unwrap())).await.unwrap();
+                        }
+                    }
+                    _ = &mut deadline, if !auth.is_authenticated() => {
+                        nip42::close_unauthenticated(&mut socket).await.unwrap();
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn recv(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> RelayMessage {
+        let message = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn send(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>, message: ClientMessage) {
+        ws.send(TungsteniteMessage::Text(serde_json::to_string(&message).unwrap())).await.unwrap();
+    }
+
+    async fn challenge(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> String {
+        match recv(ws).await {
+            RelayMessage::Auth { challenge } => challenge,
+            other => panic!("expected an auth challenge, got {:?}", other),
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/", get(handler))).await.unwrap();
+    });
+    let dms = SubscriptionId::new("dms");
+    let dm_filter = Filter::new().kind(Kind::EncryptedDirectMessage);
+
+    // The challenge comes before anything else, and DMs are refused until the client authenticates
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+    let issued = challenge(&mut ws).await;
+    assert_eq!(issued.len(), 64);
+    assert!(issued.chars().all(|c| c.is_ascii_hexdigit()));
+
+    send(&mut ws, ClientMessage::req(dms.clone(), vec![dm_filter.clone()])).await;
+    assert_eq!(challenge(&mut ws).await, issued);
+    match recv(&mut ws).await {
+        RelayMessage::Closed { subscription_id, message } => {
+            assert_eq!(subscription_id, dms);
+            assert!(message.starts_with("auth-required: "));
+        }
+        other => panic!("expected CLOSED, got {:?}", other),
+    }
+
+    // Without an answer to the challenge the connection is closed with a reason
+    match tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap() {
+        TungsteniteMessage::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Policy);
+            assert_eq!(frame.reason, nip42::UNAUTHENTICATED);
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+
+    // A client that authenticates in time stays connected and can read its DMs
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+    let issued = challenge(&mut ws).await;
+    let keys = Keys::generate();
+    let auth_event = EventBuilder::auth(issued, Url::parse("ws://localhost").unwrap())
+        .to_event(&keys)
+        .unwrap();
+    send(&mut ws, ClientMessage::auth(auth_event.clone())).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::Ok { event_id: auth_event.id, status: true, message: String::new() });
+
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    send(&mut ws, ClientMessage::req(dms.clone(), vec![dm_filter])).await;
+    assert_eq!(recv(&mut ws).await, RelayMessage::EndOfStoredEvents(dms));
+}
+
+#[tokio::test]
+async fn test_invalid_filters_are_closed() {
+    // Stands in for the relay's REQ handling: refuse invalid filters with CLOSED
+    // before anything is stored or queried
+    async fn handler(ws: WebSocketUpgrade) -> Response {
+        ws.on_upgrade(|mut socket: WebSocket| async move {
+            let mut config = Config::from_env();
+            config.max_filters = 100;
+            config.max_limit = 500;
+
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let ClientMessage::Req { subscription_id, filters } = serde_json::from_str(&text).unwrap() else {
+                    continue;
+                };
+                let reply = match validation::validate_filters(&filters, &config) {
+                    Ok(()) => RelayMessage::EndOfStoredEvents(subscription_id),
+                    Err(message) => RelayMessage::Closed { subscription_id, message },
+                };
+                socket.send(Message::Text(serde_json::to_string(&reply).unwrap())).await.unwrap();
+            }
+        })
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/", get(handler))).await.unwrap();
+    });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/", addr)).await.unwrap();
+
+    let cases = [
+        ("valid", vec![Filter::new().kind(Kind::TextNote).limit(100)], None),
+        ("too-many", vec![Filter::new().kind(Kind::TextNote); 101], Some("error: too many filters (max 100)")),
+        (
+            "backwards",
+            vec![Filter::new().since(Timestamp::from(2_000)).until(Timestamp::from(1_000))],
+            Some("error: invalid time range: since > until"),
+        ),
+        ("oversized", vec![Filter::new().limit(10_000)], Some("error: filter limit too high (max 500)")),
+    ];
+
+    for (id, filters, expected) in cases {
+        let req = ClientMessage::req(SubscriptionId::new(id), filters);
+        ws.send(TungsteniteMessage::Text(serde_json::to_string(&req).unwrap())).await.unwrap();
+
+        let reply = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+        let reply: RelayMessage = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+        let expected = match expected {
+            Some(message) => RelayMessage::Closed { subscription_id: SubscriptionId::new(id), message: message.to_string() },
+            None => RelayMessage::EndOfStoredEvents(SubscriptionId::new(id)),
+        };
+        assert_eq!(reply, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_cleanup_removes_inactive_connections() {
+    let metadata = ConnectionMetadata::from_request("127.0.0.1".parse().unwrap(), &HeaderMap::new());
+    let (idle_sender, _idle_queue) = outbound::channel(10);
+    let (active_sender, _active_queue) = outbound::channel(10);
+    let (listening_sender, _listening_queue) = outbound::channel(10);
+    let idle = ConnectedClient::new(idle_sender, metadata.clone());
+    let active = ConnectedClient::new(active_sender, metadata.clone());
+    let listening = ConnectedClient::new(listening_sender, metadata);
+
+    // Run the cleanup ten minutes ahead instead of waiting out the timeout;
+    // backdating an Instant can underflow on a freshly booted host
+    let now = Instant::now() + Duration::from_secs(600);
+    *active.last_activity.lock().unwrap() = now;
+    let idle_close = idle.close.clone();
+
+    // A quiet client with an open subscription is waiting for events, not idle
+    let subscriptions = DashMap::new();
+    let listening_subs = DashMap::new();
+    listening_subs.insert("feed:0".to_string(), Filter::new().kind(Kind::TextNote));
+    subscriptions.insert("listening".to_string(), listening_subs);
+
+    let clients = RwLock::new(HashMap::from([
+        ("idle".to_string(), idle),
+        ("active".to_string(), active.clone()),
+        ("listening".to_string(), listening),
+    ]));
+
+    let removed = cleanup_inactive_connections(&clients, &subscriptions, Duration::from_secs(300), now).await;
+
+    assert_eq!(removed, 1);
+    let clients = clients.read().await;
+    assert!(!clients.contains_key("idle"));
+    assert!(clients.contains_key("active"));
+    assert!(clients.contains_key("listening"));
+    assert!(idle_close.is_cancelled());
+    assert!(!active.close.is_cancelled());
+}
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS events (
+    id VARCHAR(64) PRIMARY KEY,
+    pubkey VARCHAR(64) NOT NULL,
+    created_at BIGINT NOT NULL,
+    kind INTEGER NOT NULL,
+    tags JSONB NOT NULL,
+    content TEXT NOT NULL,
+    sig VARCHAR(128) NOT NULL,
+    raw_event TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_pubkey ON events(pubkey);
+CREATE INDEX IF NOT EXISTS idx_events_created_at ON events(created_at);
+CREATE INDEX IF NOT EXISTS idx_events_kind ON events(kind);
@@ -0,0 +1,4 @@
+-- NIP-33: parameterized replaceable events are keyed by their 'd' tag
+ALTER TABLE events ADD COLUMN IF NOT EXISTS d_tag VARCHAR;
+
+CREATE INDEX IF NOT EXISTS idx_events_pubkey_kind_d_tag ON events(pubkey, kind, d_tag);
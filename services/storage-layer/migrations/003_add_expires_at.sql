@@ -0,0 +1,4 @@
+-- NIP-40: events carrying an expiration tag stop being served after this time
+ALTER TABLE events ADD COLUMN IF NOT EXISTS expires_at BIGINT;
+
+CREATE INDEX IF NOT EXISTS idx_events_expires_at ON events(expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Tags used to be stored as TEXT; convert older tables in place so they
+-- can be indexed for tag filters
+DO $$
+BEGIN
+    IF (SELECT data_type FROM information_schema.columns
+        WHERE table_name = 'events' AND column_name = 'tags'
+        AND table_schema = current_schema()) = 'text' THEN
+        ALTER TABLE events ALTER COLUMN tags TYPE JSONB USING tags::jsonb;
+    END IF;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS idx_events_tags ON events USING GIN (tags);
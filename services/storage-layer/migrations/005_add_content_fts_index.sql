@@ -0,0 +1,2 @@
+-- NIP-50: full-text search over event content
+CREATE INDEX IF NOT EXISTS idx_events_content_fts ON events USING GIN (to_tsvector('english', content));
@@ -0,0 +1,5 @@
+-- Pubkeys banned by the operator through the admin API
+CREATE TABLE IF NOT EXISTS blocked_pubkeys (
+    pubkey VARCHAR(64) PRIMARY KEY,
+    blocked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
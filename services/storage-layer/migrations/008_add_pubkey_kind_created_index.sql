@@ -0,0 +1,2 @@
+-- "Latest events of kind K by author P", served straight from the index in order
+CREATE INDEX IF NOT EXISTS idx_events_pubkey_kind_created ON events (pubkey, kind, created_at DESC);
@@ -0,0 +1,7 @@
+-- Latest NIP-65 relay list (kind 10002) per pubkey
+CREATE TABLE IF NOT EXISTS relay_lists (
+    pubkey VARCHAR(64) PRIMARY KEY,
+    relays JSONB NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
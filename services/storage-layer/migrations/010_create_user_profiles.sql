@@ -0,0 +1,6 @@
+-- Latest kind-0 profile metadata per pubkey
+CREATE TABLE IF NOT EXISTS user_profiles (
+    pubkey VARCHAR(64) PRIMARY KEY,
+    metadata JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- NIP-50: search is a case-insensitive substring match, the same one live
+-- subscriptions use, so content gets a trigram index instead of full-text
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+DROP INDEX IF EXISTS idx_events_content_fts;
+
+CREATE INDEX IF NOT EXISTS idx_events_content_trgm ON events USING GIN (content gin_trgm_ops);
@@ -0,0 +1,19 @@
+-- Single-letter tags, one row per tag, so `#e`/`#p`/... filters are
+-- plain index lookups instead of JSON containment checks
+CREATE TABLE IF NOT EXISTS event_tags (
+    event_id VARCHAR(64) NOT NULL REFERENCES events(id) ON DELETE CASCADE,
+    tag_name VARCHAR(10) NOT NULL,
+    tag_value TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_tags_name_value ON event_tags(tag_name, tag_value);
+
+-- Keeps cascaded deletes from scanning the whole table
+CREATE INDEX IF NOT EXISTS idx_event_tags_event_id ON event_tags(event_id);
+
+-- Fill the table from events stored before it existed
+INSERT INTO event_tags (event_id, tag_name, tag_value)
+SELECT id, tag->>0, tag->>1
+FROM events, jsonb_array_elements(tags) AS tag
+WHERE length(tag->>0) = 1 AND tag->>1 IS NOT NULL
+  AND NOT EXISTS (SELECT 1 FROM event_tags);
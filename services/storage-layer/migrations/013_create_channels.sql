@@ -0,0 +1,7 @@
+-- NIP-28: channels and their current metadata, for channel discovery
+CREATE TABLE IF NOT EXISTS channels (
+    channel_id VARCHAR(64) PRIMARY KEY,
+    creator_pubkey VARCHAR(64) NOT NULL,
+    metadata JSONB NOT NULL,
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- NIP-26: the pubkey a delegated event was published on behalf of, set only
+-- once the relay has verified the delegation token, so author filters can
+-- match the delegator. Events stored earlier aren't backfilled, since their
+-- tokens were never checked.
+ALTER TABLE events ADD COLUMN IF NOT EXISTS delegator VARCHAR(64);
+
+CREATE INDEX IF NOT EXISTS idx_events_delegator ON events(delegator) WHERE delegator IS NOT NULL;
@@ -3,12 +3,19 @@ pub mod cache;
 pub mod repository;
 pub mod error;
 pub mod migrations;
+pub mod query_builder;
+pub mod read_write_pool;
+pub mod slow_query;
 
 // Re-export main types
 pub use database::{Database, DatabaseConfig};
 pub use cache::{Cache, CacheConfig};
 pub use repository::{EventRepository, UserRepository, SubscriptionRepository};
 pub use error::{StorageError, StorageResult};
+pub use migrations::MIGRATOR;
+pub use query_builder::{FilterQueryBuilder, QueryParam};
+pub use read_write_pool::{ReadPool, ReadWritePool};
+pub use slow_query::{SlowQuery, SlowQueryLogger};
 
 /// Storage layer facade that combines database and cache
 pub struct Storage {
@@ -39,7 +46,7 @@ impl Storage {
     
     /// Run database migrations
     pub async fn migrate(&self) -> StorageResult<()> {
-        self.database.migrate().await
+        migrations::run(self.database.pool()).await
     }
     
     /// Health check for storage layer
@@ -65,6 +72,9 @@ pub struct DatabaseHealth {
     pub connected: bool,
     pub pool_size: u32,
     pub active_connections: u32,
+    /// Round-trip time of a `SELECT 1`, when it completed
+    pub latency_ms: Option<f64>,
+    pub last_error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -72,4 +82,7 @@ pub struct CacheHealth {
     pub connected: bool,
     pub memory_usage: u64,
     pub connected_clients: u32,
+    /// Round-trip time of a `PING`, when it completed
+    pub latency_ms: Option<f64>,
+    pub last_error: Option<String>,
 }
@@ -0,0 +1,258 @@
+use pleb_one_nostr_types::Filter;
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
+use sqlx::Postgres;
+
+/// Default number of events returned when no filter specifies a limit
+pub const DEFAULT_LIMIT: u64 = 500;
+
+/// Upper bound on the number of events a single query can return
+pub const MAX_LIMIT: u64 = 5000;
+
+/// A value bound to a positional (`$n`) parameter of a generated query
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryParam {
+    Text(String),
+    TextArray(Vec<String>),
+    BigInt(i64),
+    BigIntArray(Vec<i64>),
+}
+
+impl QueryParam {
+    /// Bind this parameter to the next placeholder of `query`
+    pub fn bind<'q>(self, query: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments> {
+        match self {
+            QueryParam::Text(value) => query.bind(value),
+            QueryParam::TextArray(values) => query.bind(values),
+            QueryParam::BigInt(value) => query.bind(value),
+            QueryParam::BigIntArray(values) => query.bind(values),
+        }
+    }
+
+    /// Bind all parameters, in order, to `query`
+    pub fn bind_all<'q>(
+        params: Vec<QueryParam>,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        params.into_iter().fold(query, |query, param| param.bind(query))
+    }
+}
+
+/// Builds parameterized SQL against the `events` table from a set of Nostr filters.
+///
+/// Filters are combined as `(…) OR (…)`, so an event matching any filter is returned.
+/// User-supplied values are never interpolated into the SQL text; they are returned
+/// as `QueryParam`s to be bound in order.
+pub struct FilterQueryBuilder<'a> {
+    filters: &'a [Filter],
+}
+
+impl<'a> FilterQueryBuilder<'a> {
+    pub fn new(filters: &'a [Filter]) -> Self {
+        Self { filters }
+    }
+
+    /// `SELECT` query returning matching events, newest first.
+    /// The largest `limit` among the filters applies to the combined result.
+    pub fn build_query(&self) -> (String, Vec<QueryParam>) {
+        let mut params = Vec::new();
+        let where_clause = self.build_where_clause(&mut params);
+
+        let limit = self
+            .filters
+            .iter()
+            .filter_map(|filter| filter.limit)
+            .max()
+            .unwrap_or(DEFAULT_LIMIT)
+            .min(MAX_LIMIT);
+        params.push(QueryParam::BigInt(limit as i64));
+
+        let sql = format!(
+            "SELECT id, pubkey, created_at, kind, tags, content, sig FROM events WHERE {} ORDER BY created_at DESC LIMIT ${}",
+            where_clause,
+            params.len()
+        );
+
+        (sql, params)
+    }
+
+    /// `SELECT COUNT(*)` query over the same conditions (NIP-45); limits are ignored
+    pub fn build_count_query(&self) -> (String, Vec<QueryParam>) {
+        let mut params = Vec::new();
+        let where_clause = self.build_where_clause(&mut params);
+
+        let sql = format!("SELECT COUNT(*) AS count FROM events WHERE {}", where_clause);
+
+        (sql, params)
+    }
+
+    fn build_where_clause(&self, params: &mut Vec<QueryParam>) -> String {
+        if self.filters.is_empty() {
+            return "TRUE".to_string();
+        }
+
+        let clauses: Vec<String> = self
+            .filters
+            .iter()
+            .map(|filter| format!("({})", Self::filter_conditions(filter, params)))
+            .collect();
+
+        clauses.join(" OR ")
+    }
+
+    fn filter_conditions(filter: &Filter, params: &mut Vec<QueryParam>) -> String {
+        let mut conditions = Vec::new();
+
+        if let Some(ids) = &filter.ids {
+            let n = Self::add_param(params, QueryParam::TextArray(ids.clone()));
+            conditions.push(format!("id = ANY(${})", n));
+        }
+
+        if let Some(authors) = &filter.authors {
+            let n = Self::add_param(params, QueryParam::TextArray(authors.clone()));
+            conditions.push(format!("pubkey = ANY(${})", n));
+        }
+
+        if let Some(kinds) = &filter.kinds {
+            let kinds = kinds.iter().map(|kind| *kind as i64).collect();
+            let n = Self::add_param(params, QueryParam::BigIntArray(kinds));
+            conditions.push(format!("kind = ANY(${})", n));
+        }
+
+        if let Some(since) = filter.since {
+            let n = Self::add_param(params, QueryParam::BigInt(since));
+            conditions.push(format!("created_at >= ${}", n));
+        }
+
+        if let Some(until) = filter.until {
+            let n = Self::add_param(params, QueryParam::BigInt(until));
+            conditions.push(format!("created_at <= ${}", n));
+        }
+
+        // NIP-50: the relay's case-insensitive substring match, served by the
+        // content trigram index
+        if let Some(search) = &filter.search {
+            let n = Self::add_param(params, QueryParam::Text(format!("%{}%", Self::escape_like(search))));
+            conditions.push(format!("content ILIKE ${}", n));
+        }
+
+        // Tag filters are keyed "#<letter>"; anything else isn't a valid tag query.
+        // Sorted so the generated SQL is stable for a given filter.
+        let mut tag_keys: Vec<&String> = filter.tags.keys().collect();
+        tag_keys.sort();
+        for key in tag_keys {
+            let Some(letter) = key.strip_prefix('#').filter(|name| name.chars().count() == 1) else {
+                continue;
+            };
+            let name = Self::add_param(params, QueryParam::Text(letter.to_string()));
+            let values = Self::add_param(params, QueryParam::TextArray(filter.tags[key].clone()));
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM jsonb_array_elements(tags::jsonb) AS t WHERE t->>0 = ${} AND t->>1 = ANY(${}))",
+                name, values
+            ));
+        }
+
+        if conditions.is_empty() {
+            return "TRUE".to_string();
+        }
+
+        conditions.join(" AND ")
+    }
+
+    // Escape LIKE wildcards so search text is matched literally
+    fn escape_like(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    }
+
+    // Append a parameter and return its 1-based placeholder index
+    fn add_param(params: &mut Vec<QueryParam>, param: QueryParam) -> usize {
+        params.push(param);
+        params.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_filters() {
+        let (sql, params) = FilterQueryBuilder::new(&[]).build_query();
+        assert_eq!(
+            sql,
+            "SELECT id, pubkey, created_at, kind, tags, content, sig FROM events WHERE TRUE ORDER BY created_at DESC LIMIT $1"
+        );
+        assert_eq!(params, vec![QueryParam::BigInt(DEFAULT_LIMIT as i64)]);
+    }
+
+    #[test]
+    fn test_all_fields() {
+        let filter = Filter::new()
+            .id("abc")
+            .author("def")
+            .kind(1)
+            .since(1000)
+            .until(2000)
+            .tag("e", "123")
+            .limit(10);
+        let (sql, params) = FilterQueryBuilder::new(std::slice::from_ref(&filter)).build_query();
+
+        assert!(sql.contains(
+            "WHERE (id = ANY($1) AND pubkey = ANY($2) AND kind = ANY($3) AND created_at >= $4 AND created_at <= $5 \
+             AND EXISTS (SELECT 1 FROM jsonb_array_elements(tags::jsonb) AS t WHERE t->>0 = $6 AND t->>1 = ANY($7)))"
+        ));
+        assert!(sql.ends_with("LIMIT $8"));
+        assert_eq!(params.len(), 8);
+        assert_eq!(params[5], QueryParam::Text("e".to_string()));
+        assert_eq!(params[7], QueryParam::BigInt(10));
+    }
+
+    #[test]
+    fn test_multiple_filters_are_ored() {
+        let filters = vec![Filter::new().kind(1).limit(20), Filter::new().author("abc").limit(50)];
+        let (sql, params) = FilterQueryBuilder::new(&filters).build_query();
+
+        assert!(sql.contains("WHERE (kind = ANY($1)) OR (pubkey = ANY($2))"));
+        // The largest limit applies to the combined result
+        assert_eq!(params.last(), Some(&QueryParam::BigInt(50)));
+    }
+
+    #[test]
+    fn test_count_query() {
+        let filters = vec![Filter::new().kind(7).limit(5)];
+        let (sql, params) = FilterQueryBuilder::new(&filters).build_count_query();
+
+        assert_eq!(sql, "SELECT COUNT(*) AS count FROM events WHERE (kind = ANY($1))");
+        assert_eq!(params, vec![QueryParam::BigIntArray(vec![7])]);
+    }
+
+    #[test]
+    fn test_search_combined_with_kind_and_author() {
+        let filter = Filter::new().kind(1).author("abc").search("nostr relay");
+        let (sql, params) = FilterQueryBuilder::new(&[filter]).build_count_query();
+
+        assert_eq!(
+            sql,
+            "SELECT COUNT(*) AS count FROM events WHERE (pubkey = ANY($1) AND kind = ANY($2) AND content ILIKE $3)"
+        );
+        assert_eq!(params[2], QueryParam::Text("%nostr relay%".to_string()));
+    }
+
+    #[test]
+    fn test_search_wildcards_are_escaped() {
+        assert_eq!(FilterQueryBuilder::escape_like("nostr relay"), "nostr relay");
+        assert_eq!(FilterQueryBuilder::escape_like("100%_done"), "100\\%\\_done");
+        assert_eq!(FilterQueryBuilder::escape_like("C:\\path"), "C:\\\\path");
+    }
+
+    #[test]
+    fn test_invalid_tag_keys_are_ignored() {
+        let mut filter = Filter::new();
+        filter.tags.insert("#long".to_string(), vec!["x".to_string()]);
+        filter.tags.insert("search".to_string(), vec!["x".to_string()]);
+        let (sql, params) = FilterQueryBuilder::new(&[filter]).build_count_query();
+
+        assert_eq!(sql, "SELECT COUNT(*) AS count FROM events WHERE (TRUE)");
+        assert!(params.is_empty());
+    }
+}